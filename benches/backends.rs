@@ -0,0 +1,94 @@
+// 各阶段与各后端的基准测试：cargo bench
+//
+// 语法还没有循环与字符串字面量，用较长的直线程序代替循环密集的程序；
+// vm 的函数调用还不能运行，jit 只支持单个无参函数，fib 只在解释器上运行
+use std::hint::black_box;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+#[cfg(feature = "jit")]
+use plua::backend::JitBackend;
+use plua::backend::{Backend, InterpBackend, VmBackend};
+use plua::emitter::Emitter;
+use plua::parser::Parser;
+use plua::scanner::Scanner;
+
+const FIB: &str = "function fib(n)
+  if n < 2 then
+    return n;
+  end
+  return fib(n - 1) + fib(n - 2);
+end
+
+return fib(15);";
+
+// 每个变量依赖前两个变量，结果保持在较小的范围内
+fn straight_line(count: usize) -> String {
+    let mut source = String::from("local v0 = 1;\nlocal v1 = 2;\n");
+    for i in 2..count {
+        source += &format!("local v{} = v{} - v{} + {} * 2;\n", i, i - 1, i - 2, i % 7);
+    }
+    source += &format!("return v{} < v{};", count - 1, count - 2);
+    source
+}
+
+// jit 中赋值即声明，表达式中还不能引用变量
+#[cfg(feature = "jit")]
+fn jit_function(count: usize) -> String {
+    let mut source = String::from("function main()\n");
+    for i in 0..count {
+        source += &format!("  v{} = {} + {} * 3 - 8 / 2;\n", i, i, i % 5);
+    }
+    source += &format!("  return v{};\nend", count - 1);
+    source
+}
+
+fn front_end(c: &mut Criterion) {
+    let straight = straight_line(1000);
+    for (name, source) in [("fib", FIB), ("straight", straight.as_str())] {
+        c.bench_function(&format!("scan/{}", name), |b| {
+            b.iter(|| {
+                let mut scanner = Scanner::new(black_box(source).to_string());
+                scanner.scan_tokens().unwrap();
+                scanner.take_tokens()
+            })
+        });
+
+        let mut scanner = Scanner::new(source.to_string());
+        scanner.scan_tokens().unwrap();
+        let tokens = scanner.take_tokens();
+        c.bench_function(&format!("parse/{}", name), |b| {
+            b.iter(|| Parser::new(black_box(tokens.clone())).parse().unwrap())
+        });
+
+        let statements = Parser::new(tokens).parse().unwrap();
+        c.bench_function(&format!("emit/{}", name), |b| {
+            b.iter(|| {
+                Emitter::default()
+                    .emit_all(black_box(&statements))
+                    .unwrap()
+                    .len()
+            })
+        });
+    }
+}
+
+fn run<B: Backend>(c: &mut Criterion, name: &str, mut backend: B, source: &str) {
+    let program = backend.compile(source).unwrap();
+    c.bench_function(&format!("run/{}/{}", name, backend.name()), |b| {
+        b.iter(|| backend.run(black_box(&program)).unwrap())
+    });
+}
+
+fn backends(c: &mut Criterion) {
+    run(c, "fib", InterpBackend::default(), FIB);
+
+    let straight = straight_line(1000);
+    run(c, "straight", InterpBackend::default(), &straight);
+    run(c, "straight", VmBackend::default(), &straight);
+
+    #[cfg(feature = "jit")]
+    run(c, "function", JitBackend::default(), &jit_function(100));
+}
+
+criterion_group!(benches, front_end, backends);
+criterion_main!(benches);
@@ -1,18 +1,77 @@
-use std::io::{stdin, stdout};
-use std::path::PathBuf;
+use std::io::{stdin, stdout, BufRead, Write};
+use std::path::{Path, PathBuf};
 
 use structopt::StructOpt;
 
 extern crate plua;
-use plua::bf::vm::BfVM;
+use plua::bf::interp::BfInterp;
+use plua::bf::{self, Backend, BfVmOptions, CellWrap, Eof};
 
 #[derive(Debug, StructOpt)]
 struct Opt {
-    #[structopt(name = "file")]
-    file_path: PathBuf,
+    #[structopt(name = "file", required_unless = "interactive")]
+    file_path: Option<PathBuf>,
+
+    #[structopt(
+        short = "i",
+        long = "interactive",
+        help = "Start a REPL that keeps the tape between inputs"
+    )]
+    interactive: bool,
 
     #[structopt(short = "o", long = "optimize", help = "Optimize code")]
     optimize: bool,
+
+    #[structopt(
+        long = "interp",
+        help = "Use the portable interpreter instead of the JIT"
+    )]
+    interp: bool,
+
+    #[structopt(
+        long = "cranelift",
+        help = "Use the Cranelift code generator instead of the dynasm JIT"
+    )]
+    cranelift: bool,
+
+    #[structopt(
+        long = "vm",
+        help = "Translate to plua bytecode and run on the plua VM"
+    )]
+    vm: bool,
+
+    #[structopt(
+        long = "memory",
+        default_value = "4194304",
+        help = "Memory size in bytes"
+    )]
+    memory_size: usize,
+
+    #[structopt(
+        long = "dump",
+        help = "Print the IR listing and generated code offsets instead of running"
+    )]
+    dump: bool,
+
+    #[structopt(
+        long = "profile",
+        help = "Run with the interpreter and report op counts and the hottest loops"
+    )]
+    profile: bool,
+
+    #[structopt(long = "debug", help = "Step through the program with the interpreter")]
+    debug: bool,
+
+    #[structopt(
+        long = "eof",
+        default_value = "unchanged",
+        parse(try_from_str = parse_eof),
+        help = "Cell value after reading EOF: unchanged, 0 or 255"
+    )]
+    eof: Eof,
+
+    #[structopt(long = "no-wrap", help = "Report an error when a cell overflows")]
+    no_wrap: bool,
 }
 
 fn main() {
@@ -21,13 +80,80 @@ fn main() {
     let stdin = stdin();
     let stdout = stdout();
 
-    let ret = BfVM::new(
-        &opt.file_path,
+    let options = BfVmOptions {
+        memory_size: opt.memory_size,
+        cell_wrap: if opt.no_wrap {
+            CellWrap::Error
+        } else {
+            CellWrap::Wrap
+        },
+        eof: opt.eof,
+        optimized: opt.optimize,
+    };
+
+    let file_path = match opt.file_path {
+        Some(file_path) if !opt.interactive => file_path,
+        _ => {
+            let ret = repl(options);
+            if let Err(e) = &ret {
+                eprintln!("bf: {}", e);
+            }
+            std::process::exit(ret.is_err() as i32)
+        }
+    };
+
+    if opt.dump {
+        let ret = std::fs::read_to_string(&file_path)
+            .map_err(bf::error::VMError::from)
+            .and_then(|src| bf::listing::listing(&src, options));
+        match ret {
+            Ok(out) => print!("{}", out),
+            Err(e) => {
+                eprintln!("bf: {}", e);
+                std::process::exit(1)
+            }
+        }
+        return;
+    }
+
+    if opt.profile {
+        let ret = profile(&file_path, options);
+        if let Err(e) = &ret {
+            eprintln!("bf: {}", e);
+        }
+        std::process::exit(ret.is_err() as i32)
+    }
+
+    if opt.debug {
+        let ret = debug(&file_path, options);
+        if let Err(e) = &ret {
+            eprintln!("bf: {}", e);
+        }
+        std::process::exit(ret.is_err() as i32)
+    }
+
+    if opt.vm {
+        if let Err(e) = run_on_vm(&file_path, options) {
+            eprintln!("bf: {}", e);
+            std::process::exit(1)
+        }
+        return;
+    }
+
+    let backend = if opt.interp {
+        Backend::Interp
+    } else if opt.cranelift {
+        Backend::Cranelift
+    } else {
+        Backend::native()
+    };
+    let ret = bf::run(
+        &file_path,
         Box::new(stdin.lock()),
         Box::new(stdout.lock()),
-        opt.optimize,
-    )
-    .and_then(|mut vm| vm.run());
+        options,
+        backend,
+    );
 
     if let Err(e) = &ret {
         eprintln!("bf: {}", e);
@@ -35,3 +161,137 @@ fn main() {
 
     std::process::exit(ret.is_err() as i32)
 }
+
+// 交互模式使用解释器，每行代码在同一块内存上执行
+// :tape [n] 打印指针前后 n 个单元，:reset 清空内存，:quit 退出
+fn repl(options: BfVmOptions) -> bf::error::Result<()> {
+    let mut interp = BfInterp::from_source("", Box::new(stdin()), Box::new(stdout()), options)?;
+    let mut line = String::new();
+    loop {
+        print!("bf> ");
+        stdout().flush()?;
+        line.clear();
+        if stdin().lock().read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some(":quit") => return Ok(()),
+            Some(":reset") => interp.reset(),
+            Some(":tape") => {
+                let radius = words.next().and_then(|n| n.parse().ok()).unwrap_or(8);
+                print_tape(&interp, radius);
+            }
+            _ => {
+                if let Err(e) = interp.eval(&line) {
+                    eprintln!("bf: {}", e);
+                }
+                // 输出不一定以换行结束
+                println!();
+            }
+        }
+    }
+}
+
+fn print_tape(interp: &BfInterp, radius: usize) {
+    let (start, cells) = interp.tape(radius);
+    let cells: Vec<_> = cells
+        .iter()
+        .enumerate()
+        .map(|(i, cell)| {
+            if start + i == interp.ptr() {
+                format!("[{}]", cell)
+            } else {
+                cell.to_string()
+            }
+        })
+        .collect();
+    println!("{}: {}", start, cells.join(" "));
+}
+
+// 调试命令：s [n] 单步执行 n 条 ir，c 继续执行到断点，b/d 行:列 设置/删除断点，
+// t [n] 打印指针前后 n 个单元，q 退出
+fn debug(file_path: &Path, options: BfVmOptions) -> bf::error::Result<()> {
+    let mut interp = BfInterp::new(file_path, Box::new(stdin()), Box::new(stdout()), options)?;
+    let mut line = String::new();
+    loop {
+        match interp.current() {
+            Some((ir, Some((l, c)))) => println!("{}:{} {:?}", l, c, ir),
+            Some((ir, None)) => println!("{} {:?}", interp.pc(), ir),
+            None => println!("finished"),
+        }
+        print!("(bf) ");
+        stdout().flush()?;
+        line.clear();
+        if stdin().lock().read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+
+        let mut words = line.split_whitespace();
+        let ret = match words.next() {
+            Some("q") => return Ok(()),
+            Some("s") => {
+                let n = words.next().and_then(|n| n.parse().ok()).unwrap_or(1);
+                (0..n).try_for_each(|_| interp.step().map(drop))
+            }
+            Some("c") => interp.cont().map(drop),
+            Some(cmd @ ("b" | "d")) => {
+                match words.next().and_then(parse_position) {
+                    Some(position) if cmd == "b" => interp.add_breakpoint(position),
+                    Some(position) => {
+                        interp.remove_breakpoint(position);
+                    }
+                    None => eprintln!("usage: {} line:col", cmd),
+                }
+                Ok(())
+            }
+            Some("t") => {
+                let radius = words.next().and_then(|n| n.parse().ok()).unwrap_or(8);
+                print_tape(&interp, radius);
+                Ok(())
+            }
+            _ => Ok(()),
+        };
+        if let Err(e) = ret {
+            eprintln!("bf: {}", e);
+        }
+    }
+}
+
+fn parse_eof(s: &str) -> Result<Eof, String> {
+    match s {
+        "unchanged" => Ok(Eof::Unchanged),
+        "0" => Ok(Eof::Zero),
+        "255" => Ok(Eof::Max),
+        _ => Err(format!("invalid EOF behavior: {}", s)),
+    }
+}
+
+fn parse_position(s: &str) -> Option<(u32, u32)> {
+    let (line, col) = s.split_once(':')?;
+    Some((line.parse().ok()?, col.parse().ok()?))
+}
+
+// 统计只在解释器中实现，报告输出到 stderr
+fn profile(file_path: &Path, options: BfVmOptions) -> bf::error::Result<()> {
+    let mut interp = BfInterp::new(file_path, Box::new(stdin()), Box::new(stdout()), options)?;
+    interp.enable_profile();
+    let ret = interp.run();
+    if let Some(profile) = interp.take_profile() {
+        eprint!("{}", profile.report(10));
+    }
+    ret
+}
+
+// 纸带大小取 memory_size，单元总是回绕
+fn run_on_vm(file_path: &Path, options: BfVmOptions) -> Result<(), Box<dyn std::error::Error>> {
+    let src = std::fs::read_to_string(file_path)?;
+    let mut code = bf::compile::compile(&src)?;
+    if options.optimized {
+        bf::compile::optimize(&mut code, CellWrap::Wrap);
+    }
+    let mut vm = bf::bytecode::new_vm(options.memory_size);
+    vm.eval(&bf::bytecode::translate(&code, options.eof)?)?;
+    Ok(())
+}
@@ -0,0 +1,17 @@
+#![no_main]
+
+// 任意 UTF-8 输入经过词法、语法分析只能返回错误，不能 panic 或死循环
+use libfuzzer_sys::fuzz_target;
+use plua::parser::Parser;
+use plua::scanner::Scanner;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(source) = std::str::from_utf8(data) else {
+        return;
+    };
+    let mut scanner = Scanner::new(source.to_string());
+    if scanner.scan_tokens().is_err() {
+        return;
+    }
+    let _ = Parser::new(scanner.take_tokens()).parse();
+});
@@ -0,0 +1,52 @@
+#![no_main]
+
+// 任意输入经过词法、语法分析、静态检查、解释器、字节码生成、vm 与 JIT，都只能返回错误，不能 panic
+use libfuzzer_sys::fuzz_target;
+use plua::emitter::Emitter;
+use plua::intercepter::Intercepter;
+use plua::jit::JIT;
+use plua::parser::Parser;
+use plua::resolver::Resolver;
+use plua::scanner::Scanner;
+use plua::statement::Stmt;
+use plua::vm::{Limits, VM};
+
+fn limits() -> Limits {
+    Limits {
+        max_steps: Some(10_000),
+        max_memory: Some(1 << 20),
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let source = String::from_utf8_lossy(data).into_owned();
+    let mut scanner = Scanner::new(source);
+    let tokens = match scanner.scan_tokens() {
+        Ok(tokens) => tokens.clone(),
+        Err(_) => return,
+    };
+    let statements = match Parser::new(tokens).parse() {
+        Ok(statements) => statements,
+        Err(_) => return,
+    };
+
+    let _ = Resolver::default().lint(&statements);
+    let _ = Resolver::default().resolve(&statements);
+
+    let mut intercepter = Intercepter::new();
+    intercepter.set_limits(limits());
+    let _ = intercepter.eval(&statements);
+
+    if let Ok(funcs) = Emitter::default().emit_all(&statements) {
+        let mut vm = VM::new_with_funcs(funcs.clone());
+        vm.set_limits(limits());
+        let _ = vm.eval_all();
+    }
+
+    let mut jit = JIT::default();
+    for stmt in &statements {
+        if let Stmt::FunctionStmt(..) = stmt {
+            let _ = jit.compile(stmt);
+        }
+    }
+});
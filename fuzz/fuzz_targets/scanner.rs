@@ -0,0 +1,13 @@
+#![no_main]
+
+// 任意 UTF-8 输入经过词法分析只能返回错误，不能 panic 或死循环
+use libfuzzer_sys::fuzz_target;
+use plua::scanner::Scanner;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(source) = std::str::from_utf8(data) else {
+        return;
+    };
+    let mut scanner = Scanner::new(source.to_string());
+    let _ = scanner.scan_tokens();
+});
@@ -0,0 +1,29 @@
+#![no_main]
+
+// 任意字节作为字节码文件加载并在 vm 上运行，只能返回错误，不能 panic
+use libfuzzer_sys::fuzz_target;
+use plua::bytecode::ByteCode;
+use plua::dump::undump;
+use plua::vm::{Limits, VM};
+
+fuzz_target!(|data: &[u8]| {
+    let funcs = match undump(data) {
+        Ok(funcs) => funcs,
+        Err(_) => return,
+    };
+    // GetChar 会阻塞在 stdin 上
+    let reads_stdin = funcs
+        .iter()
+        .flat_map(|func| &func.chunk().codes)
+        .any(|code| matches!(code, ByteCode::GetChar));
+    if reads_stdin {
+        return;
+    }
+
+    let mut vm = VM::new_with_funcs(funcs);
+    vm.set_limits(Limits {
+        max_steps: Some(10_000),
+        max_memory: Some(1 << 20),
+    });
+    let _ = vm.eval_all();
+});
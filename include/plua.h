@@ -0,0 +1,46 @@
+/* plua 的 C 接口，使用 cargo rustc --lib --release --features ffi --crate-type cdylib 生成动态库 */
+#ifndef PLUA_H
+#define PLUA_H
+
+#include <stdbool.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct PluaEngine PluaEngine;
+typedef struct PluaValue PluaValue;
+
+typedef enum PluaType {
+    PLUA_NIL = 0,
+    PLUA_BOOL = 1,
+    PLUA_INT = 2,
+    PLUA_FLOAT = 3,
+    PLUA_STRING = 4,
+    PLUA_TABLE = 5,
+    PLUA_FUNCTION = 6,
+} PluaType;
+
+/* engine，使用 plua_free 释放 */
+PluaEngine *plua_new(void);
+void plua_free(PluaEngine *engine);
+
+/* 出错时返回 NULL，错误信息通过 plua_last_error 取得，返回的值使用 plua_value_free 释放 */
+PluaValue *plua_eval(PluaEngine *engine, const char *source);
+PluaValue *plua_get_global(PluaEngine *engine, const char *name);
+const char *plua_last_error(const PluaEngine *engine);
+
+PluaType plua_value_type(const PluaValue *value);
+int64_t plua_value_int(const PluaValue *value);
+double plua_value_float(const PluaValue *value);
+bool plua_value_bool(const PluaValue *value);
+/* 在值释放前有效 */
+const char *plua_value_string(const PluaValue *value);
+void plua_value_free(PluaValue *value);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* PLUA_H */
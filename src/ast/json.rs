@@ -0,0 +1,64 @@
+use crate::error::Error;
+use crate::statement::Stmt;
+
+// 语法树输出为 json，供外部工具查看、修改后再交给解释器或 emitter，
+// 宿主注册的函数不能序列化
+pub fn to_json(statements: &[Stmt]) -> Result<String, Error> {
+    serde_json::to_string_pretty(statements).map_err(|e| Error::JsonError(e.to_string()))
+}
+
+// 从 to_json 的输出恢复语法树
+pub fn from_json(json: &str) -> Result<Vec<Stmt>, Error> {
+    serde_json::from_str(json).map_err(|e| Error::JsonError(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{from_json, to_json};
+    use crate::ast::to_source;
+    use crate::error::Error;
+    use crate::expression::Expr;
+    use crate::intercepter::Intercepter;
+    use crate::native::{IntoNativeFn, NativeFunction};
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+    use crate::statement::Stmt;
+    use crate::value::Value;
+
+    fn parse(script: &str) -> Vec<Stmt> {
+        let mut scanner = Scanner::new(script.to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        Parser::new(tokens.clone()).parse().unwrap()
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let script = include_str!("../../test/func.lua");
+        let statements = parse(script);
+        let json = to_json(&statements).unwrap();
+        let restored = from_json(&json).unwrap();
+        assert_eq!(to_source(&restored), to_source(&statements));
+        assert_eq!(to_json(&restored).unwrap(), json);
+
+        let script = "function add(a, b)\n  return a + b;\nend\nreturn add(1, 2);";
+        let restored = from_json(&to_json(&parse(script)).unwrap()).unwrap();
+        let mut intercepter = Intercepter::new();
+        assert_eq!(intercepter.eval(&restored).unwrap(), Value::Int(3));
+
+        assert!(matches!(
+            from_json("[{\"Nope\": 1}]"),
+            Err(Error::JsonError(_))
+        ));
+        let native = NativeFunction::new("f", (|| 1).into_native_fn());
+        let statements = vec![Stmt::Expression(Expr::Literal(Value::Native(native)))];
+        assert!(matches!(to_json(&statements), Err(Error::JsonError(_))));
+    }
+
+    // 解析结果与 test/simple.ast.json 比较，改动 parser 后用 to_json 重新生成
+    #[test]
+    fn test_parser_golden() {
+        let statements = parse(include_str!("../../test/simple.lua"));
+        let golden = include_str!("../../test/simple.ast.json");
+        assert_eq!(to_json(&statements).unwrap(), golden.trim_end());
+    }
+}
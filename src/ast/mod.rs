@@ -0,0 +1,9 @@
+// 语法树相关工具
+
+pub mod json;
+pub mod pretty;
+pub mod source;
+
+pub use json::{from_json, to_json};
+pub use pretty::{pretty, to_sexpr};
+pub use source::to_source;
@@ -0,0 +1,376 @@
+use crate::ast::source::literal_to_source;
+use crate::expression::Expr;
+use crate::statement::Stmt;
+
+const INDENT: &str = "  ";
+
+// 将语法树输出为缩进的树形结构，每个节点一行，子节点多缩进一层
+pub fn pretty(statements: &[Stmt]) -> String {
+    let mut printer = TreePrinter::default();
+    for stmt in statements {
+        printer.stmt(stmt);
+    }
+    printer.out
+}
+
+#[derive(Default)]
+struct TreePrinter {
+    out: String,
+    depth: usize,
+}
+
+impl TreePrinter {
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::PrintStmt(expr) => {
+                self.line("Print");
+                self.nested_expr(expr);
+            }
+            Stmt::IfStmt(condition, then_branch, else_branch) => {
+                self.line("If");
+                self.nested_expr(condition);
+                self.branch("Then", then_branch);
+                if !matches!(else_branch.as_ref(), Stmt::None) {
+                    self.branch("Else", else_branch);
+                }
+            }
+            Stmt::WhileStmt(condition, body) => {
+                self.line("While");
+                self.nested_expr(condition);
+                self.depth += 1;
+                for stmt in body {
+                    self.stmt(stmt);
+                }
+                self.depth -= 1;
+            }
+            Stmt::RepeatStmt(body, condition) => {
+                self.line("Repeat");
+                self.depth += 1;
+                for stmt in body {
+                    self.stmt(stmt);
+                }
+                self.depth -= 1;
+                self.nested_expr(condition);
+            }
+            Stmt::LocalStmt(name, init) => {
+                self.line(&format!("Local {}", name.raw));
+                self.nested_expr(init);
+            }
+            Stmt::LocalListStmt(names, values) => {
+                let names: Vec<&str> = names.iter().map(|n| n.raw.as_ref()).collect();
+                self.line(&format!("Local {}", names.join(", ")));
+                for value in values {
+                    self.nested_expr(value);
+                }
+            }
+            Stmt::FunctionStmt(name, params, body) => {
+                let params: Vec<&str> = params.iter().map(|p| p.raw.as_ref()).collect();
+                self.line(&format!("Function {}({})", name.raw, params.join(", ")));
+                self.depth += 1;
+                for stmt in body.iter() {
+                    self.stmt(stmt);
+                }
+                self.depth -= 1;
+            }
+            Stmt::ReturnStmt(_, value) => {
+                self.line("Return");
+                self.nested_expr(value);
+            }
+            Stmt::AssignStmt(name, value) => {
+                self.line(&format!("Assign {}", name.raw));
+                self.nested_expr(value);
+            }
+            Stmt::BreakStmt(_) => self.line("Break"),
+            Stmt::Expression(expr) => {
+                self.line("Expression");
+                self.nested_expr(expr);
+            }
+            Stmt::Block(stmts) => {
+                self.line("Block");
+                self.depth += 1;
+                for stmt in stmts {
+                    self.stmt(stmt);
+                }
+                self.depth -= 1;
+            }
+            Stmt::None => {}
+        }
+    }
+
+    // if 的分支，Block 直接展开到标签下
+    fn branch(&mut self, label: &str, stmt: &Stmt) {
+        self.depth += 1;
+        self.line(label);
+        self.depth += 1;
+        match stmt {
+            Stmt::Block(stmts) => stmts.iter().for_each(|stmt| self.stmt(stmt)),
+            _ => self.stmt(stmt),
+        }
+        self.depth -= 2;
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Call(callee, _, args) => {
+                self.line("Call");
+                self.nested_expr(callee);
+                for arg in args {
+                    self.nested_expr(arg);
+                }
+            }
+            Expr::Unary(operator, right) => {
+                self.line(&format!("Unary {}", operator.raw));
+                self.nested_expr(right);
+            }
+            Expr::Variable(name) => self.line(&format!("Variable {}", name.raw)),
+            Expr::Assign(name, value) => {
+                self.line(&format!("Assign {}", name.raw));
+                self.nested_expr(value);
+            }
+            Expr::Binary(left, operator, right) => {
+                self.line(&format!("Binary {}", operator.raw));
+                self.nested_expr(left);
+                self.nested_expr(right);
+            }
+            Expr::Logical(left, operator, right) => {
+                self.line(&format!("Logical {}", operator.raw));
+                self.nested_expr(left);
+                self.nested_expr(right);
+            }
+            Expr::Literal(value) => self.line(&format!("Literal {}", literal_to_source(value))),
+            Expr::Table(_, fields) => {
+                self.line("Table");
+                self.depth += 1;
+                for (key, value) in fields {
+                    if matches!(key, Expr::None) {
+                        self.expr(value);
+                    } else {
+                        self.line("Field");
+                        self.nested_expr(key);
+                        self.nested_expr(value);
+                    }
+                }
+                self.depth -= 1;
+            }
+            Expr::Index(table, _, key) => {
+                self.line("Index");
+                self.nested_expr(table);
+                self.nested_expr(key);
+            }
+            Expr::None => {}
+        }
+    }
+
+    fn nested_expr(&mut self, expr: &Expr) {
+        self.depth += 1;
+        self.expr(expr);
+        self.depth -= 1;
+    }
+
+    fn line(&mut self, line: &str) {
+        for _ in 0..self.depth {
+            self.out.push_str(INDENT);
+        }
+        self.out.push_str(line);
+        self.out.push('\n');
+    }
+}
+
+// 将语法树输出为 S 表达式，每条顶层语句一行
+pub fn to_sexpr(statements: &[Stmt]) -> String {
+    statements
+        .iter()
+        .filter(|stmt| !matches!(stmt, Stmt::None))
+        .map(|stmt| stmt_to_sexpr(stmt) + "\n")
+        .collect()
+}
+
+fn stmt_to_sexpr(stmt: &Stmt) -> String {
+    match stmt {
+        Stmt::PrintStmt(expr) => format!("(print {})", expr_to_sexpr(expr)),
+        Stmt::IfStmt(condition, then_branch, else_branch) => match else_branch.as_ref() {
+            Stmt::None => format!(
+                "(if {} {})",
+                expr_to_sexpr(condition),
+                stmt_to_sexpr(then_branch)
+            ),
+            _ => format!(
+                "(if {} {} {})",
+                expr_to_sexpr(condition),
+                stmt_to_sexpr(then_branch),
+                stmt_to_sexpr(else_branch)
+            ),
+        },
+        Stmt::WhileStmt(condition, body) => {
+            let mut sexpr = format!("(while {}", expr_to_sexpr(condition));
+            for stmt in body {
+                sexpr += " ";
+                sexpr += &stmt_to_sexpr(stmt);
+            }
+            sexpr + ")"
+        }
+        Stmt::RepeatStmt(body, condition) => {
+            let mut sexpr = "(repeat".to_string();
+            for stmt in body {
+                sexpr += " ";
+                sexpr += &stmt_to_sexpr(stmt);
+            }
+            sexpr + " " + &expr_to_sexpr(condition) + ")"
+        }
+        Stmt::LocalStmt(name, Expr::None) => format!("(local {})", name.raw),
+        Stmt::LocalStmt(name, init) => format!("(local {} {})", name.raw, expr_to_sexpr(init)),
+        Stmt::LocalListStmt(names, values) => {
+            let names: Vec<&str> = names.iter().map(|n| n.raw.as_ref()).collect();
+            let mut sexpr = format!("(local ({})", names.join(" "));
+            for value in values {
+                sexpr += " ";
+                sexpr += &expr_to_sexpr(value);
+            }
+            sexpr + ")"
+        }
+        Stmt::FunctionStmt(name, params, body) => {
+            let params: Vec<&str> = params.iter().map(|p| p.raw.as_ref()).collect();
+            let mut sexpr = format!("(function {} ({})", name.raw, params.join(" "));
+            for stmt in body.iter() {
+                sexpr += " ";
+                sexpr += &stmt_to_sexpr(stmt);
+            }
+            sexpr + ")"
+        }
+        Stmt::ReturnStmt(_, Expr::None) => "(return)".to_string(),
+        Stmt::ReturnStmt(_, value) => format!("(return {})", expr_to_sexpr(value)),
+        Stmt::AssignStmt(name, value) => format!("(= {} {})", name.raw, expr_to_sexpr(value)),
+        Stmt::BreakStmt(_) => "(break)".to_string(),
+        Stmt::Expression(expr) => expr_to_sexpr(expr),
+        Stmt::Block(stmts) => {
+            let mut sexpr = "(block".to_string();
+            for stmt in stmts {
+                sexpr += " ";
+                sexpr += &stmt_to_sexpr(stmt);
+            }
+            sexpr + ")"
+        }
+        Stmt::None => "()".to_string(),
+    }
+}
+
+fn expr_to_sexpr(expr: &Expr) -> String {
+    match expr {
+        Expr::Call(callee, _, args) => {
+            let mut sexpr = format!("(call {}", expr_to_sexpr(callee));
+            for arg in args {
+                sexpr += " ";
+                sexpr += &expr_to_sexpr(arg);
+            }
+            sexpr + ")"
+        }
+        Expr::Unary(operator, right) => format!("({} {})", operator.raw, expr_to_sexpr(right)),
+        Expr::Variable(name) => name.raw.to_string(),
+        Expr::Assign(name, value) => format!("(= {} {})", name.raw, expr_to_sexpr(value)),
+        Expr::Binary(left, operator, right) | Expr::Logical(left, operator, right) => format!(
+            "({} {} {})",
+            operator.raw,
+            expr_to_sexpr(left),
+            expr_to_sexpr(right)
+        ),
+        Expr::Literal(value) => literal_to_source(value),
+        Expr::Table(_, fields) => {
+            let mut sexpr = "(table".to_string();
+            for (key, value) in fields {
+                sexpr += " ";
+                sexpr += &match key {
+                    Expr::None => expr_to_sexpr(value),
+                    _ => format!("(field {} {})", expr_to_sexpr(key), expr_to_sexpr(value)),
+                };
+            }
+            sexpr + ")"
+        }
+        Expr::Index(table, _, key) => {
+            format!("(index {} {})", expr_to_sexpr(table), expr_to_sexpr(key))
+        }
+        Expr::None => "()".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pretty, to_sexpr};
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+    use crate::statement::Stmt;
+
+    fn parse(source: &str) -> Vec<Stmt> {
+        Parser::from_stream(Scanner::new(source.to_string()))
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_pretty() {
+        let statements = parse(
+            r#"
+            function fib(n)
+              if n < 2 then
+                return n;
+              end
+              return fib(n - 1) + fib(n - 2);
+            end
+            local a;
+            a = -fib(4);
+            print(a);
+            "#,
+        );
+        let expected = r#"Function fib(n)
+  If
+    Binary <
+      Variable n
+      Literal 2
+    Then
+      Return
+        Variable n
+  Return
+    Binary +
+      Call
+        Variable fib
+        Binary -
+          Variable n
+          Literal 1
+      Call
+        Variable fib
+        Binary -
+          Variable n
+          Literal 2
+Local a
+Assign a
+  Unary -
+    Call
+      Variable fib
+      Literal 4
+Print
+  Variable a
+"#;
+        assert_eq!(pretty(&statements), expected);
+    }
+
+    #[test]
+    fn test_to_sexpr() {
+        let statements = parse(
+            r#"
+            function max(a, b)
+              if a > b then
+                return a;
+              else
+                return b;
+              end
+            end
+            local m = max(1 + 2 * 3, nil);
+            return;
+            "#,
+        );
+        let expected = "(function max (a b) (if (> a b) (return a) (return b)))
+(local m (call max (+ 1 (* 2 3)) nil))
+(return)
+";
+        assert_eq!(to_sexpr(&statements), expected);
+    }
+}
@@ -0,0 +1,255 @@
+use crate::expression::Expr;
+use crate::scanner::{Scanner, TokenType};
+use crate::statement::Stmt;
+use crate::value::Value;
+
+const INDENT: &str = "  ";
+
+// 将语法树重新输出为规范格式的源码：两个空格缩进、运算符两侧空格、语句以 `;` 结尾
+pub fn to_source(statements: &[Stmt]) -> String {
+    let mut printer = SourcePrinter::default();
+    for stmt in statements {
+        printer.stmt(stmt);
+    }
+    printer.out
+}
+
+#[derive(Default)]
+struct SourcePrinter {
+    out: String,
+    depth: usize,
+}
+
+impl SourcePrinter {
+    fn stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::PrintStmt(expr) => {
+                let line = format!("print({});", expr_to_source(expr));
+                self.line(&line);
+            }
+            Stmt::IfStmt(condition, then_branch, else_branch) => {
+                let line = format!("if {} then", expr_to_source(condition));
+                self.line(&line);
+                self.nested(then_branch);
+                if !matches!(else_branch.as_ref(), Stmt::None) {
+                    self.line("else");
+                    self.nested(else_branch);
+                }
+                self.line("end");
+            }
+            Stmt::WhileStmt(condition, body) => {
+                let line = format!("while {} do", expr_to_source(condition));
+                self.line(&line);
+                self.depth += 1;
+                for stmt in body {
+                    self.stmt(stmt);
+                }
+                self.depth -= 1;
+                self.line("end");
+            }
+            Stmt::RepeatStmt(body, condition) => {
+                self.line("repeat");
+                self.depth += 1;
+                for stmt in body {
+                    self.stmt(stmt);
+                }
+                self.depth -= 1;
+                let line = format!("until {}", expr_to_source(condition));
+                self.line(&line);
+            }
+            Stmt::LocalStmt(name, init) => {
+                let line = match init {
+                    Expr::None => format!("local {};", name.raw),
+                    _ => format!("local {} = {};", name.raw, expr_to_source(init)),
+                };
+                self.line(&line);
+            }
+            Stmt::LocalListStmt(names, values) => {
+                let names: Vec<&str> = names.iter().map(|n| n.raw.as_ref()).collect();
+                let values: Vec<String> = values.iter().map(expr_to_source).collect();
+                let line = if values.is_empty() {
+                    format!("local {};", names.join(", "))
+                } else {
+                    format!("local {} = {};", names.join(", "), values.join(", "))
+                };
+                self.line(&line);
+            }
+            Stmt::FunctionStmt(name, params, body) => {
+                let params: Vec<&str> = params.iter().map(|p| p.raw.as_ref()).collect();
+                let line = format!("function {}({})", name.raw, params.join(", "));
+                self.line(&line);
+                self.depth += 1;
+                for stmt in body.iter() {
+                    self.stmt(stmt);
+                }
+                self.depth -= 1;
+                self.line("end");
+            }
+            Stmt::ReturnStmt(_, value) => {
+                let line = match value {
+                    Expr::None => "return;".to_string(),
+                    _ => format!("return {};", expr_to_source(value)),
+                };
+                self.line(&line);
+            }
+            Stmt::AssignStmt(name, value) => {
+                let line = format!("{} = {};", name.raw, expr_to_source(value));
+                self.line(&line);
+            }
+            Stmt::BreakStmt(_) => self.line("break;"),
+            Stmt::Expression(expr) => {
+                let line = format!("{};", expr_to_source(expr));
+                self.line(&line);
+            }
+            Stmt::Block(stmts) => {
+                for stmt in stmts {
+                    self.stmt(stmt);
+                }
+            }
+            Stmt::None => {}
+        }
+    }
+
+    fn nested(&mut self, stmt: &Stmt) {
+        self.depth += 1;
+        self.stmt(stmt);
+        self.depth -= 1;
+    }
+
+    fn line(&mut self, line: &str) {
+        for _ in 0..self.depth {
+            self.out.push_str(INDENT);
+        }
+        self.out.push_str(line);
+        self.out.push('\n');
+    }
+}
+
+fn expr_to_source(expr: &Expr) -> String {
+    match expr {
+        Expr::Call(callee, _, args) => {
+            let args: Vec<String> = args.iter().map(expr_to_source).collect();
+            format!("{}({})", expr_to_source(callee), args.join(", "))
+        }
+        Expr::Unary(operator, right) => {
+            // 避免 - -a 写成注释 --a
+            let right = expr_to_source(right);
+            if right.starts_with('-') {
+                format!("{} {}", operator.raw, right)
+            } else {
+                format!("{}{}", operator.raw, right)
+            }
+        }
+        Expr::Variable(name) => name.raw.to_string(),
+        Expr::Assign(name, value) => format!("{} = {}", name.raw, expr_to_source(value)),
+        Expr::Binary(left, operator, right) | Expr::Logical(left, operator, right) => format!(
+            "{} {} {}",
+            expr_to_source(left),
+            operator.raw,
+            expr_to_source(right)
+        ),
+        Expr::Literal(value) => literal_to_source(value),
+        Expr::Table(_, fields) => {
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|(key, value)| match key {
+                    Expr::None => expr_to_source(value),
+                    Expr::Literal(Value::String(name)) if is_name(name) => {
+                        format!("{} = {}", name, expr_to_source(value))
+                    }
+                    _ => format!("[{}] = {}", expr_to_source(key), expr_to_source(value)),
+                })
+                .collect();
+            format!("{{{}}}", fields.join(", "))
+        }
+        Expr::Index(table, _, key) => match key.as_ref() {
+            Expr::Literal(Value::String(name)) if is_name(name) => {
+                format!("{}.{}", expr_to_source(table), name)
+            }
+            _ => format!("{}[{}]", expr_to_source(table), expr_to_source(key)),
+        },
+        Expr::None => String::new(),
+    }
+}
+
+// 可以写作 t.name 与 {name = v} 的字符串，即单独扫描出一个标识符
+pub(crate) fn is_name(s: &str) -> bool {
+    let mut scanner = Scanner::new(s.to_string());
+    match scanner.scan_tokens() {
+        Ok(tokens) => {
+            tokens.len() == 2 && tokens[0].typ == TokenType::Identifier && &*tokens[0].raw == s
+        }
+        Err(_) => false,
+    }
+}
+
+pub(crate) fn literal_to_source(value: &Value) -> String {
+    match value {
+        Value::Nil => "nil".to_string(),
+        Value::String(s) => format!("\"{}\"", s),
+        Value::Float(f) if f.fract() == 0.0 => format!("{:.1}", f),
+        _ => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_source;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn format(source: &str) -> String {
+        let mut scanner = Scanner::new(source.to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens.clone());
+        let statements = parser.parse().unwrap();
+        to_source(&statements)
+    }
+
+    #[test]
+    fn test_to_source() {
+        let source = r#"
+        function fib(n)
+                if n<2 then return n;  end
+          local n1=fib(n-1);
+          local n2 = fib(n -2);
+          return n1+n2;
+        end
+        local a;
+        a = -fib(4) * 2;
+        print(a);
+        "#;
+        let expected = r#"function fib(n)
+  if n < 2 then
+    return n;
+  end
+  local n1 = fib(n - 1);
+  local n2 = fib(n - 2);
+  return n1 + n2;
+end
+local a;
+a = -fib(4) * 2;
+print(a);
+"#;
+        assert_eq!(format(source), expected);
+    }
+
+    #[test]
+    fn test_to_source_round_trip() {
+        let source = r#"
+        function max(a, b)
+          if a > b then
+            return a;
+          else
+            return b;
+          end
+        end
+        local m = max(1 + 2 * 3 - 4 / 2, nil);
+        local t = {m, k = {1}, ["a b"] = t.k[1], [m] = t["end"]};
+        m = - -m;
+        return m;
+        "#;
+        let once = format(source);
+        assert_eq!(format(&once), once);
+    }
+}
@@ -0,0 +1,372 @@
+use crate::emitter::{Emitter, Function};
+use crate::error::Error;
+use crate::intercepter::Intercepter;
+use crate::parser::Parser;
+use crate::scanner::Scanner;
+use crate::statement::Stmt;
+use crate::stdio::Stdio;
+use crate::value::Value;
+use crate::vm::{Limits, VM};
+
+// 执行后端，源码编译为 Program 后可以多次运行，用于统一测试与比较各个后端
+pub trait Backend {
+    type Program;
+
+    fn name(&self) -> &'static str;
+
+    fn compile(&mut self, source: &str) -> Result<Self::Program, Error>;
+
+    // 每次运行都从空的全局变量开始
+    fn run(&mut self, program: &Self::Program) -> Result<Value, Error>;
+}
+
+fn parse(source: &str) -> Result<Vec<Stmt>, Error> {
+    Parser::from_stream(Scanner::new(source.to_string())).parse()
+}
+
+// 在语法树上解释执行
+#[derive(Debug, Default)]
+pub struct InterpBackend {
+    limits: Limits,
+    stdio: Stdio,
+}
+
+impl InterpBackend {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            stdio: Stdio::default(),
+        }
+    }
+
+    // 替换 print 输出到的流
+    pub fn set_stdio(&mut self, stdio: Stdio) {
+        self.stdio = stdio;
+    }
+}
+
+impl Backend for InterpBackend {
+    type Program = Vec<Stmt>;
+
+    fn name(&self) -> &'static str {
+        "interpreter"
+    }
+
+    fn compile(&mut self, source: &str) -> Result<Vec<Stmt>, Error> {
+        parse(source)
+    }
+
+    fn run(&mut self, program: &Vec<Stmt>) -> Result<Value, Error> {
+        let mut intercepter = Intercepter::new();
+        intercepter.set_limits(self.limits.clone());
+        intercepter.set_stdio(self.stdio.clone());
+        intercepter.eval(program)
+    }
+}
+
+// 编译为字节码在 vm 上执行
+#[derive(Debug, Default)]
+pub struct VmBackend {
+    limits: Limits,
+    stdio: Stdio,
+}
+
+impl VmBackend {
+    pub fn new(limits: Limits) -> Self {
+        Self {
+            limits,
+            stdio: Stdio::default(),
+        }
+    }
+
+    // 替换 print 输出到的流
+    pub fn set_stdio(&mut self, stdio: Stdio) {
+        self.stdio = stdio;
+    }
+}
+
+impl Backend for VmBackend {
+    type Program = Vec<Function>;
+
+    fn name(&self) -> &'static str {
+        "vm"
+    }
+
+    fn compile(&mut self, source: &str) -> Result<Vec<Function>, Error> {
+        let statements = parse(source)?;
+        Ok(Emitter::new().emit_all(&statements)?.clone())
+    }
+
+    fn run(&mut self, program: &Vec<Function>) -> Result<Value, Error> {
+        let mut vm = VM::new_with_funcs(program.clone());
+        vm.set_limits(self.limits.clone());
+        vm.set_stdio(self.stdio.clone());
+        vm.eval_all()
+    }
+}
+
+// 编译为机器码执行，目前只支持单个无参函数组成的脚本，返回值为整数
+#[cfg(feature = "jit")]
+#[derive(Default)]
+pub struct JitBackend {
+    jit: crate::jit::JIT,
+}
+
+#[cfg(feature = "jit")]
+impl Backend for JitBackend {
+    // 指向 jit 生成的代码，只在 backend 存活期间有效
+    type Program = fn() -> i64;
+
+    fn name(&self) -> &'static str {
+        "jit"
+    }
+
+    fn compile(&mut self, source: &str) -> Result<fn() -> i64, Error> {
+        match parse(source)?.as_slice() {
+            [stmt @ Stmt::FunctionStmt(_, params, _)] if params.is_empty() => {
+                let code = self.jit.compile(stmt).map_err(Error::JitError)?;
+                Ok(unsafe { std::mem::transmute::<*const u8, fn() -> i64>(code) })
+            }
+            _ => Err(Error::JitError(
+                "script must be a single function without parameters".to_string(),
+            )),
+        }
+    }
+
+    fn run(&mut self, program: &fn() -> i64) -> Result<Value, Error> {
+        Ok(Value::Int(program() as i32))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::collection::vec;
+    use proptest::option;
+    use proptest::prelude::*;
+    use proptest::sample::select;
+
+    use super::{Backend, InterpBackend, VmBackend};
+    use crate::error::Error;
+    use crate::stdio::{Capture, Stdio};
+    use crate::value::Value;
+
+    fn eval<B: Backend>(backend: &mut B, source: &str) -> Result<Value, Error> {
+        let program = backend.compile(source)?;
+        backend.run(&program)
+    }
+
+    #[test]
+    fn test_backends_agree() {
+        let scripts = [
+            ("return 1 + 2 * 3;", Value::Int(7)),
+            (
+                "local a = 10;\nlocal b = a - 4;\nreturn b * a;",
+                Value::Int(60),
+            ),
+            ("return 1 < 2;", Value::Bool(true)),
+        ];
+        for (script, expected) in scripts {
+            assert_eq!(
+                eval(&mut InterpBackend::default(), script).unwrap(),
+                expected
+            );
+            assert_eq!(eval(&mut VmBackend::default(), script).unwrap(), expected);
+        }
+
+        let mut vm = VmBackend::default();
+        assert_eq!(vm.name(), "vm");
+        let program = vm.compile("return 5;").unwrap();
+        assert_eq!(vm.run(&program).unwrap(), Value::Int(5));
+        assert_eq!(vm.run(&program).unwrap(), Value::Int(5));
+        assert!(InterpBackend::default().compile("return (1;").is_err());
+    }
+
+    // 在解释器与 vm 上分别运行，返回值与 print 的输出都必须相同
+    fn differential(source: &str) -> (Value, String) {
+        let interp_out = Capture::default();
+        let mut stdio = Stdio::default();
+        stdio.set_stdout(interp_out.clone());
+        let mut interp = InterpBackend::default();
+        interp.set_stdio(stdio);
+        let expected = eval(&mut interp, source)
+            .unwrap_or_else(|e| panic!("interpreter failed on\n{}\n{}", source, e));
+
+        let vm_out = Capture::default();
+        let mut stdio = Stdio::default();
+        stdio.set_stdout(vm_out.clone());
+        let mut vm = VmBackend::default();
+        vm.set_stdio(stdio);
+        let actual =
+            eval(&mut vm, source).unwrap_or_else(|e| panic!("vm failed on\n{}\n{}", source, e));
+
+        let expected_out = interp_out.text();
+        let actual_out = vm_out.text();
+        assert_eq!(expected, actual, "return value differs on\n{}", source);
+        assert_eq!(expected_out, actual_out, "output differs on\n{}", source);
+        (expected, expected_out)
+    }
+
+    // vm 还不能编译赋值表达式、一元运算与函数调用，语料中不包含这些
+    #[test]
+    fn test_differential_corpus() {
+        let corpus = [
+            "print(1 + 2);\nreturn 3;",
+            "local a = 6;\nlocal b = 2 - a;\nprint(a * b);\nprint(a - b);\nreturn a / 2;",
+            "local a = 1;\nlocal b = a + 41;\nprint(b);\nreturn b < 42;",
+            "print(1 < 1);\nprint(3 > 2);\nreturn nil;",
+            "local s = \"plua\";\nprint(s);\nprint(\"lua\" < s);\nreturn s;",
+            "local s = \"a\" .. 1;\nprint(s .. 2 * 3 .. s);\nreturn s .. 0.5;",
+            "local a, b, c = 1, 2;\nlocal d = 3, 4;\nprint(c);\nlocal a, b = b, a;\nreturn a - b + d;",
+            "local a = 17 % 5;\nprint(a ^ 2);\nprint(7.5 % 2);\nreturn 2 ^ 3 ^ 2 % 10;",
+            "local a = 1;\na = a + 1;\nprint(a);\nb = a * 2;\nreturn b;",
+            "local i = 0;\nrepeat\n  i = i + 1;\n  print(i);\nuntil i > 2\nreturn i;",
+            "local i = 0;\nwhile 1 do\n  print(i);\n  if i > 1 then break; end\n  i = i + 1;\nend\nreturn i;",
+        ];
+        for source in corpus {
+            differential(source);
+        }
+        let (value, output) = differential("local x = 2;\nprint(x);\nprint(x * 10);\nreturn x;");
+        assert_eq!(value, Value::Int(2));
+        assert_eq!(output, "2\n20\n");
+    }
+
+    #[test]
+    fn test_differential_logical() {
+        let source = "local a = nil and 1;
+local b = 2 or 3;
+local c = 2 and 3;
+local d = nil or 1 < 2;
+print(a);
+print(b);
+print(c);
+print(d);
+return 4 and nil or 5;";
+        let (value, output) = differential(source);
+        assert_eq!(value, Value::Int(5));
+        assert_eq!(output, "Nil\n2\n3\ntrue\n");
+
+        let source = "local a = 1;
+local b = 2;
+print(a == b and 3);
+print(a != b and b >= 2);
+print(a <= 0 or a == 1);
+return 2 * 1.5 == 3.0 and 1 + 0.5 == 1.5;";
+        let (value, output) = differential(source);
+        assert_eq!(value, Value::Bool(true));
+        assert_eq!(output, "false\ntrue\ntrue\n");
+    }
+
+    #[test]
+    fn test_differential_elseif() {
+        for (n, expected) in [(0, "a"), (2, "b"), (4, "c"), (9, "d")] {
+            let source = format!(
+                "local n = {};
+if n < 1 then
+  print(\"a\");
+elseif n < 3 then
+  print(\"b\");
+elseif n < 5 then
+  print(\"c\");
+else
+  print(\"d\");
+end
+if n > 5 then
+  print(n);
+end
+return n;",
+                n
+            );
+            let (value, output) = differential(&source);
+            assert_eq!(value, Value::Int(n));
+            let mut lines = output.lines();
+            assert_eq!(lines.next(), Some(expected));
+            assert_eq!(lines.next().is_some(), n > 5);
+        }
+    }
+
+    // 变量 v0..vars、两位以内的字面量或字面量的乘积
+    fn random_term(vars: usize) -> BoxedStrategy<String> {
+        let literal = (0..100).prop_map(|n: i32| n.to_string());
+        let product = (0..10, 0..10).prop_map(|(a, b): (i32, i32)| format!("{} * {}", a, b));
+        if vars == 0 {
+            prop_oneof![literal, product].boxed()
+        } else {
+            let var = (0..vars).prop_map(|v| format!("v{}", v));
+            prop_oneof![var, literal, product].boxed()
+        }
+    }
+
+    // 只用加减法组合变量，乘法只用于字面量，避免整数溢出；
+    // 语法还不支持括号分组
+    fn random_expr(vars: usize, terms: usize) -> impl Strategy<Value = String> {
+        let rest = vec((any::<bool>(), random_term(vars)), 0..terms);
+        (random_term(vars), rest).prop_map(|(first, rest)| {
+            rest.into_iter().fold(first, |expr, (add, term)| {
+                format!("{} {} {}", expr, if add { "+" } else { "-" }, term)
+            })
+        })
+    }
+
+    fn random_program() -> impl Strategy<Value = String> {
+        (1..=6_usize)
+            .prop_flat_map(|count| {
+                let locals: Vec<_> = (0..count)
+                    .map(|i| (random_expr(i, 4), option::of(random_expr(i + 1, 4))))
+                    .collect();
+                let op = select(vec!["+", "-", "<", ">"]);
+                (locals, random_expr(count, 2), op, random_expr(count, 2))
+            })
+            .prop_map(|(locals, lhs, op, rhs)| {
+                let mut source = String::new();
+                for (i, (init, print)) in locals.into_iter().enumerate() {
+                    source += &format!("local v{} = {};\n", i, init);
+                    if let Some(expr) = print {
+                        source += &format!("print({});\n", expr);
+                    }
+                }
+                source + &format!("return {} {} {};", lhs, op, rhs)
+            })
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(200))]
+
+        // 失败时 proptest 会把程序收缩到最小的反例
+        #[test]
+        fn test_differential_random(source in random_program()) {
+            differential(&source);
+        }
+    }
+
+    // jit 只支持单个无参函数，解释器调用同一个函数比较返回值；
+    // jit 中赋值即声明，表达式中还不能引用变量，解释器需要先声明为全局变量
+    #[cfg(feature = "jit")]
+    #[test]
+    fn test_differential_jit() {
+        use super::JitBackend;
+
+        let corpus = [
+            ("n", "function main()\n  n = 1 + 2 * 3;\n  return n;\nend"),
+            (
+                "a, b",
+                "function main()\n  a = 10 - 4;\n  b = 6 * 10;\n  return b;\nend",
+            ),
+            ("n", "function main()\n  n = 7 / 2 + 7;\n  return n;\nend"),
+        ];
+        for (globals, source) in corpus {
+            let prelude: String = globals
+                .split(", ")
+                .map(|name| format!("local {} = 0;\n", name))
+                .collect();
+            let script = format!("{}{}\nreturn main();", prelude, source);
+            let expected = eval(&mut InterpBackend::default(), &script).unwrap();
+            let mut jit = JitBackend::default();
+            assert_eq!(jit.name(), "jit");
+            assert_eq!(eval(&mut jit, source).unwrap(), expected, "{}", source);
+        }
+        assert!(matches!(
+            JitBackend::default().compile("return 1;"),
+            Err(Error::JitError(_))
+        ));
+    }
+}
@@ -0,0 +1,252 @@
+use crate::bf::compile::check;
+use crate::bf::error::CompileError;
+use crate::bf::ir::BfIR;
+use crate::bf::Eof;
+use crate::bytecode::ByteCode;
+use crate::emitter::Chunk;
+use crate::value::{Table, Value};
+use crate::vm::VM;
+
+// 纸带与指针保存在 VM 的全局变量中，纸带下标从 1 开始
+pub const TAPE: &str = "tape";
+pub const PTR: &str = "ptr";
+// 计算单元新值用的临时变量
+const TMP: &str = "tmp";
+
+// 创建纸带与指针已经初始化的 VM
+pub fn new_vm(memory_size: usize) -> VM {
+    let mut vm = VM::new();
+    let tape = Table::from_array(vec![Value::Int(0); memory_size]);
+    vm.define_global(TAPE, Value::Table(tape));
+    vm.define_global(PTR, Value::Int(1));
+    vm
+}
+
+// 将 BfIR 翻译为 VM 的字节码，需要在 new_vm 创建的 VM 上执行
+// 单元总是回绕，指针越界时 VM 报错；括号不匹配时返回错误
+pub fn translate(code: &[BfIR], eof: Eof) -> Result<Chunk, CompileError> {
+    check(code)?;
+    let mut chunk = Chunk::new();
+    let tape = chunk.add_constant(Value::String(TAPE.to_string()));
+    let ptr = chunk.add_constant(Value::String(PTR.to_string()));
+    let tmp = chunk.add_constant(Value::String(TMP.to_string()));
+    let mut translator = Translator {
+        chunk,
+        tape,
+        ptr,
+        tmp,
+        eof,
+        loops: vec![],
+    };
+    for &ir in code {
+        translator.translate(ir);
+    }
+    Ok(translator.chunk)
+}
+
+struct Translator {
+    chunk: Chunk,
+    // 名字在常量表中的下标
+    tape: usize,
+    ptr: usize,
+    tmp: usize,
+    eof: Eof,
+    // 循环条件的起始位置与 JumpIfFalse 的位置
+    loops: Vec<(usize, usize)>,
+}
+
+impl Translator {
+    fn translate(&mut self, ir: BfIR) {
+        use BfIR::*;
+        match ir {
+            AddVal(x) => {
+                self.cell(0);
+                self.emit(ByteCode::Push(Value::Int(x as i32)));
+                self.emit(ByteCode::Add);
+                self.store(0);
+            }
+            SubVal(x) => {
+                self.cell(0);
+                self.emit(ByteCode::Push(Value::Int(x as i32)));
+                self.emit(ByteCode::Sub);
+                self.store(0);
+            }
+            AddPtr(x) => self.move_ptr(x as i32),
+            SubPtr(x) => self.move_ptr(-(x as i32)),
+            GetByte => {
+                self.address(0);
+                // 读到 EOF 时写入的值
+                match self.eof {
+                    Eof::Unchanged => self.cell(0),
+                    Eof::Zero => self.emit_int(0),
+                    Eof::Max => self.emit_int(255),
+                }
+                self.emit(ByteCode::GetChar);
+                self.emit(ByteCode::SetIndex(self.tape));
+            }
+            PutByte => {
+                self.cell(0);
+                self.emit(ByteCode::PutChar);
+            }
+            Jz => {
+                let start = self.chunk.codes.len();
+                let jump = self.jump_if_zero();
+                self.loops.push((start, jump));
+            }
+            Jnz => {
+                // 括号已经检查过
+                let (start, jump) = self.loops.pop().unwrap();
+                self.emit(ByteCode::Jump(start));
+                self.patch(jump);
+            }
+            SetZero => {
+                self.address(0);
+                self.emit(ByteCode::Push(Value::Int(0)));
+                self.emit(ByteCode::SetIndex(self.tape));
+            }
+            ScanLeft => {
+                for ir in [Jz, SubPtr(1), Jnz] {
+                    self.translate(ir);
+                }
+            }
+            ScanRight => {
+                for ir in [Jz, AddPtr(1), Jnz] {
+                    self.translate(ir);
+                }
+            }
+            MulAdd(offset, factor) => {
+                let offset = offset as i32;
+                let jump = self.jump_if_zero();
+                self.cell(offset);
+                self.cell(0);
+                self.emit(ByteCode::Push(Value::Int(factor as i32)));
+                self.emit(ByteCode::Mul);
+                self.emit(ByteCode::Add);
+                self.store(offset);
+                self.patch(jump);
+            }
+        }
+    }
+
+    // 压入 ptr + offset
+    fn address(&mut self, offset: i32) {
+        self.emit(ByteCode::GetGlobal(self.ptr));
+        if offset != 0 {
+            self.emit(ByteCode::Push(Value::Int(offset)));
+            self.emit(ByteCode::Add);
+        }
+    }
+
+    // 压入 tape[ptr + offset]
+    fn cell(&mut self, offset: i32) {
+        self.address(offset);
+        self.emit(ByteCode::GetIndex(self.tape));
+    }
+
+    // 弹出栈顶的值，回绕到 0..=255 后写入 tape[ptr + offset]
+    fn store(&mut self, offset: i32) {
+        self.emit(ByteCode::DefineGlabal(self.tmp));
+        self.wrap(ByteCode::Greater, 255, -256);
+        self.wrap(ByteCode::Less, 0, 256);
+        self.address(offset);
+        self.emit(ByteCode::GetGlobal(self.tmp));
+        self.emit(ByteCode::SetIndex(self.tape));
+    }
+
+    // while tmp cmp bound { tmp += delta }
+    fn wrap(&mut self, cmp: ByteCode, bound: i32, delta: i32) {
+        let start = self.chunk.codes.len();
+        self.emit(ByteCode::GetGlobal(self.tmp));
+        self.emit(ByteCode::Push(Value::Int(bound)));
+        self.emit(cmp);
+        let jump = self.emit(ByteCode::JumpIfFalse(0));
+        self.emit(ByteCode::GetGlobal(self.tmp));
+        self.emit(ByteCode::Push(Value::Int(delta)));
+        self.emit(ByteCode::Add);
+        self.emit(ByteCode::DefineGlabal(self.tmp));
+        self.emit(ByteCode::Jump(start));
+        self.patch(jump);
+    }
+
+    fn move_ptr(&mut self, delta: i32) {
+        self.address(delta);
+        self.emit(ByteCode::DefineGlabal(self.ptr));
+    }
+
+    // 当前单元为 0 时跳转，返回待回填的 JumpIfFalse 的位置
+    fn jump_if_zero(&mut self) -> usize {
+        self.cell(0);
+        self.emit(ByteCode::Push(Value::Int(0)));
+        self.emit(ByteCode::Greater);
+        self.emit(ByteCode::JumpIfFalse(0))
+    }
+
+    // 将 pos 处的 JumpIfFalse 指向下一条字节码
+    fn patch(&mut self, pos: usize) {
+        self.chunk.codes[pos] = ByteCode::JumpIfFalse(self.chunk.codes.len());
+    }
+
+    fn emit_int(&mut self, i: i32) {
+        self.emit(ByteCode::Push(Value::Int(i)));
+    }
+
+    fn emit(&mut self, code: ByteCode) -> usize {
+        self.chunk.add_bytecode(code);
+        self.chunk.codes.len() - 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{new_vm, translate, PTR, TAPE};
+    use crate::bf::compile::{compile, optimize};
+    use crate::bf::ir::BfIR;
+    use crate::bf::{CellWrap, Eof};
+    use crate::stdio::{Capture, Stdio};
+    use crate::value::Value;
+
+    #[test]
+    fn test_translate() {
+        // 不含输入输出，只检查纸带
+        let src = "++++++++[>++++++++<-]>+>-<<+++[>>>+<<<-]>>>[<]";
+        for optimized in [false, true] {
+            let mut code = compile(src).unwrap();
+            if optimized {
+                optimize(&mut code, CellWrap::Wrap);
+            }
+            let mut vm = new_vm(8);
+            vm.eval(&translate(&code, Eof::Unchanged).unwrap()).unwrap();
+
+            let tape = vm.global(TAPE).unwrap().as_table().unwrap();
+            let cells: Vec<_> = tape.array.iter().map(|v| *v.as_int().unwrap()).collect();
+            assert_eq!(cells, [0, 65, 255, 3, 0, 0, 0, 0]);
+            assert_eq!(vm.global(PTR), Some(&Value::Int(1)));
+        }
+
+        let mut vm = new_vm(2);
+        assert!(vm
+            .eval(&translate(&compile("<+").unwrap(), Eof::Unchanged).unwrap())
+            .is_err());
+
+        // 外部传入的 ir 括号不匹配时报错而不是 panic
+        for code in [vec![BfIR::Jnz], vec![BfIR::Jz, BfIR::AddVal(1)]] {
+            assert!(translate(&code, Eof::Unchanged).is_err());
+        }
+    }
+
+    #[test]
+    fn test_translate_io() {
+        // 输入输出走 VM 的 Stdio，读到结尾时按 eof 写入
+        for (eof, expected) in [(Eof::Zero, [66, 0]), (Eof::Unchanged, [66, 66])] {
+            let out = Capture::default();
+            let mut stdio = Stdio::default();
+            stdio.set_stdin(&b"A"[..]);
+            stdio.set_stdout(out.clone());
+            let mut vm = new_vm(1);
+            vm.set_stdio(stdio);
+            vm.eval(&translate(&compile(",+.,.").unwrap(), eof).unwrap())
+                .unwrap();
+            assert_eq!(out.bytes(), expected);
+        }
+    }
+}
@@ -0,0 +1,508 @@
+use std::io::{Read, Write};
+use std::path::Path;
+use std::ptr;
+
+use cranelift::prelude::*;
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{Linkage, Module};
+
+use crate::bf::compile::{check, compile, optimize};
+use crate::bf::error::{vm_error, Result, RuntimeError, VMError};
+use crate::bf::ir::BfIR;
+use crate::bf::{BfVmOptions, CellWrap, Eof};
+
+// 基于 cranelift 的 bf 编译器，支持 cranelift 能生成代码的所有平台
+pub struct BfClif<'io> {
+    module: Option<JITModule>, // 持有生成的代码，drop 时释放
+    func: *const u8,           // 入口
+    memory: Box<[u8]>,         // 内存
+    eof: Eof,                  // 读到 EOF 时的行为
+    input: Box<dyn Read + 'io>,
+    output: Box<dyn Write + 'io>,
+}
+
+type RawFn = unsafe extern "C" fn(
+    vm: *mut BfClif<'_>,
+    memory_start: *mut u8,
+    memory_end: *const u8,
+) -> *mut VMError;
+
+impl<'io> BfClif<'io> {
+    pub fn new(
+        file_path: &Path,
+        input: Box<dyn Read + 'io>,
+        output: Box<dyn Write + 'io>,
+        options: BfVmOptions,
+    ) -> Result<Self> {
+        let src = std::fs::read_to_string(file_path)?;
+        Self::from_source(&src, input, output, options)
+    }
+
+    pub fn from_source(
+        src: &str,
+        input: Box<dyn Read + 'io>,
+        output: Box<dyn Write + 'io>,
+        options: BfVmOptions,
+    ) -> Result<Self> {
+        let ir = compile(src)?;
+        Self::from_ir(ir, input, output, options)
+    }
+
+    // 直接执行 ir，options.optimized 为 true 时同样会先优化
+    pub fn from_ir(
+        mut ir: Vec<BfIR>,
+        input: Box<dyn Read + 'io>,
+        output: Box<dyn Write + 'io>,
+        options: BfVmOptions,
+    ) -> Result<Self> {
+        options.check()?;
+        check(&ir)?;
+
+        if options.optimized {
+            optimize(&mut ir, options.cell_wrap);
+        }
+
+        let builder = JITBuilder::new(cranelift_module::default_libcall_names());
+        let mut module = JITModule::new(builder);
+        let func = Self::generate(&mut module, &ir, options.cell_wrap)?;
+
+        Ok(Self {
+            module: Some(module),
+            func,
+            memory: vec![0; options.memory_size].into_boxed_slice(),
+            eof: options.eof,
+            input,
+            output,
+        })
+    }
+
+    fn generate(module: &mut JITModule, code: &[BfIR], cell_wrap: CellWrap) -> Result<*const u8> {
+        let ptr_type = module.target_config().pointer_type();
+        let mut ctx = module.make_context();
+        let mut builder_ctx = FunctionBuilderContext::new();
+
+        // fn(vm, memory_start, memory_end) -> *mut VMError
+        for _ in 0..3 {
+            ctx.func.signature.params.push(AbiParam::new(ptr_type));
+        }
+        ctx.func.signature.returns.push(AbiParam::new(ptr_type));
+
+        let mut builder = FunctionBuilder::new(&mut ctx.func, &mut builder_ctx);
+
+        // getbyte/putbyte: fn(vm, ptr) -> *mut VMError
+        let mut io_sig = Signature::new(module.isa().default_call_conv());
+        io_sig.params.push(AbiParam::new(ptr_type));
+        io_sig.params.push(AbiParam::new(ptr_type));
+        io_sig.returns.push(AbiParam::new(ptr_type));
+        let io_sig = builder.import_signature(io_sig);
+
+        // overflow_error/cell_overflow_error: fn() -> *mut VMError
+        let mut error_sig = Signature::new(module.isa().default_call_conv());
+        error_sig.returns.push(AbiParam::new(ptr_type));
+        let error_sig = builder.import_signature(error_sig);
+
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+        let params = builder.block_params(entry).to_vec();
+
+        let exit = builder.create_block();
+        builder.append_block_param(exit, ptr_type);
+
+        let ptr = Variable::new(0);
+        builder.declare_var(ptr, ptr_type);
+        builder.def_var(ptr, params[1]);
+
+        let mut trans = Translator {
+            ptr_type,
+            builder,
+            ptr,
+            vm: params[0],
+            memory_start: params[1],
+            memory_end: params[2],
+            check: cell_wrap == CellWrap::Error,
+            io_sig,
+            exit,
+            loops: vec![],
+            overflow: None,
+            cell_overflow: None,
+        };
+        for &ir in code {
+            trans.translate(ir);
+        }
+
+        let null = trans.builder.ins().iconst(ptr_type, 0);
+        trans.builder.ins().jump(exit, &[null]);
+
+        // 错误处理块只在用到时生成
+        let handlers = [
+            (trans.overflow, BfClif::overflow_error as RawErrorFn),
+            (
+                trans.cell_overflow,
+                BfClif::cell_overflow_error as RawErrorFn,
+            ),
+        ];
+        for (block, handler) in handlers {
+            if let Some(block) = block {
+                trans.builder.switch_to_block(block);
+                let callee = trans
+                    .builder
+                    .ins()
+                    .iconst(ptr_type, handler as *const () as i64);
+                let call = trans.builder.ins().call_indirect(error_sig, callee, &[]);
+                let ret = trans.builder.inst_results(call)[0];
+                trans.builder.ins().jump(exit, &[ret]);
+            }
+        }
+
+        trans.builder.switch_to_block(exit);
+        let ret = trans.builder.block_params(exit)[0];
+        trans.builder.ins().return_(&[ret]);
+        trans.builder.seal_all_blocks();
+        trans.builder.finalize();
+
+        let codegen_error = |e: cranelift_module::ModuleError| VMError::Codegen(e.to_string());
+        let id = module
+            .declare_function("bf_main", Linkage::Local, &ctx.func.signature)
+            .map_err(codegen_error)?;
+        module
+            .define_function(id, &mut ctx)
+            .map_err(codegen_error)?;
+        module.clear_context(&mut ctx);
+        module.finalize_definitions();
+        Ok(module.get_finalized_function(id))
+    }
+
+    pub fn run(&mut self) -> Result<()> {
+        // 将内存重新解释为函数
+        let raw_fn: RawFn = unsafe { std::mem::transmute(self.func) };
+
+        let vm: *mut Self = self;
+        let memory_start = self.memory.as_mut_ptr();
+        let memory_end = unsafe { memory_start.add(self.memory.len()) };
+        let ret: *mut VMError = unsafe { raw_fn(vm, memory_start, memory_end) };
+
+        if ret.is_null() {
+            Ok(())
+        } else {
+            Err(*unsafe { Box::from_raw(ret) })
+        }
+    }
+
+    // getbyte 读取字节
+    unsafe extern "C" fn getbyte(vm: *mut Self, ptr: *mut u8) -> *mut VMError {
+        let mut buf = [0_u8];
+        let vm = &mut *vm;
+        match vm.input.read(&mut buf) {
+            Ok(0) => vm.eof.apply(&mut *ptr),
+            Ok(1) => *ptr = buf[0],
+            Err(e) => return vm_error(RuntimeError::IO(e)),
+            _ => unreachable!(),
+        }
+        ptr::null_mut()
+    }
+
+    // putbyte 输出字节
+    unsafe extern "C" fn putbyte(vm: *mut Self, ptr: *const u8) -> *mut VMError {
+        let buf = std::slice::from_ref(&*ptr);
+        let vm = &mut *vm;
+        match vm.output.write_all(buf) {
+            Ok(()) => ptr::null_mut(),
+            Err(e) => vm_error(RuntimeError::IO(e)),
+        }
+    }
+
+    // overflow_error 溢出
+    unsafe extern "C" fn overflow_error() -> *mut VMError {
+        vm_error(RuntimeError::PointerOverflow)
+    }
+
+    // cell_overflow_error 单元溢出
+    unsafe extern "C" fn cell_overflow_error() -> *mut VMError {
+        vm_error(RuntimeError::CellOverflow)
+    }
+}
+
+impl Drop for BfClif<'_> {
+    fn drop(&mut self) {
+        if let Some(module) = self.module.take() {
+            // 之后不会再执行生成的代码
+            unsafe { module.free_memory() };
+        }
+    }
+}
+
+type RawErrorFn = unsafe extern "C" fn() -> *mut VMError;
+
+// 将 BfIR 翻译为 cranelift ir
+struct Translator<'a> {
+    ptr_type: Type,
+    builder: FunctionBuilder<'a>,
+    ptr: Variable,
+    vm: Value,
+    memory_start: Value,
+    memory_end: Value,
+    check: bool, // 是否检查单元溢出
+    io_sig: codegen::ir::SigRef,
+    exit: Block,                // 参数为返回值
+    loops: Vec<(Block, Block)>, // 循环体与循环之后的块
+    overflow: Option<Block>,
+    cell_overflow: Option<Block>,
+}
+
+impl Translator<'_> {
+    fn translate(&mut self, ir: BfIR) {
+        use BfIR::*;
+        match ir {
+            AddPtr(x) => {
+                let ptr = self.builder.use_var(self.ptr);
+                let ptr = self.builder.ins().iadd_imm(ptr, x as i64);
+                self.check_end(ptr);
+                self.builder.def_var(self.ptr, ptr);
+            }
+            SubPtr(x) => {
+                let ptr = self.builder.use_var(self.ptr);
+                let ptr = self.builder.ins().iadd_imm(ptr, -(x as i64));
+                self.check_start(ptr);
+                self.builder.def_var(self.ptr, ptr);
+            }
+            AddVal(x) => {
+                let ptr = self.builder.use_var(self.ptr);
+                let value = self.load(ptr);
+                let value = self.builder.ins().iadd_imm(value, x as i64);
+                if self.check {
+                    let overflow =
+                        self.builder
+                            .ins()
+                            .icmp_imm(IntCC::UnsignedGreaterThan, value, 255);
+                    let block = self.cell_overflow_block();
+                    self.jump_if(overflow, block);
+                }
+                self.store(value, ptr);
+            }
+            SubVal(x) => {
+                let ptr = self.builder.use_var(self.ptr);
+                let value = self.load(ptr);
+                if self.check {
+                    let overflow =
+                        self.builder
+                            .ins()
+                            .icmp_imm(IntCC::UnsignedLessThan, value, x as i64);
+                    let block = self.cell_overflow_block();
+                    self.jump_if(overflow, block);
+                }
+                let value = self.builder.ins().iadd_imm(value, -(x as i64));
+                self.store(value, ptr);
+            }
+            GetByte => self.call_io(BfClif::getbyte as *const () as i64),
+            PutByte => self.call_io(BfClif::putbyte as *const () as i64),
+            Jz => {
+                let body = self.builder.create_block();
+                let after = self.builder.create_block();
+                self.loops.push((body, after));
+
+                let ptr = self.builder.use_var(self.ptr);
+                let value = self.load(ptr);
+                self.builder.ins().brz(value, after, &[]); // jmp if *ptr == 0
+                self.builder.ins().jump(body, &[]);
+                self.builder.switch_to_block(body);
+            }
+            Jnz => {
+                let (body, after) = self.loops.pop().unwrap();
+                let ptr = self.builder.use_var(self.ptr);
+                let value = self.load(ptr);
+                self.builder.ins().brnz(value, body, &[]); // jmp if *ptr != 0
+                self.builder.ins().jump(after, &[]);
+                self.builder.switch_to_block(after);
+            }
+            SetZero => {
+                let ptr = self.builder.use_var(self.ptr);
+                let zero = self.builder.ins().iconst(types::I32, 0);
+                self.store(zero, ptr);
+            }
+            ScanLeft => self.scan(-1),
+            ScanRight => self.scan(1),
+            MulAdd(offset, factor) => {
+                let skip = self.builder.create_block();
+                let ptr = self.builder.use_var(self.ptr);
+                let value = self.load(ptr);
+                // 当前单元为 0 时循环不会执行
+                let zero = self.builder.ins().icmp_imm(IntCC::Equal, value, 0);
+                self.jump_if(zero, skip);
+
+                let target = self.builder.ins().iadd_imm(ptr, offset as i64);
+                self.check_start(target);
+                self.check_end(target);
+                let old = self.load(target);
+                let delta = self.builder.ins().imul_imm(value, factor as i64);
+                let new = self.builder.ins().iadd(old, delta);
+                self.store(new, target);
+                self.builder.ins().jump(skip, &[]);
+                self.builder.switch_to_block(skip);
+            }
+        }
+    }
+
+    fn scan(&mut self, step: i64) {
+        let head = self.builder.create_block();
+        let done = self.builder.create_block();
+        self.builder.ins().jump(head, &[]);
+        self.builder.switch_to_block(head);
+
+        let ptr = self.builder.use_var(self.ptr);
+        let value = self.load(ptr);
+        let zero = self.builder.ins().icmp_imm(IntCC::Equal, value, 0);
+        self.jump_if(zero, done);
+
+        let ptr = self.builder.ins().iadd_imm(ptr, step);
+        if step < 0 {
+            self.check_start(ptr);
+        } else {
+            self.check_end(ptr);
+        }
+        self.builder.def_var(self.ptr, ptr);
+        self.builder.ins().jump(head, &[]);
+        self.builder.switch_to_block(done);
+    }
+
+    // 调用 getbyte/putbyte，返回错误时直接退出
+    fn call_io(&mut self, func: i64) {
+        let ptr = self.builder.use_var(self.ptr);
+        let callee = self.builder.ins().iconst(self.ptr_type, func);
+        let call = self
+            .builder
+            .ins()
+            .call_indirect(self.io_sig, callee, &[self.vm, ptr]);
+        let ret = self.builder.inst_results(call)[0];
+        let next = self.builder.create_block();
+        self.builder.ins().brnz(ret, self.exit, &[ret]);
+        self.builder.ins().jump(next, &[]);
+        self.builder.switch_to_block(next);
+    }
+
+    // ptr < memory_start 时跳转到 overflow
+    fn check_start(&mut self, ptr: Value) {
+        let below = self
+            .builder
+            .ins()
+            .icmp(IntCC::UnsignedLessThan, ptr, self.memory_start);
+        let block = self.overflow_block();
+        self.jump_if(below, block);
+    }
+
+    // ptr >= memory_end 时跳转到 overflow
+    fn check_end(&mut self, ptr: Value) {
+        let above =
+            self.builder
+                .ins()
+                .icmp(IntCC::UnsignedGreaterThanOrEqual, ptr, self.memory_end);
+        let block = self.overflow_block();
+        self.jump_if(above, block);
+    }
+
+    // 条件成立时跳转到 target，否则在新的块中继续
+    fn jump_if(&mut self, cond: Value, target: Block) {
+        let next = self.builder.create_block();
+        self.builder.ins().brnz(cond, target, &[]);
+        self.builder.ins().jump(next, &[]);
+        self.builder.switch_to_block(next);
+    }
+
+    fn overflow_block(&mut self) -> Block {
+        let builder = &mut self.builder;
+        *self.overflow.get_or_insert_with(|| builder.create_block())
+    }
+
+    fn cell_overflow_block(&mut self) -> Block {
+        let builder = &mut self.builder;
+        *self
+            .cell_overflow
+            .get_or_insert_with(|| builder.create_block())
+    }
+
+    // 单元按 u8 读取，在 i32 上运算
+    fn load(&mut self, ptr: Value) -> Value {
+        self.builder
+            .ins()
+            .uload8(types::I32, MemFlags::trusted(), ptr, 0)
+    }
+
+    fn store(&mut self, value: Value, ptr: Value) {
+        self.builder
+            .ins()
+            .istore8(MemFlags::trusted(), value, ptr, 0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BfClif;
+    use crate::bf::error::{RuntimeError, VMError};
+    use crate::bf::ir::BfIR;
+    use crate::bf::{BfVmOptions, CellWrap, Eof};
+
+    #[test]
+    fn test_clif() {
+        let mut output = vec![];
+        let src = "++++++++[>++++++++<-]>+.,+.>>+++++[->++>+++<<]>.>.[<]>[>]<.";
+        for optimized in [false, true] {
+            output.clear();
+            let options = BfVmOptions {
+                optimized,
+                ..Default::default()
+            };
+            let mut vm =
+                BfClif::from_source(src, Box::new(&b"a"[..]), Box::new(&mut output), options)
+                    .unwrap();
+            vm.run().unwrap();
+            drop(vm);
+            assert_eq!(output, [b'A', b'b', 10, 15, 15]);
+        }
+
+        let code = vec![
+            BfIR::AddVal(3),
+            BfIR::MulAdd(2, 4),
+            BfIR::AddPtr(2),
+            BfIR::PutByte,
+        ];
+        let options = BfVmOptions::default();
+        let mut vm =
+            BfClif::from_ir(code, Box::new(&b""[..]), Box::new(&mut output), options).unwrap();
+        vm.run().unwrap();
+        drop(vm);
+        assert_eq!(output.last(), Some(&12));
+
+        let mut vm =
+            BfClif::from_source("<", Box::new(&b""[..]), Box::new(vec![]), options).unwrap();
+        match vm.run() {
+            Err(VMError::Runtime(RuntimeError::PointerOverflow)) => {}
+            _ => panic!(),
+        }
+
+        let options = BfVmOptions {
+            memory_size: 2,
+            cell_wrap: CellWrap::Error,
+            ..Default::default()
+        };
+        for (src, ok) in [(">", true), (">>", false), ("+-", true), ("-", false)] {
+            let ret = BfClif::from_source(src, Box::new(&b""[..]), Box::new(vec![]), options)
+                .unwrap()
+                .run();
+            assert_eq!(ret.is_ok(), ok, "{}", src);
+        }
+
+        for (eof, value) in [(Eof::Unchanged, 3), (Eof::Zero, 0), (Eof::Max, 255)] {
+            let mut output = vec![];
+            let options = BfVmOptions {
+                eof,
+                ..Default::default()
+            };
+            let mut vm =
+                BfClif::from_source("+++,.", Box::new(&b""[..]), Box::new(&mut output), options)
+                    .unwrap();
+            vm.run().unwrap();
+            drop(vm);
+            assert_eq!(output, [value]);
+        }
+    }
+}
@@ -4,10 +4,21 @@ use std::vec;
 use crate::bf::error::CompileError;
 use crate::bf::error::CompileErrorKind;
 use crate::bf::ir::BfIR;
+use crate::bf::pass::Pipeline;
+use crate::bf::CellWrap;
+
+// ir 对应源码的 (行, 列)，从 1 开始
+pub type Position = (u32, u32);
 
 // 将bf代码编译为ir(opcode)
 pub fn compile(src: &str) -> Result<Vec<BfIR>, CompileError> {
-    let mut code: Vec<BfIR> = vec![];
+    let code = compile_with_positions(src)?;
+    Ok(code.into_iter().map(|(ir, _)| ir).collect())
+}
+
+// 编译并保留每条 ir 在源码中的 (行, 列)
+pub fn compile_with_positions(src: &str) -> Result<Vec<(BfIR, Position)>, CompileError> {
+    let mut code: Vec<(BfIR, Position)> = vec![];
     let mut stk: Vec<(u32, u32, u32)> = vec![];
 
     let mut line: u32 = 1;
@@ -20,16 +31,16 @@ pub fn compile(src: &str) -> Result<Vec<BfIR>, CompileError> {
                 line += 1;
                 col = 0;
             }
-            '+' => code.push(BfIR::AddVal(1)),
-            '-' => code.push(BfIR::SubVal(1)),
-            '>' => code.push(BfIR::AddPtr(1)),
-            '<' => code.push(BfIR::SubPtr(1)),
-            ',' => code.push(BfIR::GetByte),
-            '.' => code.push(BfIR::PutByte),
+            '+' => code.push((BfIR::AddVal(1), (line, col))),
+            '-' => code.push((BfIR::SubVal(1), (line, col))),
+            '>' => code.push((BfIR::AddPtr(1), (line, col))),
+            '<' => code.push((BfIR::SubPtr(1), (line, col))),
+            ',' => code.push((BfIR::GetByte, (line, col))),
+            '.' => code.push((BfIR::PutByte, (line, col))),
             '[' => {
                 let pos = code.len() as u32; // 当前字节长度
                 stk.push((pos, line, col));
-                code.push(BfIR::Jz);
+                code.push((BfIR::Jz, (line, col)));
             }
             ']' => {
                 stk.pop().ok_or(CompileError {
@@ -37,7 +48,7 @@ pub fn compile(src: &str) -> Result<Vec<BfIR>, CompileError> {
                     col,
                     kind: CompileErrorKind::UnexcpectedRightBracket,
                 })?;
-                code.push(BfIR::Jnz);
+                code.push((BfIR::Jnz, (line, col)));
             }
             _ => {} // 其它字符，忽略
         }
@@ -55,51 +66,42 @@ pub fn compile(src: &str) -> Result<Vec<BfIR>, CompileError> {
     return Ok(code);
 }
 
-pub fn optimize(code: &mut Vec<BfIR>) {
-    let mut i = 0;
-    let mut pc = 0;
-    let len = code.len();
-
-    macro_rules! _fold_ir {
-        ($variant:ident, $x:ident) => {{
-            let mut j = i + 1;
-            while j < len {
-                if let $variant(d) = code[j] {
-                    $x = $x.wrapping_add(d);
-                } else {
-                    break;
-                }
-                j += 1;
+// 检查外部传入的 ir 括号是否匹配，ir 没有源码位置，line 为 0，col 为指令下标(从 1 开始)
+pub fn check(code: &[BfIR]) -> Result<(), CompileError> {
+    let mut stk = vec![];
+    for (pc, ir) in code.iter().enumerate() {
+        match ir {
+            BfIR::Jz => stk.push(pc),
+            BfIR::Jnz => {
+                stk.pop().ok_or(CompileError {
+                    line: 0,
+                    col: pc as u32 + 1,
+                    kind: CompileErrorKind::UnexcpectedRightBracket,
+                })?;
             }
-            i = j;
-            code[pc] = $variant($x);
-            pc += 1;
-        }};
+            _ => {}
+        }
     }
-
-    macro_rules! _normal_ir {
-        () => {{
-            code[pc] = code[i];
-            pc += 1;
-            i += 1;
-        }};
+    if let Some(pc) = stk.pop() {
+        return Err(CompileError {
+            line: 0,
+            col: pc as u32 + 1,
+            kind: CompileErrorKind::UnclosedLeftBracket,
+        });
     }
+    Ok(())
+}
 
-    use BfIR::*;
-    while i < len {
-        match code[i] {
-            AddPtr(mut x) => _fold_ir!(AddPtr, x),
-            SubPtr(mut x) => _fold_ir!(SubPtr, x),
-            AddVal(mut x) => _fold_ir!(AddVal, x),
-            SubVal(mut x) => _fold_ir!(SubVal, x),
-            GetByte => _normal_ir!(),
-            PutByte => _normal_ir!(),
-            Jz => _normal_ir!(),
-            Jnz => _normal_ir!(),
-        }
-    }
-    code.truncate(pc);
-    code.shrink_to_fit();
+// 单元溢出报错时，不做会改变溢出行为的优化
+pub fn optimize(code: &mut Vec<BfIR>, cell_wrap: CellWrap) {
+    let mut positioned = code.drain(..).map(|ir| (ir, (0, 0))).collect();
+    optimize_with_positions(&mut positioned, cell_wrap);
+    *code = positioned.into_iter().map(|(ir, _)| ir).collect();
+}
+
+// 使用默认的 pass 流水线优化，合并后的 ir 取第一条被合并 ir 的位置
+pub fn optimize_with_positions(code: &mut Vec<(BfIR, Position)>, cell_wrap: CellWrap) {
+    Pipeline::default().run(code, cell_wrap);
 }
 
 mod tests {
@@ -128,7 +130,90 @@ mod tests {
         };
 
         let mut code = compile("[+++++]").unwrap();
-        optimize(&mut code);
+        optimize(&mut code, CellWrap::Wrap);
         assert_eq!(code, vec![BfIR::Jz, BfIR::AddVal(5), BfIR::Jnz]);
+
+        let mut code = compile("+[-]>[+]<[<]>[>][--]").unwrap();
+        optimize(&mut code, CellWrap::Wrap);
+        assert_eq!(
+            code,
+            vec![
+                BfIR::AddVal(1),
+                BfIR::SetZero,
+                BfIR::AddPtr(1),
+                BfIR::SetZero,
+                BfIR::SubPtr(1),
+                BfIR::ScanLeft,
+                BfIR::AddPtr(1),
+                BfIR::ScanRight,
+                BfIR::Jz,
+                BfIR::SubVal(2),
+                BfIR::Jnz,
+            ]
+        );
+
+        let mut code = compile("[->+<]>[->++>+++<<]<[>-<-][->+<<]").unwrap();
+        optimize(&mut code, CellWrap::Wrap);
+        assert_eq!(
+            code,
+            vec![
+                BfIR::MulAdd(1, 1),
+                BfIR::SetZero,
+                BfIR::AddPtr(1),
+                BfIR::MulAdd(1, 2),
+                BfIR::MulAdd(2, 3),
+                BfIR::SetZero,
+                BfIR::SubPtr(1),
+                BfIR::MulAdd(1, 255),
+                BfIR::SetZero,
+                BfIR::Jz,
+                BfIR::SubVal(1),
+                BfIR::AddPtr(1),
+                BfIR::AddVal(1),
+                BfIR::SubPtr(2),
+                BfIR::Jnz,
+            ]
+        );
+
+        assert!(check(&compile("[[]][]").unwrap()).is_ok());
+        assert_eq!(check(&[BfIR::AddVal(1), BfIR::Jnz]).unwrap_err().col, 2);
+        match check(&[BfIR::Jz, BfIR::Jz, BfIR::Jnz]).unwrap_err().kind {
+            CompileErrorKind::UnclosedLeftBracket => {}
+            _ => panic!(),
+        };
+
+        let mut code = compile_with_positions("+\n [-]>>+ +").unwrap();
+        optimize_with_positions(&mut code, CellWrap::Wrap);
+        assert_eq!(
+            code,
+            vec![
+                (BfIR::AddVal(1), (1, 1)),
+                (BfIR::SetZero, (2, 2)),
+                (BfIR::AddPtr(2), (2, 5)),
+                (BfIR::AddVal(2), (2, 7)),
+            ]
+        );
+
+        let mut code = compile(&"+".repeat(300)).unwrap();
+        optimize(&mut code, CellWrap::Wrap);
+        assert_eq!(code, vec![BfIR::AddVal(255), BfIR::AddVal(45)]);
+
+        let mut code = compile("[-][+][->+<]").unwrap();
+        optimize(&mut code, CellWrap::Error);
+        assert_eq!(
+            code,
+            vec![
+                BfIR::SetZero,
+                BfIR::Jz,
+                BfIR::AddVal(1),
+                BfIR::Jnz,
+                BfIR::Jz,
+                BfIR::SubVal(1),
+                BfIR::AddPtr(1),
+                BfIR::AddVal(1),
+                BfIR::SubPtr(1),
+                BfIR::Jnz,
+            ]
+        );
     }
 }
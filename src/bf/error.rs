@@ -30,6 +30,9 @@ pub enum RuntimeError {
 
     #[error("Pointer overflow")]
     PointerOverflow,
+
+    #[error("Cell overflow")]
+    CellOverflow,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -42,6 +45,20 @@ pub enum VMError {
 
     #[error("Runtime: {0}")]
     Runtime(#[from] RuntimeError),
+
+    #[error("Options: {0}")]
+    Options(String),
+
+    #[error("Codegen: {0}")]
+    Codegen(String),
+}
+
+// 生成的代码通过指针返回错误，空指针表示成功
+#[cfg(feature = "bf-jit")]
+#[inline(always)]
+pub(crate) fn vm_error(re: RuntimeError) -> *mut VMError {
+    let e = Box::new(VMError::from(re));
+    Box::into_raw(e)
 }
 
 pub type Result<T> = std::result::Result<T, VMError>;
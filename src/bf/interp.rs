@@ -0,0 +1,554 @@
+use std::collections::HashSet;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use crate::bf::compile::{
+    check, compile_with_positions, optimize, optimize_with_positions, Position,
+};
+use crate::bf::error::{Result, RuntimeError};
+use crate::bf::ir::BfIR;
+use crate::bf::profile::{BfProfile, Counters};
+use crate::bf::{BfVmOptions, CellWrap};
+
+// 纯 Rust 实现的 BfIR 解释器，用于不支持 jit 的平台
+// 多次执行之间保留内存与指针，可用于交互模式
+// 支持单步执行与源码位置上的断点，可用于调试
+pub struct BfInterp<'io> {
+    code: Vec<BfIR>,
+    positions: Vec<Position>, // 每条 ir 的源码位置，直接执行 ir 时为空
+    jumps: Vec<usize>,        // 每个 Jz/Jnz 匹配的另一半括号的位置
+    profile: Option<Counters>,
+    breakpoints: HashSet<Position>,
+    memory: Box<[u8]>,
+    ptr: usize,
+    pc: usize, // 下一条要执行的 ir
+    options: BfVmOptions,
+    input: Box<dyn Read + 'io>,
+    output: Box<dyn Write + 'io>,
+}
+
+impl<'io> BfInterp<'io> {
+    pub fn new(
+        file_path: &Path,
+        input: Box<dyn Read + 'io>,
+        output: Box<dyn Write + 'io>,
+        options: BfVmOptions,
+    ) -> Result<Self> {
+        let src = std::fs::read_to_string(file_path)?;
+        Self::from_source(&src, input, output, options)
+    }
+
+    pub fn from_source(
+        src: &str,
+        input: Box<dyn Read + 'io>,
+        output: Box<dyn Write + 'io>,
+        options: BfVmOptions,
+    ) -> Result<Self> {
+        let mut interp = Self::empty(input, output, options)?;
+        interp.load(src)?;
+        Ok(interp)
+    }
+
+    // 直接执行 ir，options.optimized 为 true 时同样会先优化
+    pub fn from_ir(
+        code: Vec<BfIR>,
+        input: Box<dyn Read + 'io>,
+        output: Box<dyn Write + 'io>,
+        options: BfVmOptions,
+    ) -> Result<Self> {
+        let mut interp = Self::empty(input, output, options)?;
+        interp.load_ir(code)?;
+        Ok(interp)
+    }
+
+    fn empty(
+        input: Box<dyn Read + 'io>,
+        output: Box<dyn Write + 'io>,
+        options: BfVmOptions,
+    ) -> Result<Self> {
+        options.check()?;
+        Ok(Self {
+            code: vec![],
+            positions: vec![],
+            jumps: vec![],
+            profile: None,
+            breakpoints: HashSet::new(),
+            memory: vec![0; options.memory_size].into_boxed_slice(),
+            ptr: 0,
+            pc: 0,
+            options,
+            input,
+            output,
+        })
+    }
+
+    // 替换要执行的代码，内存与指针保持不变，从第一条 ir 开始执行
+    pub fn load(&mut self, src: &str) -> Result<()> {
+        let mut code = compile_with_positions(src)?;
+        if self.options.optimized {
+            optimize_with_positions(&mut code, self.options.cell_wrap);
+        }
+        let (code, positions) = code.into_iter().unzip();
+        self.set_code(code, positions);
+        Ok(())
+    }
+
+    pub fn load_ir(&mut self, mut code: Vec<BfIR>) -> Result<()> {
+        check(&code)?;
+        if self.options.optimized {
+            optimize(&mut code, self.options.cell_wrap);
+        }
+        self.set_code(code, vec![]);
+        Ok(())
+    }
+
+    fn set_code(&mut self, code: Vec<BfIR>, positions: Vec<Position>) {
+        // 括号已经检查过
+        let mut jumps = vec![0; code.len()];
+        let mut loops = vec![];
+        for (pc, ir) in code.iter().enumerate() {
+            match ir {
+                BfIR::Jz => loops.push(pc),
+                BfIR::Jnz => {
+                    let left = loops.pop().unwrap();
+                    jumps[left] = pc;
+                    jumps[pc] = left;
+                }
+                _ => {}
+            }
+        }
+
+        if self.profile.is_some() {
+            self.profile = Some(Counters::new(code.len()));
+        }
+        self.code = code;
+        self.positions = positions;
+        self.jumps = jumps;
+        self.pc = 0;
+    }
+
+    // 开始统计每条 ir 的执行次数与循环的迭代次数，加载新代码时重新计数
+    pub fn enable_profile(&mut self) {
+        self.profile = Some(Counters::new(self.code.len()));
+    }
+
+    // 取出当前的统计结果并重新计数
+    pub fn take_profile(&mut self) -> Option<BfProfile> {
+        let counters = self.profile.as_mut()?;
+        let profile = BfProfile::new(&self.code, &self.positions, counters);
+        *counters = Counters::new(self.code.len());
+        Some(profile)
+    }
+
+    // 在当前内存上执行一段代码
+    pub fn eval(&mut self, src: &str) -> Result<()> {
+        self.load(src)?;
+        self.run()
+    }
+
+    // 清空内存，指针回到 0
+    pub fn reset(&mut self) {
+        self.memory.fill(0);
+        self.ptr = 0;
+    }
+
+    pub fn ptr(&self) -> usize {
+        self.ptr
+    }
+
+    // 指针前后 radius 个单元，返回起始下标与内容
+    pub fn tape(&self, radius: usize) -> (usize, &[u8]) {
+        let start = self.ptr.saturating_sub(radius);
+        let end = (self.ptr + radius + 1).min(self.memory.len());
+        (start, &self.memory[start..end])
+    }
+
+    // 断点设在源码的 (行, 列) 上，在该位置的 ir 执行前停下
+    // 优化后合并的 ir 只保留第一条的位置，直接执行 ir 时没有位置，断点不会生效
+    pub fn add_breakpoint(&mut self, position: Position) {
+        self.breakpoints.insert(position);
+    }
+
+    pub fn remove_breakpoint(&mut self, position: Position) -> bool {
+        self.breakpoints.remove(&position)
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    // 下一条要执行的 ir 的下标，执行完毕时等于 ir 的数量
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    // 下一条要执行的 ir 及其源码位置
+    pub fn current(&self) -> Option<(BfIR, Option<Position>)> {
+        let ir = *self.code.get(self.pc)?;
+        Some((ir, self.positions.get(self.pc).copied()))
+    }
+
+    // 从头执行到结束，忽略断点
+    pub fn run(&mut self) -> Result<()> {
+        self.pc = 0;
+        self.exec(false)?;
+        self.output.flush().map_err(RuntimeError::IO)?;
+        Ok(())
+    }
+
+    // 从当前位置继续执行，至少执行一条 ir，停在断点时返回断点位置，执行完毕时返回 None
+    pub fn cont(&mut self) -> Result<Option<Position>> {
+        let ret = self.exec(true)?;
+        self.output.flush().map_err(RuntimeError::IO)?;
+        Ok(ret)
+    }
+
+    // 执行一条 ir，已经执行完毕时返回 false
+    pub fn step(&mut self) -> Result<bool> {
+        if self.pc >= self.code.len() {
+            return Ok(false);
+        }
+        let (mut pc, mut ptr) = (self.pc, self.ptr);
+        self.exec_ir(&mut pc, &mut ptr)?;
+        (self.pc, self.ptr) = (pc, ptr);
+        self.output.flush().map_err(RuntimeError::IO)?;
+        Ok(true)
+    }
+
+    fn exec(&mut self, breakpoints: bool) -> Result<Option<Position>> {
+        // 出错时指针与 pc 停在出错的 ir 之前
+        let (mut pc, mut ptr) = (self.pc, self.ptr);
+        let ret = loop {
+            if pc >= self.code.len() {
+                break Ok(None);
+            }
+            if let Err(e) = self.exec_ir(&mut pc, &mut ptr) {
+                break Err(e);
+            }
+            // 执行后检查，继续执行时不会停在当前的断点上
+            if breakpoints {
+                if let Some(&position) = self.positions.get(pc) {
+                    if self.breakpoints.contains(&position) {
+                        break Ok(Some(position));
+                    }
+                }
+            }
+        };
+        (self.pc, self.ptr) = (pc, ptr);
+        ret
+    }
+
+    // 执行 pc 处的 ir，出错时 pc 与指针保持不变
+    #[inline(always)]
+    fn exec_ir(&mut self, pc: &mut usize, ptr: &mut usize) -> Result<()> {
+        let len = self.memory.len();
+
+        use BfIR::*;
+        let ir = self.code[*pc];
+        if let Some(counters) = &mut self.profile {
+            counters.ops[*pc] += 1;
+            match ir {
+                Jz if self.memory[*ptr] != 0 => {
+                    counters.entries[*pc] += 1;
+                    counters.iterations[*pc] += 1;
+                }
+                Jnz if self.memory[*ptr] != 0 => counters.iterations[self.jumps[*pc]] += 1,
+                _ => {}
+            }
+        }
+        match ir {
+            AddPtr(x) => {
+                *ptr = Some(*ptr + x as usize)
+                    .filter(|&p| p < len)
+                    .ok_or(RuntimeError::PointerOverflow)?;
+            }
+            SubPtr(x) => {
+                *ptr = ptr
+                    .checked_sub(x as usize)
+                    .ok_or(RuntimeError::PointerOverflow)?;
+            }
+            AddVal(x) => {
+                self.memory[*ptr] = match self.options.cell_wrap {
+                    CellWrap::Wrap => self.memory[*ptr].wrapping_add(x),
+                    CellWrap::Error => self.memory[*ptr]
+                        .checked_add(x)
+                        .ok_or(RuntimeError::CellOverflow)?,
+                }
+            }
+            SubVal(x) => {
+                self.memory[*ptr] = match self.options.cell_wrap {
+                    CellWrap::Wrap => self.memory[*ptr].wrapping_sub(x),
+                    CellWrap::Error => self.memory[*ptr]
+                        .checked_sub(x)
+                        .ok_or(RuntimeError::CellOverflow)?,
+                }
+            }
+            GetByte => {
+                let mut buf = [0_u8];
+                if self.input.read(&mut buf).map_err(RuntimeError::IO)? == 1 {
+                    self.memory[*ptr] = buf[0];
+                } else {
+                    self.options.eof.apply(&mut self.memory[*ptr]);
+                }
+            }
+            PutByte => self
+                .output
+                .write_all(&self.memory[*ptr..*ptr + 1])
+                .map_err(RuntimeError::IO)?,
+            Jz => {
+                if self.memory[*ptr] == 0 {
+                    *pc = self.jumps[*pc];
+                }
+            }
+            Jnz => {
+                if self.memory[*ptr] != 0 {
+                    *pc = self.jumps[*pc];
+                }
+            }
+            SetZero => self.memory[*ptr] = 0,
+            ScanLeft => {
+                *ptr = self.memory[..=*ptr]
+                    .iter()
+                    .rposition(|&b| b == 0)
+                    .ok_or(RuntimeError::PointerOverflow)?;
+            }
+            ScanRight => {
+                *ptr += self.memory[*ptr..]
+                    .iter()
+                    .position(|&b| b == 0)
+                    .ok_or(RuntimeError::PointerOverflow)?;
+            }
+            MulAdd(offset, factor) => {
+                let value = self.memory[*ptr];
+                if value != 0 {
+                    let target = ptr
+                        .checked_add_signed(offset as isize)
+                        .filter(|&target| target < len)
+                        .ok_or(RuntimeError::PointerOverflow)?;
+                    let cell = &mut self.memory[target];
+                    *cell = cell.wrapping_add(value.wrapping_mul(factor));
+                }
+            }
+        }
+        *pc += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BfInterp;
+    use crate::bf::error::{RuntimeError, VMError};
+    use crate::bf::ir::BfIR;
+    use crate::bf::{BfVmOptions, CellWrap, Eof};
+
+    #[test]
+    fn test_interp() {
+        let mut output = vec![];
+        let src = "++++++++[>++++++++<-]>+.,+.";
+        for optimized in [false, true] {
+            output.clear();
+            let options = BfVmOptions {
+                optimized,
+                ..Default::default()
+            };
+            let mut vm =
+                BfInterp::from_source(src, Box::new(&b"a"[..]), Box::new(&mut output), options)
+                    .unwrap();
+            vm.run().unwrap();
+            drop(vm);
+            assert_eq!(output, b"Ab");
+        }
+
+        for optimized in [false, true] {
+            output.clear();
+            let options = BfVmOptions {
+                optimized,
+                ..Default::default()
+            };
+            let src = "+++[-].>+>+>+[<]>.[>].";
+            let mut vm =
+                BfInterp::from_source(src, Box::new(&b""[..]), Box::new(&mut output), options)
+                    .unwrap();
+            vm.run().unwrap();
+            drop(vm);
+            assert_eq!(output, [0, 1, 0]);
+        }
+
+        for optimized in [false, true] {
+            output.clear();
+            let options = BfVmOptions {
+                optimized,
+                ..Default::default()
+            };
+            let src = "+++++[->++>+++<<]>.>.<<+++[>>>+<<<-]>>>.";
+            let mut vm =
+                BfInterp::from_source(src, Box::new(&b""[..]), Box::new(&mut output), options)
+                    .unwrap();
+            vm.run().unwrap();
+            drop(vm);
+            assert_eq!(output, [10, 15, 3]);
+        }
+
+        let options = BfVmOptions::default();
+        let mut vm =
+            BfInterp::from_source("<", Box::new(&b""[..]), Box::new(vec![]), options).unwrap();
+        match vm.run() {
+            Err(VMError::Runtime(RuntimeError::PointerOverflow)) => {}
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn test_interp_options() {
+        let run = |src: &str, options: BfVmOptions| {
+            BfInterp::from_source(src, Box::new(&b""[..]), Box::new(vec![]), options)?.run()
+        };
+
+        let small = BfVmOptions {
+            memory_size: 4,
+            ..Default::default()
+        };
+        assert!(run(">>>", small).is_ok());
+        match run(">>>>", small) {
+            Err(VMError::Runtime(RuntimeError::PointerOverflow)) => {}
+            _ => panic!(),
+        }
+        match run(
+            "",
+            BfVmOptions {
+                memory_size: 0,
+                ..small
+            },
+        ) {
+            Err(VMError::Options(_)) => {}
+            _ => panic!(),
+        }
+
+        for optimized in [false, true] {
+            let options = BfVmOptions {
+                cell_wrap: CellWrap::Error,
+                optimized,
+                ..Default::default()
+            };
+            assert!(run("+[-]-+", BfVmOptions::default()).is_ok());
+            assert!(run(&"+".repeat(255), options).is_ok());
+            for src in ["-", &"+".repeat(256), "+[+]"] {
+                match run(src, options) {
+                    Err(VMError::Runtime(RuntimeError::CellOverflow)) => {}
+                    _ => panic!("{}", src),
+                }
+            }
+        }
+
+        for (eof, value) in [(Eof::Unchanged, 3), (Eof::Zero, 0), (Eof::Max, 255)] {
+            let mut output = vec![];
+            let options = BfVmOptions {
+                eof,
+                ..Default::default()
+            };
+            let mut vm =
+                BfInterp::from_source("+++,.", Box::new(&b""[..]), Box::new(&mut output), options)
+                    .unwrap();
+            vm.run().unwrap();
+            drop(vm);
+            assert_eq!(output, [value]);
+        }
+    }
+
+    #[test]
+    fn test_interp_eval() {
+        let mut output = vec![];
+        let options = BfVmOptions::default();
+        let mut vm =
+            BfInterp::from_source("", Box::new(&b""[..]), Box::new(&mut output), options).unwrap();
+        vm.eval("+++>++").unwrap();
+        vm.eval("<[->+<]>").unwrap();
+        assert_eq!(vm.ptr(), 1);
+        assert_eq!(vm.tape(1), (0, &[0, 5, 0][..]));
+
+        // 出错后内存与指针保留出错前的状态
+        assert!(vm.eval("+<<").is_err());
+        assert_eq!(vm.ptr(), 0);
+        assert_eq!(vm.tape(1), (0, &[0, 6][..]));
+        assert!(vm.eval("[").is_err());
+
+        vm.eval(">.").unwrap();
+        vm.reset();
+        assert_eq!((vm.ptr(), vm.tape(0).1), (0, &[0][..]));
+        drop(vm);
+        assert_eq!(output, [6]);
+    }
+
+    #[test]
+    fn test_interp_profile() {
+        let src = "+++[>++[-]<-]\n>>++[-<+>]";
+        let mut vm = BfInterp::from_source(
+            src,
+            Box::new(&b""[..]),
+            Box::new(vec![]),
+            BfVmOptions::default(),
+        )
+        .unwrap();
+        assert!(vm.take_profile().is_none());
+        vm.enable_profile();
+        vm.run().unwrap();
+
+        let profile = vm.take_profile().unwrap();
+        let loops: Vec<_> = profile
+            .loops
+            .iter()
+            .map(|l| (l.position, l.entries, l.iterations))
+            .collect();
+        assert_eq!(
+            loops,
+            vec![
+                (Some((1, 8)), 3, 6),
+                (Some((1, 4)), 1, 3),
+                (Some((2, 5)), 1, 2)
+            ]
+        );
+        assert_eq!(profile.ops[0], ("AddVal", 13));
+        assert!(profile.report(1).contains("1:8"));
+
+        // 取出后重新计数
+        assert_eq!(vm.take_profile().unwrap().total(), 0);
+    }
+    #[test]
+    fn test_interp_debug() {
+        let mut output = vec![];
+        let src = "++[>+\n.<-]";
+        let mut vm = BfInterp::from_source(
+            src,
+            Box::new(&b""[..]),
+            Box::new(&mut output),
+            BfVmOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(vm.current(), Some((BfIR::AddVal(1), Some((1, 1)))));
+        assert!(vm.step().unwrap());
+        assert!(vm.step().unwrap());
+        assert_eq!((vm.pc(), vm.tape(0).1), (2, &[2][..]));
+
+        // 每轮循环都停在断点上
+        vm.add_breakpoint((2, 1));
+        assert_eq!(vm.cont().unwrap(), Some((2, 1)));
+        assert_eq!((vm.ptr(), vm.tape(1)), (1, (0, &[2, 1, 0][..])));
+        assert_eq!(vm.cont().unwrap(), Some((2, 1)));
+        assert_eq!(vm.tape(1), (0, &[1, 2, 0][..]));
+
+        assert!(vm.remove_breakpoint((2, 1)));
+        assert_eq!(vm.cont().unwrap(), None);
+        assert!(!vm.step().unwrap());
+        assert_eq!(vm.current(), None);
+
+        // 出错时停在出错的 ir 之前
+        vm.load("><<").unwrap();
+        assert!(vm.step().unwrap());
+        assert!(vm.step().unwrap());
+        assert!(vm.step().is_err());
+        assert_eq!((vm.pc(), vm.ptr()), (2, 0));
+        drop(vm);
+        assert_eq!(output, [1, 2]);
+    }
+}
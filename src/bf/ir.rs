@@ -8,4 +8,29 @@ pub enum BfIR {
     PutByte,    // .
     Jz,         // [
     Jnz,        // ]
+    SetZero,    // [-] 或 [+]
+    ScanLeft,   // [<]，左移到第一个为 0 的单元
+    ScanRight,  // [>]，右移到第一个为 0 的单元
+    // [->+<] 等乘加循环：*(ptr + offset) += *ptr * factor，后面跟着 SetZero
+    MulAdd(i16, u8),
+}
+
+impl BfIR {
+    // 不带参数的名字，用于统计
+    pub fn name(&self) -> &'static str {
+        match self {
+            BfIR::AddVal(_) => "AddVal",
+            BfIR::SubVal(_) => "SubVal",
+            BfIR::AddPtr(_) => "AddPtr",
+            BfIR::SubPtr(_) => "SubPtr",
+            BfIR::GetByte => "GetByte",
+            BfIR::PutByte => "PutByte",
+            BfIR::Jz => "Jz",
+            BfIR::Jnz => "Jnz",
+            BfIR::SetZero => "SetZero",
+            BfIR::ScanLeft => "ScanLeft",
+            BfIR::ScanRight => "ScanRight",
+            BfIR::MulAdd(..) => "MulAdd",
+        }
+    }
 }
@@ -0,0 +1,99 @@
+use std::fmt::Write;
+
+use crate::bf::compile::{compile_with_positions, optimize_with_positions};
+use crate::bf::error::Result;
+use crate::bf::BfVmOptions;
+
+// 打印 ir 列表，每行为序号、源码位置与 ir，
+// 支持并编译了 jit 的平台上同时打印每条 ir 的机器码偏移与长度
+pub fn listing(src: &str, options: BfVmOptions) -> Result<String> {
+    let mut code = compile_with_positions(src)?;
+    if options.optimized {
+        optimize_with_positions(&mut code, options.cell_wrap);
+    }
+    let (ir, positions): (Vec<_>, Vec<_>) = code.into_iter().unzip();
+
+    let offsets = machine_code(&ir, options)?;
+    let mut out = String::new();
+    for (i, (op, (line, col))) in ir.iter().zip(positions).enumerate() {
+        let op = format!("{:?}", op);
+        let _ = match &offsets {
+            Some((offsets, _)) => writeln!(
+                out,
+                "{:>5} {:>4}:{:<4} {:<16} {:#06x} {:>3}",
+                i,
+                line,
+                col,
+                op,
+                offsets[i],
+                offsets[i + 1] - offsets[i]
+            ),
+            None => writeln!(out, "{:>5} {:>4}:{:<4} {}", i, line, col, op),
+        };
+    }
+    let _ = write!(out, "{} ir", ir.len());
+    if let Some((offsets, len)) = &offsets {
+        let body = offsets.last().unwrap() - offsets[0];
+        let _ = write!(out, ", {} bytes of machine code ({} in body)", len, body);
+    }
+    out.push('\n');
+    Ok(out)
+}
+
+// 生成机器码并返回每条 ir 的偏移与总长度，不支持 jit 的平台返回 None
+#[cfg(all(
+    feature = "bf-jit",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+fn machine_code(
+    ir: &[crate::bf::ir::BfIR],
+    options: BfVmOptions,
+) -> Result<Option<(Vec<usize>, usize)>> {
+    use crate::bf::vm::BfVM;
+
+    // ir 已经优化过
+    let options = BfVmOptions {
+        optimized: false,
+        ..options
+    };
+    let vm = BfVM::from_ir(
+        ir.to_vec(),
+        Box::new(std::io::empty()),
+        Box::new(std::io::sink()),
+        options,
+    )?;
+    Ok(Some((vm.code_offsets().to_vec(), vm.code_len())))
+}
+
+#[cfg(not(all(
+    feature = "bf-jit",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+)))]
+fn machine_code(
+    _ir: &[crate::bf::ir::BfIR],
+    _options: BfVmOptions,
+) -> Result<Option<(Vec<usize>, usize)>> {
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::listing;
+    use crate::bf::BfVmOptions;
+
+    #[test]
+    fn test_listing() {
+        let options = BfVmOptions {
+            optimized: true,
+            ..Default::default()
+        };
+        let out = listing("++\n>[-]<.", options).unwrap();
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines.len(), 6);
+        assert!(lines[0].starts_with("    0    1:1    AddVal(2)"));
+        assert!(lines[2].starts_with("    2    2:2    SetZero"));
+        assert!(lines[5].starts_with("5 ir"));
+
+        assert!(listing("[", options).is_err());
+    }
+}
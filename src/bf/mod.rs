@@ -1,6 +1,128 @@
 // brainfuck 解释器\jit编译器实现
 
+use std::io::{Read, Write};
+use std::path::Path;
+
+pub mod bytecode;
+#[cfg(feature = "bf-jit")]
+pub mod clif;
 pub mod compile;
-pub mod vm;
 pub mod error;
+pub mod interp;
 pub mod ir;
+pub mod listing;
+pub mod pass;
+pub mod profile;
+// jit 支持 x86-64 与 aarch64，其它平台或关闭 bf-jit 时使用解释器
+#[cfg(all(
+    feature = "bf-jit",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+pub mod vm;
+
+use crate::bf::error::{Result, VMError};
+use crate::bf::interp::BfInterp;
+
+// 默认内存大小
+pub const MEMORY_SIZE: usize = 4 * 1024 * 1024;
+
+// 单元加减越过 0 或 255 时的行为
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CellWrap {
+    // 回绕，255 + 1 = 0
+    Wrap,
+    // 报错
+    Error,
+}
+
+// 读取输入遇到 EOF 时写入单元的值
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Eof {
+    // 保持原值
+    Unchanged,
+    // 写入 0
+    Zero,
+    // 写入 255
+    Max,
+}
+
+impl Eof {
+    pub(crate) fn apply(self, cell: &mut u8) {
+        match self {
+            Eof::Unchanged => {}
+            Eof::Zero => *cell = 0,
+            Eof::Max => *cell = u8::MAX,
+        }
+    }
+}
+
+// 运行选项
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BfVmOptions {
+    pub memory_size: usize,
+    pub cell_wrap: CellWrap,
+    pub eof: Eof,
+    pub optimized: bool,
+}
+
+impl Default for BfVmOptions {
+    fn default() -> Self {
+        Self {
+            memory_size: MEMORY_SIZE,
+            cell_wrap: CellWrap::Wrap,
+            eof: Eof::Unchanged,
+            optimized: false,
+        }
+    }
+}
+
+impl BfVmOptions {
+    pub(crate) fn check(&self) -> Result<()> {
+        if self.memory_size == 0 {
+            return Err(VMError::Options("memory size must be positive".to_string()));
+        }
+        Ok(())
+    }
+}
+
+// 执行后端
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Jit,
+    Cranelift,
+    Interp,
+}
+
+impl Backend {
+    // 当前平台支持的最快后端
+    pub fn native() -> Self {
+        if cfg!(all(
+            feature = "bf-jit",
+            any(target_arch = "x86_64", target_arch = "aarch64")
+        )) {
+            Backend::Jit
+        } else {
+            Backend::Interp
+        }
+    }
+}
+
+// 使用指定后端运行 bf 文件，平台不支持或没有编译 jit 时回退到解释器
+pub fn run<'io>(
+    file_path: &Path,
+    input: Box<dyn Read + 'io>,
+    output: Box<dyn Write + 'io>,
+    options: BfVmOptions,
+    backend: Backend,
+) -> Result<()> {
+    match backend {
+        #[cfg(all(
+            feature = "bf-jit",
+            any(target_arch = "x86_64", target_arch = "aarch64")
+        ))]
+        Backend::Jit => vm::BfVM::new(file_path, input, output, options)?.run(),
+        #[cfg(feature = "bf-jit")]
+        Backend::Cranelift => clif::BfClif::new(file_path, input, output, options)?.run(),
+        _ => BfInterp::new(file_path, input, output, options)?.run(),
+    }
+}
@@ -0,0 +1,310 @@
+use crate::bf::compile::Position;
+use crate::bf::ir::BfIR;
+use crate::bf::CellWrap;
+
+// 优化 pass，在带源码位置的 ir 上改写，合并后的 ir 取第一条被合并 ir 的位置
+// 单元溢出报错时，不能做会改变溢出行为的优化
+pub trait OptimizationPass {
+    // pass 的名字，用于从流水线中移除
+    fn name(&self) -> &'static str;
+    fn run(&self, code: &mut Vec<(BfIR, Position)>, cell_wrap: CellWrap);
+}
+
+// 按顺序执行的一组 pass，默认包含全部内置 pass
+pub struct Pipeline {
+    passes: Vec<Box<dyn OptimizationPass>>,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        let mut pipeline = Self::empty();
+        pipeline.add(Box::new(FoldPass));
+        pipeline.add(Box::new(ClearLoopPass));
+        pipeline.add(Box::new(ScanLoopPass));
+        pipeline.add(Box::new(MulLoopPass));
+        pipeline
+    }
+}
+
+impl Pipeline {
+    pub fn empty() -> Self {
+        Self { passes: vec![] }
+    }
+
+    // 加到流水线末尾
+    pub fn add(&mut self, pass: Box<dyn OptimizationPass>) {
+        self.passes.push(pass);
+    }
+
+    // 移除所有同名的 pass，返回是否移除了 pass
+    pub fn remove(&mut self, name: &str) -> bool {
+        let len = self.passes.len();
+        self.passes.retain(|pass| pass.name() != name);
+        self.passes.len() != len
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.passes.iter().map(|pass| pass.name()).collect()
+    }
+
+    pub fn run(&self, code: &mut Vec<(BfIR, Position)>, cell_wrap: CellWrap) {
+        for pass in &self.passes {
+            pass.run(code, cell_wrap);
+        }
+        code.shrink_to_fit();
+    }
+}
+
+// 合并连续的 +-<>
+pub struct FoldPass;
+
+impl OptimizationPass for FoldPass {
+    fn name(&self) -> &'static str {
+        "fold"
+    }
+
+    fn run(&self, code: &mut Vec<(BfIR, Position)>, _cell_wrap: CellWrap) {
+        let mut i = 0;
+        let mut pc = 0;
+        let len = code.len();
+
+        macro_rules! _fold_ir {
+            ($variant:ident, $x:ident) => {{
+                let mut j = i + 1;
+                while j < len {
+                    // 合并后会溢出时另起一条，保证溢出检查不被跳过
+                    match code[j].0 {
+                        $variant(d) if $x.checked_add(d).is_some() => $x += d,
+                        _ => break,
+                    }
+                    j += 1;
+                }
+                code[pc] = ($variant($x), code[i].1);
+                i = j;
+                pc += 1;
+            }};
+        }
+
+        macro_rules! _normal_ir {
+            () => {{
+                code[pc] = code[i];
+                pc += 1;
+                i += 1;
+            }};
+        }
+
+        use BfIR::*;
+        while i < len {
+            match code[i].0 {
+                AddPtr(mut x) => _fold_ir!(AddPtr, x),
+                SubPtr(mut x) => _fold_ir!(SubPtr, x),
+                AddVal(mut x) => _fold_ir!(AddVal, x),
+                SubVal(mut x) => _fold_ir!(SubVal, x),
+                GetByte => _normal_ir!(),
+                PutByte => _normal_ir!(),
+                Jz => _normal_ir!(),
+                Jnz => _normal_ir!(),
+                SetZero | ScanLeft | ScanRight | MulAdd(..) => _normal_ir!(),
+            }
+        }
+        code.truncate(pc);
+    }
+}
+
+// [-]、[+] 清零，[+] 只在单元回绕时替换
+pub struct ClearLoopPass;
+
+impl OptimizationPass for ClearLoopPass {
+    fn name(&self) -> &'static str {
+        "clear"
+    }
+
+    fn run(&self, code: &mut Vec<(BfIR, Position)>, cell_wrap: CellWrap) {
+        use BfIR::*;
+        replace_loops(code, |body| match body {
+            [(SubVal(1), _), (Jnz, _), ..] => Some((vec![SetZero], 1)),
+            [(AddVal(1), _), (Jnz, _), ..] if cell_wrap == CellWrap::Wrap => {
+                Some((vec![SetZero], 1))
+            }
+            _ => None,
+        });
+    }
+}
+
+// [<]、[>] 查找 0
+pub struct ScanLoopPass;
+
+impl OptimizationPass for ScanLoopPass {
+    fn name(&self) -> &'static str {
+        "scan"
+    }
+
+    fn run(&self, code: &mut Vec<(BfIR, Position)>, _cell_wrap: CellWrap) {
+        use BfIR::*;
+        replace_loops(code, |body| match body {
+            [(SubPtr(1), _), (Jnz, _), ..] => Some((vec![ScanLeft], 1)),
+            [(AddPtr(1), _), (Jnz, _), ..] => Some((vec![ScanRight], 1)),
+            _ => None,
+        });
+    }
+}
+
+// [->+<] 等乘加循环，MulAdd 总是回绕，只在单元回绕时替换
+pub struct MulLoopPass;
+
+impl OptimizationPass for MulLoopPass {
+    fn name(&self) -> &'static str {
+        "mul"
+    }
+
+    fn run(&self, code: &mut Vec<(BfIR, Position)>, cell_wrap: CellWrap) {
+        if cell_wrap != CellWrap::Wrap {
+            return;
+        }
+        replace_loops(code, |body| {
+            let (ops, len) = mul_loop(body)?;
+            let mut irs: Vec<_> = ops
+                .into_iter()
+                .map(|(offset, factor)| BfIR::MulAdd(offset, factor))
+                .collect();
+            irs.push(BfIR::SetZero);
+            Some((irs, len))
+        });
+    }
+}
+
+// 对每个 Jz 之后的 ir 调用 f，f 返回替换整个循环的 ir 与循环体的长度(不含括号)
+fn replace_loops<F>(code: &mut Vec<(BfIR, Position)>, f: F)
+where
+    F: Fn(&[(BfIR, Position)]) -> Option<(Vec<BfIR>, usize)>,
+{
+    let mut out = Vec::with_capacity(code.len());
+    let mut i = 0;
+    while i < code.len() {
+        let (ir, pos) = code[i];
+        if ir == BfIR::Jz {
+            if let Some((irs, len)) = f(&code[i + 1..]) {
+                out.extend(irs.into_iter().map(|ir| (ir, pos)));
+                i += len + 2;
+                continue;
+            }
+        }
+        out.push(code[i]);
+        i += 1;
+    }
+    *code = out;
+}
+
+// 乘加循环的偏移不超过该值，保证生成代码时可以直接编码为立即数
+const MAX_MUL_OFFSET: i32 = 4095;
+
+// 循环体只移动指针与加减值，指针最终回到原位，且当前单元每轮减 1 时，
+// 返回每个偏移处每轮增加的值与循环体长度
+fn mul_loop(body: &[(BfIR, Position)]) -> Option<(Vec<(i16, u8)>, usize)> {
+    use BfIR::*;
+    let mut deltas: Vec<(i32, u8)> = vec![];
+    let mut offset: i32 = 0;
+    for (len, &(ir, _)) in body.iter().enumerate() {
+        let delta = match ir {
+            AddPtr(x) => {
+                offset += x as i32;
+                continue;
+            }
+            SubPtr(x) => {
+                offset -= x as i32;
+                continue;
+            }
+            AddVal(x) => x,
+            SubVal(x) => x.wrapping_neg(),
+            Jnz => {
+                if offset != 0 {
+                    return None;
+                }
+                let mut ops = vec![];
+                let mut step = 0;
+                for (offset, delta) in deltas {
+                    if offset == 0 {
+                        step = delta;
+                    } else if delta != 0 {
+                        ops.push((offset as i16, delta));
+                    }
+                }
+                return if step == u8::MAX {
+                    Some((ops, len))
+                } else {
+                    None
+                };
+            }
+            _ => return None,
+        };
+        if offset.abs() > MAX_MUL_OFFSET {
+            return None;
+        }
+        match deltas.iter_mut().find(|(o, _)| *o == offset) {
+            Some((_, d)) => *d = d.wrapping_add(delta),
+            None => deltas.push((offset, delta)),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OptimizationPass, Pipeline};
+    use crate::bf::compile::{compile_with_positions, Position};
+    use crate::bf::ir::BfIR;
+    use crate::bf::CellWrap;
+
+    // 删除所有输出
+    struct NoOutput;
+
+    impl OptimizationPass for NoOutput {
+        fn name(&self) -> &'static str {
+            "no-output"
+        }
+
+        fn run(&self, code: &mut Vec<(BfIR, Position)>, _cell_wrap: CellWrap) {
+            code.retain(|(ir, _)| *ir != BfIR::PutByte);
+        }
+    }
+
+    #[test]
+    fn test_pipeline() {
+        let optimize = |pipeline: &Pipeline, src: &str| {
+            let mut code = compile_with_positions(src).unwrap();
+            pipeline.run(&mut code, CellWrap::Wrap);
+            code.into_iter().map(|(ir, _)| ir).collect::<Vec<_>>()
+        };
+
+        let mut pipeline = Pipeline::default();
+        assert_eq!(pipeline.names(), ["fold", "clear", "scan", "mul"]);
+        assert_eq!(
+            optimize(&pipeline, "[->+<]."),
+            [BfIR::MulAdd(1, 1), BfIR::SetZero, BfIR::PutByte]
+        );
+
+        // 关闭乘加后，[-] 仍然被替换
+        assert!(pipeline.remove("mul"));
+        assert!(!pipeline.remove("mul"));
+        assert_eq!(
+            optimize(&pipeline, "[->+<][-]"),
+            [
+                BfIR::Jz,
+                BfIR::SubVal(1),
+                BfIR::AddPtr(1),
+                BfIR::AddVal(1),
+                BfIR::SubPtr(1),
+                BfIR::Jnz,
+                BfIR::SetZero,
+            ]
+        );
+
+        pipeline.add(Box::new(NoOutput));
+        assert_eq!(
+            optimize(&pipeline, "++.[<]."),
+            [BfIR::AddVal(2), BfIR::ScanLeft]
+        );
+
+        assert_eq!(optimize(&Pipeline::empty(), "++"), [BfIR::AddVal(1); 2]);
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use crate::bf::compile::Position;
+use crate::bf::ir::BfIR;
+
+// 单个循环(一对括号)的执行统计
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoopProfile {
+    // Jz 在 ir 中的下标
+    pub pc: usize,
+    // 源码位置，直接执行 ir 时为 None
+    pub position: Option<Position>,
+    // 进入循环的次数
+    pub entries: u64,
+    // 循环体执行的总次数
+    pub iterations: u64,
+}
+
+// 解释器的执行统计
+#[derive(Debug, Clone, Default)]
+pub struct BfProfile {
+    // 每种 ir 的执行次数，从多到少
+    pub ops: Vec<(&'static str, u64)>,
+    // 执行过的循环，按迭代次数从多到少
+    pub loops: Vec<LoopProfile>,
+}
+
+impl BfProfile {
+    pub(crate) fn new(code: &[BfIR], positions: &[Position], counters: &Counters) -> Self {
+        let mut ops: HashMap<&'static str, u64> = HashMap::new();
+        let mut loops = vec![];
+        for (pc, ir) in code.iter().enumerate() {
+            if counters.ops[pc] > 0 {
+                *ops.entry(ir.name()).or_default() += counters.ops[pc];
+            }
+            if *ir == BfIR::Jz && counters.entries[pc] > 0 {
+                loops.push(LoopProfile {
+                    pc,
+                    position: positions.get(pc).copied(),
+                    entries: counters.entries[pc],
+                    iterations: counters.iterations[pc],
+                });
+            }
+        }
+
+        let mut ops: Vec<_> = ops.into_iter().collect();
+        ops.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+        loops.sort_by(|a, b| b.iterations.cmp(&a.iterations).then(a.pc.cmp(&b.pc)));
+        Self { ops, loops }
+    }
+
+    // 执行的 ir 总数
+    pub fn total(&self) -> u64 {
+        self.ops.iter().map(|(_, n)| n).sum()
+    }
+
+    // 各 ir 的执行次数与最热的 top 个循环
+    pub fn report(&self, top: usize) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "{} ops executed", self.total());
+        for (name, count) in &self.ops {
+            let _ = writeln!(out, "  {:<10} {:>12}", name, count);
+        }
+        let _ = writeln!(out, "hottest loops:");
+        for l in self.loops.iter().take(top) {
+            let position = match l.position {
+                Some((line, col)) => format!("{}:{}", line, col),
+                None => "-".to_string(),
+            };
+            let _ = writeln!(
+                out,
+                "  {:<10} ir {:<6} {:>12} iterations {:>8} entries",
+                position, l.pc, l.iterations, l.entries
+            );
+        }
+        out
+    }
+}
+
+// 按 ir 下标计数，结束后再汇总
+#[derive(Debug, Clone)]
+pub(crate) struct Counters {
+    pub ops: Vec<u64>,
+    pub entries: Vec<u64>,
+    pub iterations: Vec<u64>,
+}
+
+impl Counters {
+    pub fn new(len: usize) -> Self {
+        Self {
+            ops: vec![0; len],
+            entries: vec![0; len],
+            iterations: vec![0; len],
+        }
+    }
+}
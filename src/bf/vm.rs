@@ -5,24 +5,39 @@ use std::ptr;
 use dynasm::dynasm;
 use dynasmrt::{DynasmApi, DynasmLabelApi};
 
-use crate::bf::compile::{compile, optimize};
-use crate::bf::error::{Result, RuntimeError, VMError};
+use crate::bf::compile::{check, compile, optimize};
+use crate::bf::error::{vm_error, Result, RuntimeError, VMError};
 use crate::bf::ir::BfIR;
-
-const MEMORY_SIZE: usize = 4 * 1024 * 1024;
+use crate::bf::{BfVmOptions, CellWrap, Eof};
 
 pub struct BfVM<'io> {
     code: dynasmrt::ExecutableBuffer, // 汇编流
     start: dynasmrt::AssemblyOffset,  // 开始地址
+    offsets: Vec<usize>,              // 每条 ir 的机器码起始偏移
     memory: Box<[u8]>,                // 内存
+    eof: Eof,                         // 读到 EOF 时的行为
     input: Box<dyn Read + 'io>,       // 输入
     output: Box<dyn Write + 'io>,     // 输出
 }
 
-#[inline(always)]
-fn vm_error(re: RuntimeError) -> *mut VMError {
-    let e = Box::new(VMError::from(re));
-    Box::into_raw(e)
+// 机器码、入口与每条 ir 的起始偏移(最后一项为循环体结束位置)
+type Generated = (
+    dynasmrt::ExecutableBuffer,
+    dynasmrt::AssemblyOffset,
+    Vec<usize>,
+);
+
+// 生成代码调用的函数使用平台的 C 调用约定：x86-64 上为 sysv64，aarch64 上为 AAPCS64
+macro_rules! jit_fn {
+    ($(#[$attr:meta])* unsafe fn $name:ident($($arg:ident: $ty:ty),*) -> $ret:ty $body:block) => {
+        $(#[$attr])*
+        #[cfg(target_arch = "x86_64")]
+        unsafe extern "sysv64" fn $name($($arg: $ty),*) -> $ret $body
+
+        $(#[$attr])*
+        #[cfg(target_arch = "aarch64")]
+        unsafe extern "C" fn $name($($arg: $ty),*) -> $ret $body
+    };
 }
 
 impl<'io> BfVM<'io> {
@@ -30,35 +45,71 @@ impl<'io> BfVM<'io> {
         file_path: &Path,
         input: Box<dyn Read + 'io>,
         output: Box<dyn Write + 'io>,
-        optimized: bool,
+        options: BfVmOptions,
     ) -> Result<Self> {
         let src = std::fs::read_to_string(file_path)?;
-        let mut ir = compile(&src)?;
+        Self::from_source(&src, input, output, options)
+    }
+
+    pub fn from_source(
+        src: &str,
+        input: Box<dyn Read + 'io>,
+        output: Box<dyn Write + 'io>,
+        options: BfVmOptions,
+    ) -> Result<Self> {
+        let ir = compile(src)?;
+        Self::from_ir(ir, input, output, options)
+    }
 
-        if optimized {
-            optimize(&mut ir);
+    // 直接执行 ir，options.optimized 为 true 时同样会先优化
+    pub fn from_ir(
+        mut ir: Vec<BfIR>,
+        input: Box<dyn Read + 'io>,
+        output: Box<dyn Write + 'io>,
+        options: BfVmOptions,
+    ) -> Result<Self> {
+        options.check()?;
+        check(&ir)?;
+
+        if options.optimized {
+            optimize(&mut ir, options.cell_wrap);
         }
 
-        let (code, start) = Self::generate(&ir)?;
-        let memory = vec![0; MEMORY_SIZE].into_boxed_slice();
+        let (code, start, offsets) = Self::generate(&ir, options.cell_wrap)?;
+        let memory = vec![0; options.memory_size].into_boxed_slice();
 
         Ok(Self {
             code,
             start,
+            offsets,
             memory,
+            eof: options.eof,
             input,
             output,
         })
     }
 
+    #[cfg(target_arch = "x86_64")]
+    fn generate(code: &[BfIR], cell_wrap: CellWrap) -> Result<Generated> {
+        Self::generate_x64(code, cell_wrap)
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn generate(code: &[BfIR], cell_wrap: CellWrap) -> Result<Generated> {
+        Self::generate_aarch64(code, cell_wrap)
+    }
+
     // Checks for casts of a function pointer to a numeric type except usize.
+    #[cfg(target_arch = "x86_64")]
     #[allow(clippy::fn_to_numeric_cast)]
-    fn generate(code: &[BfIR]) -> Result<(dynasmrt::ExecutableBuffer, dynasmrt::AssemblyOffset)> {
+    fn generate_x64(code: &[BfIR], cell_wrap: CellWrap) -> Result<Generated> {
         let mut ops = dynasmrt::x64::Assembler::new()?;
         let start = ops.offset(); // 开始地址
 
         // 当作栈来使用
         let mut loops = vec![];
+        let mut offsets = Vec::with_capacity(code.len() + 1);
+        let check = cell_wrap == CellWrap::Error;
 
         // 下面是生成的汇编代码，并不是直接调用：
         // sysv64 调用约定规定 rdi, rsi, rdx, rcx 存放前四个整数参数，rax 存放返回值
@@ -70,7 +121,11 @@ impl<'io> BfVM<'io> {
         // memory_end:   rdx r14
         // ptr:          rcx r15
         dynasm!(ops
-            ; push rax       // 保存 rax 的值
+            ; push r12       // r12 - r15 由被调用者保存
+            ; push r13
+            ; push r14
+            ; push r15
+            ; push rax       // 保存 rax 的值，同时使栈 16 字节对齐
             ; mov r12, rdi   // save vm, r12 = rdi
             ; mov r13, rsi   // save memory_start
             ; mov r14, rdx   // save memory_end
@@ -79,6 +134,7 @@ impl<'io> BfVM<'io> {
 
         use BfIR::*;
         for &ir in code {
+            offsets.push(ops.offset().0);
             match ir {
                 AddPtr(x) => dynasm!(ops
                     ; add rcx, x as i32     // ptr += x
@@ -92,12 +148,26 @@ impl<'io> BfVM<'io> {
                     ; cmp rcx, r13          // ptr - memory_start
                     ; jb  ->overflow        // jmp if ptr < memory_start
                 ),
-                AddVal(x) => dynasm!(ops
-                    ; add BYTE [rcx], x as i8    // *ptr += x
-                ),
-                SubVal(x) => dynasm!(ops
-                    ; sub BYTE [rcx], x as i8    // *ptr -= x
-                ),
+                AddVal(x) => {
+                    dynasm!(ops
+                        ; add BYTE [rcx], x as i8    // *ptr += x
+                    );
+                    if check {
+                        dynasm!(ops
+                            ; jc ->cell_overflow     // jmp if *ptr > 255
+                        )
+                    }
+                }
+                SubVal(x) => {
+                    dynasm!(ops
+                        ; sub BYTE [rcx], x as i8    // *ptr -= x
+                    );
+                    if check {
+                        dynasm!(ops
+                            ; jc ->cell_overflow     // jmp if *ptr < 0
+                        )
+                    }
+                }
                 GetByte => dynasm!(ops
                     ; mov  r15, rcx         // save ptr
                     ; mov  rdi, r12
@@ -137,8 +207,45 @@ impl<'io> BfVM<'io> {
                         ; => right
                     )
                 }
+                SetZero => dynasm!(ops
+                    ; mov BYTE [rcx], 0     // *ptr = 0
+                ),
+                ScanLeft => dynasm!(ops
+                    ; scan:
+                    ; cmp BYTE [rcx], 0
+                    ; jz  >done             // jmp if *ptr == 0
+                    ; sub rcx, 1            // ptr -= 1
+                    ; cmp rcx, r13          // ptr - memory_start
+                    ; jb  ->overflow        // jmp if ptr < memory_start
+                    ; jmp <scan
+                    ; done:
+                ),
+                ScanRight => dynasm!(ops
+                    ; scan:
+                    ; cmp BYTE [rcx], 0
+                    ; jz  >done             // jmp if *ptr == 0
+                    ; add rcx, 1            // ptr += 1
+                    ; cmp rcx, r14          // ptr - memory_end
+                    ; jnb ->overflow        // jmp if ptr >= memory_end
+                    ; jmp <scan
+                    ; done:
+                ),
+                MulAdd(offset, factor) => dynasm!(ops
+                    ; movzx eax, BYTE [rcx] // eax = *ptr
+                    ; test eax, eax
+                    ; jz  >done             // 当前单元为 0 时循环不会执行
+                    ; imul eax, eax, factor as i32
+                    ; lea rdx, [rcx + offset as i32]
+                    ; cmp rdx, r13          // target - memory_start
+                    ; jb  ->overflow        // jmp if target < memory_start
+                    ; cmp rdx, r14          // target - memory_end
+                    ; jnb ->overflow        // jmp if target >= memory_end
+                    ; add BYTE [rdx], al    // *target += *ptr * factor
+                    ; done:
+                ),
             }
         }
+        offsets.push(ops.offset().0);
 
         dynasm!(ops
             ; xor rax, rax // rax = 0
@@ -147,29 +254,298 @@ impl<'io> BfVM<'io> {
             ; mov rax, QWORD BfVM::overflow_error as _
             ; call rax
             ; jmp >exit
+            ; -> cell_overflow: // 定义 cell_overflow
+            ; mov rax, QWORD BfVM::cell_overflow_error as *const () as _
+            ; call rax
+            ; jmp >exit
             ; -> io_error: // 定义 io_error
             ; exit:       // 定义 exit
             ; pop rdx
+            ; pop r15
+            ; pop r14
+            ; pop r13
+            ; pop r12
+            ; ret
+        );
+
+        let code = ops.finalize().unwrap();
+
+        Ok((code, start, offsets))
+    }
+
+    // 在非 aarch64 平台上只用于测试代码生成
+    #[cfg(any(test, target_arch = "aarch64"))]
+    fn generate_aarch64(code: &[BfIR], cell_wrap: CellWrap) -> Result<Generated> {
+        let mut ops = dynasmrt::aarch64::Assembler::new()?;
+        let start = ops.offset(); // 开始地址
+
+        // 当作栈来使用
+        let mut loops = vec![];
+        let mut offsets = Vec::with_capacity(code.len() + 1);
+        let check = cell_wrap == CellWrap::Error;
+
+        // AAPCS64 调用约定规定 x0 - x7 存放前八个整数参数，x0 存放返回值，x19 - x28 由被调用者保存
+        // vm:           x0 x19
+        // memory_start: x1 x20
+        // memory_end:   x2 x21
+        // ptr:             x22
+        dynasm!(ops
+            ; .arch aarch64
+            ; stp x29, x30, [sp, #-48]!   // 保存 fp, lr
+            ; mov x29, sp
+            ; stp x19, x20, [sp, #16]
+            ; stp x21, x22, [sp, #32]
+            ; mov x19, x0                 // save vm
+            ; mov x20, x1                 // save memory_start
+            ; mov x21, x2                 // save memory_end
+            ; mov x22, x1                 // ptr = memory_start
+        );
+
+        // 将函数地址分四段载入 x9
+        macro_rules! call {
+            ($func:expr) => {{
+                let addr = $func as *const () as u64;
+                dynasm!(ops
+                    ; .arch aarch64
+                    ; movz x9, #(addr & 0xffff) as u32
+                    ; movk x9, #((addr >> 16) & 0xffff) as u32, lsl #16
+                    ; movk x9, #((addr >> 32) & 0xffff) as u32, lsl #32
+                    ; movk x9, #((addr >> 48) & 0xffff) as u32, lsl #48
+                    ; blr x9
+                )
+            }};
+        }
+
+        use BfIR::*;
+        for &ir in code {
+            offsets.push(ops.offset().0);
+            match ir {
+                AddPtr(x) => dynasm!(ops
+                    ; .arch aarch64
+                    ; add x22, x22, #x as u32    // ptr += x
+                    ; cmp x22, x21               // ptr - memory_end
+                    ; b.hs ->overflow            // jmp if ptr >= memory_end
+                ),
+                SubPtr(x) => dynasm!(ops
+                    ; .arch aarch64
+                    ; sub x22, x22, #x as u32    // ptr -= x
+                    ; cmp x22, x20               // ptr - memory_start
+                    ; b.lo ->overflow            // jmp if ptr < memory_start
+                ),
+                AddVal(x) => {
+                    dynasm!(ops
+                        ; .arch aarch64
+                        ; ldrb w9, [x22]
+                        ; add w9, w9, #x as u32      // *ptr += x
+                    );
+                    if check {
+                        dynasm!(ops
+                            ; .arch aarch64
+                            ; cmp w9, #255
+                            ; b.hi ->cell_overflow   // jmp if *ptr > 255
+                        )
+                    }
+                    dynasm!(ops
+                        ; .arch aarch64
+                        ; strb w9, [x22]
+                    )
+                }
+                SubVal(x) => {
+                    dynasm!(ops
+                        ; .arch aarch64
+                        ; ldrb w9, [x22]
+                        ; subs w9, w9, #x as u32     // *ptr -= x
+                    );
+                    if check {
+                        dynasm!(ops
+                            ; .arch aarch64
+                            ; b.lo ->cell_overflow   // jmp if *ptr < 0
+                        )
+                    }
+                    dynasm!(ops
+                        ; .arch aarch64
+                        ; strb w9, [x22]
+                    )
+                }
+                GetByte => {
+                    dynasm!(ops
+                        ; .arch aarch64
+                        ; mov x0, x19
+                        ; mov x1, x22            // arg0: this, arg1: ptr
+                    );
+                    call!(BfVM::getbyte); // getbyte(this, ptr)
+                    dynasm!(ops
+                        ; .arch aarch64
+                        ; cbnz x0, ->io_error    // jmp if x0 != 0
+                    )
+                }
+                PutByte => {
+                    dynasm!(ops
+                        ; .arch aarch64
+                        ; mov x0, x19
+                        ; mov x1, x22            // arg0: this, arg1: ptr
+                    );
+                    call!(BfVM::putbyte); // putbyte(this, ptr)
+                    dynasm!(ops
+                        ; .arch aarch64
+                        ; cbnz x0, ->io_error    // jmp if x0 != 0
+                    )
+                }
+                Jz => {
+                    let left = ops.new_dynamic_label();
+                    let right = ops.new_dynamic_label();
+                    loops.push((left, right));
+
+                    dynasm!(ops
+                        ; .arch aarch64
+                        ; ldrb w9, [x22]
+                        ; cbz w9, => right       // jmp if *ptr == 0
+                        ; => left
+                    )
+                }
+                Jnz => {
+                    let (left, right) = loops.pop().unwrap();
+                    dynasm!(ops
+                        ; .arch aarch64
+                        ; ldrb w9, [x22]
+                        ; cbnz w9, => left       // jmp if *ptr != 0
+                        ; => right
+                    )
+                }
+                SetZero => dynasm!(ops
+                    ; .arch aarch64
+                    ; strb wzr, [x22]            // *ptr = 0
+                ),
+                ScanLeft => dynasm!(ops
+                    ; .arch aarch64
+                    ; scan:
+                    ; ldrb w9, [x22]
+                    ; cbz w9, >done              // jmp if *ptr == 0
+                    ; sub x22, x22, #1           // ptr -= 1
+                    ; cmp x22, x20               // ptr - memory_start
+                    ; b.lo ->overflow            // jmp if ptr < memory_start
+                    ; b <scan
+                    ; done:
+                ),
+                ScanRight => dynasm!(ops
+                    ; .arch aarch64
+                    ; scan:
+                    ; ldrb w9, [x22]
+                    ; cbz w9, >done              // jmp if *ptr == 0
+                    ; add x22, x22, #1           // ptr += 1
+                    ; cmp x22, x21               // ptr - memory_end
+                    ; b.hs ->overflow            // jmp if ptr >= memory_end
+                    ; b <scan
+                    ; done:
+                ),
+                MulAdd(offset, factor) => {
+                    dynasm!(ops
+                        ; .arch aarch64
+                        ; ldrb w9, [x22]         // w9 = *ptr
+                        ; cbz w9, >done          // 当前单元为 0 时循环不会执行
+                        ; movz w10, #factor as u32
+                        ; mul w9, w9, w10
+                    );
+                    // offset 不超过 4095 时可以直接编码为立即数，否则先载入 x10
+                    let abs = (offset as i32).unsigned_abs();
+                    if abs > 4095 {
+                        dynasm!(ops
+                            ; .arch aarch64
+                            ; movz x10, #abs
+                        )
+                    }
+                    match (offset >= 0, abs > 4095) {
+                        (true, false) => dynasm!(ops
+                            ; .arch aarch64
+                            ; add x11, x22, #abs
+                        ),
+                        (false, false) => dynasm!(ops
+                            ; .arch aarch64
+                            ; sub x11, x22, #abs
+                        ),
+                        (true, true) => dynasm!(ops
+                            ; .arch aarch64
+                            ; add x11, x22, x10
+                        ),
+                        (false, true) => dynasm!(ops
+                            ; .arch aarch64
+                            ; sub x11, x22, x10
+                        ),
+                    }
+                    dynasm!(ops
+                        ; .arch aarch64
+                        ; cmp x11, x20           // target - memory_start
+                        ; b.lo ->overflow        // jmp if target < memory_start
+                        ; cmp x11, x21           // target - memory_end
+                        ; b.hs ->overflow        // jmp if target >= memory_end
+                        ; ldrb w12, [x11]
+                        ; add w12, w12, w9       // *target += *ptr * factor
+                        ; strb w12, [x11]
+                        ; done:
+                    )
+                }
+            }
+        }
+        offsets.push(ops.offset().0);
+
+        dynasm!(ops
+            ; .arch aarch64
+            ; mov x0, xzr  // x0 = 0
+            ; b >exit      // jmp => exit
+            ; -> overflow: // 定义 overflow
+        );
+        call!(BfVM::overflow_error);
+        dynasm!(ops
+            ; .arch aarch64
+            ; b >exit
+            ; -> cell_overflow: // 定义 cell_overflow
+        );
+        call!(BfVM::cell_overflow_error);
+        dynasm!(ops
+            ; .arch aarch64
+            ; b >exit
+            ; -> io_error: // 定义 io_error
+            ; exit:        // 定义 exit
+            ; ldp x21, x22, [sp, #32]
+            ; ldp x19, x20, [sp, #16]
+            ; ldp x29, x30, [sp], #48
             ; ret
         );
 
         let code = ops.finalize().unwrap();
 
-        Ok((code, start))
+        Ok((code, start, offsets))
+    }
+
+    // 每条 ir 对应机器码的起始偏移，最后一项为循环体结束位置
+    pub fn code_offsets(&self) -> &[usize] {
+        &self.offsets
+    }
+
+    // 机器码总长度，包括入口与出口
+    pub fn code_len(&self) -> usize {
+        self.code.len()
     }
 
     pub fn run(&mut self) -> Result<()> {
+        #[cfg(target_arch = "x86_64")]
         type RawFn = unsafe extern "sysv64" fn(
             vm: *mut BfVM<'_>,
             memory_start: *mut u8,
             memory_end: *const u8,
         ) -> *mut VMError;
+        #[cfg(target_arch = "aarch64")]
+        type RawFn = unsafe extern "C" fn(
+            vm: *mut BfVM<'_>,
+            memory_start: *mut u8,
+            memory_end: *const u8,
+        ) -> *mut VMError;
         // 将内存重新解释为函数
         let raw_fn: RawFn = unsafe { std::mem::transmute(self.code.ptr(self.start)) };
 
         let vm: *mut Self = self;
         let memory_start = self.memory.as_mut_ptr();
-        let memory_end = unsafe { memory_start.add(MEMORY_SIZE) };
+        let memory_end = unsafe { memory_start.add(self.memory.len()) };
         let ret: *mut VMError = unsafe { raw_fn(vm, memory_start, memory_end) };
 
         if ret.is_null() {
@@ -179,31 +555,132 @@ impl<'io> BfVM<'io> {
         }
     }
 
-    // getbyte 读取字节
-    unsafe extern "sysv64" fn getbyte(vm: *mut Self, ptr: *mut u8) -> *mut VMError {
-        let mut buf = [0_u8];
-        let vm = &mut *vm;
-        match vm.input.read(&mut buf) {
-            Ok(0) => {}
-            Ok(1) => *ptr = buf[0],
-            Err(e) => return vm_error(RuntimeError::IO(e)),
-            _ => unreachable!(),
+    jit_fn! {
+        // getbyte 读取字节
+        unsafe fn getbyte(vm: *mut Self, ptr: *mut u8) -> *mut VMError {
+            let mut buf = [0_u8];
+            let vm = &mut *vm;
+            match vm.input.read(&mut buf) {
+                Ok(0) => vm.eof.apply(&mut *ptr),
+                Ok(1) => *ptr = buf[0],
+                Err(e) => return vm_error(RuntimeError::IO(e)),
+                _ => unreachable!(),
+            }
+            ptr::null_mut()
+        }
+    }
+
+    jit_fn! {
+        // putbyte 输出字节
+        unsafe fn putbyte(vm: *mut Self, ptr: *const u8) -> *mut VMError {
+            let buf = std::slice::from_ref(&*ptr);
+            let vm = &mut *vm;
+            match vm.output.write_all(buf) {
+                Ok(()) => ptr::null_mut(),
+                Err(e) => vm_error(RuntimeError::IO(e)),
+            }
+        }
+    }
+
+    jit_fn! {
+        // overflow_error 溢出
+        unsafe fn overflow_error() -> *mut VMError {
+            vm_error(RuntimeError::PointerOverflow)
         }
-        ptr::null_mut()
     }
 
-    // putbyte 输出字节
-    unsafe extern "sysv64" fn putbyte(vm: *mut Self, ptr: *const u8) -> *mut VMError {
-        let buf = std::slice::from_ref(&*ptr);
-        let vm = &mut *vm;
-        match vm.output.write_all(buf) {
-            Ok(()) => ptr::null_mut(),
-            Err(e) => vm_error(RuntimeError::IO(e)),
+    jit_fn! {
+        // cell_overflow_error 单元溢出
+        unsafe fn cell_overflow_error() -> *mut VMError {
+            vm_error(RuntimeError::CellOverflow)
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BfVM;
+    use crate::bf::compile::{compile, optimize};
+    use crate::bf::error::{RuntimeError, VMError};
+    use crate::bf::ir::BfIR;
+    use crate::bf::{BfVmOptions, CellWrap, Eof};
+
+    #[test]
+    fn test_generate_aarch64() {
+        let mut code = compile("++++++++[>++++++++<-]>+.,[-]<[<]>[>]+[->++<<+++>]").unwrap();
+        let (buffer, start, offsets) = BfVM::generate_aarch64(&code, CellWrap::Wrap).unwrap();
+        assert_eq!(start.0, 0);
+        assert_eq!(offsets.len(), code.len() + 1);
+        assert!(offsets.windows(2).all(|w| w[0] < w[1]));
+        // aarch64 指令定长 4 字节
+        assert_eq!(buffer.len() % 4, 0);
+
+        let (checked, ..) = BfVM::generate_aarch64(&code, CellWrap::Error).unwrap();
+        assert!(checked.len() > buffer.len());
+
+        optimize(&mut code, CellWrap::Wrap);
+        let (optimized, ..) = BfVM::generate_aarch64(&code, CellWrap::Wrap).unwrap();
+        assert!(optimized.len() < buffer.len());
+
+        let code = [BfIR::MulAdd(-5000, 2), BfIR::MulAdd(5000, 3)];
+        assert!(BfVM::generate_aarch64(&code, CellWrap::Wrap).is_ok());
+    }
 
-    // overflow_error 溢出
-    unsafe extern "sysv64" fn overflow_error() -> *mut VMError {
-        vm_error(RuntimeError::PointerOverflow)
+    #[test]
+    fn test_vm() {
+        let mut output = vec![];
+        let src = "++++++++[>++++++++<-]>+.,+.>>+++++[->++>+++<<]>.>.";
+        for optimized in [false, true] {
+            output.clear();
+            let options = BfVmOptions {
+                optimized,
+                ..Default::default()
+            };
+            let mut vm =
+                BfVM::from_source(src, Box::new(&b"a"[..]), Box::new(&mut output), options)
+                    .unwrap();
+            vm.run().unwrap();
+            drop(vm);
+            assert_eq!(output, [b'A', b'b', 10, 15]);
+        }
+
+        let code = vec![
+            BfIR::AddVal(3),
+            BfIR::MulAdd(2, 4),
+            BfIR::AddPtr(2),
+            BfIR::PutByte,
+        ];
+        let options = BfVmOptions::default();
+        let mut vm =
+            BfVM::from_ir(code, Box::new(&b""[..]), Box::new(&mut output), options).unwrap();
+        vm.run().unwrap();
+        drop(vm);
+        assert_eq!(output.last(), Some(&12));
+
+        let code = vec![BfIR::Jnz];
+        match BfVM::from_ir(code, Box::new(&b""[..]), Box::new(vec![]), options) {
+            Err(VMError::Compile(_)) => {}
+            _ => panic!(),
+        }
+
+        let mut vm = BfVM::from_source("<", Box::new(&b""[..]), Box::new(vec![]), options).unwrap();
+        match vm.run() {
+            Err(VMError::Runtime(RuntimeError::PointerOverflow)) => {}
+            _ => panic!(),
+        }
+
+        for (eof, value) in [(Eof::Unchanged, 3), (Eof::Zero, 0), (Eof::Max, 255)] {
+            let mut output = vec![];
+            let options = BfVmOptions {
+                eof,
+                ..Default::default()
+            };
+            let mut vm =
+                BfVM::from_source("+++,.", Box::new(&b""[..]), Box::new(&mut output), options)
+                    .unwrap();
+            vm.run().unwrap();
+            drop(vm);
+            assert_eq!(output, [value]);
+        }
     }
 }
@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+use std::error::Error;
+
+use lsp_server::{Connection, ExtractError, Message, Notification, Request, RequestId, Response};
+use lsp_types::notification::{
+    DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _,
+    PublishDiagnostics,
+};
+use lsp_types::request::{DocumentSymbolRequest, HoverRequest};
+use lsp_types::{
+    Diagnostic, DiagnosticSeverity, DocumentSymbol, DocumentSymbolResponse, Hover, HoverContents,
+    HoverProviderCapability, MarkedString, OneOf, Position, PublishDiagnosticsParams, Range,
+    ServerCapabilities, SymbolKind, TextDocumentSyncCapability, TextDocumentSyncKind, Url,
+};
+use plua::diagnostic::{self, Severity};
+use plua::resolver::Resolver;
+use plua::scanner::{Scanner, Token, TokenType};
+use plua::statement::Stmt;
+use plua::{error, parser::Parser};
+
+// 基于 scanner/parser/resolver 的 language server，通过 stdio 通信
+fn main() -> Result<(), Box<dyn Error + Sync + Send>> {
+    let (connection, io_threads) = Connection::stdio();
+
+    let capabilities = serde_json::to_value(ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+        document_symbol_provider: Some(OneOf::Left(true)),
+        hover_provider: Some(HoverProviderCapability::Simple(true)),
+        ..Default::default()
+    })?;
+    connection.initialize(capabilities)?;
+    main_loop(connection)?;
+    io_threads.join()?;
+    Ok(())
+}
+
+fn main_loop(connection: Connection) -> Result<(), Box<dyn Error + Sync + Send>> {
+    // 打开的文档，每次修改都是全量同步
+    let mut documents: HashMap<Url, String> = HashMap::new();
+
+    for msg in &connection.receiver {
+        match msg {
+            Message::Request(req) => {
+                if connection.handle_shutdown(&req)? {
+                    return Ok(());
+                }
+                let (id, result) = handle_request(&documents, req)?;
+                connection
+                    .sender
+                    .send(Message::Response(Response::new_ok(id, result)))?;
+            }
+            Message::Notification(not) => {
+                let uri = match not.method.as_str() {
+                    DidOpenTextDocument::METHOD => {
+                        let params: lsp_types::DidOpenTextDocumentParams =
+                            serde_json::from_value(not.params)?;
+                        let uri = params.text_document.uri;
+                        documents.insert(uri.clone(), params.text_document.text);
+                        uri
+                    }
+                    DidChangeTextDocument::METHOD => {
+                        let params: lsp_types::DidChangeTextDocumentParams =
+                            serde_json::from_value(not.params)?;
+                        let uri = params.text_document.uri;
+                        if let Some(change) = params.content_changes.into_iter().last() {
+                            documents.insert(uri.clone(), change.text);
+                        }
+                        uri
+                    }
+                    DidCloseTextDocument::METHOD => {
+                        let params: lsp_types::DidCloseTextDocumentParams =
+                            serde_json::from_value(not.params)?;
+                        documents.remove(&params.text_document.uri);
+                        continue;
+                    }
+                    _ => continue,
+                };
+                let text = documents.get(&uri).map(String::as_str).unwrap_or_default();
+                let params = PublishDiagnosticsParams::new(uri, diagnostics(text), None);
+                connection
+                    .sender
+                    .send(Message::Notification(Notification::new(
+                        PublishDiagnostics::METHOD.to_string(),
+                        params,
+                    )))?;
+            }
+            Message::Response(_) => {}
+        }
+    }
+    Ok(())
+}
+
+fn handle_request(
+    documents: &HashMap<Url, String>,
+    req: Request,
+) -> Result<(RequestId, serde_json::Value), Box<dyn Error + Sync + Send>> {
+    let req = match cast::<DocumentSymbolRequest>(req) {
+        Ok((id, params)) => {
+            let text = documents.get(&params.text_document.uri);
+            let symbols = text
+                .and_then(|text| parse(text).ok())
+                .map(|stmts| DocumentSymbolResponse::Nested(symbols(&stmts)));
+            return Ok((id, serde_json::to_value(symbols)?));
+        }
+        Err(req) => req?,
+    };
+    let req = match cast::<HoverRequest>(req) {
+        Ok((id, params)) => {
+            let position = params.text_document_position_params;
+            let text = documents.get(&position.text_document.uri);
+            let hover = text.and_then(|text| hover(text, position.position));
+            return Ok((id, serde_json::to_value(hover)?));
+        }
+        Err(req) => req?,
+    };
+    Ok((req.id, serde_json::Value::Null))
+}
+
+fn cast<R>(req: Request) -> Result<(RequestId, R::Params), Result<Request, ExtractError<Request>>>
+where
+    R: lsp_types::request::Request,
+{
+    match req.extract(R::METHOD) {
+        Ok(it) => Ok(it),
+        Err(ExtractError::MethodMismatch(req)) => Err(Ok(req)),
+        Err(e) => Err(Err(e)),
+    }
+}
+
+fn parse(text: &str) -> Result<Vec<Stmt>, error::Error> {
+    let mut scanner = Scanner::new(text.to_string());
+    scanner.scan_tokens()?;
+    let mut parser = Parser::new(scanner.take_tokens());
+    parser.parse()
+}
+
+fn error_to_lsp(e: &error::Error, text: &str) -> Diagnostic {
+    let mut result = to_lsp(&diagnostic::Diagnostic::from(e), text);
+    result.message = e.to_string();
+    result
+}
+
+// 报告所有的词法错误，语法错误只报告第一个，通过后再报告 resolver 的检查结果
+fn diagnostics(text: &str) -> Vec<Diagnostic> {
+    let (tokens, errors) = Scanner::new(text.to_string()).scan_all();
+    if !errors.is_empty() {
+        return errors.iter().map(|e| error_to_lsp(e, text)).collect();
+    }
+    let (stmts, errors) = Parser::new(tokens).parse_all();
+    if !errors.is_empty() {
+        return errors.iter().map(|e| error_to_lsp(e, text)).collect();
+    }
+
+    let mut resolver = Resolver::default();
+    resolver
+        .lint(&stmts)
+        .iter()
+        .map(|lint| to_lsp(&diagnostic::Diagnostic::from(lint), text))
+        .collect()
+}
+
+// 范围取诊断的主要位置，没有位置时标记文档开头
+fn to_lsp(d: &diagnostic::Diagnostic, text: &str) -> Diagnostic {
+    let range = match d.segments(text).next() {
+        Some(segment) => {
+            let line = segment.line as u32 - 1;
+            let col = segment.col as u32 - 1;
+            Range::new(
+                Position::new(line, col),
+                Position::new(line, col + segment.len as u32),
+            )
+        }
+        None => Range::new(Position::new(0, 0), Position::new(0, 1)),
+    };
+    let severity = match d.severity {
+        Severity::Warning => DiagnosticSeverity::WARNING,
+        Severity::Error => DiagnosticSeverity::ERROR,
+    };
+    diagnostic(range, severity, d.message.clone())
+}
+
+fn diagnostic(range: Range, severity: DiagnosticSeverity, message: String) -> Diagnostic {
+    Diagnostic {
+        range,
+        severity: Some(severity),
+        source: Some("plua".to_string()),
+        message,
+        ..Default::default()
+    }
+}
+
+// 文档中的函数与局部变量，函数内的声明作为子节点
+fn symbols(stmts: &[Stmt]) -> Vec<DocumentSymbol> {
+    let mut result = vec![];
+    for stmt in stmts {
+        match stmt {
+            Stmt::LocalStmt(name, _) => {
+                result.push(symbol(name, SymbolKind::VARIABLE, None, vec![]))
+            }
+            Stmt::LocalListStmt(names, _) => result.extend(
+                names
+                    .iter()
+                    .map(|name| symbol(name, SymbolKind::VARIABLE, None, vec![])),
+            ),
+            Stmt::FunctionStmt(name, params, body) => {
+                let mut children: Vec<_> = params
+                    .iter()
+                    .map(|p| symbol(p, SymbolKind::VARIABLE, Some("parameter"), vec![]))
+                    .collect();
+                children.extend(symbols(body));
+                let detail = signature(name, params);
+                result.push(symbol(
+                    name,
+                    SymbolKind::FUNCTION,
+                    Some(detail.as_str()),
+                    children,
+                ));
+            }
+            Stmt::IfStmt(_, then_branch, else_branch) => {
+                result.extend(symbols(std::slice::from_ref(then_branch.as_ref())));
+                result.extend(symbols(std::slice::from_ref(else_branch.as_ref())));
+            }
+            Stmt::WhileStmt(_, stmts) | Stmt::RepeatStmt(stmts, _) | Stmt::Block(stmts) => {
+                result.extend(symbols(stmts))
+            }
+            _ => {}
+        }
+    }
+    result
+}
+
+#[allow(deprecated)]
+fn symbol(
+    name: &Token,
+    kind: SymbolKind,
+    detail: Option<&str>,
+    children: Vec<DocumentSymbol>,
+) -> DocumentSymbol {
+    let range = token_range(name);
+    DocumentSymbol {
+        name: name.raw.to_string(),
+        detail: detail.map(str::to_string),
+        kind,
+        tags: None,
+        deprecated: None,
+        range,
+        selection_range: range,
+        children: Some(children),
+    }
+}
+
+fn token_range(token: &Token) -> Range {
+    let line = token.line as u32 - 1;
+    let col = token.col as u32 - 1;
+    let len = token.raw.chars().count() as u32;
+    Range::new(Position::new(line, col), Position::new(line, col + len))
+}
+
+fn signature(name: &Token, params: &[Token]) -> String {
+    let params: Vec<_> = params.iter().map(|p| p.raw.as_ref()).collect();
+    format!("function {}({})", name.raw, params.join(", "))
+}
+
+// 光标处标识符的声明，取光标之前最近的一处声明
+fn hover(text: &str, position: Position) -> Option<Hover> {
+    let mut scanner = Scanner::new(text.to_string());
+    let tokens = scanner.scan_tokens().ok()?;
+    let token = tokens.iter().find(|t| {
+        let range = token_range(t);
+        t.typ == TokenType::Identifier
+            && range.start.line == position.line
+            && range.start.character <= position.character
+            && position.character < range.end.character
+    })?;
+
+    let stmts = parse(text).ok()?;
+    let mut declarations = vec![];
+    declarations_in(&stmts, &mut declarations);
+    let (_, detail) = declarations
+        .into_iter()
+        .filter(|(decl, _)| {
+            decl.raw == token.raw && (decl.line, decl.col) <= (token.line, token.col)
+        })
+        .max_by_key(|(decl, _)| (decl.line, decl.col))?;
+
+    Some(Hover {
+        contents: HoverContents::Scalar(MarkedString::LanguageString(lsp_types::LanguageString {
+            language: "lua".to_string(),
+            value: detail,
+        })),
+        range: Some(token_range(token)),
+    })
+}
+
+fn declarations_in<'a>(stmts: &'a [Stmt], declarations: &mut Vec<(&'a Token, String)>) {
+    for stmt in stmts {
+        match stmt {
+            Stmt::LocalStmt(name, _) => declarations.push((name, format!("local {}", name.raw))),
+            Stmt::LocalListStmt(names, _) => {
+                for name in names {
+                    declarations.push((name, format!("local {}", name.raw)));
+                }
+            }
+            Stmt::FunctionStmt(name, params, body) => {
+                declarations.push((name, signature(name, params)));
+                for param in params {
+                    declarations
+                        .push((param, format!("{} -- parameter of {}", param.raw, name.raw)));
+                }
+                declarations_in(body, declarations);
+            }
+            Stmt::IfStmt(_, then_branch, else_branch) => {
+                declarations_in(std::slice::from_ref(then_branch.as_ref()), declarations);
+                declarations_in(std::slice::from_ref(else_branch.as_ref()), declarations);
+            }
+            Stmt::WhileStmt(_, stmts) | Stmt::RepeatStmt(stmts, _) | Stmt::Block(stmts) => {
+                declarations_in(stmts, declarations)
+            }
+            _ => {}
+        }
+    }
+}
@@ -1,21 +1,72 @@
 #![allow(dead_code)]
 
-use std::{fs, path::PathBuf};
+use std::fs;
+use std::io::{BufWriter, IsTerminal, Read};
+use std::panic;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
 
+use plua::ast::{pretty, to_json, to_sexpr, to_source};
+use plua::diagnostic::{Diagnostic, Severity};
+use plua::dump::{dump, undump};
+use plua::emitter::Emitter;
+use plua::error::Error;
+#[cfg(feature = "jit")]
+use plua::jit::JIT;
+use plua::resolver::{LintLevel, Resolver};
+use plua::statement::Stmt;
+use plua::trace::{Traceback, Tracer};
+use plua::transpile::transpile;
+use plua::value::{Table, Value};
+use plua::vm::{Limits, Stats, VM};
 use plua::{intercepter::Intercepter, parser::Parser, scanner::Scanner};
+use serde_json::json;
+use structopt::clap;
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "tinylua", about = "A tiny language compiler <😆>.")]
 struct Opt {
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
+
     /// Activate debug mode
     // short and long flags (-d, --debug) will be deduced from the field's name
     #[structopt(short, long)]
     debug: bool,
 
-    /// Input file
+    /// Print the elapsed time after a run
+    #[structopt(long, global = true)]
+    time: bool,
+
+    /// Print instructions executed, function calls and peak stack depth after a run
+    #[structopt(long, global = true)]
+    stats: bool,
+
+    /// Write an execution trace to this file
+    #[structopt(long, global = true, parse(from_os_str))]
+    trace: Option<PathBuf>,
+
+    /// Abort with exit code 3 after executing this many steps
+    #[structopt(long, global = true)]
+    max_steps: Option<usize>,
+
+    /// Abort with exit code 3 when live values take more than this many bytes
+    #[structopt(long, global = true)]
+    max_memory: Option<usize>,
+
+    /// Track heap objects held by scopes and fail when some are still alive but unreachable at exit
+    #[structopt(long)]
+    leak_check: bool,
+
+    /// Do not print compile warnings before running or compiling
+    #[structopt(long, global = true)]
+    no_warnings: bool,
+
+    /// Input file, `-` to read the script from stdin
     #[structopt(parse(from_os_str))]
-    input: PathBuf,
+    input: Option<PathBuf>,
 
     /// Output file, stdout if not present
     #[structopt(parse(from_os_str))]
@@ -28,38 +79,690 @@ struct Opt {
     /// File name: only required when `out-type` is set to `file`
     #[structopt(name = "FILE", required_if("out-type", "file"))]
     file_name: Option<String>,
+
+    /// Script arguments after `--`, exposed to the script as `arg`
+    #[structopt(last = true)]
+    args: Vec<String>,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Compile a script into precompiled bytecode
+    Compile {
+        /// Input file, `-` to read the script from stdin
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+
+        /// Bytecode output file
+        #[structopt(short, long, parse(from_os_str))]
+        output: PathBuf,
+    },
+    /// Run precompiled bytecode on the vm
+    Run {
+        /// Bytecode file
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+
+        /// Script arguments after `--`, exposed to the script as `arg`
+        #[structopt(last = true)]
+        args: Vec<String>,
+    },
+    /// Print the tokens of a script
+    Dump {
+        /// Input file, `-` to read the script from stdin
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+
+        /// Output format: `text` or `json`
+        #[structopt(long = "output", default_value = "text", possible_values = &["text", "json"])]
+        format: Format,
+    },
+    /// Print a script in canonical source format
+    Fmt {
+        /// Input file, `-` to read the script from stdin
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+    },
+    /// Print a script as Lua 5.4 source, to compare against the reference interpreter
+    Transpile {
+        /// Input file, `-` to read the script from stdin
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+    },
+    /// Print the syntax tree of a script
+    Ast {
+        /// Input file, `-` to read the script from stdin
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+
+        /// Output format: `json`, an indented `tree` or `sexpr`
+        #[structopt(long = "output", default_value = "json", possible_values = &["json", "tree", "sexpr"])]
+        format: AstFormat,
+    },
+    /// Report undefined variables, unused locals, shadowing and unreachable code
+    Lint {
+        /// Input file, `-` to read the script from stdin
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+
+        /// Exit with failure on warnings too
+        #[structopt(long)]
+        warnings_as_errors: bool,
+
+        /// Output format: `text` or `json`
+        #[structopt(long = "output", default_value = "text", possible_values = &["text", "json"])]
+        format: Format,
+    },
+    /// Run a script under the profiler and report time spent per function
+    Profile {
+        /// Input file, `-` to read the script from stdin
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+
+        /// Also write folded stacks for flamegraph tooling to this file
+        #[structopt(long, parse(from_os_str))]
+        folded: Option<PathBuf>,
+    },
+    /// Run a script and report which lines executed in lcov format
+    Coverage {
+        /// Input file, `-` to read the script from stdin
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+
+        /// Write the lcov report to this file instead of stdout
+        #[structopt(short, long, parse(from_os_str))]
+        output: Option<PathBuf>,
+    },
+    /// Run a script repeatedly under every engine and compare them
+    Bench {
+        /// Input file, `-` to read the script from stdin
+        #[structopt(parse(from_os_str))]
+        input: PathBuf,
+
+        /// How many times each engine runs the script
+        #[structopt(short = "n", long, default_value = "10")]
+        iterations: u32,
+
+        /// Output format: `text` or `json`
+        #[structopt(long = "output", default_value = "text", possible_values = &["text", "json"])]
+        format: Format,
+    },
+}
+
+// 子命令的输出格式，json 供编辑器与 CI 使用
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Text,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("unknown output format {}", s)),
+        }
+    }
+}
+
+// 语法树的输出格式
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum AstFormat {
+    Json,
+    Tree,
+    Sexpr,
+}
+
+impl FromStr for AstFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(AstFormat::Json),
+            "tree" => Ok(AstFormat::Tree),
+            "sexpr" => Ok(AstFormat::Sexpr),
+            _ => Err(format!("unknown output format {}", s)),
+        }
+    }
 }
 
 fn main() {
     let opt = Opt::from_args();
 
-    let debug = opt.debug;
-    let script = fs::read_to_string(opt.input).expect("could not read file");
+    // 出错时用于定位的脚本路径与源码，字节码文件没有源码
+    let (input, script) = match &opt.cmd {
+        Some(Command::Compile { input, .. })
+        | Some(Command::Dump { input, .. })
+        | Some(Command::Fmt { input })
+        | Some(Command::Transpile { input })
+        | Some(Command::Ast { input, .. })
+        | Some(Command::Lint { input, .. })
+        | Some(Command::Profile { input, .. })
+        | Some(Command::Coverage { input, .. })
+        | Some(Command::Bench { input, .. }) => (input.clone(), Some(load(input))),
+        Some(Command::Run { input, .. }) => (input.clone(), None),
+        None => {
+            let input = opt.input.clone().unwrap_or_else(|| {
+                clap::Error::with_description(
+                    "The following required arguments were not provided: <input>",
+                    clap::ErrorKind::MissingRequiredArgument,
+                )
+                .exit()
+            });
+            let script = load(&input);
+            (input, Some(script))
+        }
+    };
+
+    let limits = Limits {
+        max_steps: opt.max_steps,
+        max_memory: opt.max_memory,
+    };
+
+    // 编译警告输出到 stderr，与错误分开，不影响退出码
+    let file = input.display().to_string();
+    let color = std::io::stderr().is_terminal();
+    let warn = |warnings: Vec<Diagnostic>| {
+        if !opt.no_warnings {
+            for warning in warnings {
+                eprint!("{}", warning.render(&file, script.as_deref(), color));
+            }
+        }
+    };
+
+    // 解释器出错时的调用栈；字节码中没有行号表，run 子命令在 vm 上出错时不输出
+    let mut traceback = None;
+    let result = match opt.cmd {
+        Some(Command::Compile { ref output, .. }) => {
+            compile(script.clone().unwrap(), output, &warn).map(|_| 0)
+        }
+        Some(Command::Run {
+            ref input,
+            ref args,
+        }) => report_run(opt.time, opt.stats, || {
+            run(input, args, limits, tracer(opt.trace.as_deref())?)
+        })
+        .map(|v| exit_code(&v)),
+        Some(Command::Dump { format, .. }) => {
+            dump_tokens(script.clone().unwrap(), format).map(|_| 0)
+        }
+        Some(Command::Fmt { .. }) => fmt(script.clone().unwrap()).map(|_| 0),
+        Some(Command::Transpile { .. }) => to_lua(script.clone().unwrap()).map(|_| 0),
+        Some(Command::Ast { format, .. }) => ast(script.clone().unwrap(), format).map(|_| 0),
+        Some(Command::Lint {
+            warnings_as_errors,
+            format,
+            ..
+        }) => lint(&input, script.clone().unwrap(), warnings_as_errors, format),
+        Some(Command::Profile { ref folded, .. }) => {
+            profile(script.clone().unwrap(), folded.as_deref()).map(|_| 0)
+        }
+        Some(Command::Coverage { ref output, .. }) => {
+            coverage(&input, script.clone().unwrap(), output.as_deref()).map(|_| 0)
+        }
+        Some(Command::Bench {
+            iterations, format, ..
+        }) => bench(script.clone().unwrap(), iterations, format).map(|_| 0),
+        None => report_run(opt.time, opt.stats, || {
+            eval(script.clone().unwrap(), &opt, limits, &mut traceback, &warn)
+        })
+        .map(|v| exit_code(&v)),
+    };
 
+    let ret = match result {
+        Ok(code) => code,
+        Err(e) => {
+            report(&input, script.as_deref(), &e);
+            if let Some(traceback) = traceback {
+                eprint!("{}", traceback.render(&input.display().to_string()));
+            }
+            match e {
+                Error::LimitError(_) => 3,
+                _ => 1,
+            }
+        }
+    };
+    std::process::exit(ret);
+}
+
+// 输出错误，有位置信息时附带出错的源码行，终端下带颜色
+fn report(input: &Path, script: Option<&str>, e: &Error) {
+    let color = std::io::stderr().is_terminal();
+    let diagnostic = Diagnostic::from(e);
+    eprint!(
+        "{}",
+        diagnostic.render(&input.display().to_string(), script, color)
+    );
+}
+
+// 运行脚本，按需在 stderr 输出耗时与运行统计
+fn report_run<F>(time: bool, stats: bool, f: F) -> Result<Value, Error>
+where
+    F: FnOnce() -> Result<(Value, Stats), Error>,
+{
+    let start = Instant::now();
+    let (value, run_stats) = f()?;
+    let elapsed = start.elapsed();
+    if time {
+        eprintln!("time: {:.3}ms", elapsed.as_secs_f64() * 1e3);
+    }
+    if stats {
+        eprintln!("instructions: {}", run_stats.instructions);
+        eprintln!("calls: {}", run_stats.calls);
+        eprintln!("peak stack: {}", run_stats.max_stack);
+    }
+    Ok(value)
+}
+
+fn eval(
+    script: String,
+    opt: &Opt,
+    limits: Limits,
+    traceback: &mut Option<Traceback>,
+    warn: &dyn Fn(Vec<Diagnostic>),
+) -> Result<(Value, Stats), Error> {
     let mut scanner = Scanner::new(script);
-    let tokens = scanner.scan_tokens().unwrap();
-    if debug {
+    let tokens = scanner.scan_tokens()?;
+    if opt.debug {
         println!("{:?}", tokens);
     }
 
-    let mut parser = Parser::new(tokens.clone());
-    let statements = parser.parse().unwrap();
-    if debug {
-        println!("{:?}", statements);
+    let mut parser = Parser::new(scanner.take_tokens());
+    let statements = parser.parse()?;
+    if opt.debug {
+        print!("{}", pretty(&statements));
     }
+    warn(warnings(&mut scanner, &statements));
 
     let mut intercepter = Intercepter::new();
+    intercepter.define_global("arg", script_args(&opt.args));
+    intercepter.set_limits(limits);
+    if let Some(tracer) = tracer(opt.trace.as_deref())? {
+        intercepter.set_tracer(tracer);
+    }
+    if opt.leak_check {
+        intercepter.enable_leak_check();
+    }
+    let value = intercepter.eval(&statements);
+    *traceback = intercepter.take_traceback();
+    let value = value?;
+    intercepter.check_leaks()?;
+    Ok((value, intercepter.stats().clone()))
+}
+
+fn parse(script: String) -> Result<(Vec<Stmt>, Vec<Diagnostic>), Error> {
+    let mut scanner = Scanner::new(script);
+    scanner.scan_tokens()?;
+    let mut parser = Parser::new(scanner.take_tokens());
+    let statements = parser.parse()?;
+    let warnings = warnings(&mut scanner, &statements);
+    Ok((statements, warnings))
+}
+
+// 编译期的警告：词法分析的警告与静态检查中的警告，静态检查的错误留给运行时报告
+fn warnings(scanner: &mut Scanner, statements: &[Stmt]) -> Vec<Diagnostic> {
+    let mut warnings = scanner.take_warnings();
+    let lints = Resolver::default().lint(statements);
+    warnings.extend(
+        lints
+            .iter()
+            .filter(|lint| lint.level == LintLevel::Warning)
+            .map(Diagnostic::from),
+    );
+    warnings
+}
+
+// 编译脚本为字节码文件
+fn compile(script: String, output: &Path, warn: &dyn Fn(Vec<Diagnostic>)) -> Result<(), Error> {
+    let (statements, warnings) = parse(script)?;
+    warn(warnings);
+    let mut emitter = Emitter::default();
+    let funcs = emitter.emit_all(&statements)?;
+    let bytes = dump(funcs)?;
+    fs::write(output, bytes).map_err(|e| io_error(output, e))?;
+    Ok(())
+}
+
+// 在 vm 上运行字节码文件，跳过词法、语法分析与字节码生成
+fn run(
+    input: &Path,
+    args: &[String],
+    limits: Limits,
+    tracer: Option<Tracer>,
+) -> Result<(Value, Stats), Error> {
+    let bytes = fs::read(input).map_err(|e| io_error(input, e))?;
+    let funcs = undump(&bytes)?;
+    let mut vm = VM::new_with_funcs(funcs);
+    vm.define_global("arg", script_args(args));
+    vm.set_limits(limits);
+    if let Some(tracer) = tracer {
+        vm.set_tracer(tracer);
+    }
+    let value = vm.eval_all()?;
+    Ok((value, vm.stats().clone()))
+}
+
+// 格式化脚本并输出到 stdout
+fn fmt(script: String) -> Result<(), Error> {
+    let (statements, _) = parse(script)?;
+    print!("{}", to_source(&statements));
+    Ok(())
+}
+
+fn to_lua(script: String) -> Result<(), Error> {
+    let (statements, _) = parse(script)?;
+    print!("{}", transpile(&statements)?);
+    Ok(())
+}
+
+// 输出语法树，json 可以用 plua::ast::from_json 读回
+fn ast(script: String, format: AstFormat) -> Result<(), Error> {
+    let (statements, _) = parse(script)?;
+    match format {
+        AstFormat::Json => println!("{}", to_json(&statements)?),
+        AstFormat::Tree => print!("{}", pretty(&statements)),
+        AstFormat::Sexpr => print!("{}", to_sexpr(&statements)),
+    }
+    Ok(())
+}
+
+// 静态检查脚本，有错误时退出码为 1
+fn lint(
+    input: &Path,
+    script: String,
+    warnings_as_errors: bool,
+    format: Format,
+) -> Result<i32, Error> {
+    let mut scanner = Scanner::new(script.clone());
+    scanner.scan_tokens()?;
+    let statements = Parser::new(scanner.take_tokens()).parse()?;
+    let mut resolver = Resolver::default();
+    let lints = resolver.lint(&statements);
+
+    let file = input.display().to_string();
+    let color = std::io::stdout().is_terminal();
+    let mut failed = false;
+    let mut diagnostics = vec![];
+    let all = scanner
+        .take_warnings()
+        .into_iter()
+        .chain(lints.iter().map(Diagnostic::from));
+    for diagnostic in all {
+        match format {
+            Format::Text => print!("{}", diagnostic.render(&file, Some(&script), color)),
+            Format::Json => diagnostics.push(diagnostic.to_json(&file, Some(&script))),
+        }
+        failed |= diagnostic.severity == Severity::Error || warnings_as_errors;
+    }
+    if format == Format::Json {
+        println!("{}", serde_json::Value::Array(diagnostics));
+    }
+    Ok(failed as i32)
+}
+
+// 输出脚本的 token 序列
+fn dump_tokens(script: String, format: Format) -> Result<(), Error> {
+    let mut scanner = Scanner::new(script);
+    let tokens = scanner.scan_tokens()?;
+    match format {
+        Format::Text => {
+            for token in tokens {
+                println!(
+                    "{}:{}\t{:?}\t{}",
+                    token.line, token.col, token.typ, token.raw
+                );
+            }
+        }
+        Format::Json => {
+            let tokens: Vec<_> = tokens
+                .iter()
+                .map(|token| {
+                    json!({
+                        "type": format!("{:?}", token.typ),
+                        "raw": token.raw,
+                        "line": token.line,
+                        "col": token.col,
+                    })
+                })
+                .collect();
+            println!("{}", serde_json::Value::Array(tokens));
+        }
+    }
+    Ok(())
+}
+
+// 在解释器上运行脚本，输出每个函数的调用次数、自身耗时与总耗时
+fn profile(script: String, folded: Option<&Path>) -> Result<(), Error> {
+    let (statements, _) = parse(script)?;
+    let mut intercepter = Intercepter::new();
+    intercepter.enable_profiler();
     let result = intercepter.eval(&statements);
+    let profiler = intercepter.take_profiler().unwrap();
+    result?;
 
-    let ret = match result {
-        Ok(v) => {
-            println!("{:?}", v);
-            0
+    eprintln!(
+        "{:20} {:>8} {:>12} {:>12}",
+        "function", "calls", "self(ms)", "total(ms)"
+    );
+    for (name, func) in profiler.report() {
+        eprintln!(
+            "{:20} {:>8} {:>12.3} {:>12.3}",
+            name,
+            func.calls,
+            func.self_time.as_secs_f64() * 1e3,
+            func.total_time.as_secs_f64() * 1e3
+        );
+    }
+    if let Some(folded) = folded {
+        fs::write(folded, profiler.folded()).map_err(|e| io_error(folded, e))?;
+    }
+    Ok(())
+}
+
+// 运行脚本并输出 lcov 格式的行覆盖率，脚本出错时也输出已执行的部分
+fn coverage(input: &Path, script: String, output: Option<&Path>) -> Result<(), Error> {
+    let (statements, _) = parse(script)?;
+    let mut intercepter = Intercepter::new();
+    intercepter.enable_coverage();
+    let result = intercepter.eval(&statements);
+    let coverage = intercepter.take_coverage().unwrap();
+
+    let report = coverage.lcov(&input.display().to_string());
+    match output {
+        Some(output) => fs::write(output, report).map_err(|e| io_error(output, e))?,
+        None => print!("{}", report),
+    }
+    eprintln!("lines: {}/{}", coverage.lines_hit(), coverage.lines().len());
+    result.map(|_| ())
+}
+
+// 单个引擎的基准测试结果
+struct BenchResult {
+    engine: &'static str,
+    elapsed: Result<Duration, &'static str>,
+    instructions: Option<usize>,
+}
+
+// 分别在解释器、vm、jit 上运行脚本 n 次，比较耗时
+fn bench(script: String, iterations: u32, format: Format) -> Result<(), Error> {
+    let (statements, _) = parse(script)?;
+    let mut results = vec![];
+
+    // 引擎 panic 时只记录失败，不打印 panic 信息，结束后恢复原来的 hook
+    let prev = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+
+    let elapsed = time(iterations, || {
+        let mut intercepter = Intercepter::new();
+        intercepter.eval(&statements).is_ok()
+    });
+    results.push(BenchResult {
+        engine: "interpreter",
+        elapsed,
+        instructions: None,
+    });
+
+    let mut emitter = Emitter::default();
+    let funcs = emitter.emit_all(&statements)?.clone();
+    let mut instructions = 0;
+    let elapsed = time(iterations, || {
+        let mut vm = VM::new_with_funcs(funcs.clone());
+        let ok = vm.eval_all().is_ok();
+        instructions = vm.stats().instructions;
+        ok
+    });
+    results.push(BenchResult {
+        engine: "vm",
+        elapsed,
+        instructions: elapsed.ok().map(|_| instructions),
+    });
+
+    let elapsed = bench_jit(&statements, iterations);
+    panic::set_hook(prev);
+    results.push(BenchResult {
+        engine: "jit",
+        elapsed,
+        instructions: None,
+    });
+
+    let baseline = results[0].elapsed.ok();
+    if format == Format::Json {
+        let results: Vec<_> = results
+            .iter()
+            .map(|result| match result.elapsed {
+                Ok(elapsed) => json!({
+                    "engine": result.engine,
+                    "total_ms": elapsed.as_secs_f64() * 1e3,
+                    "avg_us": elapsed.as_secs_f64() * 1e6 / iterations as f64,
+                    "instructions": result.instructions,
+                    "speedup": baseline.map(|b| b.as_secs_f64() / elapsed.as_secs_f64()),
+                }),
+                Err(reason) => json!({
+                    "engine": result.engine,
+                    "error": reason,
+                }),
+            })
+            .collect();
+        println!("{}", serde_json::Value::Array(results));
+        return Ok(());
+    }
+
+    println!(
+        "{:12} {:>12} {:>12} {:>14} {:>8}",
+        "engine", "total(ms)", "avg(us)", "instructions", "speedup"
+    );
+    for result in results {
+        match result.elapsed {
+            Ok(elapsed) => {
+                let instructions = result
+                    .instructions
+                    .map(|i| i.to_string())
+                    .unwrap_or_else(|| "-".to_string());
+                let speedup = baseline
+                    .map(|b| format!("{:.2}x", b.as_secs_f64() / elapsed.as_secs_f64()))
+                    .unwrap_or_else(|| "-".to_string());
+                println!(
+                    "{:12} {:>12.3} {:>12.3} {:>14} {:>8}",
+                    result.engine,
+                    elapsed.as_secs_f64() * 1e3,
+                    elapsed.as_secs_f64() * 1e6 / iterations as f64,
+                    instructions,
+                    speedup
+                );
+            }
+            Err(reason) => println!("{:12} {:>12}", result.engine, reason),
         }
-        Err(e) => {
-            eprintln!("{:?}", e);
-            1
+    }
+    Ok(())
+}
+
+// jit 目前只支持单个无参函数组成的脚本
+#[cfg(feature = "jit")]
+fn bench_jit(statements: &[Stmt], iterations: u32) -> Result<Duration, &'static str> {
+    let mut jit = JIT::default();
+    match statements {
+        [stmt @ Stmt::FunctionStmt(_, params, _)] if params.is_empty() => match jit.compile(stmt) {
+            Ok(code) => {
+                let code_fn = unsafe { std::mem::transmute::<*const u8, fn() -> i64>(code) };
+                time(iterations, || {
+                    code_fn();
+                    true
+                })
+            }
+            Err(_) => Err("unsupported"),
+        },
+        _ => Err("unsupported"),
+    }
+}
+
+#[cfg(not(feature = "jit"))]
+fn bench_jit(_statements: &[Stmt], _iterations: u32) -> Result<Duration, &'static str> {
+    Err("disabled")
+}
+
+// 运行 f n 次并计时，f 失败或 panic 时返回错误
+fn time<F: FnMut() -> bool>(iterations: u32, mut f: F) -> Result<Duration, &'static str> {
+    let start = Instant::now();
+    for _ in 0..iterations {
+        match panic::catch_unwind(panic::AssertUnwindSafe(&mut f)) {
+            Ok(true) => {}
+            _ => return Err("failed"),
         }
+    }
+    Ok(start.elapsed())
+}
+
+// 脚本参数，整数参数转为 Int，其它为 String
+fn script_args(args: &[String]) -> Value {
+    let values = args
+        .iter()
+        .map(|arg| match arg.parse::<i32>() {
+            Ok(i) => Value::Int(i),
+            Err(_) => Value::String(arg.clone()),
+        })
+        .collect();
+    Value::Table(Table::from_array(values))
+}
+
+// 顶层 return 的整数作为进程退出码，其它值退出码为 0
+fn exit_code(value: &Value) -> i32 {
+    match value {
+        Value::Int(i) => *i,
+        _ => 0,
+    }
+}
+
+// 轨迹写入文件，避免与脚本输出混在一起
+fn tracer(path: Option<&Path>) -> Result<Option<Tracer>, Error> {
+    let Some(path) = path else {
+        return Ok(None);
     };
-    std::process::exit(ret);
+    let file = fs::File::create(path).map_err(|e| io_error(path, e))?;
+    Ok(Some(Tracer::new(BufWriter::new(file))))
+}
+
+// 读取失败时与其它错误一样输出到 stderr，退出码为 1
+fn load(input: &Path) -> String {
+    read_script(input).unwrap_or_else(|e| {
+        report(input, None, &io_error(input, e));
+        std::process::exit(1)
+    })
+}
+
+fn io_error(path: &Path, e: std::io::Error) -> Error {
+    Error::IoError(format!("cannot open {}: {}", path.display(), e))
+}
+
+// 读取脚本，路径为 `-` 时从 stdin 读取
+fn read_script(input: &Path) -> std::io::Result<String> {
+    if input.as_os_str() == "-" {
+        let mut script = String::new();
+        std::io::stdin().read_to_string(&mut script)?;
+        Ok(script)
+    } else {
+        fs::read_to_string(input)
+    }
 }
@@ -0,0 +1,52 @@
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::native::{IntoNativeFn, NativeFunction};
+use crate::stdio::Stdio;
+use crate::value::Value;
+
+// 可选安装的内置函数库，嵌入时默认都不安装
+
+// io 库：write(v) 输出到 stdout 不换行，ewrite(v) 输出到 stderr，
+// read() 读入一行，整数转换为 integer，读到结尾时为 nil
+pub fn io(stdio: &Stdio) -> Vec<NativeFunction> {
+    let out = stdio.clone();
+    let write = move |value: Value| {
+        out.write(format_args!("{}", value))
+            .map_err(|e| e.to_string())
+    };
+    let err = stdio.clone();
+    let ewrite = move |value: Value| {
+        err.write_err(format_args!("{}", value))
+            .map_err(|e| e.to_string())
+    };
+    let input = stdio.clone();
+    let read = move || match input.read_line() {
+        Ok(None) => Ok(Value::Nil),
+        Ok(Some(line)) => Ok(line
+            .trim()
+            .parse()
+            .map_or_else(|_| Value::String(line), Value::Int)),
+        Err(e) => Err(e.to_string()),
+    };
+    vec![
+        NativeFunction::new("write", write.into_native_fn()),
+        NativeFunction::new("ewrite", ewrite.into_native_fn()),
+        NativeFunction::new("read", read.into_native_fn()),
+    ]
+}
+
+// os 库：clock() 为安装以来经过的秒数，time() 为 unix 时间戳
+pub fn os() -> Vec<NativeFunction> {
+    let start = Instant::now();
+    let clock = move || start.elapsed().as_secs_f64();
+    let time = || {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or_default()
+    };
+    vec![
+        NativeFunction::new("clock", clock.into_native_fn()),
+        NativeFunction::new("time", time.into_native_fn()),
+    ]
+}
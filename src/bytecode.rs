@@ -40,13 +40,24 @@ pub enum ByteCode {
     Decr,
     Mul,
     Div,
+    Mod,
+    Pow,
+    // 弹出两个值，压入连接后的字符串
+    Concat,
     Equal,
     EqualEqual,
+    NotEqual,
     Less,
+    LessEqual,
     Greater,
+    GreaterEqual,
     Jump(usize),
     JumpIfFalse(usize),
-    
+    // 栈顶为假时跳转并保留栈顶，否则弹出，用于 and
+    JumpIfFalseOrPop(usize),
+    // 栈顶为真时跳转并保留栈顶，否则弹出，用于 or
+    JumpIfTrueOrPop(usize),
+
     //
     Closure(usize),
     Call(usize),
@@ -59,7 +70,14 @@ pub enum ByteCode {
     Nil,
     Print,
     Ret,
-
+    // 常量为全局表名，弹出 key，压入 t[key]
+    GetIndex(usize),
+    // 常量为全局表名，依次弹出 value、key，原地修改 t[key] = value
+    SetIndex(usize),
+    // 弹出默认值，从标准输入读取一个字节压入，EOF 时压入默认值
+    GetChar,
+    // 弹出整数，按字节写到标准输出
+    PutChar,
     // TODO:
     // Negtive,
     // Bang,
@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+
+use crate::resolver::stmt_token;
+use crate::statement::Stmt;
+
+// 行覆盖率，由解释器在执行每条语句前记录所在的行
+#[derive(Debug, Default, Clone)]
+pub struct Coverage {
+    // 可执行的行 → 执行次数
+    lines: BTreeMap<usize, u64>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // 记录语法树中可执行的行，包括函数体与分支中的语句，未执行的次数为 0
+    pub fn add_statements(&mut self, statements: &[Stmt]) {
+        for stmt in statements {
+            match stmt {
+                Stmt::Block(stmts) => self.add_statements(stmts),
+                Stmt::None => {}
+                _ => {
+                    if let Some(token) = stmt_token(stmt) {
+                        self.lines.entry(token.line).or_default();
+                    }
+                    match stmt {
+                        Stmt::IfStmt(_, then_branch, else_branch) => {
+                            self.add_statements(std::slice::from_ref(then_branch));
+                            self.add_statements(std::slice::from_ref(else_branch));
+                        }
+                        Stmt::WhileStmt(_, body) | Stmt::RepeatStmt(body, _) => {
+                            self.add_statements(body)
+                        }
+                        Stmt::FunctionStmt(_, _, body) => self.add_statements(body),
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    // 执行一条语句
+    pub fn hit(&mut self, stmt: &Stmt) {
+        if let Some(token) = stmt_token(stmt) {
+            *self.lines.entry(token.line).or_default() += 1;
+        }
+    }
+
+    // 按行号排列的执行次数
+    pub fn lines(&self) -> &BTreeMap<usize, u64> {
+        &self.lines
+    }
+
+    // 执行过的行数
+    pub fn lines_hit(&self) -> usize {
+        self.lines.values().filter(|&&count| count > 0).count()
+    }
+
+    // lcov 格式的报告，可以交给 genhtml 等工具
+    pub fn lcov(&self, path: &str) -> String {
+        let mut report = format!("TN:\nSF:{}\n", path);
+        for (line, count) in &self.lines {
+            report += &format!("DA:{},{}\n", line, count);
+        }
+        report += &format!(
+            "LF:{}\nLH:{}\nend_of_record\n",
+            self.lines.len(),
+            self.lines_hit()
+        );
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use crate::intercepter::Intercepter;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+    use crate::stdio::Stdio;
+
+    #[test]
+    fn test_coverage() {
+        let source = "function f(n)
+  if n < 2 then
+    return n;
+  else
+    return 0;
+  end
+end
+local a = f(1);
+local b = f(0);
+print(a);";
+        let statements = Parser::from_stream(Scanner::new(source.to_string()))
+            .parse()
+            .unwrap();
+        let mut intercepter = Intercepter::new();
+        intercepter.enable_coverage();
+        let mut stdio = Stdio::default();
+        stdio.set_stdout(io::sink());
+        intercepter.set_stdio(stdio);
+        intercepter.eval(&statements).unwrap();
+        let coverage = intercepter.take_coverage().unwrap();
+
+        let lines: Vec<_> = coverage.lines().iter().map(|(&l, &c)| (l, c)).collect();
+        assert_eq!(
+            lines,
+            [(1, 1), (2, 2), (3, 2), (5, 0), (8, 1), (9, 1), (10, 1)]
+        );
+        assert_eq!(
+            coverage.lcov("a.lua"),
+            "TN:\nSF:a.lua\nDA:1,1\nDA:2,2\nDA:3,2\nDA:5,0\nDA:8,1\nDA:9,1\nDA:10,1\nLF:7\nLH:6\nend_of_record\n"
+        );
+    }
+}
@@ -1,3 +1,5 @@
+use std::fmt::Write;
+
 use crate::{
     bytecode::ByteCode,
     emitter::{Chunk, Function},
@@ -5,119 +7,121 @@ use crate::{
 
 // 输出字节码详细信息
 pub fn debug(chunk: &Chunk) {
+    print!("{}", disassemble(chunk));
+}
+
+pub fn debug_all(funcs: &[Function]) {
+    print!("{}", disassemble_all(funcs));
+}
+
+// 反汇编为文本，每条字节码一行
+pub fn disassemble(chunk: &Chunk) -> String {
+    let mut out = String::new();
     let codes = &chunk.codes;
     let constants = &chunk.constants;
     let mut offset = 0;
     while offset < codes.len() {
-        print!("{:04} ", offset);
+        write!(out, "{:04} ", offset).unwrap();
         match &codes[offset] {
-            ByteCode::Push(d) => {
-                print!("{:16} '{}", "Push", d);
-                print!("'\n");
-            }
-            ByteCode::Pop => {
-                print!("{:16}", "Pop");
-                print!("\n");
-            }
-            ByteCode::Add => {
-                print!("{:16}", "Add");
-                print!("\n");
-            }
-            ByteCode::Sub => {
-                print!("{:16}", "Sub");
-                print!("\n");
-            }
-            ByteCode::Incr => {
-                print!("{:16}", "Incr");
-                print!("\n");
-            }
-            ByteCode::Decr => {
-                print!("{:16}", "Decr");
-                print!("\n");
-            }
-            ByteCode::Mul => {
-                print!("{:16}", "Mul");
-                print!("\n");
-            }
-            ByteCode::Div => {
-                print!("{:16}", "Div");
-                print!("\n");
-            }
-            ByteCode::Greater => {
-                print!("{:16}", "Greater");
-                print!("\n");
-            }
-            ByteCode::Less => {
-                print!("{:16}", "Less");
-                print!("\n");
-            }
-            ByteCode::EqualEqual => {
-                print!("{:16}", "Equal");
-                print!("\n");
-            }
-            ByteCode::Jump(i) => {
-                print!("{:16} '{:04}", "Jump", i);
-                print!("'\n");
-            }
-            ByteCode::GetLocal(i) => {
-                print!("{:16} {} '{}", "GetLocal", i, constants[*i]);
-                print!("'\n");
-            }
-            ByteCode::SetLocal(i) => {
-                print!("{:16} {} '{}", "SetLocal", i, constants[*i]);
-                print!("'\n");
-            }
-            ByteCode::Print => {
-                print!("{:16}", "Print");
-                print!("\n");
-            }
-            ByteCode::Call(c) => {
-                print!("{:16} '{}", "Call", c);
-                print!("'\n");
-            }
-            ByteCode::Ret => {
-                print!("{:16}", "Ret");
-                print!("\n");
-            }
+            ByteCode::Push(d) => writeln!(out, "{:16} '{}'", "Push", d),
+            ByteCode::Pop => writeln!(out, "{:16}", "Pop"),
+            ByteCode::Add => writeln!(out, "{:16}", "Add"),
+            ByteCode::Sub => writeln!(out, "{:16}", "Sub"),
+            ByteCode::Incr => writeln!(out, "{:16}", "Incr"),
+            ByteCode::Decr => writeln!(out, "{:16}", "Decr"),
+            ByteCode::Mul => writeln!(out, "{:16}", "Mul"),
+            ByteCode::Div => writeln!(out, "{:16}", "Div"),
+            ByteCode::Mod => writeln!(out, "{:16}", "Mod"),
+            ByteCode::Pow => writeln!(out, "{:16}", "Pow"),
+            ByteCode::Concat => writeln!(out, "{:16}", "Concat"),
+            ByteCode::Greater => writeln!(out, "{:16}", "Greater"),
+            ByteCode::Less => writeln!(out, "{:16}", "Less"),
+            ByteCode::GreaterEqual => writeln!(out, "{:16}", "GreaterEqual"),
+            ByteCode::LessEqual => writeln!(out, "{:16}", "LessEqual"),
+            ByteCode::EqualEqual => writeln!(out, "{:16}", "Equal"),
+            ByteCode::NotEqual => writeln!(out, "{:16}", "NotEqual"),
+            ByteCode::Jump(i) => writeln!(out, "{:16} '{:04}'", "Jump", i),
+            ByteCode::GetLocal(i) => writeln!(out, "{:16} {} '{}'", "GetLocal", i, constants[*i]),
+            ByteCode::SetLocal(i) => writeln!(out, "{:16} {} '{}'", "SetLocal", i, constants[*i]),
+            ByteCode::Print => writeln!(out, "{:16}", "Print"),
+            ByteCode::Call(c) => writeln!(out, "{:16} '{}'", "Call", c),
+            ByteCode::Ret => writeln!(out, "{:16}", "Ret"),
             ByteCode::Equal => todo!(),
-            ByteCode::JumpIfFalse(_) => todo!(),
-            ByteCode::Closure(i) => {
-                print!("{:16} {} '{}", "Closure", i, constants[*i]);
-                print!("'\n");
-            }
+            ByteCode::JumpIfFalse(i) => writeln!(out, "{:16} {}", "JumpIfFalse", i),
+            ByteCode::JumpIfFalseOrPop(i) => writeln!(out, "{:16} {}", "JumpIfFalseOrPop", i),
+            ByteCode::JumpIfTrueOrPop(i) => writeln!(out, "{:16} {}", "JumpIfTrueOrPop", i),
+            ByteCode::Closure(i) => writeln!(out, "{:16} {} '{}'", "Closure", i, constants[*i]),
             ByteCode::DefineGlabal(i) => {
-                print!("{:16} {} '{}", "DefineGlabal", i, constants[*i]);
-                print!("'\n");
-            }
-            ByteCode::GetGlobal(i) => {
-                print!("{:16} {} '{}", "GetGlobal", i, constants[*i]);
-                print!("'\n");
-            }
-            ByteCode::SetGlobal(i) => {
-                print!("{:16} {} '{}", "SetGlobal", i, constants[*i]);
-                print!("'\n");
-            }
-            ByteCode::Constant(i) => {
-                print!("{:16} {} '{}", "Constant", i, constants[*i]);
-                print!("'\n");
-            }
-            ByteCode::Nil => {
-                print!("{:16}", "Nil");
-                print!("\n");
-            }
+                writeln!(out, "{:16} {} '{}'", "DefineGlabal", i, constants[*i])
+            }
+            ByteCode::GetGlobal(i) => writeln!(out, "{:16} {} '{}'", "GetGlobal", i, constants[*i]),
+            ByteCode::SetGlobal(i) => writeln!(out, "{:16} {} '{}'", "SetGlobal", i, constants[*i]),
+            ByteCode::Constant(i) => writeln!(out, "{:16} {} '{}'", "Constant", i, constants[*i]),
+            ByteCode::Nil => writeln!(out, "{:16}", "Nil"),
+            ByteCode::GetIndex(i) => writeln!(out, "{:16} {} '{}'", "GetIndex", i, constants[*i]),
+            ByteCode::SetIndex(i) => writeln!(out, "{:16} {} '{}'", "SetIndex", i, constants[*i]),
+            ByteCode::GetChar => writeln!(out, "{:16}", "GetChar"),
+            ByteCode::PutChar => writeln!(out, "{:16}", "PutChar"),
         }
+        .unwrap();
         offset += 1;
     }
+    out
 }
 
-pub fn debug_all(funcs: &Vec<Function>) {
+pub fn disassemble_all(funcs: &[Function]) -> String {
+    let mut out = String::new();
     for func in funcs {
-        print!(
-            "== {} arity: {}  value_count: {} ==\n",
+        writeln!(
+            out,
+            "== {} arity: {}  value_count: {} ==",
             func.name.as_str(),
             func.arity,
             func.value_count
-        );
-        debug(&func.chunk());
+        )
+        .unwrap();
+        out += &disassemble(func.chunk());
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::disassemble_all;
+    use crate::emitter::Emitter;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn disassemble_source(source: &str) -> String {
+        let mut scanner = Scanner::new(source.to_string());
+        scanner.scan_tokens().unwrap();
+        let statements = Parser::new(scanner.take_tokens()).parse().unwrap();
+        let text = disassemble_all(Emitter::default().emit_all(&statements).unwrap());
+        // 对齐用的行尾空格不写入 golden 文件
+        text.lines()
+            .map(|line| line.trim_end().to_string() + "\n")
+            .collect()
+    }
+
+    // 字节码生成有变化时需要同时更新 test 目录下的 .disasm 文件
+    #[test]
+    fn test_disassemble_golden() {
+        let cases = [
+            (
+                include_str!("../test/arith.lua"),
+                include_str!("../test/arith.disasm"),
+            ),
+            (
+                include_str!("../test/simple.lua"),
+                include_str!("../test/simple.disasm"),
+            ),
+            (
+                include_str!("../test/func.lua"),
+                include_str!("../test/func.disasm"),
+            ),
+        ];
+        for (source, golden) in cases {
+            assert_eq!(disassemble_source(source), golden, "{}", source);
+        }
     }
 }
@@ -0,0 +1,420 @@
+use std::fmt::Write;
+
+use serde_json::json;
+
+use crate::error::{Error, Span};
+use crate::resolver::{Lint, LintLevel};
+
+// 诊断的级别
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        }
+    }
+}
+
+// 附加在某段源码上的说明
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+impl Label {
+    pub fn new(span: Span, message: impl Into<String>) -> Self {
+        Self {
+            span,
+            message: message.into(),
+        }
+    }
+}
+
+// 诊断在源码中标注的一段：所在行与下划线的位置，供终端输出与编辑器使用
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment<'a> {
+    // 行号与起始列，均从 1 开始，列按字符计算
+    pub line: usize,
+    pub col: usize,
+    // 下划线覆盖的字符数，不超过行尾，至少为 1
+    pub len: usize,
+    // 所在行的源码，不含换行符
+    pub text: &'a str,
+    // 主要位置为 true，附加说明为 false
+    pub primary: bool,
+    pub message: &'a str,
+}
+
+// 结构化的诊断信息，由错误与静态检查结果转换而来，可以输出到终端或 json
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    // 稳定的错误码，如 parse-error、unused-variable
+    pub code: &'static str,
+    pub message: String,
+    // 主要位置，运行时错误等没有源码位置
+    pub primary_span: Option<Span>,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            code,
+            message: message.into(),
+            primary_span: None,
+            labels: vec![],
+            notes: vec![],
+        }
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.primary_span = Some(span);
+        self
+    }
+
+    pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.labels.push(Label::new(span, message));
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    // 主要位置与各个附加说明对应的源码段，主要位置在前
+    pub fn segments<'a>(&'a self, source: &'a str) -> impl Iterator<Item = Segment<'a>> + 'a {
+        let primary = self.primary_span.map(|span| (span, true, ""));
+        let labels = self
+            .labels
+            .iter()
+            .map(|label| (label.span, false, label.message.as_str()));
+        primary
+            .into_iter()
+            .chain(labels)
+            .map(move |(span, primary, message)| {
+                let (line, col) = locate(source, span.start);
+                let text = source.lines().nth(line - 1).unwrap_or("");
+                let rest = text.chars().count().saturating_sub(col - 1);
+                let start = span.start.min(source.len());
+                let len = source[start..span.end.clamp(start, source.len())]
+                    .chars()
+                    .count()
+                    .min(rest)
+                    .max(1);
+                Segment {
+                    line,
+                    col,
+                    len,
+                    text,
+                    primary,
+                    message,
+                }
+            })
+    }
+
+    // 终端输出，有源码时附带出错的源码行与下划线，color 为 true 时带颜色，
+    // file 为空时位置只有行列号
+    pub fn render(&self, file: &str, source: Option<&str>, color: bool) -> String {
+        let paint = |code: &str, text: &str| {
+            if color {
+                format!("\x1b[{}m{}\x1b[0m", code, text)
+            } else {
+                text.to_string()
+            }
+        };
+        let level_color = match self.severity {
+            Severity::Error => "1;31",
+            Severity::Warning => "1;33",
+        };
+
+        let mut out = String::new();
+        let header = format!("{}[{}]", self.severity.as_str(), self.code);
+        let (span, source) = match (self.primary_span, source) {
+            (Some(span), Some(source)) => (span, source),
+            _ => {
+                let prefix = if file.is_empty() {
+                    String::new()
+                } else {
+                    format!("{}: ", file)
+                };
+                let _ = writeln!(
+                    out,
+                    "{}{}: {}",
+                    prefix,
+                    paint(level_color, &header),
+                    self.message
+                );
+                self.render_notes(&mut out, "", &paint);
+                return out;
+            }
+        };
+
+        let _ = writeln!(
+            out,
+            "{}: {}",
+            paint(level_color, &header),
+            paint("1", &self.message)
+        );
+        let (line, col) = locate(source, span.start);
+        let segments: Vec<_> = self.segments(source).collect();
+        // 行号栏的宽度取所有标注中最大的行号
+        let width = segments
+            .iter()
+            .map(|segment| segment.line)
+            .max()
+            .unwrap_or(line)
+            .to_string()
+            .len();
+        let gutter = " ".repeat(width);
+        let location = if file.is_empty() {
+            format!("{}:{}", line, col)
+        } else {
+            format!("{}:{}:{}", file, line, col)
+        };
+        let _ = writeln!(out, "{}{} {}", gutter, paint("1;34", "-->"), location);
+
+        let bar = paint("1;34", "|");
+        for segment in &segments {
+            let _ = writeln!(out, "{} {}", gutter, bar);
+            let number = format!("{:>width$}", segment.line, width = width);
+            let _ = writeln!(out, "{} {} {}", paint("1;34", &number), bar, segment.text);
+            // 按字符对齐，tab 原样保留
+            let padding: String = segment
+                .text
+                .chars()
+                .take(segment.col - 1)
+                .map(|c| if c == '\t' { '\t' } else { ' ' })
+                .collect();
+            let (marker, code) = if segment.primary {
+                ("^", level_color)
+            } else {
+                ("-", "1;34")
+            };
+            let mut underline = marker.repeat(segment.len);
+            if !segment.message.is_empty() {
+                underline = format!("{} {}", underline, segment.message);
+            }
+            let _ = writeln!(
+                out,
+                "{} {} {}{}",
+                gutter,
+                bar,
+                padding,
+                paint(code, &underline)
+            );
+        }
+        self.render_notes(&mut out, &gutter, &paint);
+        out
+    }
+
+    fn render_notes(&self, out: &mut String, gutter: &str, paint: &dyn Fn(&str, &str) -> String) {
+        for note in &self.notes {
+            let _ = writeln!(out, "{} {} note: {}", gutter, paint("1;34", "="), note);
+        }
+    }
+
+    // json 输出，供编辑器与 CI 使用，有源码时位置带行列号
+    pub fn to_json(&self, file: &str, source: Option<&str>) -> serde_json::Value {
+        let span = |span: Span| {
+            let mut value = json!({ "start": span.start, "end": span.end });
+            if let Some(source) = source {
+                let (line, col) = locate(source, span.start);
+                value["line"] = json!(line);
+                value["col"] = json!(col);
+            }
+            value
+        };
+        let labels: Vec<_> = self
+            .labels
+            .iter()
+            .map(|label| json!({ "span": span(label.span), "message": label.message }))
+            .collect();
+        json!({
+            "file": file,
+            "severity": self.severity.as_str(),
+            "code": self.code,
+            "message": self.message,
+            "span": self.primary_span.map(span),
+            "labels": labels,
+            "notes": self.notes,
+        })
+    }
+}
+
+impl From<&Error> for Diagnostic {
+    fn from(e: &Error) -> Self {
+        let (code, message, span) = match e {
+            Error::ScanError { message, span, .. } => ("scan-error", message.clone(), Some(*span)),
+            Error::LexError(message) => ("lex-error", message.clone(), None),
+            Error::ParseError { message, span, .. } => {
+                ("parse-error", message.clone(), Some(*span))
+            }
+            Error::ResolveError { message, span, .. } => {
+                ("resolve-error", message.clone(), Some(*span))
+            }
+            Error::InterceptError { message, span, .. } => {
+                ("runtime-error", message.clone(), Some(*span))
+            }
+            Error::EmitError { message, span, .. } => ("emit-error", message.clone(), Some(*span)),
+            Error::DumpError(message) => ("dump-error", message.clone(), None),
+            Error::JsonError(message) => ("json-error", message.clone(), None),
+            Error::TranspileError(message) => ("transpile-error", message.clone(), None),
+            Error::JitError(message) => ("jit-error", message.clone(), None),
+            Error::RuntimeError(message) => ("runtime-error", message.clone(), None),
+            Error::LimitError(message) => ("limit-error", message.clone(), None),
+            Error::LeakError(message) => ("leak-error", message.clone(), None),
+            Error::IoError(message) => ("io-error", message.clone(), None),
+            Error::TypeError(message) => ("type-error", message.clone(), None),
+            Error::UnknownError => ("unknown-error", "unknown error".to_string(), None),
+        };
+        let mut diagnostic = Diagnostic::new(Severity::Error, code, message);
+        diagnostic.primary_span = span;
+        diagnostic
+    }
+}
+
+impl From<&Lint> for Diagnostic {
+    fn from(lint: &Lint) -> Self {
+        let severity = match lint.level {
+            LintLevel::Warning => Severity::Warning,
+            LintLevel::Error => Severity::Error,
+        };
+        Diagnostic {
+            labels: lint.labels.clone(),
+            ..Diagnostic::new(severity, lint.code, lint.message.clone()).with_span(lint.span)
+        }
+    }
+}
+
+// 字节偏移对应的 (行, 列)，均从 1 开始，列按字符计算
+fn locate(source: &str, offset: usize) -> (usize, usize) {
+    let offset = offset.min(source.len());
+    let before = &source[..offset];
+    let line = before.matches('\n').count() + 1;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    (line, source[line_start..offset].chars().count() + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Diagnostic, Segment, Severity};
+    use crate::error::{self, Span};
+    use crate::parser::Parser;
+    use crate::resolver::Resolver;
+    use crate::scanner::Scanner;
+
+    #[test]
+    fn test_render() {
+        let source = "local a = 1;\nlocal b = a +;\n";
+        let mut scanner = Scanner::new(source.to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        let err = Parser::new(tokens.clone()).parse().unwrap_err();
+        let diagnostic = Diagnostic::from(&err).with_note("expressions end with an operand");
+        assert_eq!(diagnostic.primary_span, Some(Span::new(26, 27)));
+        assert_eq!(
+            diagnostic.render("a.lua", Some(source), false),
+            "error[parse-error]: expect expression, found ';'\n \
+             --> a.lua:2:14\n  \
+             |\n\
+             2 | local b = a +;\n  \
+             |              ^\n  \
+             = note: expressions end with an operand\n"
+        );
+        assert_eq!(
+            diagnostic.render("a.lua", None, false),
+            "a.lua: error[parse-error]: expect expression, found ';'\n \
+             = note: expressions end with an operand\n"
+        );
+
+        let json = diagnostic.to_json("a.lua", Some(source));
+        assert_eq!(json["code"], "parse-error");
+        assert_eq!(json["span"]["line"], 2);
+        assert_eq!(json["span"]["col"], 14);
+        assert!(
+            Diagnostic::new(Severity::Error, "runtime-error", "boom").to_json("a.lua", None)
+                ["span"]
+                .is_null()
+        );
+    }
+
+    #[test]
+    fn test_segments() {
+        let source = "local a = 1;\nlocal b = a +;\n";
+        let diagnostic = Diagnostic::new(Severity::Error, "parse-error", "bad")
+            .with_span(Span::new(26, 27))
+            .with_label(Span::new(6, 40), "declared here");
+        let segments: Vec<_> = error::segments(&diagnostic, source).collect();
+        assert_eq!(
+            segments,
+            [
+                Segment {
+                    line: 2,
+                    col: 14,
+                    len: 1,
+                    text: "local b = a +;",
+                    primary: true,
+                    message: "",
+                },
+                Segment {
+                    line: 1,
+                    col: 7,
+                    len: 6,
+                    text: "local a = 1;",
+                    primary: false,
+                    message: "declared here",
+                },
+            ]
+        );
+        assert_eq!(
+            error::render(&diagnostic, source),
+            "error[parse-error]: bad\n \
+             --> 2:14\n  \
+             |\n\
+             2 | local b = a +;\n  \
+             |              ^\n  \
+             |\n\
+             1 | local a = 1;\n  \
+             |       ------ declared here\n"
+        );
+        assert_eq!(
+            error::render(&Diagnostic::new(Severity::Warning, "w", "no span"), source),
+            "warning[w]: no span\n"
+        );
+    }
+
+    #[test]
+    fn test_lint_diagnostic() {
+        let source = "local a = 1;\nfunction f(x)\n  local a = x;\n  print(a);\nend\nf(a);";
+        let mut scanner = Scanner::new(source.to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        let statements = Parser::new(tokens.clone()).parse().unwrap();
+        let lints = Resolver::default().lint(&statements);
+        let diagnostic = Diagnostic::from(&lints[0]);
+        assert_eq!(
+            (diagnostic.severity, diagnostic.code),
+            (Severity::Warning, "shadowed-local")
+        );
+        assert_eq!(
+            diagnostic.render("a.lua", Some(source), false),
+            "warning[shadowed-local]: local a shadows the declaration at line 1\n \
+             --> a.lua:3:9\n  \
+             |\n\
+             3 |   local a = x;\n  \
+             |         ^\n  \
+             |\n\
+             1 | local a = 1;\n  \
+             |       - previously declared here\n"
+        );
+    }
+}
@@ -0,0 +1,352 @@
+use std::convert::TryInto;
+
+use crate::bytecode::ByteCode;
+use crate::emitter::{Chunk, Function};
+use crate::error::Error;
+use crate::value::Value;
+
+// 预编译字节码文件格式(小端序)：
+//
+//   header   → MAGIC VERSION
+//   file     → header u32(函数个数) function*
+//   function → string(name) u32(arity) u32(value_count) constants codes
+//   constants→ u32(个数) value*
+//   codes    → u32(个数) bytecode*
+//   string   → u32(长度) utf8 字节
+//
+// 第一个函数为 <script>，与 Emitter::emit_all 的输出顺序一致。
+
+const MAGIC: &[u8; 5] = b"\x1bplua";
+const VERSION: u8 = 1;
+
+// 将 emitter 生成的函数序列化为字节
+pub fn dump(funcs: &[Function]) -> Result<Vec<u8>, Error> {
+    let mut writer = Writer::default();
+    writer.buf.extend_from_slice(MAGIC);
+    writer.u8(VERSION);
+    writer.len(funcs.len());
+    for func in funcs {
+        writer.string(func.name.as_str());
+        writer.len(func.arity);
+        writer.len(func.value_count);
+
+        let chunk = func.chunk();
+        writer.len(chunk.constants.len());
+        for value in &chunk.constants {
+            writer.value(value)?;
+        }
+        writer.len(chunk.codes.len());
+        for code in &chunk.codes {
+            writer.bytecode(code)?;
+        }
+    }
+    Ok(writer.buf)
+}
+
+// 从字节中反序列化出函数
+pub fn undump(bytes: &[u8]) -> Result<Vec<Function>, Error> {
+    let mut reader = Reader { bytes, pos: 0 };
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(Error::DumpError("not a precompiled chunk".to_string()));
+    }
+    let version = reader.u8()?;
+    if version != VERSION {
+        return Err(Error::DumpError(format!(
+            "version mismatch, expect {} got {}",
+            VERSION, version
+        )));
+    }
+
+    let count = reader.len()?;
+    let mut funcs = Vec::new();
+    for _ in 0..count {
+        let mut func = Function::new(reader.string()?);
+        func.set_arity(reader.len()?);
+        func.value_count = reader.len()?;
+
+        let mut chunk = Chunk::new();
+        // 按原样读入，不合并常用值，保持字节码中的下标
+        for _ in 0..reader.len()? {
+            chunk.push_constant(reader.value()?);
+        }
+        for _ in 0..reader.len()? {
+            chunk.add_bytecode(reader.bytecode()?);
+        }
+        *func.chunk_mut() = chunk;
+        funcs.push(func);
+    }
+
+    if reader.pos != bytes.len() {
+        return Err(Error::DumpError("trailing bytes after chunk".to_string()));
+    }
+    Ok(funcs)
+}
+
+#[derive(Default)]
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn len(&mut self, v: usize) {
+        self.u32(v as u32);
+    }
+
+    fn string(&mut self, s: &str) {
+        self.len(s.len());
+        self.buf.extend_from_slice(s.as_bytes());
+    }
+
+    fn value(&mut self, value: &Value) -> Result<(), Error> {
+        match value {
+            Value::Int(i) => {
+                self.u8(0);
+                self.buf.extend_from_slice(&i.to_le_bytes());
+            }
+            Value::Float(f) => {
+                self.u8(1);
+                self.buf.extend_from_slice(&f.to_le_bytes());
+            }
+            Value::Bool(b) => {
+                self.u8(2);
+                self.u8(*b as u8);
+            }
+            Value::String(s) => {
+                self.u8(3);
+                self.string(s);
+            }
+            Value::Nil => self.u8(4),
+            Value::Closure(name, params) => {
+                self.u8(5);
+                self.len(*name);
+                self.len(params.len());
+                for param in params {
+                    self.len(*param);
+                }
+            }
+            Value::Table(_) => {
+                return Err(Error::DumpError("table is not a constant".to_string()));
+            }
+            Value::Function(name, _, _) => {
+                return Err(Error::DumpError(format!(
+                    "function {} is not bytecode",
+                    name
+                )));
+            }
+            Value::Native(native) => {
+                return Err(Error::DumpError(format!(
+                    "native function {} is not bytecode",
+                    native.name
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn bytecode(&mut self, code: &ByteCode) -> Result<(), Error> {
+        match code {
+            ByteCode::Push(v) => {
+                self.u8(0);
+                self.value(v)?;
+            }
+            ByteCode::Pop => self.u8(1),
+            ByteCode::Add => self.u8(2),
+            ByteCode::Sub => self.u8(3),
+            ByteCode::Incr => self.u8(4),
+            ByteCode::Decr => self.u8(5),
+            ByteCode::Mul => self.u8(6),
+            ByteCode::Div => self.u8(7),
+            ByteCode::Equal => self.u8(8),
+            ByteCode::EqualEqual => self.u8(9),
+            ByteCode::Less => self.u8(10),
+            ByteCode::Greater => self.u8(11),
+            ByteCode::Jump(i) => self.operand(12, *i),
+            ByteCode::JumpIfFalse(i) => self.operand(13, *i),
+            ByteCode::Closure(i) => self.operand(14, *i),
+            ByteCode::Call(i) => self.operand(15, *i),
+            ByteCode::DefineGlabal(i) => self.operand(16, *i),
+            ByteCode::GetGlobal(i) => self.operand(17, *i),
+            ByteCode::SetGlobal(i) => self.operand(18, *i),
+            ByteCode::GetLocal(i) => self.operand(19, *i),
+            ByteCode::SetLocal(i) => self.operand(20, *i),
+            ByteCode::Constant(i) => self.operand(21, *i),
+            ByteCode::Nil => self.u8(22),
+            ByteCode::Print => self.u8(23),
+            ByteCode::Ret => self.u8(24),
+            ByteCode::GetIndex(i) => self.operand(25, *i),
+            ByteCode::SetIndex(i) => self.operand(26, *i),
+            ByteCode::GetChar => self.u8(27),
+            ByteCode::PutChar => self.u8(28),
+            ByteCode::JumpIfFalseOrPop(i) => self.operand(29, *i),
+            ByteCode::JumpIfTrueOrPop(i) => self.operand(30, *i),
+            ByteCode::Concat => self.u8(31),
+            ByteCode::Mod => self.u8(32),
+            ByteCode::Pow => self.u8(33),
+            ByteCode::NotEqual => self.u8(34),
+            ByteCode::LessEqual => self.u8(35),
+            ByteCode::GreaterEqual => self.u8(36),
+        }
+        Ok(())
+    }
+
+    fn operand(&mut self, op: u8, i: usize) {
+        self.u8(op);
+        self.len(i);
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        if self.bytes.len() - self.pos < n {
+            return Err(Error::DumpError("unexpected end of chunk".to_string()));
+        }
+        let slice = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn len(&mut self) -> Result<usize, Error> {
+        Ok(self.u32()? as usize)
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        let len = self.len()?;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|e| Error::DumpError(format!("invalid string: {}", e)))
+    }
+
+    fn value(&mut self) -> Result<Value, Error> {
+        let value = match self.u8()? {
+            0 => Value::Int(i32::from_le_bytes(self.take(4)?.try_into().unwrap())),
+            1 => Value::Float(f32::from_le_bytes(self.take(4)?.try_into().unwrap())),
+            2 => Value::Bool(self.u8()? != 0),
+            3 => Value::String(self.string()?),
+            4 => Value::Nil,
+            5 => {
+                let name = self.len()?;
+                let mut params = Vec::new();
+                for _ in 0..self.len()? {
+                    params.push(self.len()?);
+                }
+                Value::Closure(name, params)
+            }
+            tag => return Err(Error::DumpError(format!("unknown value tag {}", tag))),
+        };
+        Ok(value)
+    }
+
+    fn bytecode(&mut self) -> Result<ByteCode, Error> {
+        let code = match self.u8()? {
+            0 => ByteCode::Push(self.value()?),
+            1 => ByteCode::Pop,
+            2 => ByteCode::Add,
+            3 => ByteCode::Sub,
+            4 => ByteCode::Incr,
+            5 => ByteCode::Decr,
+            6 => ByteCode::Mul,
+            7 => ByteCode::Div,
+            8 => ByteCode::Equal,
+            9 => ByteCode::EqualEqual,
+            10 => ByteCode::Less,
+            11 => ByteCode::Greater,
+            12 => ByteCode::Jump(self.len()?),
+            13 => ByteCode::JumpIfFalse(self.len()?),
+            14 => ByteCode::Closure(self.len()?),
+            15 => ByteCode::Call(self.len()?),
+            16 => ByteCode::DefineGlabal(self.len()?),
+            17 => ByteCode::GetGlobal(self.len()?),
+            18 => ByteCode::SetGlobal(self.len()?),
+            19 => ByteCode::GetLocal(self.len()?),
+            20 => ByteCode::SetLocal(self.len()?),
+            21 => ByteCode::Constant(self.len()?),
+            22 => ByteCode::Nil,
+            23 => ByteCode::Print,
+            24 => ByteCode::Ret,
+            25 => ByteCode::GetIndex(self.len()?),
+            26 => ByteCode::SetIndex(self.len()?),
+            27 => ByteCode::GetChar,
+            28 => ByteCode::PutChar,
+            29 => ByteCode::JumpIfFalseOrPop(self.len()?),
+            30 => ByteCode::JumpIfTrueOrPop(self.len()?),
+            31 => ByteCode::Concat,
+            32 => ByteCode::Mod,
+            33 => ByteCode::Pow,
+            34 => ByteCode::NotEqual,
+            35 => ByteCode::LessEqual,
+            36 => ByteCode::GreaterEqual,
+            op => return Err(Error::DumpError(format!("unknown opcode {}", op))),
+        };
+        Ok(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dump, undump};
+    use crate::emitter::Emitter;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+    use crate::value::Value;
+    use crate::vm::VM;
+
+    #[test]
+    fn test_dump_undump() {
+        let source = r#"
+        function fib(n)
+            return n + 3;
+        end
+
+        local a = 1 + 2 + 34;
+        print(a);
+        "#;
+        let mut scanner = Scanner::new(source.to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens.clone());
+        let statements = parser.parse().unwrap();
+        let mut emitter = Emitter::default();
+        let funcs = emitter.emit_all(&statements).unwrap();
+
+        let bytes = dump(funcs).unwrap();
+        let loaded = undump(&bytes).unwrap();
+        assert_eq!(loaded.len(), funcs.len());
+        for (l, r) in loaded.iter().zip(funcs.iter()) {
+            assert_eq!(l.name, r.name);
+            assert_eq!(l.arity, r.arity);
+            assert_eq!(format!("{:?}", l.chunk()), format!("{:?}", r.chunk()));
+        }
+
+        let mut vm = VM::new_with_funcs(loaded);
+        assert_eq!(vm.eval_all().unwrap(), Value::Nil);
+    }
+
+    #[test]
+    fn test_undump_err() {
+        assert!(undump(b"").is_err());
+        assert!(undump(b"\x1bplua\x09").is_err());
+
+        let mut bytes = dump(&[]).unwrap();
+        bytes.push(0);
+        assert!(undump(&bytes).is_err());
+    }
+}
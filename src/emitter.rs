@@ -1,14 +1,19 @@
+use std::collections::BTreeMap;
+
 use crate::bytecode::ByteCode;
 use crate::error::Error;
 use crate::expression::Expr;
+use crate::parser::MAX_DEPTH;
 use crate::scanner::{Token, TokenType};
 use crate::statement::Stmt;
-use crate::value::Value;
+use crate::value::{CommonValue, Value};
 
 #[derive(Debug, Clone)]
 pub struct Chunk {
     pub codes: Vec<ByteCode>,
     pub constants: Vec<Value>,
+    // 常用值在常量表中的下标
+    common: BTreeMap<CommonValue, usize>,
 }
 
 impl Chunk {
@@ -16,14 +21,30 @@ impl Chunk {
         Self {
             codes: vec![],
             constants: vec![],
+            common: BTreeMap::new(),
         }
     }
 
+    // 添加常量，常用值复用已有的下标
     pub fn add_constant(&mut self, value: Value) -> usize {
+        if let Some(common) = value.as_common() {
+            if let Some(&index) = self.common.get(&common) {
+                return index;
+            }
+            self.common.insert(common, self.constants.len());
+        }
         self.constants.push(value);
         self.constants.len() - 1
     }
 
+    // 按原样追加常量，用于读入已经编译好的字节码，下标不变
+    pub(crate) fn push_constant(&mut self, value: Value) {
+        if let Some(common) = value.as_common() {
+            self.common.entry(common).or_insert(self.constants.len());
+        }
+        self.constants.push(value);
+    }
+
     pub fn add_bytecode(&mut self, bytecode: ByteCode) {
         self.codes.push(bytecode);
     }
@@ -35,6 +56,8 @@ pub struct Function {
     pub arity: usize, // arguments count
     pub value_count: usize,
     chunk: Chunk,
+    // 每层循环中待回填的 break 跳转
+    breaks: Vec<Vec<usize>>,
 }
 
 impl Function {
@@ -44,6 +67,7 @@ impl Function {
             arity: 0,
             value_count: 0,
             chunk: Chunk::new(),
+            breaks: vec![],
         }
     }
 
@@ -73,6 +97,8 @@ impl Default for Function {
 pub struct Emitter {
     functions: Vec<Function>,
     current: usize,
+    // 语句与表达式的嵌套层数，语法树不一定来自 parser(如 json)，需要单独检查
+    depth: usize,
 }
 
 impl Default for Emitter {
@@ -87,6 +113,7 @@ impl Emitter {
         Self {
             functions: vec![script],
             current: 0,
+            depth: 0,
         }
     }
 
@@ -112,6 +139,13 @@ impl Emitter {
     }
 
     fn emit_stmt(&mut self, stmt: &Stmt) -> Result<(), Error> {
+        self.deepen()?;
+        let result = self.emit_nested_stmt(stmt);
+        self.depth -= 1;
+        result
+    }
+
+    fn emit_nested_stmt(&mut self, stmt: &Stmt) -> Result<(), Error> {
         match stmt {
             Stmt::PrintStmt(expr) => {
                 self.emit_expr(expr)?;
@@ -121,9 +155,14 @@ impl Emitter {
             Stmt::IfStmt(condition, then_branch, else_branch) => {
                 self.emit_if_stmt(condition, then_branch.as_ref(), else_branch.as_ref())
             }
+            Stmt::WhileStmt(condition, body) => self.emit_while_stmt(condition, body),
+            Stmt::RepeatStmt(body, condition) => self.emit_repeat_stmt(body, condition),
             Stmt::LocalStmt(name, init) => self.emit_local_stmt(name, init),
+            Stmt::LocalListStmt(names, values) => self.emit_local_list_stmt(names, values),
             Stmt::FunctionStmt(name, params, body) => self.emit_func_stmt(name, params, body),
+            Stmt::AssignStmt(name, value) => self.emit_assign_stmt(name, value),
             Stmt::ReturnStmt(keyword, value) => self.emit_return_stmt(keyword, value),
+            Stmt::BreakStmt(keyword) => self.emit_break_stmt(keyword),
             Stmt::Expression(expr) => self.emit_expr(expr),
             Stmt::Block(stmts) => self.emit_block(stmts),
             Stmt::None => Ok(()),
@@ -144,13 +183,30 @@ impl Emitter {
         Ok(())
     }
 
+    // 跳转目标在循环结束时回填
+    fn emit_break_stmt(&mut self, keyword: &Token) -> Result<(), Error> {
+        let at = self.emit_jump(ByteCode::Jump(0));
+        match self.current().breaks.last_mut() {
+            Some(breaks) => {
+                breaks.push(at);
+                Ok(())
+            }
+            None => Err(Error::EmitError {
+                message: "break outside a loop".to_string(),
+                line: keyword.line,
+                col: keyword.col,
+                span: keyword.span,
+            }),
+        }
+    }
+
     fn emit_func_stmt(
         &mut self,
         name: &Token,
         params: &Vec<Token>,
-        body: &Vec<Stmt>,
+        body: &[Stmt],
     ) -> Result<(), Error> {
-        self.begin_scope(name.raw.as_str());
+        self.begin_scope(name.raw.as_ref());
         self.current().set_arity(params.len());
 
         for stmt in body {
@@ -159,11 +215,11 @@ impl Emitter {
 
         self.end_scope();
 
-        let func_name = name.raw.as_str();
+        let func_name = name.raw.as_ref();
         let idx = self.add_constant(Value::String(func_name.to_string()));
         let mut indexes = vec![];
         for param in params {
-            indexes.push(self.add_constant(Value::String(param.raw.clone())));
+            indexes.push(self.add_constant(Value::String(param.raw.to_string())));
         }
         let idx = self.add_constant(Value::Closure(idx, indexes));
         self.emit_bytecode(ByteCode::Closure(idx));
@@ -177,12 +233,37 @@ impl Emitter {
     fn emit_local_stmt(&mut self, name: &Token, init: &Expr) -> Result<(), Error> {
         self.emit_expr(init)?;
 
-        let name = name.raw.as_str();
+        let name = name.raw.as_ref();
         let index = self.add_constant(Value::String(name.to_string()));
         self.emit_bytecode(ByteCode::DefineGlabal(index));
         Ok(())
     }
 
+    fn emit_assign_stmt(&mut self, name: &Token, value: &Expr) -> Result<(), Error> {
+        self.emit_expr(value)?;
+        let index = self.add_constant(Value::String(name.raw.to_string()));
+        self.emit_bytecode(ByteCode::SetGlobal(index));
+        Ok(())
+    }
+
+    // 值依次入栈，不足的补 nil、多余的弹出，再从最后一个名字开始定义
+    fn emit_local_list_stmt(&mut self, names: &[Token], values: &[Expr]) -> Result<(), Error> {
+        for value in values {
+            self.emit_expr(value)?;
+        }
+        for _ in values.len()..names.len() {
+            self.emit_bytecode(ByteCode::Nil);
+        }
+        for _ in names.len()..values.len() {
+            self.emit_bytecode(ByteCode::Pop);
+        }
+        for name in names.iter().rev() {
+            let index = self.add_constant(Value::String(name.raw.to_string()));
+            self.emit_bytecode(ByteCode::DefineGlabal(index));
+        }
+        Ok(())
+    }
+
     fn emit_if_stmt(
         &mut self,
         condition: &Expr,
@@ -190,23 +271,95 @@ impl Emitter {
         else_branch: &Stmt,
     ) -> Result<(), Error> {
         self.emit_expr(condition)?;
-        // let then_jmp = self.bytecodes.len();
-        // self.bytecodes.push(ByteCode::JE(0));
-        // self.bytecodes.push(ByteCode::Pop);
-
+        let then_jmp = self.emit_jump(ByteCode::JumpIfFalse(0));
         self.emit_stmt(then_branch)?;
-        // let else_jmp = self.bytecodes.len();
-        // self.bytecodes.push(ByteCode::Jump(0));
+        if matches!(else_branch, Stmt::None) {
+            self.patch_jump(then_jmp);
+            return Ok(());
+        }
+        let else_jmp = self.emit_jump(ByteCode::Jump(0));
+        self.patch_jump(then_jmp);
+        self.emit_stmt(else_branch)?;
+        self.patch_jump(else_jmp);
+        Ok(())
+    }
 
-        // self.bytecodes[then_jmp] = ByteCode::JE(self.bytecodes.len());
-        // self.bytecodes.push(ByteCode::Pop);
+    // 先写入跳转，目标位置由 patch_jump 回填
+    fn emit_jump(&mut self, jump: ByteCode) -> usize {
+        self.emit_bytecode(jump);
+        self.current().chunk().codes.len() - 1
+    }
 
-        self.emit_stmt(else_branch)?;
-        // self.bytecodes[else_jmp] = ByteCode::Jump(self.bytecodes.len());
+    // 跳转到当前位置
+    fn patch_jump(&mut self, at: usize) {
+        let chunk = self.current().chunk_mut();
+        let target = chunk.codes.len();
+        match &mut chunk.codes[at] {
+            ByteCode::Jump(p)
+            | ByteCode::JumpIfFalse(p)
+            | ByteCode::JumpIfFalseOrPop(p)
+            | ByteCode::JumpIfTrueOrPop(p) => *p = target,
+            _ => unreachable!("patch a non-jump bytecode"),
+        }
+    }
+
+    // 条件为假时跳出循环，循环体结束后跳回条件
+    fn emit_while_stmt(&mut self, condition: &Expr, body: &[Stmt]) -> Result<(), Error> {
+        let start = self.current().chunk().codes.len();
+        self.emit_expr(condition)?;
+        let exit = self.emit_jump(ByteCode::JumpIfFalse(0));
+        self.emit_loop_body(body)?;
+        self.emit_bytecode(ByteCode::Jump(start));
+        self.patch_jump(exit);
+        self.patch_breaks();
+        Ok(())
+    }
+
+    // 条件为假时跳回循环体开头
+    fn emit_repeat_stmt(&mut self, body: &[Stmt], condition: &Expr) -> Result<(), Error> {
+        let start = self.current().chunk().codes.len();
+        self.emit_loop_body(body)?;
+        self.emit_expr(condition)?;
+        self.emit_bytecode(ByteCode::JumpIfFalse(start));
+        self.patch_breaks();
+        Ok(())
+    }
+
+    // 循环体中的 break 记录在新的一层中
+    fn emit_loop_body(&mut self, body: &[Stmt]) -> Result<(), Error> {
+        self.current().breaks.push(vec![]);
+        for stmt in body {
+            self.emit_stmt(stmt)?;
+        }
+        Ok(())
+    }
+
+    // break 跳转到循环之后
+    fn patch_breaks(&mut self) {
+        if let Some(breaks) = self.current().breaks.pop() {
+            for at in breaks {
+                self.patch_jump(at);
+            }
+        }
+    }
+
+    // 嵌套层数加一，语句与表达式共用
+    fn deepen(&mut self) -> Result<(), Error> {
+        if self.depth >= MAX_DEPTH {
+            return Err(Error::LimitError("nested too deeply".to_string()));
+        }
+        self.depth += 1;
         Ok(())
     }
 
     fn emit_expr(&mut self, expr: &Expr) -> Result<(), Error> {
+        self.deepen()?;
+        let result = self.emit_nested_expr(expr);
+        self.depth -= 1;
+        result
+    }
+
+    fn emit_nested_expr(&mut self, expr: &Expr) -> Result<(), Error> {
         match expr {
             Expr::Call(callee, paren, args) => self.emit_call(callee.as_ref(), paren, args)?,
             Expr::Unary(operator, right) => self.emit_unary(operator, right.as_ref())?,
@@ -215,7 +368,18 @@ impl Emitter {
             Expr::Binary(left, operator, right) => {
                 self.emit_binary(left.as_ref(), operator, right.as_ref())?
             }
+            Expr::Logical(left, operator, right) => {
+                self.emit_logical(left.as_ref(), operator, right.as_ref())?
+            }
             Expr::Literal(value) => self.emit_literal(value)?,
+            Expr::Table(token, _) | Expr::Index(_, token, _) => {
+                return Err(Error::EmitError {
+                    message: "tables are not supported by the vm".to_string(),
+                    line: token.line,
+                    col: token.col,
+                    span: token.span,
+                })
+            }
             Expr::None => (),
         }
 
@@ -234,29 +398,50 @@ impl Emitter {
         self.emit_expr(right)?;
         // left op right
         match operator.typ {
-            TokenType::Equal => self.emit_bytecode(ByteCode::Equal),
+            TokenType::EqualEqual => self.emit_bytecode(ByteCode::EqualEqual),
+            TokenType::BangEqual => self.emit_bytecode(ByteCode::NotEqual),
             TokenType::Greater => self.emit_bytecode(ByteCode::Greater),
+            TokenType::GreaterEqual => self.emit_bytecode(ByteCode::GreaterEqual),
             TokenType::Less => self.emit_bytecode(ByteCode::Less),
+            TokenType::LessEqual => self.emit_bytecode(ByteCode::LessEqual),
             TokenType::Plus => self.emit_bytecode(ByteCode::Add),
             TokenType::Minus => self.emit_bytecode(ByteCode::Sub),
             TokenType::Star => self.emit_bytecode(ByteCode::Mul),
             TokenType::Slash => self.emit_bytecode(ByteCode::Div),
+            TokenType::Percent => self.emit_bytecode(ByteCode::Mod),
+            TokenType::Caret => self.emit_bytecode(ByteCode::Pow),
+            TokenType::DotDot => self.emit_bytecode(ByteCode::Concat),
             _ => {
-                return Err(Error::EmitError(format!(
-                    "{:?} operator not support",
-                    operator.typ
-                )));
+                return Err(Error::EmitError {
+                    message: format!("{:?} operator not support", operator.typ),
+                    line: operator.line,
+                    col: operator.col,
+                    span: operator.span,
+                });
             }
         }
         Ok(())
     }
 
+    // 左侧已经决定结果时跳过右侧，保留左侧的值
+    fn emit_logical(&mut self, left: &Expr, operator: &Token, right: &Expr) -> Result<(), Error> {
+        self.emit_expr(left)?;
+        let jump = match operator.typ {
+            TokenType::And => ByteCode::JumpIfFalseOrPop(0),
+            _ => ByteCode::JumpIfTrueOrPop(0),
+        };
+        let jump = self.emit_jump(jump);
+        self.emit_expr(right)?;
+        self.patch_jump(jump);
+        Ok(())
+    }
+
     fn emit_assign(&mut self, _name: &Token, _value: &Expr) -> Result<(), Error> {
         Ok(())
     }
 
     fn emit_variable(&mut self, name: &Token) -> Result<(), Error> {
-        let index = self.add_constant(Value::String(name.raw.clone()));
+        let index = self.add_constant(Value::String(name.raw.to_string()));
         if self.current > 0 {
             self.emit_bytecode(ByteCode::GetLocal(index));
         } else {
@@ -309,9 +494,24 @@ impl Emitter {
 #[cfg(test)]
 mod tests {
     use crate::debug::{debug, debug_all};
-    use crate::emitter::Emitter;
+    use crate::emitter::{Chunk, Emitter};
     use crate::parser::Parser;
     use crate::scanner::Scanner;
+    use crate::value::Value;
+
+    #[test]
+    fn test_emit_rejects_tables() {
+        let source = "local t = {1};\nprint(t[1]);";
+        let statements = Parser::from_stream(Scanner::new(source.to_string()))
+            .parse()
+            .unwrap();
+        let e = Emitter::default().emit(&statements).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Emit error: tables are not supported by the vm"
+        );
+        assert_eq!(e.location(), Some((1, 11)));
+    }
 
     #[test]
     fn test_emit_local() {
@@ -388,4 +588,44 @@ mod tests {
         assert_eq!(r.len(), 2);
         debug_all(r);
     }
+
+    #[test]
+    fn test_common_constants() {
+        let mut chunk = Chunk::new();
+        let one = chunk.add_constant(Value::Int(1));
+        let big = chunk.add_constant(Value::Int(5000));
+        let empty = chunk.add_constant(Value::String(String::new()));
+        assert_eq!(chunk.add_constant(Value::Int(1)), one);
+        assert_eq!(chunk.add_constant(Value::String(String::new())), empty);
+        assert_ne!(chunk.add_constant(Value::Int(5000)), big);
+        assert_ne!(chunk.add_constant(Value::Float(1.0)), one);
+        let yes = chunk.add_constant(Value::Bool(true));
+        assert_eq!(chunk.add_constant(Value::Bool(true)), yes);
+        assert_eq!(chunk.add_constant(Value::Nil), 6);
+        assert_eq!(chunk.add_constant(Value::Nil), 6);
+        assert_eq!(chunk.constants.len(), 7);
+    }
+
+    #[test]
+    fn test_emit_depth_limit() {
+        use crate::error::Error;
+        use crate::expression::Expr;
+        use crate::scanner::{Token, TokenType};
+        use crate::statement::Stmt;
+        use crate::value::Value;
+
+        // 不经过 parser 构造的深层语法树
+        let plus = Token::new(TokenType::Plus, "+", Value::Nil, 1, 1, Default::default());
+        let mut expr = Expr::Literal(Value::Int(1));
+        for _ in 0..1000 {
+            expr = Expr::Binary(
+                Box::new(expr),
+                plus.clone(),
+                Box::new(Expr::Literal(Value::Int(1))),
+            );
+        }
+        let statements = vec![Stmt::Expression(expr)];
+        let err = Emitter::default().emit(&statements).unwrap_err();
+        assert!(matches!(err, Error::LimitError(_)));
+    }
 }
@@ -0,0 +1,533 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+
+use crate::builtins;
+use crate::error::Error;
+use crate::hook::{HookFn, HookMask};
+use crate::intercepter::{Intercepter, MAX_CALL_DEPTH};
+use crate::native::{FromValue, IntoArgs, IntoNativeFn, NativeFunction};
+use crate::parser::{Parser, ParserOptions};
+use crate::scanner::Scanner;
+use crate::stdio::Stdio;
+use crate::value::{Table, Value};
+use crate::vm::Limits;
+
+// 嵌入用的入口，使用解释器执行脚本，宿主函数注册为全局变量
+pub struct Engine {
+    intercepter: Intercepter,
+    // 解析时语句与表达式的最大嵌套层数
+    parser_options: ParserOptions,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine {
+    // 没有 io/os 库与运行限制的 engine
+    pub fn new() -> Self {
+        EngineBuilder::new().build()
+    }
+
+    pub fn builder() -> EngineBuilder {
+        EngineBuilder::new()
+    }
+
+    // 注册宿主函数，名字取 native.name
+    pub fn register_native(&mut self, native: NativeFunction) {
+        let name = native.name.clone();
+        self.intercepter.define_global(&name, Value::Native(native));
+    }
+
+    // 注册宿主函数，如 engine.register_fn("add", |a: i64, b: i64| a + b)
+    pub fn register_fn<Args, F: IntoNativeFn<Args>>(&mut self, name: &str, f: F) {
+        self.register_native(NativeFunction::new(name, f.into_native_fn()));
+    }
+
+    // 取得脚本中定义或宿主注册的全局函数
+    pub fn get_function(&mut self, name: &str) -> Result<ScriptFunction<'_>, Error> {
+        match self.intercepter.global(name) {
+            Some(Value::Function(..) | Value::Native(_)) => Ok(ScriptFunction {
+                engine: self,
+                name: name.to_string(),
+            }),
+            Some(value) => Err(Error::TypeError(format!(
+                "{} is a {}, not a function",
+                name,
+                value.type_name()
+            ))),
+            None => Err(Error::RuntimeError(format!("undefined function {}", name))),
+        }
+    }
+
+    // 设置调试钩子，如 engine.set_hook(HookFn::native(f), HookMask::new("cl", 0))
+    pub fn set_hook(&mut self, func: HookFn, mask: HookMask) {
+        self.intercepter.set_hook(func, mask);
+    }
+
+    pub fn clear_hook(&mut self) {
+        self.intercepter.clear_hook();
+    }
+
+    // 全局变量的值
+    pub fn global(&self, name: &str) -> Option<&Value> {
+        self.intercepter.global(name)
+    }
+
+    // 执行脚本，全局变量在多次执行之间保留
+    pub fn eval(&mut self, source: &str) -> Result<Value, Error> {
+        let mut parser = Parser::from_stream(Scanner::new(source.to_string()));
+        parser.set_options(self.parser_options);
+        let statements = parser.parse()?;
+        self.intercepter.eval(&statements)
+    }
+}
+
+// 运行不可信脚本时的沙箱配置，io/os 库默认不安装，默认没有运行限制
+#[derive(Debug, Clone)]
+pub struct EngineBuilder {
+    io: bool,
+    os: bool,
+    limits: Limits,
+    max_call_depth: usize,
+    parser_options: ParserOptions,
+    stdio: Stdio,
+    // 启动时定义的全局变量，如配置数据
+    globals: HashMap<String, Value>,
+}
+
+impl Default for EngineBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EngineBuilder {
+    pub fn new() -> Self {
+        Self {
+            io: false,
+            os: false,
+            limits: Limits::default(),
+            max_call_depth: MAX_CALL_DEPTH,
+            parser_options: ParserOptions::default(),
+            stdio: Stdio::default(),
+            globals: HashMap::new(),
+        }
+    }
+
+    // 安装 io 库：write、ewrite、read
+    pub fn io(mut self, enabled: bool) -> Self {
+        self.io = enabled;
+        self
+    }
+
+    // 安装 os 库：clock、time
+    pub fn os(mut self, enabled: bool) -> Self {
+        self.os = enabled;
+        self
+    }
+
+    // print、write 与 read 使用的流，默认为进程的 stdin/stdout/stderr
+    pub fn stdin<R: Read + Send + 'static>(mut self, stdin: R) -> Self {
+        self.stdio.set_stdin(stdin);
+        self
+    }
+
+    pub fn stdout<W: Write + Send + 'static>(mut self, stdout: W) -> Self {
+        self.stdio.set_stdout(stdout);
+        self
+    }
+
+    pub fn stderr<W: Write + Send + 'static>(mut self, stderr: W) -> Self {
+        self.stdio.set_stderr(stderr);
+        self
+    }
+
+    // 存活值占用的最大字节数(估算)
+    pub fn max_memory(mut self, bytes: usize) -> Self {
+        self.limits.max_memory = Some(bytes);
+        self
+    }
+
+    // 每次执行最多求值的语句与表达式个数
+    pub fn max_instructions(mut self, count: usize) -> Self {
+        self.limits.max_steps = Some(count);
+        self
+    }
+
+    // 函数调用的最大嵌套深度，不能超过 MAX_CALL_DEPTH
+    pub fn max_call_depth(mut self, depth: usize) -> Self {
+        self.max_call_depth = depth.min(MAX_CALL_DEPTH);
+        self
+    }
+
+    // 表达式、语句的嵌套层数与函数参数个数的限制，超出时报语法错误，
+    // 嵌套层数不能超过 parser::MAX_DEPTH
+    pub fn parser_options(mut self, options: ParserOptions) -> Self {
+        self.parser_options = options;
+        self
+    }
+
+    // 预先定义的全局变量，与内置函数同名时覆盖内置函数
+    pub fn with_globals(mut self, globals: HashMap<String, Value>) -> Self {
+        self.globals.extend(globals);
+        self
+    }
+
+    // 预先定义的表，如 with_table("config", entries)
+    pub fn with_table(mut self, name: &str, entries: HashMap<String, Value>) -> Self {
+        let table = Table {
+            array: vec![],
+            hash: entries.into_iter().collect(),
+        };
+        self.globals.insert(name.to_string(), Value::Table(table));
+        self
+    }
+
+    pub fn build(self) -> Engine {
+        let mut intercepter = Intercepter::new();
+        intercepter.set_limits(self.limits);
+        intercepter.set_max_call_depth(self.max_call_depth);
+        intercepter.set_stdio(self.stdio.clone());
+        let mut engine = Engine {
+            intercepter,
+            parser_options: self.parser_options,
+        };
+        if self.io {
+            builtins::io(&self.stdio)
+                .into_iter()
+                .for_each(|f| engine.register_native(f));
+        }
+        if self.os {
+            builtins::os()
+                .into_iter()
+                .for_each(|f| engine.register_native(f));
+        }
+        for (name, value) in self.globals {
+            engine.intercepter.define_global(&name, value);
+        }
+        engine
+    }
+}
+
+// 可以从宿主调用的函数，如 engine.get_function("fib")?.call::<i64>((30,))
+pub struct ScriptFunction<'a> {
+    engine: &'a mut Engine,
+    name: String,
+}
+
+impl ScriptFunction<'_> {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // 参数转换为脚本中的值，返回值转换为 R，类型不匹配时返回 TypeError
+    pub fn call<R: FromValue>(&mut self, args: impl IntoArgs) -> Result<R, Error> {
+        let args = args.into_args().map_err(Error::TypeError)?;
+        let value = self.engine.intercepter.call(&self.name, args)?;
+        R::from_value(&value).ok_or_else(|| {
+            Error::TypeError(format!(
+                "{} returned {}, {} expected",
+                self.name,
+                value.type_name(),
+                R::TYPE
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::Engine;
+    use crate::error::Error;
+    use crate::hook::{HookEvent, HookFn, HookMask};
+    use crate::parser::ParserOptions;
+    use crate::value::Value;
+
+    #[test]
+    fn test_register_fn() {
+        let mut engine = Engine::new();
+        engine.register_fn("add", |a: i64, b: i64| a + b);
+        engine.register_fn("half", |x: f64| x / 2.0);
+        assert_eq!(engine.eval("return add(1, 2);").unwrap(), Value::Int(3));
+        assert_eq!(
+            engine.eval("return half(add(1, 2));").unwrap(),
+            Value::Float(1.5)
+        );
+
+        let e = engine.eval("local a = 1;\nreturn add(a);").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Intercept error: add: wrong number of arguments (expected 2, got 1)"
+        );
+        assert_eq!(e.location(), Some((2, 13)));
+        let e = engine.eval("return add(1, nil);").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Intercept error: add: bad argument #2 (integer expected, got nil)"
+        );
+    }
+
+    #[test]
+    fn test_call_function() {
+        let mut engine = Engine::new();
+        engine.register_fn("add", |a: i64, b: i64| a + b);
+        let script = r#"
+        function fib(n)
+          if n < 2 then
+            return n;
+          end
+          return fib(n - 1) + fib(n - 2);
+        end
+        local answer = 42;
+        "#;
+        engine.eval(script).unwrap();
+
+        let mut fib = engine.get_function("fib").unwrap();
+        assert_eq!(fib.call::<i64>((10,)).unwrap(), 55);
+        assert_eq!(fib.call::<f64>((1,)).unwrap(), 1.0);
+        assert!(matches!(fib.call::<bool>((1,)), Err(Error::TypeError(_))));
+        assert_eq!(
+            engine
+                .get_function("add")
+                .unwrap()
+                .call::<i64>((2, 3))
+                .unwrap(),
+            5
+        );
+        assert!(matches!(
+            engine
+                .get_function("add")
+                .unwrap()
+                .call::<i64>((i64::MAX, 3)),
+            Err(Error::TypeError(_))
+        ));
+        assert!(matches!(
+            engine.get_function("answer"),
+            Err(Error::TypeError(_))
+        ));
+        assert!(matches!(
+            engine.get_function("missing"),
+            Err(Error::RuntimeError(_))
+        ));
+    }
+
+    #[test]
+    fn test_engine_builder() {
+        let mut engine = Engine::new();
+        assert!(engine.get_function("clock").is_err());
+        assert!(engine.get_function("write").is_err());
+
+        let mut engine = Engine::builder().os(true).build();
+        assert!(
+            engine
+                .get_function("time")
+                .unwrap()
+                .call::<i64>(())
+                .unwrap()
+                > 0
+        );
+        assert!(engine.eval("return clock();").unwrap().as_float().is_some());
+        assert!(engine.get_function("read").is_err());
+
+        let script = "function f(n)\n  return f(n + 1);\nend\nf(0);";
+        let e = Engine::builder()
+            .max_call_depth(10)
+            .build()
+            .eval(script)
+            .unwrap_err();
+        assert_eq!(e.to_string(), "Intercept error: stack overflow");
+        let e = Engine::builder()
+            .max_instructions(100)
+            .build()
+            .eval(script)
+            .unwrap_err();
+        assert!(matches!(e, Error::LimitError(_)));
+        let e = Engine::builder()
+            .max_memory(8 * std::mem::size_of::<Value>())
+            .build()
+            .eval(script)
+            .unwrap_err();
+        assert!(matches!(e, Error::LimitError(_)));
+        let options = ParserOptions {
+            max_expr_depth: 10,
+            ..ParserOptions::default()
+        };
+        let e = Engine::builder()
+            .parser_options(options)
+            .build()
+            .eval(&format!("return {}1;", "- ".repeat(20)))
+            .unwrap_err();
+        assert!(matches!(e, Error::ParseError { .. }));
+        let options = ParserOptions {
+            max_params: 1,
+            ..ParserOptions::default()
+        };
+        let e = Engine::builder()
+            .parser_options(options)
+            .build()
+            .eval("function f(a, b)\n  return a;\nend")
+            .unwrap_err();
+        assert!(matches!(e, Error::ParseError { .. }));
+    }
+
+    #[test]
+    fn test_preloaded_globals() {
+        let globals = HashMap::from([
+            ("limit".to_string(), Value::Int(10)),
+            ("debug".to_string(), Value::Bool(true)),
+        ]);
+        let config = HashMap::from([("name".to_string(), Value::String("plua".to_string()))]);
+        let mut engine = Engine::builder()
+            .os(true)
+            .with_globals(globals)
+            .with_globals(HashMap::from([("time".to_string(), Value::Int(0))]))
+            .with_table("config", config)
+            .build();
+        assert_eq!(engine.eval("return limit * 2;").unwrap(), Value::Int(20));
+        assert_eq!(engine.global("debug"), Some(&Value::Bool(true)));
+        assert_eq!(engine.global("time"), Some(&Value::Int(0)));
+        let config = engine.global("config").unwrap().as_table().unwrap();
+        assert_eq!(
+            config.get(&Value::String("name".to_string())),
+            Value::String("plua".to_string())
+        );
+    }
+
+    #[test]
+    fn test_redirect_stdio() {
+        use crate::stdio::Capture;
+        use std::io::Cursor;
+
+        let (out, err) = (Capture::default(), Capture::default());
+        let mut engine = Engine::builder()
+            .io(true)
+            .stdin(Cursor::new("41\nplua\r\n"))
+            .stdout(out.clone())
+            .stderr(err.clone())
+            .build();
+        let script = r#"
+        local n = read();
+        print(n + 1);
+        write(read());
+        ewrite(n);
+        "#;
+        engine.eval(script).unwrap();
+        assert_eq!(out.bytes(), b"42\nplua");
+        assert_eq!(err.bytes(), b"41");
+
+        // 没有安装 io 库时 print 同样输出到替换的流
+        let out = Capture::default();
+        let mut engine = Engine::builder().stdout(out.clone()).build();
+        engine.eval("print(1);").unwrap();
+        assert_eq!(out.bytes(), b"1\n");
+    }
+
+    #[test]
+    fn test_engines_in_parallel() {
+        fn assert_send<T: Send>() {}
+        assert_send::<Engine>();
+        assert_send::<crate::vm::VM>();
+
+        let script = r#"
+        function fib(n)
+          if n < 2 then
+            return n;
+          end
+          return fib(n - 1) + fib(n - 2);
+        end
+        "#;
+        // 每个线程各自的 engine，全局变量互不影响
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let mut engine = Engine::new();
+                engine.register_fn("base", move || i);
+                std::thread::spawn(move || {
+                    engine.eval(script).unwrap();
+                    engine.eval("local n = base();").unwrap();
+                    engine.eval("return n + fib(15);").unwrap()
+                })
+            })
+            .collect();
+        for (i, handle) in handles.into_iter().enumerate() {
+            assert_eq!(handle.join().unwrap(), Value::Int(i as i32 + 610));
+        }
+    }
+
+    #[test]
+    fn test_debug_hooks() {
+        use std::sync::{Arc, Mutex};
+
+        let script = r#"
+        function add(a, b)
+          return a + b;
+        end
+        local n = add(1, 2);
+        return n;
+        "#;
+        let events = Arc::new(Mutex::new(vec![]));
+        let mut engine = Engine::new();
+        let log = events.clone();
+        engine.set_hook(
+            HookFn::native(move |event| {
+                log.lock().unwrap().push(event.clone());
+                Ok(())
+            }),
+            HookMask::new("cl", 0),
+        );
+        assert_eq!(engine.eval(script).unwrap(), Value::Int(3));
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                HookEvent::Line(2),
+                HookEvent::Line(5),
+                HookEvent::Call("add".to_string()),
+                HookEvent::Line(3),
+                HookEvent::Return("add".to_string()),
+                HookEvent::Line(6),
+            ]
+        );
+
+        // 脚本中的钩子函数，执行钩子期间不再触发
+        let lines = Arc::new(Mutex::new(vec![]));
+        let log = lines.clone();
+        let mut engine = Engine::new();
+        engine.register_fn("record", move |event: String, line: i64| {
+            log.lock().unwrap().push((event, line));
+        });
+        engine
+            .eval("function linehook(event, line)\n  record(event, line);\nend")
+            .unwrap();
+        engine.set_hook(
+            HookFn::Script("linehook".to_string()),
+            HookMask::new("l", 0),
+        );
+        engine.eval(script).unwrap();
+        let lines: Vec<_> = lines.lock().unwrap().iter().map(|(_, l)| *l).collect();
+        assert_eq!(lines, [2, 5, 3, 6]);
+
+        // count 钩子作为 watchdog 中止无限递归
+        let mut engine = Engine::new();
+        let mut steps = 0;
+        engine.set_hook(
+            HookFn::native(move |_| {
+                steps += 1;
+                match steps < 3 {
+                    true => Ok(()),
+                    false => Err("too slow".to_string()),
+                }
+            }),
+            HookMask::new("", 10),
+        );
+        let e = engine
+            .eval("function f(n)\n  return f(n + 1);\nend\nf(0);")
+            .unwrap_err();
+        assert_eq!(e.to_string(), "Runtime error: hook: too slow");
+        engine.clear_hook();
+        assert_eq!(engine.eval("return 1;").unwrap(), Value::Int(1));
+    }
+}
@@ -1,24 +1,132 @@
+use serde::{Deserialize, Serialize};
+
+use crate::diagnostic::{Diagnostic, Segment};
+
+// 源码中的字节范围 [start, end)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(start: usize, end: usize) -> Self {
+        Self { start, end }
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     // 词法分析错误
-    #[error("Scan error: {0}")]
-    ScanError(String),
+    #[error("Scan error: {message}")]
+    ScanError {
+        message: String,
+        line: usize,
+        col: usize,
+        span: Span,
+    },
     // 词法错误
     #[error("Lex error: {0}")]
     LexError(String),
     // 语法错误
-    #[error("Parse error: {0}")]
-    ParseError(String),
+    #[error("Parse error: {message}")]
+    ParseError {
+        message: String,
+        line: usize,
+        col: usize,
+        span: Span,
+    },
     // 语义错误
-    #[error("Resolve error: {0}")]
-    ResolveError(String),
+    #[error("Resolve error: {message}")]
+    ResolveError {
+        message: String,
+        line: usize,
+        col: usize,
+        span: Span,
+    },
     // 解释运行时错误
-    #[error("Intercept error: {0}")]
-    InterceptError(String),
+    #[error("Intercept error: {message}")]
+    InterceptError {
+        message: String,
+        line: usize,
+        col: usize,
+        span: Span,
+    },
     // 生成字节码错误
-    #[error("Emit error: {0}")]
-    EmitError(String),
+    #[error("Emit error: {message}")]
+    EmitError {
+        message: String,
+        line: usize,
+        col: usize,
+        span: Span,
+    },
+    // 字节码序列化错误
+    #[error("Dump error: {0}")]
+    DumpError(String),
+    // 语法树 json 序列化错误
+    #[error("Json error: {0}")]
+    JsonError(String),
+    // 语法树转换为 Lua 源码时不支持的结构
+    #[error("Transpile error: {0}")]
+    TranspileError(String),
+    // jit 编译错误
+    #[error("Jit error: {0}")]
+    JitError(String),
+    // 虚拟机运行时错误
+    #[error("Runtime error: {0}")]
+    RuntimeError(String),
+    // 超出运行限制
+    #[error("Limit error: {0}")]
+    LimitError(String),
+    // 泄漏检查发现存活但不可达的对象
+    #[error("Leak error: {0}")]
+    LeakError(String),
+    // 读写文件等输入输出错误
+    #[error("IO error: {0}")]
+    IoError(String),
+    // 宿主与脚本之间的值类型不匹配
+    #[error("Type error: {0}")]
+    TypeError(String),
     // 未知错误
     #[error("Unknown error")]
     UnknownError,
 }
+
+impl Error {
+    // 错误所在的位置 (行, 列)，行列均从 1 开始
+    pub fn location(&self) -> Option<(usize, usize)> {
+        match self {
+            Error::ScanError { line, col, .. }
+            | Error::ParseError { line, col, .. }
+            | Error::ResolveError { line, col, .. }
+            | Error::InterceptError { line, col, .. }
+            | Error::EmitError { line, col, .. } => Some((*line, *col)),
+            _ => None,
+        }
+    }
+
+    // 错误在源码中的字节范围
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Error::ScanError { span, .. }
+            | Error::ParseError { span, .. }
+            | Error::ResolveError { span, .. }
+            | Error::InterceptError { span, .. }
+            | Error::EmitError { span, .. } => Some(*span),
+            _ => None,
+        }
+    }
+}
+
+// 不带颜色与文件名的诊断输出，供嵌入方与编辑器直接展示
+pub fn render(diagnostic: &Diagnostic, source: &str) -> String {
+    diagnostic.render("", Some(source), false)
+}
+
+// 诊断标注的各个源码段，嵌入方可以按自己的方式排版
+pub fn segments<'a>(
+    diagnostic: &'a Diagnostic,
+    source: &'a str,
+) -> impl Iterator<Item = Segment<'a>> + 'a {
+    diagnostic.segments(source)
+}
@@ -1,16 +1,23 @@
 use enum_as_inner::EnumAsInner;
+use serde::{Deserialize, Serialize};
 
 use crate::scanner::Token;
 use crate::value::Value;
 
 // Expr 表达式
-#[derive(Debug, Clone, EnumAsInner)]
+#[derive(Debug, Clone, EnumAsInner, Serialize, Deserialize)]
 pub enum Expr {
     Call(Box<Expr>, Token, Vec<Expr>),
     Unary(Token, Box<Expr>),
     Variable(Token),
     Assign(Token, Box<Expr>),
     Binary(Box<Expr>, Token, Box<Expr>),
+    // and 与 or，右侧只在需要时求值
+    Logical(Box<Expr>, Token, Box<Expr>),
     Literal(Value),
+    // 表构造，{ 与依次排列的 (键, 值)，按位置排列的元素键为 None
+    Table(Token, Vec<(Expr, Expr)>),
+    // t[k] 与 t.k，token 为 [ 或 .
+    Index(Box<Expr>, Token, Box<Expr>),
     None,
 }
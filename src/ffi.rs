@@ -0,0 +1,278 @@
+// C 接口，供非 Rust 程序嵌入解释器，头文件见 include/plua.h
+//
+// engine 与值都是不透明的句柄，由对应的 free 函数释放；
+// 出错时返回空指针，错误信息通过 plua_last_error 取得
+
+use std::ffi::{c_char, CStr, CString};
+use std::ptr;
+
+use crate::engine::Engine;
+use crate::value::Value;
+
+pub struct PluaEngine {
+    engine: Engine,
+    // 最近一次出错的信息
+    error: Option<CString>,
+}
+
+pub struct PluaValue {
+    value: Value,
+    // 字符串值的 C 表示，含有 \0 时为 None
+    string: Option<CString>,
+}
+
+// 值的类型，其它类型(table、function)只能通过句柄传回脚本
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PluaType {
+    Nil = 0,
+    Bool = 1,
+    Int = 2,
+    Float = 3,
+    String = 4,
+    Table = 5,
+    Function = 6,
+}
+
+impl PluaValue {
+    fn new(value: Value) -> *mut PluaValue {
+        let string = match &value {
+            Value::String(s) => CString::new(s.as_str()).ok(),
+            _ => None,
+        };
+        Box::into_raw(Box::new(PluaValue { value, string }))
+    }
+}
+
+impl PluaEngine {
+    fn fail(&mut self, message: String) -> *mut PluaValue {
+        // 错误信息中的 \0 替换掉，保证总能取得
+        self.error = CString::new(message.replace('\0', "\\0")).ok();
+        ptr::null_mut()
+    }
+}
+
+/// 创建没有 io/os 库的 engine，使用 plua_free 释放
+#[no_mangle]
+pub extern "C" fn plua_new() -> *mut PluaEngine {
+    Box::into_raw(Box::new(PluaEngine {
+        engine: Engine::new(),
+        error: None,
+    }))
+}
+
+/// 执行脚本，返回脚本的返回值，出错时返回空指针
+///
+/// # Safety
+///
+/// engine 由 plua_new 创建且未释放，source 为以 \0 结尾的 UTF-8 字符串
+#[no_mangle]
+pub unsafe extern "C" fn plua_eval(
+    engine: *mut PluaEngine,
+    source: *const c_char,
+) -> *mut PluaValue {
+    let Some(engine) = engine.as_mut() else {
+        return ptr::null_mut();
+    };
+    if source.is_null() {
+        return engine.fail("source is null".to_string());
+    }
+    let source = match CStr::from_ptr(source).to_str() {
+        Ok(source) => source,
+        Err(e) => return engine.fail(e.to_string()),
+    };
+    match engine.engine.eval(source) {
+        Ok(value) => {
+            engine.error = None;
+            PluaValue::new(value)
+        }
+        Err(e) => engine.fail(e.to_string()),
+    }
+}
+
+/// 取得全局变量，未定义时返回空指针
+///
+/// # Safety
+///
+/// engine 由 plua_new 创建且未释放，name 为以 \0 结尾的 UTF-8 字符串
+#[no_mangle]
+pub unsafe extern "C" fn plua_get_global(
+    engine: *mut PluaEngine,
+    name: *const c_char,
+) -> *mut PluaValue {
+    let Some(engine) = engine.as_mut() else {
+        return ptr::null_mut();
+    };
+    if name.is_null() {
+        return engine.fail("name is null".to_string());
+    }
+    let name = match CStr::from_ptr(name).to_str() {
+        Ok(name) => name,
+        Err(e) => return engine.fail(e.to_string()),
+    };
+    match engine.engine.global(name) {
+        Some(value) => {
+            let value = PluaValue::new(value.clone());
+            engine.error = None;
+            value
+        }
+        None => engine.fail(format!("undefined variable {}", name)),
+    }
+}
+
+/// plua_eval/plua_get_global 最近一次出错的信息，成功时返回空指针，在下次调用前有效
+///
+/// # Safety
+///
+/// engine 由 plua_new 创建且未释放
+#[no_mangle]
+pub unsafe extern "C" fn plua_last_error(engine: *const PluaEngine) -> *const c_char {
+    match engine.as_ref().and_then(|engine| engine.error.as_ref()) {
+        Some(error) => error.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// 释放 engine，空指针时什么也不做
+///
+/// # Safety
+///
+/// engine 由 plua_new 创建，且只释放一次
+#[no_mangle]
+pub unsafe extern "C" fn plua_free(engine: *mut PluaEngine) {
+    if !engine.is_null() {
+        drop(Box::from_raw(engine));
+    }
+}
+
+/// 值的类型，空指针视为 nil
+///
+/// # Safety
+///
+/// value 由 plua_eval 或 plua_get_global 返回且未释放
+#[no_mangle]
+pub unsafe extern "C" fn plua_value_type(value: *const PluaValue) -> PluaType {
+    match value.as_ref().map(|value| &value.value) {
+        None | Some(Value::Nil) => PluaType::Nil,
+        Some(Value::Bool(_)) => PluaType::Bool,
+        Some(Value::Int(_)) => PluaType::Int,
+        Some(Value::Float(_)) => PluaType::Float,
+        Some(Value::String(_)) => PluaType::String,
+        Some(Value::Table(_)) => PluaType::Table,
+        Some(Value::Function(..) | Value::Closure(..) | Value::Native(_)) => PluaType::Function,
+    }
+}
+
+/// 整数值，其它类型返回 0
+///
+/// # Safety
+///
+/// 同 plua_value_type
+#[no_mangle]
+pub unsafe extern "C" fn plua_value_int(value: *const PluaValue) -> i64 {
+    match value.as_ref().map(|value| &value.value) {
+        Some(Value::Int(i)) => *i as i64,
+        _ => 0,
+    }
+}
+
+/// 数值，整数转换为浮点数，其它类型返回 0
+///
+/// # Safety
+///
+/// 同 plua_value_type
+#[no_mangle]
+pub unsafe extern "C" fn plua_value_float(value: *const PluaValue) -> f64 {
+    match value.as_ref().map(|value| &value.value) {
+        Some(Value::Int(i)) => *i as f64,
+        Some(Value::Float(f)) => *f as f64,
+        _ => 0.0,
+    }
+}
+
+/// 按脚本的规则判断真假，nil 与 false 为假
+///
+/// # Safety
+///
+/// 同 plua_value_type
+#[no_mangle]
+pub unsafe extern "C" fn plua_value_bool(value: *const PluaValue) -> bool {
+    value.as_ref().is_some_and(|value| value.value.is_truthy())
+}
+
+/// 字符串值，在值释放前有效，其它类型或含有 \0 时返回空指针
+///
+/// # Safety
+///
+/// 同 plua_value_type
+#[no_mangle]
+pub unsafe extern "C" fn plua_value_string(value: *const PluaValue) -> *const c_char {
+    match value.as_ref().and_then(|value| value.string.as_ref()) {
+        Some(s) => s.as_ptr(),
+        None => ptr::null(),
+    }
+}
+
+/// 释放值，空指针时什么也不做
+///
+/// # Safety
+///
+/// value 由 plua_eval 或 plua_get_global 返回，且只释放一次
+#[no_mangle]
+pub unsafe extern "C" fn plua_value_free(value: *mut PluaValue) {
+    if !value.is_null() {
+        drop(Box::from_raw(value));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::{CStr, CString};
+
+    use super::*;
+
+    #[test]
+    fn test_ffi() {
+        let c = |s: &str| CString::new(s).unwrap();
+        unsafe {
+            let engine = plua_new();
+            // 脚本中还没有字符串与浮点数字面量，由宿主函数产生
+            let host = &mut (*engine).engine;
+            host.register_fn("half", |x: f64| x / 2.0);
+            host.register_fn("greet", || "plua");
+            let source = c("local answer = 40 + 2;\nlocal name = greet();\nreturn half(3);");
+            let value = plua_eval(engine, source.as_ptr());
+            assert_eq!(plua_value_type(value), PluaType::Float);
+            assert_eq!(plua_value_float(value), 1.5);
+            plua_value_free(value);
+
+            let answer = plua_get_global(engine, c("answer").as_ptr());
+            assert_eq!(plua_value_type(answer), PluaType::Int);
+            assert_eq!(plua_value_int(answer), 42);
+            assert!(plua_value_bool(answer));
+            assert!(plua_value_string(answer).is_null());
+            plua_value_free(answer);
+
+            let name = plua_get_global(engine, c("name").as_ptr());
+            assert_eq!(plua_value_type(name), PluaType::String);
+            assert_eq!(CStr::from_ptr(plua_value_string(name)).to_str(), Ok("plua"));
+            plua_value_free(name);
+
+            assert!(plua_last_error(engine).is_null());
+            assert!(plua_get_global(engine, c("missing").as_ptr()).is_null());
+            assert_eq!(
+                CStr::from_ptr(plua_last_error(engine)).to_str(),
+                Ok("undefined variable missing")
+            );
+            assert!(plua_eval(engine, c("return 1 / 0;").as_ptr()).is_null());
+            assert!(!plua_last_error(engine).is_null());
+            assert!(plua_eval(engine, ptr::null()).is_null());
+
+            let value = plua_eval(engine, c("local x = 1;").as_ptr());
+            assert_eq!(plua_value_type(value), PluaType::Nil);
+            assert!(plua_last_error(engine).is_null());
+            plua_value_free(value);
+            plua_free(engine);
+        }
+    }
+}
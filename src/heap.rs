@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+use crate::value::Value;
+
+// 堆上对象的种类
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ObjectKind {
+    String,
+    Table,
+    Function,
+    Env,
+}
+
+impl ObjectKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ObjectKind::String => "string",
+            ObjectKind::Table => "table",
+            ObjectKind::Function => "function",
+            ObjectKind::Env => "env",
+        }
+    }
+}
+
+// 记录作用域中分配与释放的堆对象，结束时与仍然可达的对象比较找出泄漏；
+// 只统计存入作用域的值，表达式求值的临时值不计入
+#[derive(Debug, Default, Clone)]
+pub struct Heap {
+    allocated: BTreeMap<ObjectKind, usize>,
+    freed: BTreeMap<ObjectKind, usize>,
+}
+
+impl Heap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn alloc(&mut self, kind: ObjectKind) {
+        *self.allocated.entry(kind).or_default() += 1;
+    }
+
+    pub fn free(&mut self, kind: ObjectKind) {
+        *self.freed.entry(kind).or_default() += 1;
+    }
+
+    // 值本身与表中的元素
+    pub fn alloc_value(&mut self, value: &Value) {
+        objects(value, &mut |kind| self.alloc(kind));
+    }
+
+    pub fn free_value(&mut self, value: &Value) {
+        objects(value, &mut |kind| self.free(kind));
+    }
+
+    // 累计分配的个数
+    pub fn allocated(&self, kind: ObjectKind) -> usize {
+        self.allocated.get(&kind).copied().unwrap_or_default()
+    }
+
+    // 已分配但还没有释放的个数
+    pub fn live(&self, kind: ObjectKind) -> usize {
+        let freed = self.freed.get(&kind).copied().unwrap_or_default();
+        self.allocated(kind).saturating_sub(freed)
+    }
+
+    // 存活但从 envs 个作用域与 roots 中的值都不可达的对象
+    pub fn leaks<'a>(&self, envs: usize, roots: impl IntoIterator<Item = &'a Value>) -> LeakReport {
+        let mut reachable = BTreeMap::new();
+        reachable.insert(ObjectKind::Env, envs);
+        for value in roots {
+            objects(value, &mut |kind| *reachable.entry(kind).or_default() += 1);
+        }
+        let leaks = self
+            .allocated
+            .keys()
+            .map(|&kind| {
+                let reachable = reachable.get(&kind).copied().unwrap_or_default();
+                (kind, self.live(kind).saturating_sub(reachable))
+            })
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        LeakReport { leaks }
+    }
+}
+
+// 值本身与表中元素对应的堆对象
+pub(crate) fn objects(value: &Value, f: &mut impl FnMut(ObjectKind)) {
+    match value {
+        Value::String(_) => f(ObjectKind::String),
+        Value::Table(table) => {
+            f(ObjectKind::Table);
+            for value in table.array.iter().chain(table.hash.values()) {
+                objects(value, f);
+            }
+        }
+        Value::Function(..) | Value::Closure(..) | Value::Native(_) => f(ObjectKind::Function),
+        _ => {}
+    }
+}
+
+// 泄漏的对象种类与个数
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct LeakReport {
+    pub leaks: Vec<(ObjectKind, usize)>,
+}
+
+impl LeakReport {
+    pub fn is_empty(&self) -> bool {
+        self.leaks.is_empty()
+    }
+}
+
+impl Display for LeakReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let leaks: Vec<String> = self
+            .leaks
+            .iter()
+            .map(|(kind, count)| format!("{} {}", count, kind.name()))
+            .collect();
+        write!(f, "leaked {}", leaks.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Heap, ObjectKind};
+    use crate::value::{Table, Value};
+
+    #[test]
+    fn test_heap_leaks() {
+        let mut heap = Heap::new();
+        let table = Value::Table(Table::from_array(vec![
+            Value::String("a".to_string()),
+            Value::Int(1),
+        ]));
+        heap.alloc(ObjectKind::Env);
+        heap.alloc_value(&table);
+        heap.alloc_value(&Value::String("b".to_string()));
+        assert_eq!(heap.live(ObjectKind::String), 2);
+        assert!(heap
+            .leaks(1, [&table, &Value::String("b".to_string())])
+            .is_empty());
+
+        // 作用域已经不可达，其中的值没有释放
+        heap.alloc(ObjectKind::Env);
+        heap.alloc_value(&table);
+        let report = heap.leaks(1, [&table, &Value::String("b".to_string())]);
+        assert_eq!(
+            report.leaks,
+            [
+                (ObjectKind::String, 1),
+                (ObjectKind::Table, 1),
+                (ObjectKind::Env, 1)
+            ]
+        );
+        assert_eq!(report.to_string(), "leaked 1 string, 1 table, 1 env");
+
+        heap.free(ObjectKind::Env);
+        heap.free_value(&table);
+        assert!(heap
+            .leaks(1, [&table, &Value::String("b".to_string())])
+            .is_empty());
+        assert_eq!(heap.allocated(ObjectKind::Table), 2);
+    }
+}
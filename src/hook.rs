@@ -0,0 +1,78 @@
+use std::fmt;
+
+// 调试钩子的事件
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HookEvent {
+    // 开始执行新的一行
+    Line(usize),
+    // 调用函数，包括宿主函数
+    Call(String),
+    // 函数正常返回
+    Return(String),
+    // 每执行 count 步
+    Count,
+}
+
+impl HookEvent {
+    // 与 Lua 中传给钩子的事件名相同
+    pub fn name(&self) -> &'static str {
+        match self {
+            HookEvent::Line(_) => "line",
+            HookEvent::Call(_) => "call",
+            HookEvent::Return(_) => "return",
+            HookEvent::Count => "count",
+        }
+    }
+}
+
+// 触发钩子的事件，count 为 0 时不按步数触发
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HookMask {
+    pub line: bool,
+    pub call: bool,
+    pub count: usize,
+}
+
+impl HookMask {
+    // 与 debug.sethook 的参数相同，mask 中 'l' 为 line，'c' 与 'r' 为 call 与 return
+    pub fn new(mask: &str, count: usize) -> Self {
+        Self {
+            line: mask.contains('l'),
+            call: mask.contains('c') || mask.contains('r'),
+            count,
+        }
+    }
+}
+
+type NativeHook = Box<dyn FnMut(&HookEvent) -> Result<(), String> + Send>;
+
+// 钩子的回调，返回错误时中止脚本，可以用于实现 watchdog
+pub enum HookFn {
+    Native(NativeHook),
+    // 脚本中的全局函数，以 (事件名, 行号) 调用，非 line 事件的行号为 nil
+    Script(String),
+}
+
+impl HookFn {
+    pub fn native<F>(f: F) -> Self
+    where
+        F: FnMut(&HookEvent) -> Result<(), String> + Send + 'static,
+    {
+        HookFn::Native(Box::new(f))
+    }
+}
+
+impl fmt::Debug for HookFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HookFn::Native(_) => f.write_str("Native"),
+            HookFn::Script(name) => write!(f, "Script({})", name),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Hook {
+    pub func: HookFn,
+    pub mask: HookMask,
+}
@@ -1,51 +1,54 @@
-use std::collections::HashMap;
-use std::ptr::NonNull;
+use std::collections::BTreeMap;
 
+use crate::ast::to_source;
+use crate::coverage::Coverage;
 use crate::error::Error;
 use crate::expression::Expr;
-use crate::scanner::TokenType;
+use crate::heap::{Heap, ObjectKind};
+use crate::hook::{Hook, HookEvent, HookFn, HookMask};
+use crate::native::NativeFunction;
+use crate::profiler::Profiler;
+use crate::resolver::stmt_token;
+use crate::scanner::{Token, TokenType};
 use crate::statement::Stmt;
-use crate::value::Value;
+use crate::stdio::Stdio;
+use crate::trace::{TraceFrame, Traceback, Tracer};
+use crate::value::{Table, Value};
+use crate::vm::{Limits, Stats};
 
-type Link = Option<NonNull<Env>>;
+// 函数调用与表达式求值的最大嵌套深度，超出时报错而不是耗尽宿主的栈(测试线程的栈只有 2MB)
+pub const MAX_CALL_DEPTH: usize = 128;
+const MAX_EXPR_DEPTH: usize = 200;
 
-#[derive(Debug)]
+// 作用域，父作用域由子作用域持有，不使用裸指针以便解释器可以在线程间转移
+#[derive(Debug, Default)]
 pub struct Env {
-    values: HashMap<String, Value>,
-    parent: Link,
+    values: BTreeMap<String, Value>,
+    parent: Option<Box<Env>>,
 }
 
 impl Env {
     pub fn new() -> Self {
-        Self {
-            values: HashMap::new(),
-            parent: None,
-        }
+        Self::default()
     }
 
     pub fn new_with_parent(parent: Box<Env>) -> Self {
-        let p = unsafe { NonNull::new_unchecked(Box::into_raw(parent)) };
         Self {
-            values: HashMap::new(),
-            parent: Some(p),
+            values: BTreeMap::new(),
+            parent: Some(parent),
         }
     }
 
-    pub fn new_ptr() -> NonNull<Self> {
-        unsafe { NonNull::new_unchecked(Box::into_raw(Box::new(Self::new()))) }
+    pub fn define(&mut self, key: &str, value: Value) {
+        self.values.insert(key.to_string(), value);
     }
 
-    pub fn new_ptr_with_parent(parent: NonNull<Env>) -> NonNull<Self> {
-        unsafe {
-            NonNull::new_unchecked(Box::into_raw(Box::new(Self {
-                values: HashMap::new(),
-                parent: Some(parent),
-            })))
+    // 在当前与外层的作用域中查找
+    pub fn get_mut(&mut self, key: &str) -> Option<&mut Value> {
+        if self.values.contains_key(key) {
+            return self.values.get_mut(key);
         }
-    }
-
-    pub fn define(&mut self, key: &str, value: Value) {
-        self.values.insert(key.to_string(), value.clone());
+        self.parent.as_deref_mut()?.get_mut(key)
     }
 
     pub fn get(&self, key: &str) -> Option<&Value> {
@@ -59,33 +62,238 @@ impl Env {
     }
 
     pub fn parent(&self) -> Option<&Env> {
-        unsafe { self.parent.map(|node| &(*node.as_ptr())) }
+        self.parent.as_deref()
     }
 
     pub fn parent_mut(&mut self) -> Option<&mut Env> {
-        unsafe { self.parent.map(|node| &mut (*node.as_ptr())) }
+        self.parent.as_deref_mut()
+    }
+
+    // 取出父作用域，丢弃当前作用域
+    pub fn into_parent(self) -> Option<Env> {
+        self.parent.map(|parent| *parent)
+    }
+
+    // 最外层的全局作用域
+    pub fn root(&self) -> &Env {
+        match self.parent() {
+            Some(parent) => parent.root(),
+            None => self,
+        }
+    }
+
+    pub fn root_mut(&mut self) -> &mut Env {
+        match self.parent {
+            Some(ref mut parent) => parent.root_mut(),
+            None => self,
+        }
     }
 }
 
 #[derive(Debug)]
 pub struct Intercepter {
-    current_env: NonNull<Env>,
+    current_env: Env,
+    stats: Stats,
+    // 当前表达式求值的嵌套深度
+    depth: usize,
+    // 执行了 break，直到最内层的循环结束
+    breaking: bool,
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+    // 开启泄漏检查时记录作用域中的堆对象
+    heap: Option<Heap>,
+    limits: Limits,
+    // 所有作用域中存活的变量个数
+    live_values: usize,
+    tracer: Option<Tracer>,
+    // 调试钩子，执行钩子期间取出，不会递归触发
+    hook: Option<Hook>,
+    // 上一次 line 事件的行
+    hook_line: usize,
+    // 当前函数调用的嵌套深度，用于缩进轨迹
+    call_depth: usize,
+    max_call_depth: usize,
+    // 正在执行的函数名与调用处的行
+    frames: Vec<(String, usize)>,
+    // 最近一次出错时的调用栈
+    traceback: Option<Traceback>,
+    // print 的输出
+    stdio: Stdio,
 }
 
 impl Intercepter {
     pub fn new() -> Self {
-        let mut global_env = Env::new_ptr();
-        unsafe {
-            global_env.as_mut().define("VERSION", Value::Int(1));
-        }
+        let mut global_env = Env::new();
+        global_env.define("VERSION", Value::Int(1));
         Self {
             current_env: global_env,
+            stats: Stats::default(),
+            depth: 0,
+            breaking: false,
+            profiler: None,
+            coverage: None,
+            heap: None,
+            limits: Limits::default(),
+            live_values: 1,
+            tracer: None,
+            hook: None,
+            hook_line: 0,
+            call_depth: 0,
+            max_call_depth: MAX_CALL_DEPTH,
+            frames: vec![],
+            traceback: None,
+            stdio: Stdio::default(),
+        }
+    }
+
+    // 设置运行限制，steps 为求值的语句与表达式个数
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    // 函数调用的最大嵌套深度，不能超过 MAX_CALL_DEPTH
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth.min(MAX_CALL_DEPTH);
+    }
+
+    // 替换 print 输出到的流
+    pub fn set_stdio(&mut self, stdio: Stdio) {
+        self.stdio = stdio;
+    }
+
+    // 记录执行的语句与函数调用、返回
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    // 设置调试钩子，与 debug.sethook 相同，替换之前的钩子
+    pub fn set_hook(&mut self, func: HookFn, mask: HookMask) {
+        self.hook = Some(Hook { func, mask });
+        self.hook_line = 0;
+    }
+
+    pub fn clear_hook(&mut self) {
+        self.hook = None;
+    }
+
+    // 开启函数级别的 profile
+    pub fn enable_profiler(&mut self) {
+        self.profiler = Some(Profiler::new());
+    }
+
+    // 取出 profiler，结束所有未返回的调用
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        let mut profiler = self.profiler.take()?;
+        profiler.finish();
+        Some(profiler)
+    }
+
+    // 开启行覆盖率统计
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Coverage::new());
+    }
+
+    pub fn take_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take()
+    }
+
+    // 开启泄漏检查，已经存在的作用域与变量也计入
+    pub fn enable_leak_check(&mut self) {
+        let mut heap = Heap::new();
+        let mut env = Some(&self.current_env);
+        while let Some(e) = env {
+            heap.alloc(ObjectKind::Env);
+            e.values.values().for_each(|value| heap.alloc_value(value));
+            env = e.parent();
+        }
+        self.heap = Some(heap);
+    }
+
+    pub fn heap(&self) -> Option<&Heap> {
+        self.heap.as_ref()
+    }
+
+    // 检查存活但已经不可达的对象，没有开启泄漏检查时总是成功
+    pub fn check_leaks(&self) -> Result<(), Error> {
+        let Some(heap) = self.heap.as_ref() else {
+            return Ok(());
+        };
+        let mut envs = vec![];
+        let mut env = Some(&self.current_env);
+        while let Some(e) = env {
+            envs.push(e);
+            env = e.parent();
+        }
+        let report = heap.leaks(envs.len(), envs.iter().flat_map(|e| e.values.values()));
+        match report.is_empty() {
+            true => Ok(()),
+            false => Err(Error::LeakError(report.to_string())),
+        }
+    }
+
+    // 运行统计，instructions 为求值的语句与表达式个数，max_stack 为表达式嵌套的最大深度
+    pub fn stats(&self) -> &Stats {
+        &self.stats
+    }
+
+    // 定义全局变量
+    pub fn define_global(&mut self, name: &str, value: Value) {
+        let env = self.current_env.root_mut();
+        if !env.values.contains_key(name) {
+            self.live_values += 1;
+        }
+        if let Some(heap) = self.heap.as_mut() {
+            track_store(heap, env.values.get(name), &value);
         }
+        env.define(name, value);
+    }
+
+    // 全局变量的值
+    pub fn global(&self, name: &str) -> Option<&Value> {
+        self.current_env.root().values.get(name)
+    }
+
+    // 从宿主调用全局函数，出错时同样记录调用栈
+    pub fn call(&mut self, name: &str, args: Vec<Value>) -> Result<Value, Error> {
+        self.traceback = None;
+        self.call_global(name, args)
+    }
+
+    fn call_global(&mut self, name: &str, args: Vec<Value>) -> Result<Value, Error> {
+        let func = self
+            .global(name)
+            .cloned()
+            .ok_or_else(|| Error::RuntimeError(format!("undefined function {}", name)))?;
+        match func {
+            Value::Function(name, params, block) => {
+                self.call_function(&name, params, &block, args, 0)
+            }
+            Value::Native(native) => native
+                .call(&args)
+                .map_err(|message| Error::RuntimeError(format!("{}: {}", name, message))),
+            _ => Err(Error::TypeError(format!(
+                "{} is a {}, not a function",
+                name,
+                func.type_name()
+            ))),
+        }
+    }
+
+    // 取出最近一次 eval 出错时的调用栈
+    pub fn take_traceback(&mut self) -> Option<Traceback> {
+        self.traceback.take()
     }
 
     pub fn eval(&mut self, statements: &Vec<Stmt>) -> Result<Value, Error> {
+        self.traceback = None;
+        self.breaking = false;
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.add_statements(statements);
+        }
         for stmt in statements {
-            let val = self.execute_stmt(stmt)?;
+            let val = self
+                .execute_stmt(stmt)
+                .map_err(|e| self.record_traceback(e))?;
             if val != Value::Nil {
                 return Ok(val);
             }
@@ -93,11 +301,84 @@ impl Intercepter {
         Ok(Value::Nil)
     }
 
+    // 错误第一次离开函数(或主程序)时记录调用栈，外层不再覆盖
+    fn record_traceback(&mut self, e: Error) -> Error {
+        if self.traceback.is_none() {
+            let mut line = e.location().map(|(line, _)| line);
+            let mut frames = vec![];
+            for (name, call_line) in self.frames.iter().rev() {
+                frames.push(TraceFrame {
+                    function: Some(name.clone()),
+                    line,
+                });
+                line = Some(*call_line);
+            }
+            frames.push(TraceFrame {
+                function: None,
+                line,
+            });
+            self.traceback = Some(Traceback { frames });
+        }
+        e
+    }
+
+    // 触发调试钩子，钩子中的错误中止脚本
+    fn run_hook(&mut self, event: HookEvent) -> Result<(), Error> {
+        let Some(mut hook) = self.hook.take() else {
+            return Ok(());
+        };
+        let result = match &mut hook.func {
+            HookFn::Native(f) => {
+                f(&event).map_err(|message| Error::RuntimeError(format!("hook: {}", message)))
+            }
+            HookFn::Script(name) => {
+                let line = match event {
+                    HookEvent::Line(line) => Value::Int(line as i32),
+                    _ => Value::Nil,
+                };
+                let args = vec![Value::String(event.name().to_string()), line];
+                self.call_global(name, args).map(|_| ())
+            }
+        };
+        // 钩子中没有设置新的钩子时放回
+        self.hook.get_or_insert(hook);
+        result
+    }
+
+    // 执行一步：检查运行限制，每执行 count 步触发一次 count 事件
+    fn step(&mut self) -> Result<(), Error> {
+        self.stats.instructions += 1;
+        self.limits
+            .check(self.stats.instructions, self.live_values)?;
+        match self.hook.as_ref().map(|hook| hook.mask.count) {
+            Some(count) if count > 0 && self.stats.instructions.is_multiple_of(count) => {
+                self.run_hook(HookEvent::Count)
+            }
+            _ => Ok(()),
+        }
+    }
+
     fn execute_stmt(&mut self, stmt: &Stmt) -> Result<Value, Error> {
+        self.step()?;
+        // 块内的语句会单独记录
+        let traced = !matches!(stmt, Stmt::Block(_) | Stmt::None);
+        if let (Some(tracer), true) = (self.tracer.as_mut(), traced) {
+            let source = to_source(std::slice::from_ref(stmt));
+            let line = source.lines().next().unwrap_or_default();
+            tracer.log(format_args!("{}{}", "  ".repeat(self.call_depth), line));
+        }
+        if let (Some(coverage), true) = (self.coverage.as_mut(), traced) {
+            coverage.hit(stmt);
+        }
+        if traced && self.hook.is_some() {
+            self.line_hook(stmt)?;
+        }
         match stmt {
             Stmt::PrintStmt(expr) => {
                 let value = self.execute_expr(expr)?;
-                println!("{}", value);
+                self.stdio
+                    .write(format_args!("{}\n", value))
+                    .map_err(|e| Error::RuntimeError(e.to_string()))?;
                 Ok(Value::Nil)
             }
             Stmt::IfStmt(condition, if_stmt, else_stmt) => {
@@ -108,61 +389,172 @@ impl Intercepter {
                     self.execute_stmt(else_stmt)
                 }
             }
+            Stmt::WhileStmt(condition, body) => self.execute_while(condition, body),
+            Stmt::RepeatStmt(body, condition) => self.execute_repeat(body, condition),
             Stmt::LocalStmt(token, expr) => {
                 let value = self.execute_expr(expr)?;
-                self.assign_variable(token.raw.as_str(), value)?;
+                self.assign_variable(token.raw.as_ref(), value)?;
                 Ok(Value::Nil)
             }
+            Stmt::LocalListStmt(names, values) => self.execute_local_list(names, values),
             Stmt::FunctionStmt(name, params, block) => {
                 let func = Value::Function(
-                    name.raw.clone(),
-                    params.iter().map(|p| p.raw.clone()).collect(),
+                    name.raw.to_string(),
+                    params.iter().map(|p| p.raw.to_string()).collect(),
                     block.clone(),
                 );
-                self.assign_variable(name.raw.as_str(), func)?;
+                self.assign_variable(name.raw.as_ref(), func)?;
                 Ok(Value::Nil)
             }
+            Stmt::AssignStmt(name, value) => self.execute_assign(name, value),
             Stmt::ReturnStmt(_token, expr) => {
                 let value = self.execute_expr(expr)?;
                 Ok(value)
             }
+            Stmt::BreakStmt(_) => {
+                self.breaking = true;
+                Ok(Value::Nil)
+            }
             Stmt::Expression(expr) => self.execute_expr(expr),
-            Stmt::Block(stmts) => self.execute_block(stmts, HashMap::new()),
+            Stmt::Block(stmts) => self.execute_block(stmts, BTreeMap::new()),
             Stmt::None => Ok(Value::Nil),
         }
     }
 
+    // 与 Lua 相同，没有可见的变量时赋值给全局变量
+    fn execute_assign(&mut self, name: &Token, value: &Expr) -> Result<Value, Error> {
+        let value = self.execute_expr(value)?;
+        match self.current_env.get_mut(name.raw.as_ref()) {
+            Some(slot) => {
+                if let Some(heap) = self.heap.as_mut() {
+                    track_store(heap, Some(slot), &value);
+                }
+                *slot = value;
+            }
+            None => self.define_global(name.raw.as_ref(), value),
+        }
+        Ok(Value::Nil)
+    }
+
+    // 先求出所有的值再依次赋值
+    fn execute_local_list(&mut self, names: &[Token], values: &[Expr]) -> Result<Value, Error> {
+        let mut evaluated = Vec::with_capacity(values.len());
+        for value in values {
+            evaluated.push(self.execute_expr(value)?);
+        }
+        let mut evaluated = evaluated.into_iter();
+        for name in names {
+            let value = evaluated.next().unwrap_or(Value::Nil);
+            self.assign_variable(name.raw.as_ref(), value)?;
+        }
+        Ok(Value::Nil)
+    }
+
+    // 每次迭代的循环体都在新的作用域中执行，返回非 nil 值或 break 时结束循环
+    fn execute_while(&mut self, condition: &Expr, body: &[Stmt]) -> Result<Value, Error> {
+        while self.execute_expr(condition)?.is_truthy() {
+            let value = self.execute_block(body, BTreeMap::new())?;
+            if std::mem::take(&mut self.breaking) || value != Value::Nil {
+                return Ok(value);
+            }
+        }
+        Ok(Value::Nil)
+    }
+
+    // 循环体至少执行一次，之后条件为真时结束循环
+    fn execute_repeat(&mut self, body: &[Stmt], condition: &Expr) -> Result<Value, Error> {
+        loop {
+            // 条件与循环体在同一个作用域中求值，每次迭代结束后丢弃
+            self.push_env();
+            let done = self.execute_repeat_iteration(body, condition);
+            self.pop_env();
+            if let Some(value) = done? {
+                return Ok(value);
+            }
+        }
+    }
+
+    // 循环结束时返回 Some
+    fn execute_repeat_iteration(
+        &mut self,
+        body: &[Stmt],
+        condition: &Expr,
+    ) -> Result<Option<Value>, Error> {
+        let value = self.execute_stmts(body, BTreeMap::new())?;
+        if std::mem::take(&mut self.breaking) || value != Value::Nil {
+            return Ok(Some(value));
+        }
+        if self.execute_expr(condition)?.is_truthy() {
+            return Ok(Some(Value::Nil));
+        }
+        Ok(None)
+    }
+
     fn execute_block(
         &mut self,
-        stmts: &Vec<Stmt>,
-        params: HashMap<String, Value>,
+        stmts: &[Stmt],
+        params: BTreeMap<String, Value>,
     ) -> Result<Value, Error> {
-        let mut value = Value::Nil;
+        self.push_env();
+        let value = self.execute_stmts(stmts, params);
+        // Drop the env of the current block, also on error
+        self.pop_env();
+        value
+    }
 
-        let current_env = self.current_env;
-        self.current_env = Env::new_ptr_with_parent(self.current_env);
+    fn push_env(&mut self) {
+        let parent = std::mem::take(&mut self.current_env);
+        self.current_env = Env::new_with_parent(Box::new(parent));
+        if let Some(heap) = self.heap.as_mut() {
+            heap.alloc(ObjectKind::Env);
+        }
+    }
 
+    fn pop_env(&mut self) {
+        let env = std::mem::take(&mut self.current_env);
+        self.live_values -= env.values.len();
+        if let Some(heap) = self.heap.as_mut() {
+            heap.free(ObjectKind::Env);
+            env.values.values().for_each(|value| heap.free_value(value));
+        }
+        self.current_env = env.into_parent().unwrap_or_default();
+    }
+
+    fn execute_stmts(
+        &mut self,
+        stmts: &[Stmt],
+        params: BTreeMap<String, Value>,
+    ) -> Result<Value, Error> {
+        let mut value = Value::Nil;
         for (key, param) in params.into_iter() {
             self.assign_variable(key.as_str(), param)?;
         }
         for stmt in stmts {
             value = self.execute_stmt(stmt)?;
-            if value != Value::Nil {
+            if value != Value::Nil || self.breaking {
                 break;
             }
         }
-
-        // Drop the env of the current block
-        let boxed: Box<Env> = Box::into(unsafe { Box::from_raw(self.current_env.as_ptr()) });
-        drop(boxed);
-
-        self.current_env = current_env;
         Ok(value)
     }
 
     fn execute_expr(&mut self, expr: &Expr) -> Result<Value, Error> {
+        self.step()?;
+        if self.depth >= MAX_EXPR_DEPTH {
+            return Err(Error::LimitError(
+                "expression nested too deeply".to_string(),
+            ));
+        }
+        self.depth += 1;
+        self.stats.max_stack = self.stats.max_stack.max(self.depth);
+        let value = self.evaluate_expr(expr);
+        self.depth -= 1;
+        value
+    }
+
+    fn evaluate_expr(&mut self, expr: &Expr) -> Result<Value, Error> {
         match expr {
-            Expr::Call(callee, _token, params) => {
+            Expr::Call(callee, paren, params) => {
                 let func = self.execute_expr(callee)?;
                 let mut values = vec![];
                 for param in params {
@@ -170,103 +562,289 @@ impl Intercepter {
                     values.push(value);
                 }
                 match func {
-                    Value::Function(_name, params, block) => {
-                        let mut params_map = HashMap::new();
-                        for (i, value) in values.into_iter().enumerate() {
-                            params_map.insert(params[i].clone(), value);
+                    Value::Function(name, params, block) => {
+                        if self.call_depth >= self.max_call_depth {
+                            return Err(Error::InterceptError {
+                                message: "stack overflow".to_string(),
+                                line: paren.line,
+                                col: paren.col,
+                                span: paren.span,
+                            });
                         }
-                        let value = self.execute_block(&block, params_map)?;
-                        // println!("return value: {}", value);
-                        Ok(value)
+                        self.call_function(&name, params, &block, values, paren.line)
                     }
-                    _ => Err(Error::InterceptError(format!("{} is not Callable", func))),
+                    Value::Native(native) => self.call_native(&native, values, paren),
+                    _ => Err(Error::InterceptError {
+                        message: format!("{} is not Callable", func),
+                        line: paren.line,
+                        col: paren.col,
+                        span: paren.span,
+                    }),
                 }
             }
             Expr::Unary(operator, expr) => {
                 let value = self.execute_expr(expr)?;
                 match operator.typ {
                     TokenType::Minus => match value {
-                        Value::Int(val) => Ok(Value::Int(-val)),
-                        _ => Err(Error::InterceptError(format!(
-                            "Unexpected unary operator {:?}",
-                            operator
-                        )))?,
+                        Value::Int(val) => Ok(Value::Int(val.wrapping_neg())),
+                        Value::Float(val) => Ok(Value::Float(-val)),
+                        _ => Err(unexpected_operator(operator))?,
                     },
                     TokenType::Bang => Ok(Value::Bool(!value.is_truthy())),
-                    _ => Err(Error::InterceptError(format!(
-                        "Unexpected unary operator {:?}",
-                        operator
-                    )))?,
+                    _ => Err(unexpected_operator(operator))?,
                 }
             }
             Expr::Variable(token) => {
-                let value = self.lookup_variable(token.raw.as_str())?;
+                let value = self.lookup_variable(token)?;
                 Ok(value.clone())
             }
             Expr::Assign(token, expr) => {
-                let _ = self.lookup_variable(token.raw.as_str())?;
+                let _ = self.lookup_variable(token)?;
                 let value = self.execute_expr(expr)?;
-                self.assign_variable(token.raw.as_str(), value)?;
+                self.assign_variable(token.raw.as_ref(), value)?;
 
                 Ok(Value::Nil)
             }
-            Expr::Binary(left, token, right) => {
-                let left_val = self.execute_expr(left)?;
-                let right_val = self.execute_expr(right)?;
-                match token.typ {
-                    TokenType::Minus => return Ok(left_val - right_val),
-                    TokenType::Plus => return Ok(left_val + right_val),
-                    TokenType::Star => return Ok(left_val * right_val),
-                    TokenType::Slash => return Ok(left_val / right_val),
-                    TokenType::BangEqual => return Ok(Value::Bool(left_val != right_val)),
-                    TokenType::EqualEqual => return Ok(Value::Bool(left_val == right_val)),
-                    TokenType::Greater => return Ok(Value::Bool(left_val > right_val)),
-                    TokenType::GreaterEqual => return Ok(Value::Bool(left_val >= right_val)),
-                    TokenType::Less => return Ok(Value::Bool(left_val < right_val)),
-                    TokenType::LessEqual => return Ok(Value::Bool(left_val <= right_val)),
-                    _ => {
-                        return Err(Error::InterceptError(format!(
-                            "Unexpected binary operator {:?}",
-                            token
-                        )))?
-                    }
-                }
-            }
+            Expr::Logical(left, operator, right) => self.evaluate_logical(left, operator, right),
+            Expr::Table(brace, fields) => self.evaluate_table(brace, fields),
+            Expr::Index(table, token, key) => self.evaluate_index(table, token, key),
+            Expr::Binary(left, token, right) => self.evaluate_binary(left, token, right),
             Expr::Literal(val) => Ok(val.clone()),
             Expr::None => Ok(Value::Nil),
         }
     }
 
-    fn lookup_variable(&self, name: &str) -> Result<&Value, Error> {
-        let env = unsafe { self.current_env.as_ref() };
-        env.get(name)
-            .ok_or_else(|| Error::InterceptError(format!("Undefined variable {}", name)))
+    fn evaluate_binary(
+        &mut self,
+        left: &Expr,
+        token: &Token,
+        right: &Expr,
+    ) -> Result<Value, Error> {
+        let left_val = self.execute_expr(left)?;
+        let right_val = self.execute_expr(right)?;
+        match token.typ {
+            TokenType::Minus => Ok(left_val - right_val),
+            TokenType::Plus => Ok(left_val + right_val),
+            TokenType::Star => Ok(left_val * right_val),
+            TokenType::Slash => left_val
+                .checked_div(right_val)
+                .ok_or_else(|| intercept_error(token, "attempt to divide by zero".to_string())),
+            TokenType::Percent => left_val.checked_rem(right_val).ok_or_else(|| {
+                intercept_error(token, "attempt to perform modulo by zero".to_string())
+            }),
+            TokenType::Caret => Ok(left_val.pow(right_val)),
+            TokenType::DotDot => concat(token, &left_val, &right_val),
+            TokenType::BangEqual => Ok(Value::Bool(left_val != right_val)),
+            TokenType::EqualEqual => Ok(Value::Bool(left_val == right_val)),
+            TokenType::Greater => Ok(Value::Bool(left_val > right_val)),
+            TokenType::GreaterEqual => Ok(Value::Bool(left_val >= right_val)),
+            TokenType::Less => Ok(Value::Bool(left_val < right_val)),
+            TokenType::LessEqual => Ok(Value::Bool(left_val <= right_val)),
+            _ => Err(unexpected_operator(token)),
+        }
+    }
+
+    // 与 Lua 相同，结果是决定真假的那个操作数
+    fn evaluate_logical(
+        &mut self,
+        left: &Expr,
+        operator: &Token,
+        right: &Expr,
+    ) -> Result<Value, Error> {
+        let left = self.execute_expr(left)?;
+        match (operator.typ, left.is_truthy()) {
+            (TokenType::And, false) | (TokenType::Or, true) => Ok(left),
+            _ => self.execute_expr(right),
+        }
+    }
+
+    // 按位置排列的元素依次放入数组部分
+    fn evaluate_table(&mut self, brace: &Token, fields: &[(Expr, Expr)]) -> Result<Value, Error> {
+        let mut table = Table::new();
+        for (key, value) in fields {
+            let value = self.execute_expr(value)?;
+            if matches!(key, Expr::None) {
+                table.array.push(value);
+                continue;
+            }
+            let key = self.execute_expr(key)?;
+            if !table.set(&key, value) {
+                return Err(intercept_error(brace, format!("invalid table key {}", key)));
+            }
+        }
+        Ok(Value::Table(table))
+    }
+
+    fn evaluate_index(&mut self, table: &Expr, token: &Token, key: &Expr) -> Result<Value, Error> {
+        let table = self.execute_expr(table)?;
+        let key = self.execute_expr(key)?;
+        match table {
+            Value::Table(table) => Ok(table.get(&key)),
+            _ => Err(intercept_error(
+                token,
+                format!("{} value is not indexable", table.type_name()),
+            )),
+        }
+    }
+
+    // 调用脚本中定义的函数，line 为调用处的行
+    fn call_function(
+        &mut self,
+        name: &str,
+        params: Vec<String>,
+        block: &[Stmt],
+        values: Vec<Value>,
+        line: usize,
+    ) -> Result<Value, Error> {
+        self.stats.calls += 1;
+        self.call_hook(name, false)?;
+        // 多余的实参丢弃
+        let params_map: BTreeMap<_, _> = params.into_iter().zip(values).collect();
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.enter(name);
+        }
+        if let Some(tracer) = self.tracer.as_mut() {
+            let indent = "  ".repeat(self.call_depth);
+            tracer.log(format_args!("{}-> call {}", indent, name));
+        }
+        self.call_depth += 1;
+        self.frames.push((name.to_string(), line));
+        let value = self
+            .execute_block(block, params_map)
+            .map_err(|e| self.record_traceback(e));
+        // 不在循环中的 break 不会离开函数
+        self.breaking = false;
+        self.frames.pop();
+        self.call_depth -= 1;
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.exit();
+        }
+        if let (Some(tracer), Ok(value)) = (self.tracer.as_mut(), &value) {
+            let indent = "  ".repeat(self.call_depth);
+            tracer.log(format_args!("{}<- {} returned {}", indent, name, value));
+        }
+        if value.is_ok() {
+            self.call_hook(name, true)?;
+        }
+        value
+    }
+
+    // 调用宿主函数
+    fn call_native(
+        &mut self,
+        native: &NativeFunction,
+        values: Vec<Value>,
+        paren: &Token,
+    ) -> Result<Value, Error> {
+        self.stats.calls += 1;
+        self.call_hook(&native.name, false)?;
+        let value = native
+            .call(&values)
+            .map_err(|message| Error::InterceptError {
+                message: format!("{}: {}", native.name, message),
+                line: paren.line,
+                col: paren.col,
+                span: paren.span,
+            })?;
+        self.call_hook(&native.name, true)?;
+        Ok(value)
+    }
+
+    // call 与 return 事件；钩子相关的代码放在单独的函数中，
+    // 不增大 execute_stmt 等递归函数的栈帧
+    fn call_hook(&mut self, name: &str, returned: bool) -> Result<(), Error> {
+        match self.hook.as_ref() {
+            Some(hook) if hook.mask.call => {
+                let event = match returned {
+                    true => HookEvent::Return(name.to_string()),
+                    false => HookEvent::Call(name.to_string()),
+                };
+                self.run_hook(event)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // 语句的行与上一次不同时触发 line 事件
+    fn line_hook(&mut self, stmt: &Stmt) -> Result<(), Error> {
+        if !self.hook.as_ref().is_some_and(|hook| hook.mask.line) {
+            return Ok(());
+        }
+        match stmt_token(stmt).map(|token| token.line) {
+            Some(line) if line != self.hook_line => {
+                self.hook_line = line;
+                self.run_hook(HookEvent::Line(line))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn lookup_variable(&self, name: &Token) -> Result<&Value, Error> {
+        self.current_env
+            .get(name.raw.as_ref())
+            .ok_or_else(|| Error::InterceptError {
+                message: format!("Undefined variable {}", name.raw),
+                line: name.line,
+                col: name.col,
+                span: name.span,
+            })
     }
 
     fn assign_variable(&mut self, name: &str, value: Value) -> Result<(), Error> {
-        let env = unsafe { self.current_env.as_mut() };
+        let env = &mut self.current_env;
+        if !env.values.contains_key(name) {
+            self.live_values += 1;
+        }
+        if let Some(heap) = self.heap.as_mut() {
+            track_store(heap, env.values.get(name), &value);
+        }
         env.define(name, value);
         Ok(())
     }
 }
 
-impl Drop for Intercepter {
-    fn drop(&mut self) {
-        let boxed: Box<Env> = Box::into(unsafe { Box::from_raw(self.current_env.as_ptr()) });
-        drop(boxed);
+// 覆盖变量时释放旧值
+fn track_store(heap: &mut Heap, old: Option<&Value>, new: &Value) {
+    if let Some(old) = old {
+        heap.free_value(old);
     }
+    heap.alloc_value(new);
+}
+
+fn intercept_error(token: &Token, message: String) -> Error {
+    Error::InterceptError {
+        message,
+        line: token.line,
+        col: token.col,
+        span: token.span,
+    }
+}
+
+fn concat(operator: &Token, left: &Value, right: &Value) -> Result<Value, Error> {
+    left.concat(right).ok_or_else(|| {
+        let culprit = match left {
+            Value::String(_) | Value::Int(_) | Value::Float(_) => right,
+            _ => left,
+        };
+        let message = format!("attempt to concatenate a {} value", culprit.type_name());
+        intercept_error(operator, message)
+    })
+}
+
+fn unexpected_operator(operator: &Token) -> Error {
+    intercept_error(operator, format!("Unexpected operator {}", operator.raw))
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::value::Table;
     use crate::{parser::Parser, scanner::Scanner};
 
     use super::*;
 
     #[test]
     fn env_basic_operations() {
-        let env = Env::new_ptr();
-        let env = unsafe { &mut (*env.as_ptr()) };
+        let mut env = Env::new();
         env.define("a", Value::Int(1));
         env.define("b", Value::Int(2));
         env.define("c", Value::Int(3));
@@ -280,14 +858,12 @@ mod tests {
 
     #[test]
     fn env_with_parent() {
-        let parent = Env::new_ptr();
-        let parent_ref = unsafe { &mut (*parent.as_ptr()) };
-        parent_ref.define("a", Value::Int(1));
-        parent_ref.define("b", Value::Int(2));
-        parent_ref.define("c", Value::Int(3));
-
-        let env_raw = Env::new_ptr_with_parent(parent);
-        let env = unsafe { &mut (*env_raw.as_ptr()) };
+        let mut parent = Env::new();
+        parent.define("a", Value::Int(1));
+        parent.define("b", Value::Int(2));
+        parent.define("c", Value::Int(3));
+
+        let mut env = Env::new_with_parent(Box::new(parent));
         env.define("d", Value::Int(4));
         env.define("e", Value::Int(5));
         env.define("f", Value::Int(6));
@@ -300,8 +876,7 @@ mod tests {
         assert_eq!(env.get("f").unwrap(), &Value::Int(6));
         assert_eq!(env.get("g"), None);
 
-        let env_raw = Env::new_ptr_with_parent(env_raw);
-        let env = unsafe { &mut (*env_raw.as_ptr()) };
+        let mut env = Env::new_with_parent(Box::new(env));
         env.define("g", Value::Int(7));
         assert_eq!(env.get("g").unwrap(), &Value::Int(7));
         assert_eq!(env.get("a").unwrap(), &Value::Int(1));
@@ -331,6 +906,22 @@ mod tests {
         assert_eq!(result.unwrap(), Value::Nil);
     }
 
+    #[test]
+    fn intercepter_define_global() {
+        let script = r#"
+        return n;
+        "#;
+        let mut scanner = Scanner::new(script.to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens.clone());
+        let statements = parser.parse().unwrap();
+
+        let mut intercepter = Intercepter::new();
+        intercepter.define_global("n", Value::Int(3));
+        let result = intercepter.eval(&statements);
+        assert_eq!(result.unwrap(), Value::Int(3));
+    }
+
     #[test]
     fn intercepter_print_variable() {
         let script = r#"
@@ -415,6 +1006,7 @@ mod tests {
         let mut intercepter = Intercepter::new();
         let result = intercepter.eval(&statements);
         assert_eq!(result.unwrap(), Value::Nil);
+        assert_eq!(intercepter.stats().calls, 4);
     }
 
     #[test]
@@ -472,4 +1064,514 @@ mod tests {
         let result = intercepter.eval(&statements);
         assert_eq!(result.unwrap(), Value::Int(12));
     }
+
+    #[test]
+    fn intercepter_limits() {
+        let script = r#"
+        function f(n)
+          if n < 1 then
+            return 0;
+          end
+          return f(n - 1);
+        end
+
+        return f(100);
+        "#;
+        let mut scanner = Scanner::new(script.to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens.clone());
+        let statements = parser.parse().unwrap();
+
+        let mut intercepter = Intercepter::new();
+        intercepter.set_limits(Limits {
+            max_steps: Some(50),
+            max_memory: None,
+        });
+        let result = intercepter.eval(&statements);
+        assert!(matches!(result, Err(Error::LimitError(_))));
+
+        let mut intercepter = Intercepter::new();
+        intercepter.set_limits(Limits {
+            max_steps: None,
+            max_memory: Some(20 * std::mem::size_of::<Value>()),
+        });
+        let result = intercepter.eval(&statements);
+        assert!(matches!(result, Err(Error::LimitError(_))));
+
+        let mut intercepter = Intercepter::new();
+        intercepter.set_limits(Limits {
+            max_steps: Some(10_000),
+            max_memory: Some(200 * std::mem::size_of::<Value>()),
+        });
+        assert_eq!(intercepter.eval(&statements).unwrap(), Value::Int(0));
+    }
+
+    #[test]
+    fn intercepter_traceback() {
+        let script = r#"
+        function inner(n)
+            return n + m;
+        end
+
+        function caller(n)
+            return inner(n);
+        end
+
+        print(caller(1));
+        "#;
+        let mut scanner = Scanner::new(script.to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens.clone());
+        let statements = parser.parse().unwrap();
+
+        let mut intercepter = Intercepter::new();
+        assert!(intercepter.eval(&statements).is_err());
+        let traceback = intercepter.take_traceback().unwrap();
+        assert_eq!(
+            traceback.render("a.lua"),
+            "stack traceback:\n\
+             \ta.lua:3: in function 'inner'\n\
+             \ta.lua:7: in function 'caller'\n\
+             \ta.lua:10: in main chunk\n"
+        );
+        assert!(intercepter.take_traceback().is_none());
+
+        // 运行限制错误没有位置
+        let mut intercepter = Intercepter::new();
+        intercepter.set_limits(Limits {
+            max_steps: Some(3),
+            max_memory: None,
+        });
+        assert!(intercepter.eval(&statements).is_err());
+        let frames = intercepter.take_traceback().unwrap().frames;
+        assert_eq!(frames.last().unwrap().function, None);
+        assert_eq!(frames[0].line, None);
+    }
+
+    #[test]
+    fn intercepter_trace() {
+        use crate::stdio::Capture;
+
+        let script = r#"
+        function add1(n)
+            return n + 1;
+        end
+
+        local a = add1(1);
+        "#;
+        let mut scanner = Scanner::new(script.to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens.clone());
+        let statements = parser.parse().unwrap();
+
+        let buffer = Capture::default();
+        let mut intercepter = Intercepter::new();
+        intercepter.set_tracer(Tracer::new(buffer.clone()));
+        intercepter.eval(&statements).unwrap();
+
+        let trace = buffer.text();
+        let lines: Vec<_> = trace.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "function add1(n)",
+                "local a = add1(1);",
+                "-> call add1",
+                "  return n + 1;",
+                "<- add1 returned 2",
+            ]
+        );
+    }
+
+    #[test]
+    fn intercepter_shares_function_body() {
+        let script = "function add1(n)\n  return n + 1;\nend\nlocal a = add1(1);";
+        let mut scanner = Scanner::new(script.to_string());
+        scanner.scan_tokens().unwrap();
+        let statements = Parser::new(scanner.take_tokens()).parse().unwrap();
+        let mut intercepter = Intercepter::new();
+        intercepter.eval(&statements).unwrap();
+
+        let body = statements[0].as_function_stmt().unwrap().2;
+        let func = intercepter.global("add1").unwrap().as_function().unwrap().2;
+        assert!(std::sync::Arc::ptr_eq(body, func));
+        assert_eq!(intercepter.global("a"), Some(&Value::Int(2)));
+    }
+
+    #[test]
+    fn intercepter_errors_instead_of_panics() {
+        let eval = |script: &str| {
+            let mut scanner = Scanner::new(script.to_string());
+            let tokens = scanner.scan_tokens().unwrap();
+            let statements = Parser::new(tokens.clone()).parse().unwrap();
+            Intercepter::new().eval(&statements)
+        };
+
+        let e = eval("function f(a)\n  return f(a);\nend\nf(1);").unwrap_err();
+        assert_eq!(e.to_string(), "Intercept error: stack overflow");
+        let e = eval("local a = 0;\nreturn 1 / a;").unwrap_err();
+        assert_eq!(e.to_string(), "Intercept error: attempt to divide by zero");
+        assert_eq!(eval("return 1 / 0.5;").unwrap(), Value::Float(2.0));
+        assert_eq!(
+            eval("return 1 / 0.0;").unwrap(),
+            Value::Float(f32::INFINITY)
+        );
+        assert_eq!(
+            eval("function f(a)\n  return a;\nend\nreturn f(1, 2);").unwrap(),
+            Value::Int(1)
+        );
+        let script = format!(
+            "function f(a)\n  return f(a);\nend\nreturn {}f(1);",
+            "- ".repeat(150)
+        );
+        assert!(eval(&script).is_err());
+        assert_eq!(
+            eval("return 2147483647 + 1;").unwrap(),
+            Value::Int(i32::MIN)
+        );
+    }
+
+    #[test]
+    fn intercepter_strings() {
+        let script = r#"
+        function greet(name)
+          if name == "" then
+            return "nobody";
+          end
+          return name;
+        end
+        local a = greet("plua");
+        print(a);
+        return a != greet("");
+        "#;
+        let mut scanner = Scanner::new(script.to_string());
+        scanner.scan_tokens().unwrap();
+        let statements = Parser::new(scanner.take_tokens()).parse().unwrap();
+        let mut intercepter = Intercepter::new();
+        assert_eq!(intercepter.eval(&statements).unwrap(), Value::Bool(true));
+        assert_eq!(
+            intercepter.global("a"),
+            Some(&Value::String("plua".to_string()))
+        );
+    }
+
+    #[test]
+    fn intercepter_local_list() {
+        let script = r#"
+        function f()
+          return 1;
+        end
+        local a, b, c = f(), 2;
+        local d = 3, 4;
+        local a, b = b, a;
+        return c == nil and a * 10 + b + d;
+        "#;
+        let mut scanner = Scanner::new(script.to_string());
+        scanner.scan_tokens().unwrap();
+        let statements = Parser::new(scanner.take_tokens()).parse().unwrap();
+        let mut intercepter = Intercepter::new();
+        assert_eq!(intercepter.eval(&statements).unwrap(), Value::Int(24));
+        assert_eq!(intercepter.global("c"), Some(&Value::Nil));
+    }
+
+    #[test]
+    fn intercepter_concat() {
+        let eval = |script: &str| {
+            let mut scanner = Scanner::new(script.to_string());
+            scanner.scan_tokens().unwrap();
+            let statements = Parser::new(scanner.take_tokens()).parse().unwrap();
+            Intercepter::new().eval(&statements)
+        };
+        assert_eq!(
+            eval(r#"local n = 2; return "n=" .. n + 1 .. "," .. 0.5;"#).unwrap(),
+            Value::String("n=3,0.5".to_string())
+        );
+        let e = eval(r#"return "a" .. nil;"#).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Intercept error: attempt to concatenate a nil value"
+        );
+        assert_eq!(e.location(), Some((1, 12)));
+    }
+
+    #[test]
+    fn intercepter_tables() {
+        let eval = |script: &str| {
+            let mut scanner = Scanner::new(script.to_string());
+            scanner.scan_tokens().unwrap();
+            let statements = Parser::new(scanner.take_tokens()).parse().unwrap();
+            Intercepter::new().eval(&statements)
+        };
+        let script = r#"local t = {1, 2, x = 3, ["y"] = {4}};
+return t[1] + t.x + t.y[1] + t[2];"#;
+        assert_eq!(eval(script).unwrap(), Value::Int(10));
+        assert_eq!(eval("return {1}.y == nil;").unwrap(), Value::Bool(true));
+
+        let e = eval("local a = 1;\nreturn a.x;").unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Intercept error: integer value is not indexable"
+        );
+        assert_eq!(e.location(), Some((2, 9)));
+        let e = eval("return {[1] = 2};").unwrap_err();
+        assert_eq!(e.to_string(), "Intercept error: invalid table key 1");
+    }
+
+    #[test]
+    fn intercepter_chained_calls() {
+        let script = r#"
+        function double(x) return x * 2; end
+        function pick() return double; end
+        function row(i) return {i, i * 10}; end
+        local t = {a = {b = row, pick = pick}};
+        return t.a.pick()(21) + t.a.b(3)[2] + row(1)[1];
+        "#;
+        let mut scanner = Scanner::new(script.to_string());
+        scanner.scan_tokens().unwrap();
+        let statements = Parser::new(scanner.take_tokens()).parse().unwrap();
+        let mut intercepter = Intercepter::new();
+        assert_eq!(intercepter.eval(&statements).unwrap(), Value::Int(73));
+    }
+
+    #[test]
+    fn intercepter_logical() {
+        // 右侧没有求值，不会调用未定义的 nope
+        let script = "local a = nil and nope();
+local b = 1 or nope();
+local c = nil or 2;
+local d = 1 == 2 and 3 or 4;
+return a == nil and b + c + d;";
+        let mut scanner = Scanner::new(script.to_string());
+        scanner.scan_tokens().unwrap();
+        let statements = Parser::new(scanner.take_tokens()).parse().unwrap();
+        let mut intercepter = Intercepter::new();
+        assert_eq!(intercepter.eval(&statements).unwrap(), Value::Int(7));
+        assert_eq!(intercepter.global("d"), Some(&Value::Int(4)));
+    }
+
+    #[test]
+    fn intercepter_while() {
+        let script = r#"
+        function count(n)
+          local i = 0;
+          while 1 do
+            if i > n then
+              return i;
+            end
+            i = i + 1;
+          end
+        end
+        local i = 0;
+        local s = 0;
+        while i < 4 do
+          s = s + i;
+          i = i + 1;
+        end
+        return count(s);
+        "#;
+        let mut scanner = Scanner::new(script.to_string());
+        scanner.scan_tokens().unwrap();
+        let statements = Parser::new(scanner.take_tokens()).parse().unwrap();
+        let mut intercepter = Intercepter::new();
+        assert_eq!(intercepter.eval(&statements).unwrap(), Value::Int(7));
+        assert_eq!(intercepter.global("s"), Some(&Value::Int(6)));
+    }
+
+    #[test]
+    fn intercepter_break() {
+        let script = r#"
+        local i = 0;
+        local n = 0;
+        while 1 do
+          local j = 0;
+          while j < 10 do
+            if j == i then break; end
+            n = n + 1;
+            j = j + 1;
+          end
+          if i == 3 then
+            break;
+          end
+          i = i + 1;
+        end
+        return n;
+        "#;
+        let mut scanner = Scanner::new(script.to_string());
+        scanner.scan_tokens().unwrap();
+        let statements = Parser::new(scanner.take_tokens()).parse().unwrap();
+        let mut intercepter = Intercepter::new();
+        assert_eq!(intercepter.eval(&statements).unwrap(), Value::Int(6));
+        assert_eq!(intercepter.global("i"), Some(&Value::Int(3)));
+    }
+
+    #[test]
+    fn intercepter_while_scope() {
+        let script = r#"
+        local i = 0;
+        while i < 2 do
+          local j = i;
+          i = i + 1;
+        end
+        return i;
+        "#;
+        let mut scanner = Scanner::new(script.to_string());
+        scanner.scan_tokens().unwrap();
+        let statements = Parser::new(scanner.take_tokens()).parse().unwrap();
+        let mut intercepter = Intercepter::new();
+        assert_eq!(intercepter.eval(&statements).unwrap(), Value::Int(2));
+        // 循环体中的 local 不会泄漏到循环外
+        assert_eq!(intercepter.global("j"), None);
+    }
+
+    #[test]
+    fn intercepter_repeat() {
+        let script = r#"
+        local n = 0;
+        repeat
+          n = n + 1;
+        until 1
+        function count()
+          local i = 0;
+          repeat
+            local next = i + 1;
+            i = next;
+            if i > 10 then break; end
+          until next * next > 20
+          return i;
+        end
+        return n + count() * 10;
+        "#;
+        let mut scanner = Scanner::new(script.to_string());
+        scanner.scan_tokens().unwrap();
+        let statements = Parser::new(scanner.take_tokens()).parse().unwrap();
+        let mut intercepter = Intercepter::new();
+        assert_eq!(intercepter.eval(&statements).unwrap(), Value::Int(51));
+        assert_eq!(intercepter.global("next"), None);
+    }
+
+    #[test]
+    fn intercepter_repeat_scope() {
+        let script = r#"
+        local i = 0;
+        repeat
+          local k = i + 1;
+          i = k;
+        until k > 2
+        return i;
+        "#;
+        let mut scanner = Scanner::new(script.to_string());
+        scanner.scan_tokens().unwrap();
+        let statements = Parser::new(scanner.take_tokens()).parse().unwrap();
+        let mut intercepter = Intercepter::new();
+        assert_eq!(intercepter.eval(&statements).unwrap(), Value::Int(3));
+        assert_eq!(intercepter.global("k"), None);
+    }
+
+    #[test]
+    fn intercepter_mod_pow() {
+        let script = "local a = 7 % 3; local b = -7 % 3; local c = 5.5 % 2; local d = 2 ^ 3 ^ 2; local e = 7 % -3; local f = -5.5 % 2; return -2 ^ 2;";
+        let mut scanner = Scanner::new(script.to_string());
+        scanner.scan_tokens().unwrap();
+        let statements = Parser::new(scanner.take_tokens()).parse().unwrap();
+        let mut intercepter = Intercepter::new();
+        assert_eq!(intercepter.eval(&statements).unwrap(), Value::Float(-4.0));
+        assert_eq!(intercepter.global("a"), Some(&Value::Int(1)));
+        assert_eq!(intercepter.global("b"), Some(&Value::Int(2)));
+        assert_eq!(intercepter.global("c"), Some(&Value::Float(1.5)));
+        assert_eq!(intercepter.global("d"), Some(&Value::Float(512.0)));
+        assert_eq!(intercepter.global("e"), Some(&Value::Int(-2)));
+        assert_eq!(intercepter.global("f"), Some(&Value::Float(0.5)));
+
+        let mut scanner = Scanner::new("return 1 % 0;".to_string());
+        scanner.scan_tokens().unwrap();
+        let statements = Parser::new(scanner.take_tokens()).parse().unwrap();
+        let err = Intercepter::new().eval(&statements).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Intercept error: attempt to perform modulo by zero"
+        );
+    }
+
+    #[test]
+    fn intercepter_global_assign() {
+        let script = r#"
+        local a = 1;
+        function f()
+          local a = 10;
+          a = a + 1;
+          g = a;
+        end
+        f();
+        a = a + 1;
+        return g + a;
+        "#;
+        let mut scanner = Scanner::new(script.to_string());
+        scanner.scan_tokens().unwrap();
+        let statements = Parser::new(scanner.take_tokens()).parse().unwrap();
+        let mut intercepter = Intercepter::new();
+        assert_eq!(intercepter.eval(&statements).unwrap(), Value::Int(13));
+        assert_eq!(intercepter.global("a"), Some(&Value::Int(2)));
+        assert_eq!(intercepter.global("g"), Some(&Value::Int(11)));
+    }
+
+    #[test]
+    fn intercepter_floats() {
+        let script = "local a = 1.5 * 2; local b = -0.25 + a; return b > 2.5;";
+        let mut scanner = Scanner::new(script.to_string());
+        scanner.scan_tokens().unwrap();
+        let statements = Parser::new(scanner.take_tokens()).parse().unwrap();
+        let mut intercepter = Intercepter::new();
+        assert_eq!(intercepter.eval(&statements).unwrap(), Value::Bool(true));
+        assert_eq!(intercepter.global("a"), Some(&Value::Float(3.0)));
+        assert_eq!(intercepter.global("b"), Some(&Value::Float(2.75)));
+
+        // 整数与浮点数运算的结果为浮点数
+        let script = "return 2 * 1.5 == 3.0 and 1 + 0.5 == 1.5 and 2 - 0.5 == 1.5;";
+        let mut scanner = Scanner::new(script.to_string());
+        scanner.scan_tokens().unwrap();
+        let statements = Parser::new(scanner.take_tokens()).parse().unwrap();
+        let mut intercepter = Intercepter::new();
+        assert_eq!(intercepter.eval(&statements).unwrap(), Value::Bool(true));
+    }
+
+    #[test]
+    fn intercepter_leak_check() {
+        let script = r#"
+        local s = arg;
+        function fib(n)
+          local t = arg;
+          if n < 2 then
+            return n;
+          end
+          return fib(n - 1) + fib(n - 2);
+        end
+        s = 1;
+        return fib(10);
+        "#;
+        let mut scanner = Scanner::new(script.to_string());
+        scanner.scan_tokens().unwrap();
+        let statements = Parser::new(scanner.take_tokens()).parse().unwrap();
+        let mut intercepter = Intercepter::new();
+        let arg = Table::from_array(vec![Value::String("a".to_string())]);
+        intercepter.define_global("arg", Value::Table(arg));
+        intercepter.enable_leak_check();
+        assert_eq!(intercepter.eval(&statements).unwrap(), Value::Int(55));
+        intercepter.check_leaks().unwrap();
+
+        let heap = intercepter.heap().unwrap();
+        assert_eq!(heap.live(ObjectKind::Env), 1);
+        assert_eq!(heap.allocated(ObjectKind::Env), 178);
+        assert_eq!(heap.allocated(ObjectKind::Table), 179);
+        assert_eq!(heap.live(ObjectKind::Table), 1);
+        assert_eq!(heap.live(ObjectKind::String), 1);
+        assert_eq!(heap.live(ObjectKind::Function), 1);
+
+        // 出错退出的作用域同样释放
+        let script = "function f(a)\n  local b = arg;\n  return a / 0;\nend\nf(1);";
+        let mut scanner = Scanner::new(script.to_string());
+        scanner.scan_tokens().unwrap();
+        let statements = Parser::new(scanner.take_tokens()).parse().unwrap();
+        assert!(intercepter.eval(&statements).is_err());
+        intercepter.check_leaks().unwrap();
+    }
 }
@@ -13,6 +13,44 @@ use crate::value::Value as ValueRaw;
 // jit implement by cranelift inspired by RustPython
 // see: https://github.com/RustPython/RustPython/tree/main/jit
 
+// cranelift 的优化级别
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptLevel {
+    None,
+    Speed,
+    SpeedAndSize,
+}
+
+impl OptLevel {
+    fn as_str(&self) -> &'static str {
+        match self {
+            OptLevel::None => "none",
+            OptLevel::Speed => "speed",
+            OptLevel::SpeedAndSize => "speed_and_size",
+        }
+    }
+}
+
+// 代码生成的选项，默认与 JITBuilder::new 相同
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JitOptions {
+    pub opt_level: OptLevel,
+    // 生成位置无关的代码
+    pub is_pic: bool,
+    // 生成代码前检查 ir，出错时返回错误
+    pub verifier: bool,
+}
+
+impl Default for JitOptions {
+    fn default() -> Self {
+        Self {
+            opt_level: OptLevel::None,
+            is_pic: true,
+            verifier: true,
+        }
+    }
+}
+
 pub struct JIT {
     builder_context: FunctionBuilderContext,
     ctx: codegen::Context,
@@ -22,32 +60,84 @@ pub struct JIT {
 
 impl Default for JIT {
     fn default() -> Self {
-        let builder = JITBuilder::new(cranelift_module::default_libcall_names());
+        Self::new(JitOptions::default()).unwrap()
+    }
+}
+
+impl JIT {
+    // 生成本机代码，本机平台不受 cranelift 支持时返回错误
+    pub fn new(options: JitOptions) -> Result<Self, String> {
+        let mut flag_builder = settings::builder();
+        let set = |flags: &mut settings::Builder, name: &str, value: &str| {
+            flags
+                .set(name, value)
+                .map_err(|e| format!("{} = {}: {}", name, value, e))
+        };
+        // 与 JITBuilder::new 相同，长距离的重定位才能访问到所有定义
+        set(&mut flag_builder, "use_colocated_libcalls", "false")?;
+        set(&mut flag_builder, "is_pic", &options.is_pic.to_string())?;
+        set(&mut flag_builder, "opt_level", options.opt_level.as_str())?;
+        set(
+            &mut flag_builder,
+            "enable_verifier",
+            &options.verifier.to_string(),
+        )?;
+        let isa = cranelift_native::builder()?.finish(settings::Flags::new(flag_builder));
+
+        let builder = JITBuilder::with_isa(isa, cranelift_module::default_libcall_names());
         let module = JITModule::new(builder);
-        Self {
+        Ok(Self {
             builder_context: FunctionBuilderContext::new(),
             ctx: module.make_context(),
             data_ctx: DataContext::new(),
             module,
-        }
+        })
+    }
+
+    // 生成代码使用的 cranelift 设置
+    pub fn flags(&self) -> &settings::Flags {
+        self.module.isa().flags()
     }
-}
 
-impl JIT {
     /// Compile a string in the toy language into machine code.
     pub fn compile(&mut self, input: &Stmt) -> Result<*const u8, String> {
         if let Stmt::FunctionStmt(name, params, body) = input {
+            // return 之后不能再生成指令，函数也必须以 return 结束
+            let returns = body
+                .iter()
+                .filter(|stmt| matches!(stmt, Stmt::ReturnStmt(..)))
+                .count();
+            if returns != 1 || !matches!(body.last(), Some(Stmt::ReturnStmt(..))) {
+                return Err(format!(
+                    "function {} must end with its only return",
+                    name.raw
+                ));
+            }
+
             // TODO remove the return
-            self.translate(params, "the_return".to_string(), body)?;
-
-            let id = self
-                .module
-                .declare_function(name.raw.as_str(), Linkage::Export, &self.ctx.func.signature)
-                .map_err(|e| e.to_string())?;
-            self.module
-                .define_function(id, &mut self.ctx)
-                .map_err(|e| e.to_string())?;
+            let ret = self
+                .translate(params, "the_return".to_string(), body)
+                .and_then(|_| {
+                    self.module
+                        .declare_function(
+                            name.raw.as_ref(),
+                            Linkage::Export,
+                            &self.ctx.func.signature,
+                        )
+                        .map_err(|e| e.to_string())
+                })
+                .and_then(|id| {
+                    self.module
+                        .define_function(id, &mut self.ctx)
+                        .map(|_| id)
+                        .map_err(|e| e.to_string())
+                });
+            // 出错时丢弃翻译了一半的函数，JIT 还可以继续使用
             self.module.clear_context(&mut self.ctx);
+            if ret.is_err() {
+                self.builder_context = FunctionBuilderContext::new();
+            }
+            let id = ret?;
             self.module.finalize_definitions();
 
             let code = self.module.get_finalized_function(id);
@@ -79,14 +169,14 @@ impl JIT {
         &mut self,
         params: &Vec<Token>,
         the_return: String,
-        stmts: &Vec<Stmt>,
+        stmts: &[Stmt],
     ) -> Result<(), String> {
         // 只支持一种类型的参数和一个返回值
         let int = self.module.target_config().pointer_type();
 
         let mut names = Vec::new();
         for p in params {
-            names.push(p.raw.clone());
+            names.push(p.raw.to_string());
             self.ctx.func.signature.params.push(AbiParam::new(int));
         }
 
@@ -137,7 +227,7 @@ impl<'a> FunctionTranslator<'a> {
                     };
                 }
 
-                Expr::Binary(left, op, right) => match op.raw.as_str() {
+                Expr::Binary(left, op, right) => match op.raw.as_ref() {
                     "+" => {
                         let lhs = self.translate_expr(left.as_ref())?;
                         let rhs = self.translate_expr(right.as_ref())?;
@@ -161,13 +251,19 @@ impl<'a> FunctionTranslator<'a> {
                     _ => {}
                 },
                 Expr::Assign(name, expr) => {
-                    return self.translate_assign(name.raw.clone(), expr.as_ref())
+                    return self.translate_assign(name.raw.to_string(), expr.as_ref())
                 }
                 _ => {}
             },
+            Stmt::AssignStmt(name, expr) => {
+                return self.translate_assign(name.raw.to_string(), expr);
+            }
             Stmt::ReturnStmt(_token, expr) => {
                 return if let Expr::Variable(ident) = expr {
-                    let return_variable = self.variables.get(ident.raw.as_str()).unwrap();
+                    let return_variable = self
+                        .variables
+                        .get(ident.raw.as_ref())
+                        .ok_or_else(|| format!("undefined variable {}", ident.raw))?;
                     let return_value = self.builder.use_var(*return_variable);
                     self.builder.ins().return_(&[return_value]);
                     Ok(Value::new(0))
@@ -189,7 +285,7 @@ impl<'a> FunctionTranslator<'a> {
                     Err("value type not support".to_string())
                 };
             }
-            Expr::Binary(left, op, right) => match op.raw.as_str() {
+            Expr::Binary(left, op, right) => match op.raw.as_ref() {
                 "+" => {
                     let lhs = self.translate_expr(left.as_ref())?;
                     let rhs = self.translate_expr(right.as_ref())?;
@@ -212,14 +308,17 @@ impl<'a> FunctionTranslator<'a> {
                 }
                 _ => Err("op not support".to_string()),
             },
-            Expr::Assign(name, expr) => self.translate_assign(name.raw.clone(), expr.as_ref()),
+            Expr::Assign(name, expr) => self.translate_assign(name.raw.to_string(), expr.as_ref()),
             _ => Err("un support expr".to_string()),
         }
     }
 
     fn translate_assign(&mut self, name: String, expr: &Expr) -> Result<Value, String> {
         let new_value = self.translate_expr(expr)?;
-        let variable = self.variables.get(&name).unwrap();
+        let variable = self
+            .variables
+            .get(&name)
+            .ok_or_else(|| format!("undefined variable {}", name))?;
         self.builder.def_var(*variable, new_value);
         Ok(new_value)
     }
@@ -237,7 +336,7 @@ fn declare_variables(
     builder: &mut FunctionBuilder,
     params: &[String],
     the_return: &str,
-    stmts: &Vec<Stmt>,
+    stmts: &[Stmt],
     entry_block: Block,
 ) -> HashMap<String, Variable> {
     let mut variables = HashMap::new();
@@ -270,10 +369,13 @@ fn declare_variables_in_stmt(
     match stmt {
         Stmt::Expression(expr) => match expr {
             Expr::Assign(ref name, _) => {
-                declare_variable(int, builder, variables, index, name.raw.as_str());
+                declare_variable(int, builder, variables, index, name.raw.as_ref());
             }
             _ => {}
         },
+        Stmt::AssignStmt(name, _) => {
+            declare_variable(int, builder, variables, index, name.raw.as_ref());
+        }
         _ => (),
     }
 }
@@ -296,7 +398,7 @@ fn declare_variable(
 
 #[cfg(test)]
 mod tests {
-    use super::JIT;
+    use super::{JitOptions, OptLevel, JIT};
     use crate::parser::Parser;
     use crate::scanner::Scanner;
     use std::mem;
@@ -333,4 +435,28 @@ mod tests {
             assert_eq!(i, 7);
         }
     }
+
+    #[test]
+    fn test_jit_options() {
+        let source = "function main()\n  n = 7 * 6;\n  return n;\nend";
+        let statements = Parser::from_stream(Scanner::new(source.to_string()))
+            .parse()
+            .unwrap();
+        for opt_level in [OptLevel::None, OptLevel::Speed, OptLevel::SpeedAndSize] {
+            let options = JitOptions {
+                opt_level,
+                is_pic: false,
+                verifier: false,
+            };
+            let mut jit = JIT::new(options).unwrap();
+            assert!(!jit.flags().is_pic());
+            assert!(!jit.flags().enable_verifier());
+            let code = jit.compile(&statements[0]).unwrap();
+            let code_fn = unsafe { mem::transmute::<*const u8, fn() -> i64>(code) };
+            assert_eq!(code_fn(), 42);
+        }
+        let jit = JIT::default();
+        assert!(jit.flags().is_pic());
+        assert_eq!(jit.flags().opt_level().to_string(), "none");
+    }
 }
@@ -1,16 +1,34 @@
 pub mod bf;
+#[cfg(feature = "jit")]
 pub mod toy;
 
+pub mod ast;
+pub mod backend;
+pub mod builtins;
 pub mod bytecode;
+pub mod coverage;
 pub mod debug;
+pub mod diagnostic;
+pub mod dump;
 pub mod emitter;
+pub mod engine;
 pub mod error;
 pub mod expression;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod heap;
+pub mod hook;
 pub mod intercepter;
+#[cfg(feature = "jit")]
 pub mod jit;
+pub mod native;
 pub mod parser;
+pub mod profiler;
 pub mod resolver;
 pub mod scanner;
 pub mod statement;
+pub mod stdio;
+pub mod trace;
+pub mod transpile;
 pub mod value;
 pub mod vm;
@@ -0,0 +1,277 @@
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+
+use crate::value::Value;
+
+// 宿主函数，参数个数或类型不对时返回错误信息，由解释器补上调用位置
+// 要求 Send + Sync，注册了宿主函数的 engine 仍然可以转移到其它线程
+pub type NativeFn = Arc<dyn Fn(&[Value]) -> Result<Value, String> + Send + Sync>;
+
+// 注册到脚本中的宿主函数
+#[derive(Clone)]
+pub struct NativeFunction {
+    pub name: String,
+    pub func: NativeFn,
+}
+
+impl NativeFunction {
+    pub fn new(name: &str, func: NativeFn) -> Self {
+        Self {
+            name: name.to_string(),
+            func,
+        }
+    }
+
+    pub fn call(&self, args: &[Value]) -> Result<Value, String> {
+        (self.func)(args)
+    }
+}
+
+impl Debug for NativeFunction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "NativeFunction({})", self.name)
+    }
+}
+
+// 脚本中的值转换为宿主类型
+pub trait FromValue: Sized {
+    // 出错信息中期望的类型名
+    const TYPE: &'static str;
+
+    fn from_value(value: &Value) -> Option<Self>;
+}
+
+// 宿主类型转换为脚本中的值，超出范围时返回错误
+pub trait IntoValue {
+    fn into_value(self) -> Result<Value, String>;
+}
+
+impl FromValue for () {
+    const TYPE: &'static str = "nil";
+
+    fn from_value(value: &Value) -> Option<Self> {
+        matches!(value, Value::Nil).then_some(())
+    }
+}
+
+impl FromValue for Value {
+    const TYPE: &'static str = "value";
+
+    fn from_value(value: &Value) -> Option<Self> {
+        Some(value.clone())
+    }
+}
+
+impl FromValue for i64 {
+    const TYPE: &'static str = "integer";
+
+    fn from_value(value: &Value) -> Option<Self> {
+        value.as_int().map(|i| *i as i64)
+    }
+}
+
+impl FromValue for i32 {
+    const TYPE: &'static str = "integer";
+
+    fn from_value(value: &Value) -> Option<Self> {
+        value.as_int().copied()
+    }
+}
+
+// 整数也可以作为浮点数传入
+impl FromValue for f64 {
+    const TYPE: &'static str = "number";
+
+    fn from_value(value: &Value) -> Option<Self> {
+        match value {
+            Value::Int(i) => Some(*i as f64),
+            Value::Float(f) => Some(*f as f64),
+            _ => None,
+        }
+    }
+}
+
+impl FromValue for bool {
+    const TYPE: &'static str = "boolean";
+
+    fn from_value(value: &Value) -> Option<Self> {
+        value.as_bool().copied()
+    }
+}
+
+impl FromValue for String {
+    const TYPE: &'static str = "string";
+
+    fn from_value(value: &Value) -> Option<Self> {
+        value.as_string().cloned()
+    }
+}
+
+impl IntoValue for Value {
+    fn into_value(self) -> Result<Value, String> {
+        Ok(self)
+    }
+}
+
+impl IntoValue for () {
+    fn into_value(self) -> Result<Value, String> {
+        Ok(Value::Nil)
+    }
+}
+
+impl IntoValue for i64 {
+    fn into_value(self) -> Result<Value, String> {
+        i32::try_from(self)
+            .map(Value::Int)
+            .map_err(|_| format!("integer {} out of range", self))
+    }
+}
+
+impl IntoValue for i32 {
+    fn into_value(self) -> Result<Value, String> {
+        Ok(Value::Int(self))
+    }
+}
+
+impl IntoValue for f64 {
+    fn into_value(self) -> Result<Value, String> {
+        Ok(Value::Float(self as f32))
+    }
+}
+
+impl IntoValue for bool {
+    fn into_value(self) -> Result<Value, String> {
+        Ok(Value::Bool(self))
+    }
+}
+
+impl IntoValue for String {
+    fn into_value(self) -> Result<Value, String> {
+        Ok(Value::String(self))
+    }
+}
+
+impl IntoValue for &str {
+    fn into_value(self) -> Result<Value, String> {
+        Ok(Value::String(self.to_string()))
+    }
+}
+
+// 宿主函数可以返回 Err 向脚本报告错误
+impl<T: IntoValue> IntoValue for Result<T, String> {
+    fn into_value(self) -> Result<Value, String> {
+        self.and_then(IntoValue::into_value)
+    }
+}
+
+// 参数个数固定、类型可转换的 Rust 闭包，Args 为参数类型的元组
+pub trait IntoNativeFn<Args> {
+    fn into_native_fn(self) -> NativeFn;
+}
+
+// 从宿主调用脚本函数时的参数，如 (30,)、(1, 2.5)
+pub trait IntoArgs {
+    fn into_args(self) -> Result<Vec<Value>, String>;
+}
+
+impl IntoArgs for Vec<Value> {
+    fn into_args(self) -> Result<Vec<Value>, String> {
+        Ok(self)
+    }
+}
+
+// 第 i 个参数(从 1 开始)转换为宿主类型
+fn arg<T: FromValue>(args: &[Value], i: usize) -> Result<T, String> {
+    let value = &args[i - 1];
+    T::from_value(value).ok_or_else(|| {
+        format!(
+            "bad argument #{} ({} expected, got {})",
+            i,
+            T::TYPE,
+            value.type_name()
+        )
+    })
+}
+
+macro_rules! impl_into_native_fn {
+    ($($arg:ident $index:literal),*) => {
+        impl<F, R, $($arg),*> IntoNativeFn<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> R + Send + Sync + 'static,
+            R: IntoValue,
+            $($arg: FromValue),*
+        {
+            #[allow(non_snake_case)]
+            fn into_native_fn(self) -> NativeFn {
+                Arc::new(move |args: &[Value]| {
+                    let arity = <[&str]>::len(&[$(stringify!($arg)),*]);
+                    if args.len() != arity {
+                        return Err(format!(
+                            "wrong number of arguments (expected {}, got {})",
+                            arity,
+                            args.len()
+                        ));
+                    }
+                    $(let $arg = arg::<$arg>(args, $index)?;)*
+                    self($($arg),*).into_value()
+                })
+            }
+        }
+
+        impl<$($arg: IntoValue),*> IntoArgs for ($($arg,)*) {
+            #[allow(non_snake_case)]
+            fn into_args(self) -> Result<Vec<Value>, String> {
+                let ($($arg,)*) = self;
+                Ok(vec![$($arg.into_value()?),*])
+            }
+        }
+    };
+}
+
+impl_into_native_fn!();
+impl_into_native_fn!(A 1);
+impl_into_native_fn!(A 1, B 2);
+impl_into_native_fn!(A 1, B 2, C 3);
+impl_into_native_fn!(A 1, B 2, C 3, D 4);
+impl_into_native_fn!(A 1, B 2, C 3, D 4, E 5);
+
+#[cfg(test)]
+mod tests {
+    use super::{IntoNativeFn, NativeFunction};
+    use crate::value::Value;
+
+    #[test]
+    fn test_native_fn() {
+        let add = NativeFunction::new("add", (|a: i64, b: i64| a + b).into_native_fn());
+        assert_eq!(add.call(&[Value::Int(1), Value::Int(2)]), Ok(Value::Int(3)));
+        assert_eq!(
+            add.call(&[Value::Int(1)]),
+            Err("wrong number of arguments (expected 2, got 1)".to_string())
+        );
+        assert_eq!(
+            add.call(&[Value::Int(1), Value::Nil]),
+            Err("bad argument #2 (integer expected, got nil)".to_string())
+        );
+        assert_eq!(
+            add.call(&[Value::Int(i32::MAX), Value::Int(1)]),
+            Err("integer 2147483648 out of range".to_string())
+        );
+
+        let half = (|x: f64| x / 2.0).into_native_fn();
+        assert_eq!(half(&[Value::Int(3)]), Ok(Value::Float(1.5)));
+
+        let check = (|x: i32| {
+            if x > 0 {
+                Ok(())
+            } else {
+                Err("x must be positive".to_string())
+            }
+        })
+        .into_native_fn();
+        assert_eq!(check(&[Value::Int(1)]), Ok(Value::Nil));
+        assert_eq!(
+            check(&[Value::Int(0)]),
+            Err("x must be positive".to_string())
+        );
+    }
+}
@@ -1,27 +1,295 @@
-use crate::error::Error;
+use crate::error::{Error, Span};
 use crate::expression::Expr;
-use crate::scanner::{Token, TokenType};
+use crate::scanner::{Scanner, Token, TokenType};
 use crate::statement::Stmt;
 use crate::value::Value;
 
+// ParserOptions 中嵌套层数的上限，避免耗尽宿主的栈
+pub const MAX_DEPTH: usize = 200;
+
+// 函数默认的最大参数个数
+pub const MAX_PARAMS: usize = 255;
+
+// 解析的限制，接受不可信的脚本时可以调低；两个嵌套层数都不能超过 MAX_DEPTH，
+// set_options 时超出的部分截断为 MAX_DEPTH
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParserOptions {
+    // 单条语句中表达式的最大嵌套层数
+    pub max_expr_depth: usize,
+    // 语句的最大嵌套层数，顶层语句为第 1 层，函数体与 if 分支各加一层
+    pub max_block_depth: usize,
+    // 函数的最大参数个数
+    pub max_params: usize,
+}
+
+impl Default for ParserOptions {
+    fn default() -> Self {
+        Self {
+            max_expr_depth: MAX_DEPTH,
+            max_block_depth: MAX_DEPTH / 2,
+            max_params: MAX_PARAMS,
+        }
+    }
+}
+
+// 运算符的优先级，从低到高
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Precedence {
+    Or,
+    And,
+    Equality,
+    Comparison,
+    Concat,
+    Term,
+    Factor,
+    Unary,
+    Power,
+}
+
+impl Precedence {
+    // 高一级的优先级，Power 已经是最高的
+    fn next(self) -> Self {
+        match self {
+            Precedence::Or => Precedence::And,
+            Precedence::And => Precedence::Equality,
+            Precedence::Equality => Precedence::Comparison,
+            Precedence::Comparison => Precedence::Concat,
+            Precedence::Concat => Precedence::Term,
+            Precedence::Term => Precedence::Factor,
+            Precedence::Factor => Precedence::Unary,
+            Precedence::Unary | Precedence::Power => Precedence::Power,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Assoc {
+    Left,
+    Right,
+}
+
+// 二元运算符，node 为 Expr::Binary 或短路求值的 Expr::Logical
+struct BinaryOperator {
+    typ: TokenType,
+    precedence: Precedence,
+    assoc: Assoc,
+    node: fn(Box<Expr>, Token, Box<Expr>) -> Expr,
+}
+
+const fn left(typ: TokenType, precedence: Precedence) -> BinaryOperator {
+    BinaryOperator {
+        typ,
+        precedence,
+        assoc: Assoc::Left,
+        node: Expr::Binary,
+    }
+}
+
+const fn right(typ: TokenType, precedence: Precedence) -> BinaryOperator {
+    BinaryOperator {
+        assoc: Assoc::Right,
+        ..left(typ, precedence)
+    }
+}
+
+const fn logical(typ: TokenType, precedence: Precedence) -> BinaryOperator {
+    BinaryOperator {
+        node: Expr::Logical,
+        ..left(typ, precedence)
+    }
+}
+
+// 新增二元运算符只需要在这里加一行
+static BINARY_OPERATORS: [BinaryOperator; 15] = [
+    logical(TokenType::Or, Precedence::Or),
+    logical(TokenType::And, Precedence::And),
+    left(TokenType::EqualEqual, Precedence::Equality),
+    left(TokenType::BangEqual, Precedence::Equality),
+    left(TokenType::Less, Precedence::Comparison),
+    left(TokenType::LessEqual, Precedence::Comparison),
+    left(TokenType::Greater, Precedence::Comparison),
+    left(TokenType::GreaterEqual, Precedence::Comparison),
+    right(TokenType::DotDot, Precedence::Concat),
+    left(TokenType::Plus, Precedence::Term),
+    left(TokenType::Minus, Precedence::Term),
+    left(TokenType::Star, Precedence::Factor),
+    left(TokenType::Slash, Precedence::Factor),
+    left(TokenType::Percent, Precedence::Factor),
+    right(TokenType::Caret, Precedence::Power),
+];
+
+// 一元运算符的优先级都是 Unary
+const UNARY_OPERATORS: [TokenType; 2] = [TokenType::Bang, TokenType::Minus];
+
+fn binary_operator(typ: TokenType) -> Option<&'static BinaryOperator> {
+    BINARY_OPERATORS.iter().find(|op| op.typ == typ)
+}
+
+// token 来源，scanner 按需产生时不必保存整个 token 序列
+type TokenStream = Box<dyn Iterator<Item = Result<Token, Error>>>;
+
 pub struct Parser {
-    tokens: Vec<Token>,
-    current: usize,
+    tokens: TokenStream,
+    // 只需要当前与上一个 token
+    current: Token,
+    previous: Token,
+    // 词法错误，优先于语法错误返回
+    scan_error: Option<Error>,
+    // 已经恢复的语法错误
+    errors: Vec<Error>,
+    // 当前的嵌套层数，语句与表达式合计
+    depth: usize,
+    // 当前语句的嵌套层数与语句开始时的 depth，用于计算表达式的嵌套层数
+    block_depth: usize,
+    expr_base: usize,
+    // 所在循环的层数，函数体中重新计算
+    loops: usize,
+    options: ParserOptions,
 }
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Self {
-        Self { tokens, current: 0 }
+        Self::from_stream(tokens.into_iter().map(Ok))
+    }
+
+    // 边扫描边解析，如 Parser::from_stream(Scanner::new(source))
+    pub fn from_stream<I>(tokens: I) -> Self
+    where
+        I: Iterator<Item = Result<Token, Error>> + 'static,
+    {
+        let eof = Token::new(TokenType::Eof, "", Value::Nil, 1, 0, Span::new(0, 0));
+        let mut parser = Self {
+            tokens: Box::new(tokens),
+            current: eof.clone(),
+            previous: eof,
+            scan_error: None,
+            errors: vec![],
+            depth: 0,
+            block_depth: 0,
+            expr_base: 0,
+            loops: 0,
+            options: ParserOptions::default(),
+        };
+        parser.current = parser.next_token();
+        parser
+    }
+
+    pub fn set_options(&mut self, options: ParserOptions) {
+        self.options = ParserOptions {
+            max_expr_depth: options.max_expr_depth.min(MAX_DEPTH),
+            max_block_depth: options.max_block_depth.min(MAX_DEPTH),
+            ..options
+        };
     }
 
+    pub fn options(&self) -> &ParserOptions {
+        &self.options
+    }
+
+    // 返回第一个错误
     pub fn parse(&mut self) -> Result<Vec<Stmt>, Error> {
+        let (statements, errors) = self.parse_all();
+        match errors.into_iter().next() {
+            Some(e) => Err(e),
+            None => Ok(statements),
+        }
+    }
+
+    // 出错的语句跳过，继续解析之后的语句，返回解析出的语句与所有错误，用于一次报告整个文件的错误
+    pub fn parse_all(&mut self) -> (Vec<Stmt>, Vec<Error>) {
         let mut statements = Vec::new();
+        self.depth = 0;
+        self.block_depth = 0;
+        self.expr_base = 0;
 
         while !self.is_at_end() {
-            statements.push(self.declaration()?);
+            if let Some(stmt) = self.recover_declaration(&[]) {
+                statements.push(stmt);
+            }
         }
 
-        Ok(statements)
+        let mut errors = std::mem::take(&mut self.errors);
+        errors.extend(self.scan_error.take());
+        (statements, errors)
+    }
+
+    // 解析单个表达式，如 "1 + fib(3)"，结尾的 ';' 可以省略，用于 repl 与调试器
+    pub fn parse_expression_entry(source: &str) -> Result<Expr, Error> {
+        let mut parser = Parser::from_stream(Scanner::new(source.to_string()));
+        let result = parser.nested(Self::expression).and_then(|expr| {
+            parser.match_token(TokenType::Semicolon);
+            if parser.is_at_end() {
+                Ok(expr)
+            } else {
+                Err(parser.error("expect end of expression"))
+            }
+        });
+        match parser.scan_error.take() {
+            Some(e) => Err(e),
+            None => result,
+        }
+    }
+
+    // 从来源取下一个 token，出错或缺少结尾的 Eof 时以 Eof 结束
+    fn next_token(&mut self) -> Token {
+        match self.tokens.next() {
+            Some(Ok(token)) => token,
+            Some(Err(e)) => {
+                self.scan_error.get_or_insert(e);
+                self.eof()
+            }
+            None => self.eof(),
+        }
+    }
+
+    fn eof(&self) -> Token {
+        let end = self.current.span.end;
+        Token::new(
+            TokenType::Eof,
+            "",
+            Value::Nil,
+            self.current.line,
+            0,
+            Span::new(end, end),
+        )
+    }
+
+    // 进入一层嵌套的语句或表达式
+    fn nested<T>(&mut self, f: fn(&mut Self) -> Result<T, Error>) -> Result<T, Error> {
+        self.deepen()?;
+        let result = f(self);
+        self.depth -= 1;
+        result
+    }
+
+    // 进入一层嵌套的语句，其中的表达式从 0 层开始计算
+    fn nested_stmt<T>(&mut self, f: fn(&mut Self) -> Result<T, Error>) -> Result<T, Error> {
+        if self.block_depth >= self.options.max_block_depth {
+            return Err(self.error(&format!(
+                "statements nested more than {} levels",
+                self.options.max_block_depth
+            )));
+        }
+        self.depth += 1;
+        self.block_depth += 1;
+        let expr_base = std::mem::replace(&mut self.expr_base, self.depth);
+        let result = f(self);
+        self.expr_base = expr_base;
+        self.block_depth -= 1;
+        self.depth -= 1;
+        result
+    }
+
+    // 表达式的嵌套层数加一，左结合的运算符链每多一个运算符，语法树也深一层
+    fn deepen(&mut self) -> Result<(), Error> {
+        if self.depth - self.expr_base >= self.options.max_expr_depth {
+            return Err(self.error(&format!(
+                "expression nested more than {} levels",
+                self.options.max_expr_depth
+            )));
+        }
+        self.depth += 1;
+        Ok(())
     }
 
     fn declaration(&mut self) -> Result<Stmt, Error> {
@@ -35,65 +303,93 @@ impl Parser {
     }
 
     fn function(&mut self) -> Result<Stmt, Error> {
-        let name = self
-            .consume(TokenType::Identifier, "expect function name")?
-            .clone();
+        let name = self.consume(TokenType::Identifier, "expect function name")?;
         let _ = self.consume(TokenType::LeftParen, "expect '(' after function name")?;
         let mut parameters = Vec::new();
         if !self.check(TokenType::RightParen) {
-            parameters.push(
-                self.consume(TokenType::Identifier, "expect parameter name")?
-                    .clone(),
-            );
-            while self.match_token(TokenType::Comma) {
-                parameters.push(
-                    self.consume(TokenType::Identifier, "expect parameter name")?
-                        .clone(),
-                );
+            loop {
+                if parameters.len() >= self.options.max_params {
+                    return Err(
+                        self.error(&format!("more than {} parameters", self.options.max_params))
+                    );
+                }
+                parameters.push(self.consume(TokenType::Identifier, "expect parameter name")?);
+                if !self.match_token(TokenType::Comma) {
+                    break;
+                }
             }
         }
         let _ = self.consume(TokenType::RightParen, "expect ')' after parameters")?;
-        let body = self.block()?;
-        Ok(Stmt::FunctionStmt(name, parameters, body))
+        let loops = std::mem::replace(&mut self.loops, 0);
+        let body = self.block();
+        self.loops = loops;
+        let body = body?;
+        Ok(Stmt::FunctionStmt(name, parameters, body.into()))
     }
 
     fn local_declaration(&mut self) -> Result<Stmt, Error> {
-        let name = self
-            .consume(TokenType::Identifier, "expect variable name")?
-            .clone();
-        let mut initializer = Expr::None;
+        let mut names = vec![self.consume(TokenType::Identifier, "expect variable name")?];
+        while self.match_token(TokenType::Comma) {
+            names.push(self.consume(TokenType::Identifier, "expect variable name")?);
+        }
+        let mut values = Vec::new();
         if self.match_token(TokenType::Equal) {
-            initializer = self.expression()?;
+            values.push(self.expression()?);
+            while self.match_token(TokenType::Comma) {
+                values.push(self.expression()?);
+            }
         }
         let _ = self.consume(
             TokenType::Semicolon,
             "expect ';' after variable declaration",
         )?;
-        Ok(Stmt::LocalStmt(name, initializer))
+        // 只有一个名字与至多一个值时仍是 LocalStmt
+        if names.len() == 1 && values.len() <= 1 {
+            let initializer = values.pop().unwrap_or(Expr::None);
+            return Ok(Stmt::LocalStmt(names.remove(0), initializer));
+        }
+        Ok(Stmt::LocalListStmt(names, values))
     }
 
     fn statement(&mut self) -> Result<Stmt, Error> {
         if self.match_token(TokenType::If) {
             return self.if_statement();
         }
+        if self.match_token(TokenType::While) {
+            return self.while_statement();
+        }
+        if self.match_token(TokenType::Repeat) {
+            return self.repeat_statement();
+        }
         if self.match_token(TokenType::Print) {
             return self.print_statement();
         }
         if self.match_token(TokenType::Return) {
             return self.return_statement();
         }
+        if self.match_token(TokenType::Break) {
+            return self.break_statement();
+        }
         self.expression_statement()
     }
 
     fn if_statement(&mut self) -> Result<Stmt, Error> {
+        let stmt = self.if_branches()?;
+        let _ = self.consume(TokenType::End, "expect 'end' after if body")?;
+        Ok(stmt)
+    }
+
+    // elseif 展开为 else 分支中嵌套的 if，整条链共用最后的 end
+    fn if_branches(&mut self) -> Result<Stmt, Error> {
         let condition = self.expression()?;
         let _ = self.consume(TokenType::Then, "expect 'then' after condition")?;
-        let then_branch = self.statement()?;
+        let then_branch = self.nested_stmt(Self::statement)?;
         let mut else_branch = Stmt::None;
-        if self.match_token(TokenType::Else) {
-            else_branch = self.statement()?;
+        if self.match_token(TokenType::Elseif) {
+            else_branch = self.nested_stmt(Self::if_branches)?;
+        } else if self.match_token(TokenType::Else) {
+            else_branch = self.nested_stmt(Self::statement)?;
         }
-        let _ = self.consume(TokenType::End, "expect 'end' after if body")?;
         Ok(Stmt::IfStmt(
             condition,
             Box::new(then_branch),
@@ -101,6 +397,33 @@ impl Parser {
         ))
     }
 
+    fn while_statement(&mut self) -> Result<Stmt, Error> {
+        let condition = self.expression()?;
+        let _ = self.consume(TokenType::Do, "expect 'do' after condition")?;
+        let body = self.loop_body(Self::block)?;
+        Ok(Stmt::WhileStmt(condition, body))
+    }
+
+    fn repeat_statement(&mut self) -> Result<Stmt, Error> {
+        let body = self.loop_body(Self::repeat_body)?;
+        let condition = self.expression()?;
+        Ok(Stmt::RepeatStmt(body, condition))
+    }
+
+    fn repeat_body(&mut self) -> Result<Vec<Stmt>, Error> {
+        let statements = self.statements_until(TokenType::Until);
+        let _ = self.consume(TokenType::Until, "expect 'until' after repeat body")?;
+        Ok(statements)
+    }
+
+    // 循环体中允许 break
+    fn loop_body<T>(&mut self, f: fn(&mut Self) -> Result<T, Error>) -> Result<T, Error> {
+        self.loops += 1;
+        let result = f(self);
+        self.loops -= 1;
+        result
+    }
+
     fn print_statement(&mut self) -> Result<Stmt, Error> {
         let _ = self.consume(TokenType::LeftParen, "expect '(' after print")?;
         let value = self.expression()?;
@@ -110,7 +433,7 @@ impl Parser {
     }
 
     fn return_statement(&mut self) -> Result<Stmt, Error> {
-        let keyword = self.previous().clone();
+        let keyword = self.take_previous();
         let mut value = Expr::None;
         if !self.check(TokenType::Semicolon) {
             value = self.expression()?;
@@ -119,145 +442,246 @@ impl Parser {
         Ok(Stmt::ReturnStmt(keyword, value))
     }
 
+    fn break_statement(&mut self) -> Result<Stmt, Error> {
+        let keyword = self.take_previous();
+        if self.loops == 0 {
+            return Err(Error::ParseError {
+                message: "break outside a loop".to_string(),
+                line: keyword.line,
+                col: keyword.col,
+                span: keyword.span,
+            });
+        }
+        let _ = self.consume(TokenType::Semicolon, "expect ';' after break")?;
+        Ok(Stmt::BreakStmt(keyword))
+    }
+
     fn expression_statement(&mut self) -> Result<Stmt, Error> {
         let expr = self.expression()?;
         let _ = self.consume(TokenType::Semicolon, "expect ';' after return value")?;
-        Ok(Stmt::Expression(expr))
+        // 最外层的赋值作为语句，值中的赋值仍是表达式
+        match expr {
+            Expr::Assign(name, value) => Ok(Stmt::AssignStmt(name, *value)),
+            expr => Ok(Stmt::Expression(expr)),
+        }
     }
 
     fn expression(&mut self) -> Result<Expr, Error> {
-        self.assignment()
+        self.nested(Self::assignment)
     }
 
     fn block(&mut self) -> Result<Vec<Stmt>, Error> {
-        let mut statements = Vec::new();
-        while !self.check(TokenType::End) && !self.is_at_end() {
-            statements.push(self.declaration()?);
-        }
+        let statements = self.statements_until(TokenType::End);
         let _ = self.consume(TokenType::End, "expect 'end' after block")?;
         Ok(statements)
     }
 
-    fn assignment(&mut self) -> Result<Expr, Error> {
-        let expr = self.equality()?;
-        if self.match_token(TokenType::Equal) {
-            let equals = self.previous().clone();
-            let value = self.assignment()?;
-            return match expr {
-                Expr::Variable(name) => Ok(Expr::Assign(name, Box::new(value))),
-                _ => Err(Error::ParseError(format!(
-                    "{:?} invalid assignment target",
-                    equals
-                ))),
-            };
+    // 解析语句直到 typ，不消耗 typ；遇到多余的 end 时也停下，由调用方报错
+    fn statements_until(&mut self, typ: TokenType) -> Vec<Stmt> {
+        let mut statements = Vec::new();
+        while !self.check(typ) && !self.check(TokenType::End) && !self.is_at_end() {
+            if let Some(stmt) = self.recover_declaration(&[typ, TokenType::End]) {
+                statements.push(stmt);
+            }
         }
-
-        return Ok(expr);
+        statements
     }
 
-    fn equality(&mut self) -> Result<Expr, Error> {
-        let mut expr = self.comparison()?;
-        while self.match_tokens(vec![TokenType::BangEqual, TokenType::EqualEqual]) {
-            let operator = self.previous().clone();
-            let right = self.comparison()?;
-            expr = Expr::Binary(Box::new(expr), operator, Box::new(right));
+    // 出错时记录错误，跳到下一条语句，stop 为所在块的结束 token
+    fn recover_declaration(&mut self, stop: &[TokenType]) -> Option<Stmt> {
+        let depth = self.depth;
+        let start = self.peek().span;
+        match self.nested_stmt(Self::declaration) {
+            Ok(stmt) => Some(stmt),
+            Err(e) => {
+                self.depth = depth;
+                // 词法错误之后没有 token，之后的语法错误没有意义
+                if self.scan_error.is_none() {
+                    self.errors.push(e);
+                }
+                // 没有消耗 token 时先跳过出错的 token，避免在同一处反复出错
+                if self.peek().span == start {
+                    self.advance();
+                }
+                self.synchronize(stop);
+                None
+            }
         }
-        Ok(expr)
     }
 
-    fn comparison(&mut self) -> Result<Expr, Error> {
-        let mut expr = self.term()?;
-        while self.match_tokens(vec![
-            TokenType::Greater,
-            TokenType::GreaterEqual,
-            TokenType::Less,
-            TokenType::LessEqual,
-        ]) {
-            let operator = self.previous().clone();
-            let right = self.term()?;
-            expr = Expr::Binary(Box::new(expr), operator, Box::new(right));
+    // 跳过出错语句余下的 token：停在 ; 与 end 之后，或 function、local 与 stop 之前
+    fn synchronize(&mut self, stop: &[TokenType]) {
+        while !self.is_at_end() {
+            if matches!(self.previous().typ, TokenType::Semicolon | TokenType::End)
+                || self.check(TokenType::Function)
+                || self.check(TokenType::Local)
+                || stop.iter().any(|&typ| self.check(typ))
+            {
+                return;
+            }
+            self.advance();
         }
-        Ok(expr)
     }
 
-    fn term(&mut self) -> Result<Expr, Error> {
-        let mut expr = self.factor()?;
-        while self.match_tokens(vec![TokenType::Minus, TokenType::Plus]) {
-            let operator = self.previous().clone();
-            let right = self.factor()?;
-            expr = Expr::Binary(Box::new(expr), operator, Box::new(right));
+    fn assignment(&mut self) -> Result<Expr, Error> {
+        let expr = self.binary(Precedence::Or)?;
+        if self.match_token(TokenType::Equal) {
+            let equals = self.take_previous();
+            let value = self.nested(Self::assignment)?;
+            return match expr {
+                Expr::Variable(name) => Ok(Expr::Assign(name, Box::new(value))),
+                _ => Err(Error::ParseError {
+                    message: "invalid assignment target".to_string(),
+                    line: equals.line,
+                    col: equals.col,
+                    span: equals.span,
+                }),
+            };
         }
-        Ok(expr)
+
+        return Ok(expr);
     }
 
-    fn factor(&mut self) -> Result<Expr, Error> {
+    // 解析优先级不低于 min 的二元运算，左结合的右侧只接受更高的优先级
+    fn binary(&mut self, min: Precedence) -> Result<Expr, Error> {
         let mut expr = self.unary()?;
-        while self.match_tokens(vec![TokenType::Slash, TokenType::Star]) {
-            let operator = self.previous().clone();
-            let right = self.unary()?;
-            expr = Expr::Binary(Box::new(expr), operator, Box::new(right));
+        let depth = self.depth;
+        while let Some(op) = binary_operator(self.peek().typ).filter(|op| op.precedence >= min) {
+            self.advance();
+            let operator = self.take_previous();
+            self.deepen()?;
+            let right = match op.assoc {
+                Assoc::Left => self.binary(op.precedence.next())?,
+                Assoc::Right => self.binary(op.precedence)?,
+            };
+            expr = (op.node)(Box::new(expr), operator, Box::new(right));
         }
+        self.depth = depth;
         Ok(expr)
     }
 
+    // 一元运算的操作数中只有 ^ 结合得更紧：-2 ^ 2 为 -(2 ^ 2)
     fn unary(&mut self) -> Result<Expr, Error> {
-        if self.match_tokens(vec![TokenType::Bang, TokenType::Minus]) {
-            let operator = self.previous().clone();
-            let right = self.unary()?;
-            return Ok(Expr::Unary(operator, Box::new(right)));
+        if self.match_tokens(&UNARY_OPERATORS) {
+            let operator = self.take_previous();
+            self.deepen()?;
+            let right = self.binary(Precedence::Unary);
+            self.depth -= 1;
+            return Ok(Expr::Unary(operator, Box::new(right?)));
         }
         self.call()
     }
 
     fn call(&mut self) -> Result<Expr, Error> {
         let mut expr = self.primary()?;
-        if self.match_token(TokenType::LeftParen) {
-            expr = self.finish_call(expr)?;
+        let depth = self.depth;
+        loop {
+            if self.match_token(TokenType::LeftParen) {
+                self.deepen()?;
+                expr = self.finish_call(expr)?;
+            } else if self.match_token(TokenType::LeftBracket) {
+                let bracket = self.take_previous();
+                self.deepen()?;
+                let key = self.expression()?;
+                let _ = self.consume(TokenType::RightBracket, "expect ']' after index")?;
+                expr = Expr::Index(Box::new(expr), bracket, Box::new(key));
+            } else if self.match_token(TokenType::Dot) {
+                let dot = self.take_previous();
+                self.deepen()?;
+                let name = self.consume(TokenType::Identifier, "expect field name after '.'")?;
+                let key = Expr::Literal(Value::String(name.raw.to_string()));
+                expr = Expr::Index(Box::new(expr), dot, Box::new(key));
+            } else {
+                break;
+            }
         }
-
+        self.depth = depth;
         Ok(expr)
     }
 
+    // 元素之间以 , 或 ; 分隔，允许结尾多一个分隔符
+    fn table(&mut self) -> Result<Expr, Error> {
+        let brace = self.take_previous();
+        let mut fields = Vec::new();
+        while !self.check(TokenType::RightBrace) {
+            let field = if self.match_token(TokenType::LeftBracket) {
+                let key = self.expression()?;
+                let _ = self.consume(TokenType::RightBracket, "expect ']' after table key")?;
+                let _ = self.consume(TokenType::Equal, "expect '=' after table key")?;
+                (key, self.expression()?)
+            } else {
+                // name = value 先按赋值解析
+                match self.expression()? {
+                    Expr::Assign(name, value) => {
+                        (Expr::Literal(Value::String(name.raw.to_string())), *value)
+                    }
+                    value => (Expr::None, value),
+                }
+            };
+            fields.push(field);
+            if !self.match_tokens(&[TokenType::Comma, TokenType::Semicolon]) {
+                break;
+            }
+        }
+        let _ = self.consume(TokenType::RightBrace, "expect '}' after table fields")?;
+        Ok(Expr::Table(brace, fields))
+    }
+
     fn finish_call(&mut self, callee: Expr) -> Result<Expr, Error> {
         let mut arguments = Vec::new();
-        arguments.push(self.expression()?);
-        while self.match_token(TokenType::Comma) {
+        if !self.check(TokenType::RightParen) {
             arguments.push(self.expression()?);
+            while self.match_token(TokenType::Comma) {
+                arguments.push(self.expression()?);
+            }
         }
-        let paren = self
-            .consume(TokenType::RightParen, "expect ')' after arguments")?
-            .clone();
+        let paren = self.consume(TokenType::RightParen, "expect ')' after arguments")?;
         Ok(Expr::Call(Box::new(callee), paren, arguments))
     }
 
     fn primary(&mut self) -> Result<Expr, Error> {
-        // TODO: 暂时只支持 number
-        if self.match_token(TokenType::Number) {
-            return Ok(Expr::Literal(self.previous().value.clone()));
+        if self.match_tokens(&[TokenType::Number, TokenType::String]) {
+            return Ok(Expr::Literal(self.take_previous().value));
         }
         if self.match_token(TokenType::Nil) {
             return Ok(Expr::Literal(Value::Nil));
         }
         if self.match_token(TokenType::Identifier) {
-            return Ok(Expr::Variable(self.previous().clone()));
+            return Ok(Expr::Variable(self.take_previous()));
+        }
+        if self.match_token(TokenType::LeftBrace) {
+            return self.table();
         }
         // TODO: 暂时不支持 grouping，即 (1 + 2)
-        Err(Error::ParseError(format!("expect expression")))
+        Err(self.error("expect expression"))
     }
 
-    fn consume(&mut self, typ: TokenType, message: &str) -> Result<&Token, Error> {
+    // 取出匹配的 token，语法树直接持有它，不再复制
+    fn consume(&mut self, typ: TokenType, message: &str) -> Result<Token, Error> {
         if self.check(typ) {
-            return Ok(self.advance());
+            self.advance();
+            return Ok(self.take_previous());
+        }
+        Err(self.error(message))
+    }
+
+    // 在当前 token 处报错
+    fn error(&self, message: &str) -> Error {
+        let token = self.peek();
+        let found = match token.typ {
+            TokenType::Eof => "end of file".to_string(),
+            _ => format!("'{}'", token.raw),
+        };
+        Error::ParseError {
+            message: format!("{}, found {}", message, found),
+            line: token.line,
+            col: token.col,
+            span: token.span,
         }
-        Err(Error::ParseError(format!(
-            "parse at '{:?}' err: {}",
-            self.peek(),
-            message
-        )))
     }
 
-    fn match_tokens(&mut self, types: Vec<TokenType>) -> bool {
-        for typ in types {
+    fn match_tokens(&mut self, types: &[TokenType]) -> bool {
+        for &typ in types {
             if self.check(typ) {
                 self.advance();
                 return true;
@@ -285,7 +709,8 @@ impl Parser {
 
     fn advance(&mut self) -> &Token {
         if !self.is_at_end() {
-            self.current += 1;
+            let next = self.next_token();
+            self.previous = std::mem::replace(&mut self.current, next);
         }
         self.previous()
     }
@@ -295,18 +720,224 @@ impl Parser {
     }
 
     fn peek(&self) -> &Token {
-        &self.tokens[self.current]
+        &self.current
     }
 
     fn previous(&self) -> &Token {
-        &self.tokens[self.current - 1]
+        &self.previous
+    }
+
+    // 取出上一个 token，只在放入语法树时调用一次，原位置留下共享文本、没有值的占位 token
+    fn take_previous(&mut self) -> Token {
+        let token = &mut self.previous;
+        let placeholder = Token::new(
+            token.typ,
+            token.raw.clone(),
+            Value::Nil,
+            token.line,
+            token.col,
+            token.span,
+        );
+        std::mem::replace(token, placeholder)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::parser::Parser;
+    use crate::ast::pretty::to_sexpr;
+    use crate::error::{Error, Span};
+    use crate::expression::Expr;
+    use crate::parser::{Parser, ParserOptions, MAX_DEPTH};
     use crate::scanner::Scanner;
+    use crate::value::Value;
+
+    #[test]
+    fn test_parse_local_list() {
+        let source = "local a, b = f(), 2;\nlocal c, d;\nlocal e = 1, 2;\nlocal g = 3;";
+        let stmts = Parser::from_stream(Scanner::new(source.to_string()))
+            .parse()
+            .unwrap();
+        assert_eq!(
+            to_sexpr(&stmts),
+            "(local (a b) (call f) 2)\n(local (c d))\n(local (e) 1 2)\n(local g 3)\n"
+        );
+        assert!(
+            Parser::from_stream(Scanner::new("local a, = 1;".to_string()))
+                .parse()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_parse_break() {
+        let source = "while a do\n  if b then break; end\nend";
+        let stmts = Parser::from_stream(Scanner::new(source.to_string()))
+            .parse()
+            .unwrap();
+        assert_eq!(to_sexpr(&stmts), "(while a (if b (break)))\n");
+
+        // 函数体中的 break 不能跳出函数外的循环
+        for source in ["break;", "while a do function f() break; end end"] {
+            let err = Parser::from_stream(Scanner::new(source.to_string()))
+                .parse()
+                .unwrap_err();
+            assert!(err.to_string().contains("break outside a loop"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_parse_repeat() {
+        let source = "repeat\n  local a = f();\n  if a then break; end\nuntil a > 1\nprint(a);";
+        let stmts = Parser::from_stream(Scanner::new(source.to_string()))
+            .parse()
+            .unwrap();
+        assert_eq!(
+            to_sexpr(&stmts),
+            "(repeat (local a (call f)) (if a (break)) (> a 1))\n(print a)\n"
+        );
+        let err = Parser::from_stream(Scanner::new("repeat print(1); end".to_string()))
+            .parse()
+            .unwrap_err();
+        assert!(err.to_string().contains("expect 'until'"), "{}", err);
+    }
+
+    #[test]
+    fn test_parse_all() {
+        let source = "local a = ;
+print(1)
+local b = 2;
+function f()
+  local c = * 2;
+  return c;
+end
+local d = 3 +;
+end
+print(b);";
+        let (stmts, errors) = Parser::from_stream(Scanner::new(source.to_string())).parse_all();
+        assert_eq!(
+            to_sexpr(&stmts),
+            "(local b 2)\n(function f () (return c))\n(print b)\n"
+        );
+        let errors: Vec<_> = errors
+            .iter()
+            .map(|e| (e.location().unwrap().0, e.to_string()))
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                (1, "Parse error: expect expression, found ';'".to_string()),
+                (
+                    3,
+                    "Parse error: expect ';' after value, found 'local'".to_string()
+                ),
+                (5, "Parse error: expect expression, found '*'".to_string()),
+                (8, "Parse error: expect expression, found ';'".to_string()),
+                (9, "Parse error: expect expression, found 'end'".to_string()),
+            ]
+        );
+
+        // 词法错误之后不再报告语法错误
+        let (_, errors) =
+            Parser::from_stream(Scanner::new("local a = ;\nlocal b = @;".to_string())).parse_all();
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(errors[1], Error::ScanError { .. }), "{:?}", errors);
+    }
+
+    #[test]
+    fn test_parse_precedence() {
+        let source = "print(a or b and c == d < e .. f + g * -h ^ i);
+print(i ^ -h * g + f .. e < d == c and b or a);
+print(a - b - c < d .. e .. f);";
+        let stmts = Parser::from_stream(Scanner::new(source.to_string()))
+            .parse()
+            .unwrap();
+        assert_eq!(
+            to_sexpr(&stmts),
+            "(print (or a (and b (== c (< d (.. e (+ f (* g (- (^ h i))))))))))
+(print (or (and (== (< (.. (+ (* (^ i (- h)) g) f) e) d) c) b) a))
+(print (< (- (- a b) c) (.. d (.. e f))))
+"
+        );
+    }
+
+    #[test]
+    fn test_parse_mod_pow() {
+        let source = "print(-2 ^ 3 ^ 2);\nprint(2 ^ -a * b % c);\nprint(a % b + c);";
+        let stmts = Parser::from_stream(Scanner::new(source.to_string()))
+            .parse()
+            .unwrap();
+        assert_eq!(
+            to_sexpr(&stmts),
+            "(print (- (^ 2 (^ 3 2))))\n(print (% (* (^ 2 (- a)) b) c))\n(print (+ (% a b) c))\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_concat() {
+        let source = r#"print("a" .. "b" .. c == d);
+print(1 + 2 .. 3 < e);"#;
+        let stmts = Parser::from_stream(Scanner::new(source.to_string()))
+            .parse()
+            .unwrap();
+        assert_eq!(
+            to_sexpr(&stmts),
+            r#"(print (== (.. "a" (.. "b" c)) d))
+(print (< (.. (+ 1 2) 3) e))
+"#
+        );
+    }
+
+    #[test]
+    fn test_parse_tables() {
+        let source = r#"local t = {1, "a"; x = 2, [3] = {},};
+print(t.x + t[1].y[2]);
+f(1)(2);
+t.a.b(c)[d]();"#;
+        let stmts = Parser::from_stream(Scanner::new(source.to_string()))
+            .parse()
+            .unwrap();
+        assert_eq!(
+            to_sexpr(&stmts),
+            r#"(local t (table 1 "a" (field "x" 2) (field 3 (table))))
+(print (+ (index t "x") (index (index (index t 1) "y") 2)))
+(call (call f 1) 2)
+(call (index (call (index (index t "a") "b") c) d))
+"#
+        );
+
+        let source = "local t = {1 2};";
+        let e = Parser::from_stream(Scanner::new(source.to_string()))
+            .parse()
+            .unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Parse error: expect '}' after table fields, found '2'"
+        );
+        assert!(Parser::from_stream(Scanner::new("t.1;".to_string()))
+            .parse()
+            .is_err());
+    }
+
+    #[test]
+    fn test_parse_elseif() {
+        let source = "if a then print(1); elseif b then print(2); elseif c then print(3); else print(4); end";
+        let stmts = Parser::from_stream(Scanner::new(source.to_string()))
+            .parse()
+            .unwrap();
+        assert_eq!(
+            to_sexpr(&stmts),
+            "(if a (print 1) (if b (print 2) (if c (print 3) (print 4))))\n"
+        );
+
+        let source = "if a then print(1); elseif b then print(2); end end";
+        let stmts = Parser::from_stream(Scanner::new(source.to_string())).parse();
+        assert!(stmts.is_err());
+        let source = "if a then print(1); elseif b then print(2); end";
+        let stmts = Parser::from_stream(Scanner::new(source.to_string()))
+            .parse()
+            .unwrap();
+        assert_eq!(to_sexpr(&stmts), "(if a (print 1) (if b (print 2)))\n");
+    }
 
     #[test]
     fn test_parse_expr() {
@@ -359,6 +990,136 @@ mod tests {
         let stmts = parser.parse().unwrap();
         println!("{:#?}", stmts);
         assert_eq!(stmts.len(), 2);
-        assert_eq!(stmts[0].as_function_stmt().unwrap().0.raw, "fib");
+        assert_eq!(&*stmts[0].as_function_stmt().unwrap().0.raw, "fib");
+    }
+
+    #[test]
+    fn test_parse_error_span() {
+        let mut scanner = Scanner::new("local a = 1 +;".to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        let err = Parser::new(tokens.clone()).parse().unwrap_err();
+        assert_eq!(err.location(), Some((1, 14)));
+        assert_eq!(err.span(), Some(Span::new(13, 14)));
+    }
+
+    #[test]
+    fn test_parse_expression_entry() {
+        let expr = Parser::parse_expression_entry("1 + fib(3)").unwrap();
+        let Expr::Binary(left, op, right) = expr else {
+            panic!("not a binary expression");
+        };
+        assert!(matches!(*left, Expr::Literal(Value::Int(1))));
+        assert_eq!(&*op.raw, "+");
+        assert!(matches!(*right, Expr::Call(..)));
+
+        assert!(Parser::parse_expression_entry("a = 2;").is_ok());
+        let err = Parser::parse_expression_entry("1 + 2 3").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("expect end of expression, found '3'"));
+        assert!(Parser::parse_expression_entry("local a = 1;").is_err());
+        assert!(Parser::parse_expression_entry("").is_err());
+        assert!(matches!(
+            Parser::parse_expression_entry("1 + @"),
+            Err(Error::ScanError { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_stream() {
+        let source = "local a = 1 + 2;\nprint(a);";
+        let mut scanner = Scanner::new(source.to_string());
+        let tokens = scanner.scan_tokens().unwrap().clone();
+        let expected = Parser::new(tokens).parse().unwrap();
+        let streamed = Parser::from_stream(Scanner::new(source.to_string()))
+            .parse()
+            .unwrap();
+        assert_eq!(format!("{:?}", streamed), format!("{:?}", expected));
+
+        // 词法错误优先于随之产生的语法错误
+        let err = Parser::from_stream(Scanner::new("local a = 1 + @;".to_string()))
+            .parse()
+            .unwrap_err();
+        assert!(matches!(err, Error::ScanError { .. }));
+    }
+
+    #[test]
+    fn test_parse_depth_limit() {
+        let parse = |source: String| {
+            let mut scanner = Scanner::new(source);
+            let tokens = scanner.scan_tokens().unwrap();
+            Parser::new(tokens.clone()).parse()
+        };
+        assert!(parse(format!("return {}1;", "- ".repeat(150))).is_ok());
+        let err = parse(format!("return {}1;", "- ".repeat(1000))).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("expression nested more than 200 levels"));
+        assert!(parse(format!("return 1{};", " + 1".repeat(1000))).is_err());
+        let nested_if = "if a then ".repeat(1000);
+        assert!(parse(nested_if).is_err());
+
+        let source = format!("return {}1;", "- ".repeat(20));
+        let mut scanner = Scanner::new(source);
+        scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(scanner.take_tokens());
+        parser.set_options(ParserOptions {
+            max_expr_depth: 10,
+            ..ParserOptions::default()
+        });
+        assert!(matches!(parser.parse(), Err(Error::ParseError { .. })));
+
+        // 超过 MAX_DEPTH 的选项被截断
+        parser.set_options(ParserOptions {
+            max_expr_depth: usize::MAX,
+            max_block_depth: MAX_DEPTH + 1,
+            ..ParserOptions::default()
+        });
+        assert_eq!(parser.options().max_expr_depth, MAX_DEPTH);
+        assert_eq!(parser.options().max_block_depth, MAX_DEPTH);
+
+        // 缺少 Eof 的 token 序列
+        assert!(Parser::new(vec![]).parse().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parser_options() {
+        let options = ParserOptions {
+            max_expr_depth: 8,
+            max_block_depth: 3,
+            max_params: 2,
+        };
+        let parse = |source: &str| {
+            let mut parser = Parser::from_stream(Scanner::new(source.to_string()));
+            parser.set_options(options);
+            parser.parse()
+        };
+
+        // 每条语句的表达式单独计算层数
+        let expr = format!("{}1", "- ".repeat(5));
+        let source = format!(
+            "function f(a, b)\n  if a then\n    return {};\n  end\nend",
+            expr
+        );
+        assert!(parse(&source).is_ok());
+        let err = parse(&format!("return {}1;", "- ".repeat(10))).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("expression nested more than 8 levels"));
+        assert!(parse(&format!("return 1{};", " + 1".repeat(10))).is_err());
+
+        let source =
+            "function f()\n  if a then\n    if b then\n      return 1;\n    end\n  end\nend";
+        let err = parse(source).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("statements nested more than 3 levels"));
+        assert_eq!(err.location(), Some((4, 7)));
+
+        let err = parse("function f(a, b, c)\nend").unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("more than 2 parameters, found 'c'"));
+        assert_eq!(ParserOptions::default().max_params, super::MAX_PARAMS);
     }
 }
@@ -0,0 +1,154 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::{Duration, Instant};
+
+// 顶层脚本对应的函数名
+pub const SCRIPT: &str = "<script>";
+
+// 单个函数的耗时统计
+#[derive(Debug, Default, Clone)]
+pub struct FuncProfile {
+    pub calls: usize,
+    // 除去被调函数后自身的耗时
+    pub self_time: Duration,
+    // 包含被调函数的耗时，递归调用只计算最外层
+    pub total_time: Duration,
+}
+
+#[derive(Debug)]
+struct Frame {
+    name: String,
+    start: Instant,
+    // 被调函数的耗时
+    children: Duration,
+}
+
+// 函数级别的 profiler，由解释器在调用前后 enter/exit
+#[derive(Debug)]
+pub struct Profiler {
+    frames: Vec<Frame>,
+    funcs: HashMap<String, FuncProfile>,
+    // 调用栈(以 ; 连接) → 自身耗时，即 flamegraph 的 folded 格式
+    folded: BTreeMap<String, Duration>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        let mut profiler = Self {
+            frames: Vec::new(),
+            funcs: HashMap::new(),
+            folded: BTreeMap::new(),
+        };
+        profiler.enter(SCRIPT);
+        profiler
+    }
+
+    pub fn enter(&mut self, name: &str) {
+        self.funcs.entry(name.to_string()).or_default().calls += 1;
+        self.frames.push(Frame {
+            name: name.to_string(),
+            start: Instant::now(),
+            children: Duration::ZERO,
+        });
+    }
+
+    pub fn exit(&mut self) {
+        let stack = self.stack();
+        let frame = match self.frames.pop() {
+            Some(frame) => frame,
+            None => return,
+        };
+        let elapsed = frame.start.elapsed();
+        let self_time = elapsed.saturating_sub(frame.children);
+        let recursive = self.frames.iter().any(|f| f.name == frame.name);
+
+        let func = self.funcs.entry(frame.name).or_default();
+        func.self_time += self_time;
+        if !recursive {
+            func.total_time += elapsed;
+        }
+        *self.folded.entry(stack).or_default() += self_time;
+
+        if let Some(parent) = self.frames.last_mut() {
+            parent.children += elapsed;
+        }
+    }
+
+    // 结束所有未返回的调用，包括顶层脚本
+    pub fn finish(&mut self) {
+        while !self.frames.is_empty() {
+            self.exit();
+        }
+    }
+
+    // 按总耗时从大到小排列的函数统计
+    pub fn report(&self) -> Vec<(&str, &FuncProfile)> {
+        let mut funcs: Vec<_> = self
+            .funcs
+            .iter()
+            .map(|(name, func)| (name.as_str(), func))
+            .collect();
+        funcs.sort_by(|a, b| b.1.total_time.cmp(&a.1.total_time).then(a.0.cmp(b.0)));
+        funcs
+    }
+
+    // folded 格式的调用栈，权重为自身耗时的微秒数
+    pub fn folded(&self) -> String {
+        self.folded
+            .iter()
+            .map(|(stack, time)| format!("{} {}\n", stack, time.as_micros()))
+            .collect()
+    }
+
+    fn stack(&self) -> String {
+        self.frames
+            .iter()
+            .map(|f| f.name.as_str())
+            .collect::<Vec<_>>()
+            .join(";")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Profiler, SCRIPT};
+
+    #[test]
+    fn test_profiler() {
+        let mut profiler = Profiler::new();
+        profiler.enter("fib");
+        profiler.enter("fib");
+        profiler.exit();
+        profiler.exit();
+        profiler.enter("main");
+        profiler.exit();
+        profiler.finish();
+
+        let report = profiler.report();
+        assert_eq!(report.len(), 3);
+        assert_eq!(report[0].0, SCRIPT);
+        let fib = report.iter().find(|(name, _)| *name == "fib").unwrap().1;
+        assert_eq!(fib.calls, 2);
+        assert!(fib.self_time <= fib.total_time);
+
+        let folded = profiler.folded();
+        let stacks: Vec<_> = folded
+            .lines()
+            .map(|l| l.rsplit_once(' ').unwrap().0)
+            .collect();
+        assert_eq!(
+            stacks,
+            vec![
+                "<script>",
+                "<script>;fib",
+                "<script>;fib;fib",
+                "<script>;main"
+            ]
+        );
+    }
+}
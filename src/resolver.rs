@@ -1,119 +1,360 @@
 use std::collections::HashMap;
 
-use crate::error::Error;
+use crate::diagnostic::Label;
+use crate::error::{Error, Span};
 use crate::expression::Expr;
 use crate::scanner::Token;
 use crate::statement::Stmt;
 
+// 静态检查结果的级别
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintLevel {
+    Warning,
+    Error,
+}
+
+// 静态检查发现的问题
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lint {
+    pub level: LintLevel,
+    // 稳定的检查项名字，如 unused-variable
+    pub code: &'static str,
+    pub line: usize,
+    pub col: usize,
+    pub span: Span,
+    pub message: String,
+    // 相关的其它位置，如被遮蔽的声明
+    pub labels: Vec<Label>,
+}
+
+// 作用域中的一个名字
+#[derive(Debug)]
+struct Binding {
+    // 声明处的 token，内置的全局变量没有
+    token: Option<Token>,
+    // 是否为 local 声明，只有 local 才检查是否未使用
+    local: bool,
+    used: bool,
+}
+
 // Resolver 语义解析
 #[derive(Default)]
 pub struct Resolver {
-    scopes: Vec<HashMap<String, ()>>,
+    scopes: Vec<HashMap<String, Binding>>,
+    lints: Vec<Lint>,
 }
 
 impl Resolver {
+    // 语义检查，返回第一个错误
     pub fn resolve(&mut self, statements: &Vec<Stmt>) -> Result<(), Error> {
+        let lints = self.lint(statements);
+        match lints.iter().find(|lint| lint.level == LintLevel::Error) {
+            Some(lint) => Err(Error::ResolveError {
+                message: lint.message.clone(),
+                line: lint.line,
+                col: lint.col,
+                span: lint.span,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    // 语义检查，收集所有错误与警告
+    pub fn lint(&mut self, statements: &[Stmt]) -> Vec<Lint> {
+        self.lints.clear();
         self.begin_scope();
+        // 运行时内置的全局变量
+        self.declare("VERSION", None, false);
+        self.declare("arg", None, false);
+        self.resolve_block(statements);
+        self.end_scope();
+        std::mem::take(&mut self.lints)
+    }
+
+    fn resolve_block(&mut self, statements: &[Stmt]) {
+        // 函数声明提前，允许调用后定义的函数
         for stmt in statements {
-            self.resolve_stmt(stmt)?;
+            if let Stmt::FunctionStmt(name, _, _) = stmt {
+                self.declare(name.raw.as_ref(), Some(name), false);
+            }
+        }
+
+        let mut returned = false;
+        for stmt in statements {
+            if returned {
+                if let Some(token) = stmt_token(stmt) {
+                    self.warning(
+                        token,
+                        "unreachable-code",
+                        "unreachable code after return".to_string(),
+                    );
+                }
+                break;
+            }
+            self.resolve_stmt(stmt);
+            returned = matches!(stmt, Stmt::ReturnStmt(_, _));
         }
-        self.end_scope();
-        Ok(())
     }
 
-    fn resolve_stmt(&mut self, stmt: &Stmt) -> Result<(), Error> {
+    fn resolve_stmt(&mut self, stmt: &Stmt) {
         match stmt {
-            Stmt::PrintStmt(expr) => self.resolve_print_stmt(expr)?,
-            Stmt::IfStmt(_, _, _) => (),
-            Stmt::LocalStmt(_, _) => (),
-            Stmt::FunctionStmt(name, params, body) => self.resolve_func_stmt(name, params, body)?,
-            Stmt::ReturnStmt(_, _) => (),
-            Stmt::Expression(expr) => self.resolve_expr(expr)?,
-            Stmt::Block(_) => (),
+            Stmt::PrintStmt(expr) => self.resolve_expr(expr),
+            Stmt::IfStmt(condition, then_branch, else_branch) => {
+                self.resolve_expr(condition);
+                self.resolve_stmt(then_branch);
+                self.resolve_stmt(else_branch);
+            }
+            Stmt::WhileStmt(condition, body) => {
+                self.resolve_expr(condition);
+                self.begin_scope();
+                self.resolve_block(body);
+                self.end_scope();
+            }
+            Stmt::RepeatStmt(body, condition) => {
+                self.begin_scope();
+                self.resolve_block(body);
+                self.resolve_expr(condition);
+                self.end_scope();
+            }
+            Stmt::LocalStmt(name, init) => {
+                self.resolve_expr(init);
+                self.resolve_local(name);
+            }
+            Stmt::LocalListStmt(names, values) => {
+                values.iter().for_each(|value| self.resolve_expr(value));
+                names.iter().for_each(|name| self.resolve_local(name));
+            }
+            Stmt::FunctionStmt(name, params, body) => self.resolve_func_stmt(name, params, body),
+            Stmt::AssignStmt(name, value) => {
+                self.resolve_expr(value);
+                self.resolve_assign(name);
+            }
+            Stmt::ReturnStmt(_, expr) => self.resolve_expr(expr),
+            Stmt::BreakStmt(_) => (),
+            Stmt::Expression(expr) => self.resolve_expr(expr),
+            Stmt::Block(stmts) => {
+                self.begin_scope();
+                self.resolve_block(stmts);
+                self.end_scope();
+            }
             Stmt::None => (),
         }
-        Ok(())
     }
 
-    fn resolve_print_stmt(&mut self, expr: &Expr) -> Result<(), Error> {
-        self.resolve_expr(expr)?;
-        Ok(())
+    fn resolve_local(&mut self, name: &Token) {
+        let shadowed = self
+            .scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name.raw.as_ref()))
+            .map(|binding| (binding.line(), binding.token.clone()));
+        if let Some((line, token)) = shadowed {
+            self.warning(
+                name,
+                "shadowed-local",
+                format!(
+                    "local {} shadows the declaration at line {}",
+                    name.raw, line
+                ),
+            );
+            if let (Some(token), Some(lint)) = (token, self.lints.last_mut()) {
+                lint.labels
+                    .push(Label::new(token.span, "previously declared here"));
+            }
+        }
+        self.declare(name.raw.as_ref(), Some(name), true);
     }
 
-    fn resolve_func_stmt(
-        &mut self,
-        name: &Token,
-        _params: &Vec<Token>,
-        _body: &Vec<Stmt>,
-    ) -> Result<(), Error> {
-        self.define(name.raw.as_str());
-        // others...
-        Ok(())
+    // 赋值给不可见的变量时定义全局变量
+    fn resolve_assign(&mut self, name: &Token) {
+        let binding = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(name.raw.as_ref()));
+        match binding {
+            Some(binding) => binding.used = true,
+            None => {
+                self.scopes[0].insert(
+                    name.raw.to_string(),
+                    Binding {
+                        token: Some(name.clone()),
+                        local: false,
+                        used: false,
+                    },
+                );
+            }
+        }
+    }
+
+    fn resolve_func_stmt(&mut self, name: &Token, params: &[Token], body: &[Stmt]) {
+        self.declare(name.raw.as_ref(), Some(name), false);
+        self.begin_scope();
+        for param in params {
+            self.declare(param.raw.as_ref(), Some(param), false);
+        }
+        self.resolve_block(body);
+        self.end_scope();
     }
 
-    fn resolve_expr(&mut self, expr: &Expr) -> Result<(), Error> {
+    fn resolve_expr(&mut self, expr: &Expr) {
         match expr {
             Expr::Call(callee, paren, arguments) => {
-                self.resolve_call_expr(callee, paren, arguments)?
-            }
-            Expr::Unary(_, _) => (),
-            Expr::Variable(token) => {
-                let found = self.find_define(token.raw.as_str());
-                if !found {
-                    return Err(Error::ResolveError(format!(
-                        "{} identifier not found",
-                        token.raw.as_str()
-                    )));
-                }
+                self.resolve_call_expr(callee, paren, arguments)
             }
+            Expr::Unary(_, right) => self.resolve_expr(right),
+            Expr::Variable(token) => self.use_variable(token),
             Expr::Assign(token, expr) => {
-                let found = self.find_define(token.raw.as_str());
-                if !found {
-                    return Err(Error::ResolveError(format!(
-                        "{} identifier not found",
-                        token.raw.as_str()
-                    )));
+                self.use_variable(token);
+                self.resolve_expr(expr);
+            }
+            Expr::Binary(left, _, right) | Expr::Logical(left, _, right) => {
+                self.resolve_expr(left);
+                self.resolve_expr(right);
+            }
+            Expr::Table(_, fields) => {
+                for (key, value) in fields {
+                    self.resolve_expr(key);
+                    self.resolve_expr(value);
                 }
-
-                self.resolve_expr(expr)?;
             }
-            Expr::Binary(_, _, _) => (),
+            Expr::Index(table, _, key) => {
+                self.resolve_expr(table);
+                self.resolve_expr(key);
+            }
             Expr::Literal(_) => (),
             Expr::None => (),
         }
-        Ok(())
     }
 
-    fn resolve_call_expr(
-        &mut self,
-        callee: &Box<Expr>,
-        _paren: &Token,
-        _arguments: &Vec<Expr>,
-    ) -> Result<(), Error> {
-        self.resolve_expr(callee.as_ref())?;
-        Ok(())
+    fn resolve_call_expr(&mut self, callee: &Expr, _paren: &Token, arguments: &[Expr]) {
+        self.resolve_expr(callee);
+        for argument in arguments {
+            self.resolve_expr(argument);
+        }
+    }
+
+    fn use_variable(&mut self, token: &Token) {
+        let binding = self
+            .scopes
+            .iter_mut()
+            .rev()
+            .find_map(|scope| scope.get_mut(token.raw.as_ref()));
+        match binding {
+            Some(binding) => binding.used = true,
+            None => self.error(
+                token,
+                "undefined-variable",
+                format!("{} identifier not found", token.raw.as_ref()),
+            ),
+        }
     }
 
-    fn define(&mut self, name: &str) {
-        self.scopes.last_mut().unwrap().insert(name.to_string(), ());
+    fn declare(&mut self, name: &str, token: Option<&Token>, local: bool) {
+        self.scopes.last_mut().unwrap().insert(
+            name.to_string(),
+            Binding {
+                token: token.cloned(),
+                local,
+                used: false,
+            },
+        );
     }
 
-    fn find_define(&self, name: &str) -> bool {
-        self.scopes.last().unwrap().contains_key(name)
+    fn warning(&mut self, token: &Token, code: &'static str, message: String) {
+        self.add_lint(LintLevel::Warning, token, code, message);
+    }
+
+    fn error(&mut self, token: &Token, code: &'static str, message: String) {
+        self.add_lint(LintLevel::Error, token, code, message);
+    }
+
+    fn add_lint(&mut self, level: LintLevel, token: &Token, code: &'static str, message: String) {
+        self.lints.push(Lint {
+            level,
+            code,
+            line: token.line,
+            col: token.col,
+            span: token.span,
+            message,
+            labels: vec![],
+        });
     }
 
     fn begin_scope(&mut self) {
         self.scopes.push(HashMap::new());
     }
+
     fn end_scope(&mut self) {
-        self.scopes.pop();
+        let scope = self.scopes.pop().unwrap();
+        let mut unused: Vec<_> = scope
+            .into_iter()
+            .filter(|(_, binding)| binding.local && !binding.used)
+            .collect();
+        // 同一行的多个 local 按出现的位置排序，保证输出稳定
+        unused.sort_by_key(|(_, binding)| binding.position());
+        for (name, binding) in unused {
+            // 只有 local 声明会进入这里，一定有 token
+            if let Some(token) = &binding.token {
+                self.warning(
+                    token,
+                    "unused-variable",
+                    format!("unused local variable {}", name),
+                );
+            }
+        }
+    }
+}
+
+impl Binding {
+    fn line(&self) -> usize {
+        self.token.as_ref().map_or(0, |token| token.line)
+    }
+
+    fn position(&self) -> (usize, usize) {
+        self.token
+            .as_ref()
+            .map_or((0, 0), |token| (token.line, token.span.start))
+    }
+}
+
+// 语句中用于定位的 token
+pub(crate) fn stmt_token(stmt: &Stmt) -> Option<&Token> {
+    match stmt {
+        Stmt::PrintStmt(expr) | Stmt::Expression(expr) => expr_token(expr),
+        Stmt::IfStmt(condition, _, _)
+        | Stmt::WhileStmt(condition, _)
+        | Stmt::RepeatStmt(_, condition) => expr_token(condition),
+        Stmt::LocalStmt(name, _) | Stmt::FunctionStmt(name, _, _) | Stmt::AssignStmt(name, _) => {
+            Some(name)
+        }
+        Stmt::LocalListStmt(names, _) => names.first(),
+        Stmt::ReturnStmt(keyword, _) | Stmt::BreakStmt(keyword) => Some(keyword),
+        Stmt::Block(stmts) => stmts.first().and_then(stmt_token),
+        Stmt::None => None,
+    }
+}
+
+// 表达式中用于定位的 token
+fn expr_token(expr: &Expr) -> Option<&Token> {
+    match expr {
+        Expr::Call(_, token, _)
+        | Expr::Unary(token, _)
+        | Expr::Variable(token)
+        | Expr::Assign(token, _)
+        | Expr::Binary(_, token, _)
+        | Expr::Logical(_, token, _)
+        | Expr::Table(token, _)
+        | Expr::Index(_, token, _) => Some(token),
+        Expr::Literal(_) | Expr::None => None,
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::error::Span;
     use crate::parser::Parser;
-    use crate::resolver::Resolver;
+    use crate::resolver::{LintLevel, Resolver};
     use crate::scanner::Scanner;
 
     #[test]
@@ -151,13 +392,13 @@ mod tests {
     #[test]
     fn test_resolve_err() {
         let source = r#"
-        a = 1 + 2 + 3;
+        a = 1 + b;
         "#;
 
         let mut scanner = Scanner::new(source.to_string());
         let tokens = scanner.scan_tokens().unwrap();
         println!("{:#?}", tokens);
-        assert_eq!(tokens.len(), 9);
+        assert_eq!(tokens.len(), 7);
 
         let mut parser = Parser::new(tokens.clone());
         let result = parser.parse();
@@ -169,5 +410,112 @@ mod tests {
         let r = resolver.resolve(result.as_ref().unwrap());
         println!("{:#?}", r);
         assert_eq!(r.is_err(), true);
+        let start = source.find('b').unwrap();
+        let err = r.unwrap_err();
+        assert_eq!(err.location(), Some((2, 17)));
+        assert_eq!(err.span(), Some(Span::new(start, start + 1)));
+    }
+
+    #[test]
+    fn test_resolve_global_assign() {
+        let source = "a = 1;\nfunction f()\n  b = a;\n  return b;\nend\nprint(b);";
+        let statements = Parser::from_stream(Scanner::new(source.to_string()))
+            .parse()
+            .unwrap();
+        assert!(Resolver::default().resolve(&statements).is_ok());
+    }
+
+    #[test]
+    fn test_lint_loop_scope() {
+        let source = "local i = 0;
+while i < 2 do
+  local j = i;
+  print(j);
+  i = i + 1;
+end
+while i < 4 do
+  local j = i;
+  print(j);
+  i = i + 1;
+end";
+        let statements = Parser::from_stream(Scanner::new(source.to_string()))
+            .parse()
+            .unwrap();
+        let lints = Resolver::default().lint(&statements);
+        assert!(lints.is_empty(), "{:#?}", lints);
+
+        // until 中可以使用循环体里的 local，但循环外不可见
+        let source =
+            "repeat\n  local k = 1;\nuntil k > 0\nrepeat\n  local k = 2;\nuntil k\nprint(k);";
+        let statements = Parser::from_stream(Scanner::new(source.to_string()))
+            .parse()
+            .unwrap();
+        let lints = Resolver::default().lint(&statements);
+        let messages: Vec<(usize, &str)> = lints
+            .iter()
+            .map(|lint| (lint.line, lint.message.as_str()))
+            .collect();
+        assert_eq!(messages, vec![(7, "k identifier not found")]);
+    }
+
+    #[test]
+    fn test_lint_unused_order() {
+        let source = "function f()\n  local a, b, c = 1, 2;\nend";
+        let statements = Parser::from_stream(Scanner::new(source.to_string()))
+            .parse()
+            .unwrap();
+        let lints = Resolver::default().lint(&statements);
+        let messages: Vec<&str> = lints.iter().map(|lint| lint.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "unused local variable a",
+                "unused local variable b",
+                "unused local variable c",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lint() {
+        let source = r#"
+        function add(n)
+          local unused = 1;
+          return n + count;
+          print(n);
+        end
+
+        local a = 1;
+        function show(x)
+          local a = x;
+          print(a);
+        end
+        show(a);
+        "#;
+
+        let mut scanner = Scanner::new(source.to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens.clone());
+        let statements = parser.parse().unwrap();
+
+        let mut resolver = Resolver::default();
+        let lints = resolver.lint(&statements);
+        let messages: Vec<(LintLevel, usize, &str)> = lints
+            .iter()
+            .map(|lint| (lint.level, lint.line, lint.message.as_str()))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                (LintLevel::Error, 4, "count identifier not found"),
+                (LintLevel::Warning, 5, "unreachable code after return"),
+                (LintLevel::Warning, 3, "unused local variable unused"),
+                (
+                    LintLevel::Warning,
+                    10,
+                    "local a shadows the declaration at line 8"
+                ),
+            ]
+        );
     }
 }
@@ -1,11 +1,13 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
-use substring::Substring;
+use serde::{Deserialize, Serialize};
 
-use crate::error::Error;
+use crate::diagnostic::{Diagnostic, Severity};
+use crate::error::{Error, Span};
 use crate::value::Value;
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub enum TokenType {
     // Single-character tokens.
     // (
@@ -16,10 +18,16 @@ pub enum TokenType {
     LeftBrace,
     // }
     RightBrace,
+    // [
+    LeftBracket,
+    // ]
+    RightBracket,
     // ,
     Comma,
     // .
     Dot,
+    // ..
+    DotDot,
     // -
     Minus,
     // +
@@ -30,6 +38,10 @@ pub enum TokenType {
     Slash,
     // *
     Star,
+    // %
+    Percent,
+    // ^
+    Caret,
 
     // One or two character tokens.
     // !
@@ -59,6 +71,8 @@ pub enum TokenType {
     And,
     // else
     Else,
+    // elseif
+    Elseif,
     // false
     False,
     // function
@@ -85,55 +99,109 @@ pub enum TokenType {
     Local,
     // while
     While,
+    // do
+    Do,
+    // break
+    Break,
+    // repeat
+    Repeat,
+    // until
+    Until,
 
     Eof,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Token {
     pub typ: TokenType,
-    pub raw: String,
+    // 同一份源码中相同的文本共享一份内存，复制 token 只增加引用计数
+    pub raw: Arc<str>,
     pub value: Value,
     pub line: usize,
+    // 列号，从 1 开始
+    pub col: usize,
+    pub span: Span,
 }
 
 impl Token {
-    pub fn new(typ: TokenType, raw: String, value: Value, line: usize) -> Self {
+    pub fn new(
+        typ: TokenType,
+        raw: impl Into<Arc<str>>,
+        value: Value,
+        line: usize,
+        col: usize,
+        span: Span,
+    ) -> Self {
         Self {
             typ,
-            raw,
+            raw: raw.into(),
             value,
             line,
+            col,
+            span,
         }
     }
 }
 
+// 增量扫描的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanState {
+    // 已经是完整的语句，Eof 已经加入 tokens
+    Complete,
+    // 块、括号、字符串或注释还没有结束，需要更多输入
+    Incomplete,
+}
+
 pub struct Scanner {
     pub source: String,
-    chars: Vec<char>,
+    // 已经出现过的 token 文本
+    symbols: HashSet<Arc<str>>,
 
     pub tokens: Vec<Token>,
+    // 不影响扫描结果的警告
+    warnings: Vec<Diagnostic>,
+    // 当前 token 的起始与扫描到的位置，都是 source 中的字节偏移
     start: usize,
     current: usize,
     line: usize,
+    // 扫描位置的列号，以字符计
+    col: usize,
+    // 当前 token 起始的行列，多行 token(如字符串)以起始位置为准
+    start_line: usize,
+    start_col: usize,
+    // 作为迭代器时是否已经产生了 Eof 或错误
+    finished: bool,
+    // 增量扫描时还没有结束的 function、if、while 与括号
+    depth: usize,
 
     keywords: HashMap<String, TokenType>,
 }
 
 impl Scanner {
     pub fn new(source: String) -> Self {
-        // 目前只支持英文，直接 chars
-        let chars: Vec<char> = source.chars().collect();
+        // 跳过 #! 开头的第一行，脚本可以直接执行
+        let current = if source.starts_with("#!") {
+            source.find('\n').unwrap_or(source.len())
+        } else {
+            0
+        };
         Self {
             source,
-            chars,
+            symbols: HashSet::new(),
             tokens: Vec::new(),
-            start: 0,
-            current: 0,
+            warnings: Vec::new(),
+            start: current,
+            current,
             line: 1,
+            col: 1,
+            start_line: 1,
+            start_col: 1,
+            finished: false,
+            depth: 0,
             keywords: HashMap::from([
                 ("and".to_string(), TokenType::And),
                 ("else".to_string(), TokenType::Else),
+                ("elseif".to_string(), TokenType::Elseif),
                 ("false".to_string(), TokenType::False),
                 ("function".to_string(), TokenType::Function),
                 ("end".to_string(), TokenType::End),
@@ -147,25 +215,125 @@ impl Scanner {
                 ("true".to_string(), TokenType::True),
                 ("local".to_string(), TokenType::Local),
                 ("while".to_string(), TokenType::While),
+                ("do".to_string(), TokenType::Do),
+                ("break".to_string(), TokenType::Break),
+                ("repeat".to_string(), TokenType::Repeat),
+                ("until".to_string(), TokenType::Until),
             ]),
         }
     }
 
     pub fn scan_tokens(&mut self) -> Result<&Vec<Token>, Error> {
         while !self.is_at_end() {
-            self.start = self.current;
+            self.start_token();
             self.scan_token()?;
         }
 
-        // EOF token
-        self.tokens.push(Token::new(
+        let eof = self.eof();
+        self.tokens.push(eof);
+
+        Ok(&self.tokens)
+    }
+
+    // 遇到错误时记录下来继续扫描，一次得到所有的词法错误；
+    // 出错的字符不产生 token，写错的数字整个跳过
+    pub fn scan_all(&mut self) -> (Vec<Token>, Vec<Error>) {
+        let mut errors = Vec::new();
+        while !self.is_at_end() {
+            self.start_token();
+            if let Err(e) = self.scan_token() {
+                errors.push(e);
+                if self.lexeme().starts_with(|c: char| c.is_ascii_digit()) {
+                    while self.peek().is_alphanumeric() {
+                        self.advance();
+                    }
+                }
+            }
+        }
+        let eof = self.eof();
+        self.tokens.push(eof);
+        (self.take_tokens(), errors)
+    }
+
+    // 追加输入，之后由 scan_more 继续扫描
+    pub fn push_str(&mut self, input: &str) {
+        self.source.push_str(input);
+    }
+
+    // 扫描已经追加的输入，输入不完整时返回 Incomplete 而不是错误，
+    // REPL 可以继续读入下一行；Complete 时取出 tokens 交给 parser
+    pub fn scan_more(&mut self) -> Result<ScanState, Error> {
+        while !self.is_at_end() {
+            self.start_token();
+            let (line, col, count) = (self.line, self.col, self.tokens.len());
+            if let Err(e) = self.scan_token() {
+                if !self.is_unterminated() {
+                    return Err(e);
+                }
+                // 回到未结束的字符串或注释的开头，下次重新扫描
+                self.current = self.start;
+                self.line = line;
+                self.col = col;
+                return Ok(ScanState::Incomplete);
+            }
+            for token in &self.tokens[count..] {
+                match token.typ {
+                    TokenType::Function
+                    | TokenType::If
+                    | TokenType::While
+                    | TokenType::Repeat
+                    | TokenType::LeftParen
+                    | TokenType::LeftBrace
+                    | TokenType::LeftBracket => self.depth += 1,
+                    TokenType::End
+                    | TokenType::Until
+                    | TokenType::RightParen
+                    | TokenType::RightBrace
+                    | TokenType::RightBracket => self.depth = self.depth.saturating_sub(1),
+                    _ => {}
+                }
+            }
+        }
+        if self.depth > 0 {
+            return Ok(ScanState::Incomplete);
+        }
+        let eof = self.eof();
+        self.tokens.push(eof);
+        Ok(ScanState::Complete)
+    }
+
+    // 扫描到输入结尾的字符串或块注释
+    fn is_unterminated(&self) -> bool {
+        let rest = &self.source[self.start..];
+        let comment = rest.strip_prefix("--").and_then(long_bracket_level);
+        self.is_at_end() && (rest.starts_with('"') || comment.is_some())
+    }
+
+    fn start_token(&mut self) {
+        self.start = self.current;
+        self.start_line = self.line;
+        self.start_col = self.col;
+    }
+
+    fn eof(&self) -> Token {
+        Token::new(
             TokenType::Eof,
-            "".to_string(),
+            "",
             Value::Nil,
             self.line,
-        ));
+            self.col,
+            Span::new(self.source.len(), self.source.len()),
+        )
+    }
 
-        Ok(&self.tokens)
+    // 取出扫描得到的 token，交给 parser 时不必整体复制
+    pub fn take_tokens(&mut self) -> Vec<Token> {
+        std::mem::take(&mut self.tokens)
+    }
+
+    // 取出扫描过程中产生的警告
+    pub fn take_warnings(&mut self) -> Vec<Diagnostic> {
+        std::mem::take(&mut self.warnings)
     }
 
     fn scan_token(&mut self) -> Result<(), Error> {
@@ -175,12 +343,28 @@ impl Scanner {
             ')' => self.add_token(TokenType::RightParen),
             '{' => self.add_token(TokenType::LeftBrace),
             '}' => self.add_token(TokenType::RightBrace),
+            '[' => self.add_token(TokenType::LeftBracket),
+            ']' => self.add_token(TokenType::RightBracket),
             ',' => self.add_token(TokenType::Comma),
-            '.' => self.add_token(TokenType::Dot),
-            '-' => self.add_token(TokenType::Minus),
+            '.' => {
+                if self.match_char('.') {
+                    self.add_token(TokenType::DotDot);
+                } else {
+                    self.add_token(TokenType::Dot);
+                }
+            }
+            '-' => {
+                if self.match_char('-') {
+                    self.comment()?;
+                } else {
+                    self.add_token(TokenType::Minus);
+                }
+            }
             '+' => self.add_token(TokenType::Plus),
             ';' => self.add_token(TokenType::Semicolon),
             '*' => self.add_token(TokenType::Star),
+            '%' => self.add_token(TokenType::Percent),
+            '^' => self.add_token(TokenType::Caret),
             '!' => {
                 if self.match_char('=') {
                     self.add_token(TokenType::BangEqual);
@@ -220,76 +404,173 @@ impl Scanner {
                     self.add_token(TokenType::Slash);
                 }
             }
-            ' ' | '\r' | '\t' => {} // 忽略空格
-            '\n' => self.line += 1, // 换行
-            '"' => self.string()?,  // 字符串
-            'o' => {
-                if self.match_char('r') {
-                    self.add_token(TokenType::Or);
-                }
-            }
+            ' ' | '\r' | '\t' => {}  // 忽略空格
+            '\n' => self.new_line(), // 换行
+            '"' => self.string()?,   // 字符串
             _ => {
                 if c.is_digit(10) {
-                    self.number();
+                    self.number()?;
                 } else if c.is_alphabetic() {
                     self.identifier();
                 } else {
-                    return Err(Error::ScanError(format!(
-                        "Unexpected character '{}' at {}",
-                        c, self.line
-                    )));
+                    return Err(Error::ScanError {
+                        message: format!("Unexpected character '{}'", c),
+                        line: self.start_line,
+                        col: self.start_col,
+                        span: self.span(),
+                    });
                 }
             }
         }
         Ok(())
     }
 
+    // Lua 风格的注释，--[[ 或 --[==[ 开始的块注释到同样层级的 ]] 或 ]==] 结束，
+    // 其它到行尾结束
+    fn comment(&mut self) -> Result<(), Error> {
+        let Some(level) = long_bracket_level(&self.source[self.current..]) else {
+            while self.peek() != '\n' && !self.is_at_end() {
+                self.advance();
+            }
+            return Ok(());
+        };
+        for _ in 0..level + 2 {
+            self.advance(); // [=*[
+        }
+        let close = format!("]{}]", "=".repeat(level));
+        while !self.source[self.current..].starts_with(&close) {
+            if self.is_at_end() {
+                return Err(Error::ScanError {
+                    message: "Unterminated comment".to_string(),
+                    line: self.start_line,
+                    col: self.start_col,
+                    span: self.span(),
+                });
+            }
+            if self.advance() == '\n' {
+                self.new_line();
+            }
+        }
+        for _ in 0..level + 2 {
+            self.advance(); // ]=*]
+        }
+        Ok(())
+    }
+
     fn string(&mut self) -> Result<(), Error> {
         while self.peek() != '"' && !self.is_at_end() {
             if self.peek() == '\n' {
-                self.line += 1;
+                self.advance();
+                self.new_line();
+                continue;
             }
             self.advance();
         }
 
         if self.is_at_end() {
-            return Err(Error::ScanError(format!(
-                "Unterminated string at {}",
-                self.line
-            )));
+            return Err(Error::ScanError {
+                message: "Unterminated string".to_string(),
+                line: self.start_line,
+                col: self.start_col,
+                span: self.span(),
+            });
         }
         self.advance(); // "
-        let _sub = self.source.substring(self.start + 1, self.current - 1);
-        // TODO: 目前只支持 int，所以加入 nil
-        self.add_token2(TokenType::String, Value::Nil);
+                        // 去掉两侧的引号，还不支持转义字符
+        let lexeme = self.lexeme();
+        let text = lexeme[1..lexeme.len() - 1].to_string();
+        self.add_token2(TokenType::String, Value::String(text));
         Ok(())
     }
 
-    fn number(&mut self) {
+    fn number(&mut self) -> Result<(), Error> {
+        if self.lexeme() == "0" {
+            match self.peek() {
+                'x' | 'X' => return self.radix_number(16),
+                'b' | 'B' => return self.radix_number(2),
+                _ => {}
+            }
+        }
         while self.peek().is_digit(10) {
             self.advance();
         }
 
-        // TODO: 支持小数点
+        // 带小数点的是浮点数
         if self.peek() == '.' && self.peek_next().is_digit(10) {
             self.advance(); // 跳过.
             while self.peek().is_digit(10) {
                 self.advance();
             }
+            let f = self.lexeme().parse::<f32>().unwrap(); // 只由数字与小数点组成，一定能解析
+            self.add_token2(TokenType::Number, Value::Float(f));
+            return Ok(());
+        }
+        let sub = self.lexeme();
+        // 整数只支持 i32，超出范围的数截断并给出警告
+        let f = sub.parse::<f64>().unwrap(); // 只由数字组成，一定能解析
+        let n = f as i32;
+        if n as f64 != f {
+            let message = format!("number {} truncated to {}", sub, n);
+            self.warnings.push(
+                Diagnostic::new(Severity::Warning, "truncating-literal", message)
+                    .with_span(self.span()),
+            );
         }
-        let sub = self.source.substring(self.start, self.current);
-        let n = sub.parse::<i32>().unwrap(); // 目前只支持i32
-        self.add_token2(TokenType::Number, Value::Int(n))
+        self.add_token2(TokenType::Number, Value::Int(n));
+        Ok(())
+    }
+
+    // 0x 与 0b 开头的整数，与 Lua 一样超出范围时回绕
+    fn radix_number(&mut self, radix: u32) -> Result<(), Error> {
+        self.advance(); // 跳过 x 或 b
+        let mut n: i32 = 0;
+        let mut digits = 0;
+        while self.peek().is_alphanumeric() {
+            let c = self.peek();
+            let d = match c.to_digit(radix) {
+                Some(d) => d as i32,
+                None => return Err(self.char_error(format!("invalid digit '{}' in number", c))),
+            };
+            // 超出 i32 时指向溢出的那一位
+            n = match n.checked_mul(radix as i32).and_then(|n| n.checked_add(d)) {
+                Some(n) => n,
+                None => {
+                    return Err(self.char_error(format!(
+                        "number {}{} is too large",
+                        self.lexeme(),
+                        c
+                    )))
+                }
+            };
+            self.advance();
+            digits += 1;
+        }
+        if digits == 0 {
+            return Err(self.char_error(format!("missing digits after '{}'", self.lexeme())));
+        }
+        self.add_token2(TokenType::Number, Value::Int(n));
+        Ok(())
     }
 
+    // 指向当前字符的错误
+    fn char_error(&mut self, message: String) -> Error {
+        let len = self.peek().len_utf8().min(self.source.len() - self.current);
+        Error::ScanError {
+            message,
+            line: self.line,
+            col: self.col,
+            span: Span::new(self.current, self.current + len),
+        }
+    }
+
+    // 读入最长的标识符后再查关键字，or、output 等不会被拆开
     fn identifier(&mut self) {
         while self.peek().is_alphanumeric() {
             self.advance();
         }
-        let sub = self.source.substring(self.start, self.current);
         let typ = self
             .keywords
-            .get(sub)
+            .get(self.lexeme())
             .cloned()
             .unwrap_or(TokenType::Identifier);
         self.add_token(typ);
@@ -300,50 +581,112 @@ impl Scanner {
     }
 
     fn add_token2(&mut self, typ: TokenType, val: Value) {
-        let sub = self.source.substring(self.start, self.current);
-        self.tokens
-            .push(Token::new(typ, sub.to_string(), val, self.line));
+        let raw = self.intern();
+        self.tokens.push(Token::new(
+            typ,
+            raw,
+            val,
+            self.start_line,
+            self.start_col,
+            self.span(),
+        ));
     }
 
-    fn match_char(&mut self, expected: char) -> bool {
-        if self.is_at_end() {
-            return false;
+    // 当前 token 的字节范围
+    fn span(&self) -> Span {
+        Span::new(self.start, self.current)
+    }
+
+    // 当前 token 的文本，直接引用源码
+    fn lexeme(&self) -> &str {
+        &self.source[self.start..self.current]
+    }
+
+    // 相同的文本只分配一次
+    fn intern(&mut self) -> Arc<str> {
+        let lexeme = &self.source[self.start..self.current];
+        if let Some(symbol) = self.symbols.get(lexeme) {
+            return symbol.clone();
         }
-        if self.chars[self.current] != expected {
+        let symbol: Arc<str> = Arc::from(lexeme);
+        self.symbols.insert(symbol.clone());
+        symbol
+    }
+
+    fn new_line(&mut self) {
+        self.line += 1;
+        self.col = 1;
+    }
+
+    fn match_char(&mut self, expected: char) -> bool {
+        if self.peek() != expected || self.is_at_end() {
             return false;
         }
-        self.current += 1; // 只有 true 才++
-        return true;
+        self.advance(); // 只有 true 才前进
+        true
     }
 
     fn peek_next(&mut self) -> char {
-        if self.current + 1 >= self.chars.len() {
-            return '\0';
-        }
-        return self.chars[self.current + 1];
+        let mut chars = self.source[self.current..].chars();
+        chars.next();
+        chars.next().unwrap_or('\0')
     }
 
     fn peek(&mut self) -> char {
-        if self.is_at_end() {
-            return '\0';
-        }
-        return self.chars[self.current];
+        self.source[self.current..].chars().next().unwrap_or('\0')
     }
 
     fn advance(&mut self) -> char {
-        let c = self.chars[self.current];
-        self.current += 1;
-        return c;
+        let c = self.peek();
+        self.current += c.len_utf8();
+        self.col += 1;
+        c
     }
 
     fn is_at_end(&self) -> bool {
-        self.current >= self.chars.len()
+        self.current >= self.source.len()
+    }
+}
+
+// 按需扫描，每次只保留一个 token，不要与 scan_tokens 混用；
+// 以 Eof 结束，出错后不再产生 token
+impl Iterator for Scanner {
+    type Item = Result<Token, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
+            return None;
+        }
+        // 空白与注释不产生 token
+        while self.tokens.is_empty() && !self.is_at_end() {
+            self.start_token();
+            if let Err(e) = self.scan_token() {
+                self.finished = true;
+                return Some(Err(e));
+            }
+        }
+        match self.tokens.pop() {
+            Some(token) => Some(Ok(token)),
+            None => {
+                self.finished = true;
+                Some(Ok(self.eof()))
+            }
+        }
     }
 }
 
+// 以 [ 开始、中间为若干 =、再以 [ 结束的长括号，返回 = 的个数
+fn long_bracket_level(s: &str) -> Option<usize> {
+    let rest = s.strip_prefix('[')?;
+    let level = rest.len() - rest.trim_start_matches('=').len();
+    rest[level..].starts_with('[').then_some(level)
+}
+
 #[cfg(test)]
 mod tests {
-    use super::{Scanner, TokenType};
+    use super::{ScanState, Scanner, TokenType};
+    use crate::error::Span;
+    use crate::value::Value;
 
     #[test]
     fn test_scan_tokens() {
@@ -371,4 +714,334 @@ mod tests {
         assert_eq!(tokens[7].typ, TokenType::Local);
         assert_eq!(tokens[14].typ, TokenType::Local);
     }
+
+    #[test]
+    fn test_scan_locations() {
+        let mut scanner = Scanner::new("local a = 1;\n  print(a);".to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!((tokens[0].line, tokens[0].col), (1, 1));
+        assert_eq!((tokens[1].line, tokens[1].col), (1, 7));
+        assert_eq!((tokens[5].line, tokens[5].col), (2, 3));
+
+        let mut scanner = Scanner::new("local a = 1;\nlocal b = @;".to_string());
+        let err = scanner.scan_tokens().unwrap_err();
+        assert_eq!(err.location(), Some((2, 11)));
+        assert_eq!(err.span(), Some(Span::new(23, 24)));
+    }
+
+    #[test]
+    fn test_scan_keywords() {
+        let mut scanner = Scanner::new("ok or output order o endx end".to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        let tokens: Vec<_> = tokens
+            .iter()
+            .map(|token| (token.typ, token.raw.as_ref()))
+            .collect();
+        assert_eq!(
+            tokens,
+            vec![
+                (TokenType::Identifier, "ok"),
+                (TokenType::Or, "or"),
+                (TokenType::Identifier, "output"),
+                (TokenType::Identifier, "order"),
+                (TokenType::Identifier, "o"),
+                (TokenType::Identifier, "endx"),
+                (TokenType::End, "end"),
+                (TokenType::Eof, ""),
+            ]
+        );
+
+        // 以关键字开头的标识符不会被拆开
+        let mut scanner =
+            Scanner::new("open out andy iff nilable localx printer returned and".to_string());
+        let typs: Vec<_> = scanner
+            .scan_tokens()
+            .unwrap()
+            .iter()
+            .map(|token| token.typ)
+            .collect();
+        let mut expected = vec![TokenType::Identifier; 8];
+        expected.extend([TokenType::And, TokenType::Eof]);
+        assert_eq!(typs, expected);
+    }
+
+    #[test]
+    fn test_scan_shared_text() {
+        let mut scanner = Scanner::new("local 名字 = 1;\nprint(名字);".to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!(&*tokens[1].raw, "名字");
+        assert_eq!((tokens[1].col, tokens[1].span), (7, Span::new(6, 12)));
+        assert_eq!((tokens[2].col, tokens[2].span), (10, Span::new(13, 14)));
+        assert_eq!((tokens[7].line, tokens[7].col), (2, 7));
+        // 相同的文本共享同一份内存
+        assert!(std::sync::Arc::ptr_eq(&tokens[1].raw, &tokens[7].raw));
+    }
+
+    #[test]
+    fn test_scan_iterator() {
+        let source = "local a = 1; // 注释\nprint(a + 2);";
+        let mut scanner = Scanner::new(source.to_string());
+        let expected: Vec<_> = scanner
+            .scan_tokens()
+            .unwrap()
+            .iter()
+            .map(|t| (t.typ, t.raw.clone(), t.line, t.col, t.span))
+            .collect();
+        let streamed: Vec<_> = Scanner::new(source.to_string())
+            .map(|t| t.unwrap())
+            .map(|t| (t.typ, t.raw.clone(), t.line, t.col, t.span))
+            .collect();
+        assert_eq!(streamed, expected);
+
+        let mut scanner = Scanner::new("local a = @;".to_string());
+        assert_eq!(scanner.next().unwrap().unwrap().typ, TokenType::Local);
+        assert_eq!(scanner.next().unwrap().unwrap().typ, TokenType::Identifier);
+        assert_eq!(scanner.next().unwrap().unwrap().typ, TokenType::Equal);
+        assert!(scanner.next().unwrap().is_err());
+        assert!(scanner.next().is_none());
+    }
+
+    #[test]
+    fn test_scan_strings() {
+        let mut scanner = Scanner::new("print(\"hello, 世界\");\nlocal s = \"a\nb\";".to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        assert_eq!(tokens[2].typ, TokenType::String);
+        assert_eq!(tokens[2].raw.as_ref(), "\"hello, 世界\"");
+        assert_eq!(tokens[2].value, Value::String("hello, 世界".to_string()));
+        assert_eq!(tokens[8].value, Value::String("a\nb".to_string()));
+        assert_eq!(tokens[9].line, 3);
+        assert!(Scanner::new("\"open".to_string()).scan_tokens().is_err());
+    }
+
+    #[test]
+    fn test_scan_comments() {
+        let source = "local a = 1 - 2; -- 行注释\n--[[ 块注释\n跨越多行 ]] local b = a;\n// 旧的注释\nprint(b);";
+        let mut scanner = Scanner::new(source.to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        let raws: Vec<_> = tokens.iter().map(|token| token.raw.as_ref()).collect();
+        assert_eq!(
+            raws,
+            [
+                "local", "a", "=", "1", "-", "2", ";", "local", "b", "=", "a", ";", "print", "(",
+                "b", ")", ";", ""
+            ]
+        );
+        assert_eq!(tokens[7].line, 3);
+        assert_eq!(tokens[12].line, 5);
+        assert!(Scanner::new("--[[ open\n".to_string())
+            .scan_tokens()
+            .is_err());
+
+        // 长括号的层级必须一致，--[= 后不是 [ 时为行注释
+        let source = "--[==[ x ]] ]=] ]==] print(42);\n--[=x print(1);\nprint(2);";
+        let tokens = Scanner::new(source.to_string())
+            .scan_tokens()
+            .unwrap()
+            .clone();
+        let raws: Vec<_> = tokens.iter().map(|token| token.raw.as_ref()).collect();
+        assert_eq!(
+            raws,
+            ["print", "(", "42", ")", ";", "print", "(", "2", ")", ";", ""]
+        );
+        assert!(Scanner::new("--[=[ open ]]\n".to_string())
+            .scan_tokens()
+            .is_err());
+    }
+
+    #[test]
+    fn test_scan_dots() {
+        let mut scanner = Scanner::new("a..b t.k 1..2 1.5".to_string());
+        let tokens: Vec<_> = scanner
+            .scan_tokens()
+            .unwrap()
+            .iter()
+            .map(|token| (token.typ, token.raw.to_string()))
+            .collect();
+        let typs: Vec<_> = tokens.iter().map(|(typ, _)| *typ).collect();
+        assert_eq!(
+            typs,
+            [
+                TokenType::Identifier,
+                TokenType::DotDot,
+                TokenType::Identifier,
+                TokenType::Identifier,
+                TokenType::Dot,
+                TokenType::Identifier,
+                TokenType::Number,
+                TokenType::DotDot,
+                TokenType::Number,
+                TokenType::Number,
+                TokenType::Eof
+            ]
+        );
+        assert_eq!(tokens[9].1, "1.5");
+    }
+
+    #[test]
+    fn test_scan_radix_numbers() {
+        let mut scanner = Scanner::new("0x1F + 0b1010 + 0XfF + 0x7FFFFFFF + 0".to_string());
+        let values: Vec<_> = scanner
+            .scan_tokens()
+            .unwrap()
+            .iter()
+            .filter(|token| token.typ == TokenType::Number)
+            .map(|token| token.value.clone())
+            .collect();
+        assert_eq!(
+            values,
+            [
+                Value::Int(31),
+                Value::Int(10),
+                Value::Int(255),
+                Value::Int(i32::MAX),
+                Value::Int(0)
+            ]
+        );
+
+        let err = Scanner::new("local a = 0b102;".to_string())
+            .scan_tokens()
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Scan error: invalid digit '2' in number");
+        assert_eq!(err.location(), Some((1, 15)));
+        assert_eq!(err.span(), Some(Span::new(14, 15)));
+        let err = Scanner::new("print(0x80000000);".to_string())
+            .scan_tokens()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Scan error: number 0x80000000 is too large"
+        );
+        assert_eq!(err.span(), Some(Span::new(15, 16)));
+        let err = Scanner::new("0x;".to_string()).scan_tokens().unwrap_err();
+        assert_eq!(err.to_string(), "Scan error: missing digits after '0x'");
+        assert_eq!(err.span(), Some(Span::new(2, 3)));
+    }
+
+    #[test]
+    fn test_scan_more() {
+        let mut scanner = Scanner::new(String::new());
+        let lines = [
+            "function f(a,\n",
+            "  b)\n",
+            "  return \"a\n",
+            "b\";\n",
+            "end --[[ 注释\n",
+            "]]\n",
+        ];
+        let states: Vec<_> = lines
+            .iter()
+            .map(|line| {
+                scanner.push_str(line);
+                scanner.scan_more().unwrap()
+            })
+            .collect();
+        assert_eq!(
+            states,
+            [
+                ScanState::Incomplete,
+                ScanState::Incomplete,
+                ScanState::Incomplete,
+                ScanState::Incomplete,
+                ScanState::Incomplete,
+                ScanState::Complete
+            ]
+        );
+        let tokens = scanner.take_tokens();
+        assert_eq!(tokens.len(), 12);
+        assert_eq!(tokens[8].value, Value::String("a\nb".to_string()));
+        assert_eq!(tokens[10].line, 5);
+        assert_eq!(tokens[11].typ, TokenType::Eof);
+
+        // 下一条语句从新的 token 开始
+        scanner.push_str("print(1);\n");
+        assert_eq!(scanner.scan_more().unwrap(), ScanState::Complete);
+        assert_eq!(scanner.take_tokens()[0].line, 7);
+        scanner.push_str("repeat\n");
+        assert_eq!(scanner.scan_more().unwrap(), ScanState::Incomplete);
+        scanner.push_str("until a\n");
+        assert_eq!(scanner.scan_more().unwrap(), ScanState::Complete);
+        scanner.take_tokens();
+        scanner.push_str("local a = @;\n");
+        assert!(scanner.scan_more().is_err());
+    }
+
+    #[test]
+    fn test_scan_shebang() {
+        let source = "#!/usr/bin/env plua\nprint(1);";
+        let tokens = Scanner::new(source.to_string())
+            .scan_tokens()
+            .unwrap()
+            .clone();
+        assert_eq!(tokens[0].typ, TokenType::Print);
+        assert_eq!(tokens[0].line, 2);
+        assert_eq!(tokens[0].span, Span::new(20, 25));
+        assert_eq!(Scanner::new(source.to_string()).count(), 6);
+        assert_eq!(
+            Scanner::new("#!plua".to_string())
+                .scan_tokens()
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_scan_all() {
+        let source = "local a = @;\nlocal b = 0b12 + 1;\nprint($);\nlocal s = \"open";
+        let (tokens, errors) = Scanner::new(source.to_string()).scan_all();
+        let locations: Vec<_> = errors.iter().map(|e| e.location().unwrap()).collect();
+        assert_eq!(locations, [(1, 11), (2, 14), (3, 7), (4, 11)]);
+        let raws: Vec<_> = tokens.iter().map(|token| token.raw.as_ref()).collect();
+        assert_eq!(
+            raws,
+            [
+                "local", "a", "=", ";", "local", "b", "=", "+", "1", ";", "print", "(", ")", ";",
+                "local", "s", "=", ""
+            ]
+        );
+        assert_eq!(tokens.last().unwrap().typ, TokenType::Eof);
+    }
+
+    #[test]
+    fn test_scan_warnings() {
+        let mut scanner = Scanner::new("local a = 1.5 + 3000000000 + 7;".to_string());
+        let values: Vec<_> = scanner
+            .scan_tokens()
+            .unwrap()
+            .iter()
+            .filter(|token| token.typ == TokenType::Number)
+            .map(|token| token.value.clone())
+            .collect();
+        assert_eq!(
+            values,
+            [Value::Float(1.5), Value::Int(i32::MAX), Value::Int(7)]
+        );
+
+        let warnings = scanner.take_warnings();
+        let messages: Vec<_> = warnings.iter().map(|w| w.message.as_str()).collect();
+        assert_eq!(messages, ["number 3000000000 truncated to 2147483647"]);
+        assert_eq!(warnings[0].primary_span, Some(Span::new(16, 26)));
+        assert!(scanner.take_warnings().is_empty());
+    }
+
+    #[test]
+    fn test_scan_spans() {
+        // 非 ASCII 字符按字节计算偏移
+        let source = "print(\"é\"); local ab = 12;";
+        let mut scanner = Scanner::new(source.to_string());
+        let tokens = scanner.scan_tokens().unwrap();
+        let raws: Vec<_> = tokens
+            .iter()
+            .map(|token| &source[token.span.start..token.span.end])
+            .collect();
+        assert_eq!(
+            raws,
+            ["print", "(", "\"é\"", ")", ";", "local", "ab", "=", "12", ";", ""]
+        );
+        assert_eq!(tokens[2].span, Span::new(6, 10));
+
+        let mut scanner = Scanner::new("local s = \"abc".to_string());
+        let err = scanner.scan_tokens().unwrap_err();
+        assert_eq!(err.span(), Some(Span::new(10, 14)));
+    }
 }
@@ -1,16 +1,30 @@
+use std::sync::Arc;
+
 use enum_as_inner::EnumAsInner;
+use serde::{Deserialize, Serialize};
 
 use crate::expression::Expr;
 use crate::scanner::Token;
 
 // Stmt 语句 trait
-#[derive(Debug, Clone, EnumAsInner)]
+#[derive(Debug, Clone, EnumAsInner, Serialize, Deserialize)]
 pub enum Stmt {
     PrintStmt(Expr),
     IfStmt(Expr, Box<Stmt>, Box<Stmt>),
+    // 每次迭代的循环体都有自己的作用域
+    WhileStmt(Expr, Vec<Stmt>),
+    // 先执行循环体再检查条件，条件中可以使用循环体中的 local
+    RepeatStmt(Vec<Stmt>, Expr),
     LocalStmt(Token, Expr),
-    FunctionStmt(Token, Vec<Token>, Vec<Stmt>),
+    // local a, b = x, y，值不够时以 nil 补齐，多余的值求值后丢弃
+    LocalListStmt(Vec<Token>, Vec<Expr>),
+    // 函数体与函数值共享，复制时只增加引用计数
+    FunctionStmt(Token, Vec<Token>, Arc<[Stmt]>),
+    // a = 1，更新可见的变量，没有时定义全局变量
+    AssignStmt(Token, Expr),
     ReturnStmt(Token, Expr),
+    // 跳出最内层的循环
+    BreakStmt(Token),
     Expression(Expr),
     Block(Vec<Stmt>),
     None,
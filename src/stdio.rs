@@ -0,0 +1,113 @@
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+// 脚本的标准输入输出，默认为进程的 stdin/stdout/stderr，嵌入时可以替换，
+// 复制后共享同一组流
+#[derive(Clone)]
+pub struct Stdio {
+    stdin: Arc<Mutex<dyn BufRead + Send>>,
+    stdout: Arc<Mutex<dyn Write + Send>>,
+    stderr: Arc<Mutex<dyn Write + Send>>,
+}
+
+impl Default for Stdio {
+    fn default() -> Self {
+        Self {
+            stdin: Arc::new(Mutex::new(BufReader::new(io::stdin()))),
+            stdout: Arc::new(Mutex::new(io::stdout())),
+            stderr: Arc::new(Mutex::new(io::stderr())),
+        }
+    }
+}
+
+impl fmt::Debug for Stdio {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Stdio")
+    }
+}
+
+// 其它线程写入时 panic 不影响后续的输出
+fn lock<T: ?Sized>(m: &Mutex<T>) -> MutexGuard<'_, T> {
+    m.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+impl Stdio {
+    pub fn set_stdin<R: Read + Send + 'static>(&mut self, stdin: R) {
+        self.stdin = Arc::new(Mutex::new(BufReader::new(stdin)));
+    }
+
+    pub fn set_stdout<W: Write + Send + 'static>(&mut self, stdout: W) {
+        self.stdout = Arc::new(Mutex::new(stdout));
+    }
+
+    pub fn set_stderr<W: Write + Send + 'static>(&mut self, stderr: W) {
+        self.stderr = Arc::new(Mutex::new(stderr));
+    }
+
+    // 输出到 stdout 并 flush
+    pub fn write(&self, args: fmt::Arguments) -> io::Result<()> {
+        let mut out = lock(&self.stdout);
+        out.write_fmt(args)?;
+        out.flush()
+    }
+
+    pub fn write_err(&self, args: fmt::Arguments) -> io::Result<()> {
+        let mut out = lock(&self.stderr);
+        out.write_fmt(args)?;
+        out.flush()
+    }
+
+    pub fn write_bytes(&self, buf: &[u8]) -> io::Result<()> {
+        let mut out = lock(&self.stdout);
+        out.write_all(buf)?;
+        out.flush()
+    }
+
+    // 读入一个字节，读到结尾时为 None
+    pub fn read_byte(&self) -> io::Result<Option<u8>> {
+        let mut buf = [0_u8];
+        match lock(&self.stdin).read(&mut buf)? {
+            0 => Ok(None),
+            _ => Ok(Some(buf[0])),
+        }
+    }
+
+    // 读入一行，不含换行符，读到结尾时为 None
+    pub fn read_line(&self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        if lock(&self.stdin).read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let len = line.trim_end_matches(['\r', '\n']).len();
+        line.truncate(len);
+        Ok(Some(line))
+    }
+}
+
+// 测试中捕获输出的 Write，复制后共享同一块缓冲区
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub(crate) struct Capture(Arc<Mutex<Vec<u8>>>);
+
+#[cfg(test)]
+impl Capture {
+    pub(crate) fn bytes(&self) -> Vec<u8> {
+        lock(&self.0).clone()
+    }
+
+    pub(crate) fn text(&self) -> String {
+        String::from_utf8(self.bytes()).unwrap()
+    }
+}
+
+#[cfg(test)]
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        lock(&self.0).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
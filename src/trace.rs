@@ -0,0 +1,64 @@
+use std::fmt;
+use std::io::Write;
+
+// 执行轨迹输出，写入失败时忽略，不影响脚本执行
+// 输出需要是 Send，解释器与虚拟机才能在线程间转移
+pub struct Tracer {
+    out: Box<dyn Write + Send>,
+}
+
+impl Tracer {
+    pub fn new<W: Write + Send + 'static>(out: W) -> Self {
+        Self { out: Box::new(out) }
+    }
+
+    pub fn log(&mut self, args: fmt::Arguments) {
+        let _ = self.out.write_fmt(args);
+        let _ = self.out.write_all(b"\n");
+    }
+}
+
+// 出错时的函数调用栈，从最内层开始，最后一帧为主程序
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Traceback {
+    pub frames: Vec<TraceFrame>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceFrame {
+    // 函数名，主程序为 None
+    pub function: Option<String>,
+    // 该帧正在执行的行，最内层为出错的行，其它为调用下一层的行
+    pub line: Option<usize>,
+}
+
+impl Traceback {
+    // 与 Lua 相同的格式
+    pub fn render(&self, file: &str) -> String {
+        let mut out = "stack traceback:\n".to_string();
+        for frame in &self.frames {
+            let location = match frame.line {
+                Some(line) => format!("{}:{}", file, line),
+                None => file.to_string(),
+            };
+            let function = match &frame.function {
+                Some(name) => format!("function '{}'", name),
+                None => "main chunk".to_string(),
+            };
+            out.push_str(&format!("\t{}: in {}\n", location, function));
+        }
+        out
+    }
+}
+
+impl fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Tracer")
+    }
+}
+
+impl Drop for Tracer {
+    fn drop(&mut self) {
+        let _ = self.out.flush();
+    }
+}
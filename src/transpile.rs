@@ -0,0 +1,464 @@
+use crate::ast::source::is_name;
+use crate::error::Error;
+use crate::expression::Expr;
+use crate::statement::Stmt;
+use crate::value::Value;
+
+const INDENT: &str = "  ";
+
+// Lua 中是关键字、在 plua 中可以作为标识符的名字
+const LUA_KEYWORDS: [&str; 3] = ["goto", "in", "not"];
+
+// 将语法树转换为 Lua 5.4 源码，用官方的 lua 解释器对照执行结果
+//
+// 顶层的 local 在 plua 中定义全局变量，转换为全局赋值；plua 的整数除法向零取整，
+// 而 Lua 的 `//` 向下取整，除法转换为 DIV_HELPER 中的函数，有浮点数字面量时直接用 `/`；
+// 取余与 Lua 相同，向下取整；print 输出 nil 时 plua 为 Nil
+pub fn transpile(statements: &[Stmt]) -> Result<String, Error> {
+    let mut transpiler = Transpiler::default();
+    transpiler.stmts(statements)?;
+    if transpiler.out.contains(DIV_NAME) {
+        return Ok(format!("{}{}", DIV_HELPER, transpiler.out));
+    }
+    Ok(transpiler.out)
+}
+
+const DIV_NAME: &str = "plua_div";
+
+// 两个整数相除时向零取整，否则为浮点数除法
+const DIV_HELPER: &str = r#"local function plua_div(a, b)
+  if math.type(a) == "integer" and math.type(b) == "integer" then
+    local q = a // b
+    if q < 0 and q * b ~= a then
+      q = q + 1
+    end
+    return q
+  end
+  return a / b
+end
+"#;
+
+#[derive(Default)]
+struct Transpiler {
+    out: String,
+    depth: usize,
+}
+
+impl Transpiler {
+    // Lua 中 return 只能是块的最后一条语句，其它位置放入 do ... end
+    fn stmts(&mut self, stmts: &[Stmt]) -> Result<(), Error> {
+        for (i, stmt) in stmts.iter().enumerate() {
+            match stmt {
+                Stmt::ReturnStmt(..) if i + 1 < stmts.len() => {
+                    self.line("do");
+                    self.nested(stmt)?;
+                    self.line("end");
+                }
+                _ => self.stmt(stmt)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn stmt(&mut self, stmt: &Stmt) -> Result<(), Error> {
+        match stmt {
+            Stmt::PrintStmt(expr) => {
+                let line = format!("print({})", expr_to_lua(expr)?);
+                self.line(&line);
+            }
+            Stmt::IfStmt(condition, then_branch, else_branch) => {
+                let line = format!("if {} then", expr_to_lua(condition)?);
+                self.line(&line);
+                self.branch(then_branch)?;
+                if !matches!(else_branch.as_ref(), Stmt::None) {
+                    self.line("else");
+                    self.branch(else_branch)?;
+                }
+                self.line("end");
+            }
+            Stmt::WhileStmt(condition, body) => {
+                let line = format!("while {} do", expr_to_lua(condition)?);
+                self.line(&line);
+                self.depth += 1;
+                self.stmts(body)?;
+                self.depth -= 1;
+                self.line("end");
+            }
+            Stmt::RepeatStmt(body, condition) => {
+                self.line("repeat");
+                self.depth += 1;
+                self.stmts(body)?;
+                self.depth -= 1;
+                let line = format!("until {}", expr_to_lua(condition)?);
+                self.line(&line);
+            }
+            Stmt::LocalStmt(name, init) => {
+                let name = name_to_lua(&name.raw)?;
+                let init = match init {
+                    Expr::None => "nil".to_string(),
+                    _ => expr_to_lua(init)?,
+                };
+                let line = match self.depth {
+                    0 => format!("{} = {}", name, init),
+                    _ => format!("local {} = {}", name, init),
+                };
+                self.line(&line);
+            }
+            Stmt::LocalListStmt(names, values) => {
+                let names = names
+                    .iter()
+                    .map(|n| name_to_lua(&n.raw))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let mut values = values
+                    .iter()
+                    .map(expr_to_lua)
+                    .collect::<Result<Vec<_>, _>>()?;
+                if values.is_empty() {
+                    values.push("nil".to_string());
+                }
+                let line = match self.depth {
+                    0 => format!("{} = {}", names.join(", "), values.join(", ")),
+                    _ => format!("local {} = {}", names.join(", "), values.join(", ")),
+                };
+                self.line(&line);
+            }
+            Stmt::FunctionStmt(name, params, body) => {
+                let params = params
+                    .iter()
+                    .map(|p| name_to_lua(&p.raw))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let name = name_to_lua(&name.raw)?;
+                let line = match self.depth {
+                    0 => format!("function {}({})", name, params.join(", ")),
+                    _ => format!("local function {}({})", name, params.join(", ")),
+                };
+                self.line(&line);
+                self.depth += 1;
+                self.stmts(body)?;
+                self.depth -= 1;
+                self.line("end");
+            }
+            Stmt::ReturnStmt(_, value) => {
+                let line = match value {
+                    Expr::None => "return".to_string(),
+                    _ => format!("return {}", expr_to_lua(value)?),
+                };
+                self.line(&line);
+            }
+            Stmt::BreakStmt(_) => self.line("break"),
+            Stmt::AssignStmt(name, value) => {
+                let line = format!("{} = {}", name_to_lua(&name.raw)?, expr_to_lua(value)?);
+                self.line(&line);
+            }
+            // Lua 中赋值与调用之外的表达式不能作为语句
+            Stmt::Expression(expr @ Expr::Call(..)) => {
+                let line = expr_to_lua(expr)?;
+                self.line(&line);
+            }
+            Stmt::Expression(expr) => {
+                let line = format!("local _ = {}", expr_to_lua(expr)?);
+                self.line(&line);
+            }
+            Stmt::Block(stmts) => {
+                self.line("do");
+                self.depth += 1;
+                self.stmts(stmts)?;
+                self.depth -= 1;
+                self.line("end");
+            }
+            Stmt::None => {}
+        }
+        Ok(())
+    }
+
+    // if 的分支本身就是一个块，Block 直接展开
+    fn branch(&mut self, stmt: &Stmt) -> Result<(), Error> {
+        match stmt {
+            Stmt::Block(stmts) => {
+                self.depth += 1;
+                self.stmts(stmts)?;
+                self.depth -= 1;
+                Ok(())
+            }
+            _ => self.nested(stmt),
+        }
+    }
+
+    fn nested(&mut self, stmt: &Stmt) -> Result<(), Error> {
+        self.depth += 1;
+        let result = self.stmt(stmt);
+        self.depth -= 1;
+        result
+    }
+
+    fn line(&mut self, line: &str) {
+        for _ in 0..self.depth {
+            self.out.push_str(INDENT);
+        }
+        self.out.push_str(line);
+        self.out.push('\n');
+    }
+}
+
+// 在 Lua 中也可以作为名字的字符串
+fn is_lua_name(name: &str) -> bool {
+    is_name(name) && !LUA_KEYWORDS.contains(&name)
+}
+
+fn name_to_lua(name: &str) -> Result<String, Error> {
+    if LUA_KEYWORDS.contains(&name) {
+        return Err(Error::TranspileError(format!(
+            "{} is a keyword in Lua",
+            name
+        )));
+    }
+    Ok(name.to_string())
+}
+
+// 运算符在 Lua 中的写法与优先级，== 与 < 等在 Lua 中是同一级
+fn operator_to_lua(operator: &str) -> Result<(&'static str, u8), Error> {
+    match operator {
+        "or" => Ok(("or", 1)),
+        "and" => Ok(("and", 2)),
+        "==" => Ok(("==", 3)),
+        "!=" => Ok(("~=", 3)),
+        "<" => Ok(("<", 3)),
+        "<=" => Ok(("<=", 3)),
+        ">" => Ok((">", 3)),
+        ">=" => Ok((">=", 3)),
+        ".." => Ok(("..", 4)),
+        "+" => Ok(("+", 5)),
+        "-" => Ok(("-", 5)),
+        "*" => Ok(("*", 6)),
+        "/" => Ok(("/", 6)),
+        "%" => Ok(("%", 6)),
+        // 一元运算符为 7
+        "^" => Ok(("^", 8)),
+        _ => Err(Error::TranspileError(format!(
+            "operator {} is not supported",
+            operator
+        ))),
+    }
+}
+
+fn expr_to_lua(expr: &Expr) -> Result<String, Error> {
+    match expr {
+        Expr::Call(callee, _, args) => {
+            let args = args
+                .iter()
+                .map(expr_to_lua)
+                .collect::<Result<Vec<_>, _>>()?;
+            let callee = match callee.as_ref() {
+                Expr::Variable(_) | Expr::Call(..) | Expr::Index(..) => expr_to_lua(callee)?,
+                _ => format!("({})", expr_to_lua(callee)?),
+            };
+            Ok(format!("{}({})", callee, args.join(", ")))
+        }
+        Expr::Unary(operator, right) => {
+            let operator = match operator.raw.as_ref() {
+                "!" => "not ",
+                _ => "-",
+            };
+            // 避免 - -a 写成注释 --a
+            let right = match right.as_ref() {
+                Expr::Binary(..) | Expr::Logical(..) | Expr::Unary(..) => {
+                    format!("({})", expr_to_lua(right)?)
+                }
+                _ => expr_to_lua(right)?,
+            };
+            Ok(format!("{}{}", operator, right))
+        }
+        Expr::Variable(name) => name_to_lua(&name.raw),
+        Expr::Assign(name, _) => Err(Error::TranspileError(format!(
+            "assignment to {} used as a value",
+            name.raw
+        ))),
+        Expr::Binary(left, operator, right)
+            if operator.raw.as_ref() == "/" && !is_float(left) && !is_float(right) =>
+        {
+            Ok(format!(
+                "{}({}, {})",
+                DIV_NAME,
+                expr_to_lua(left)?,
+                expr_to_lua(right)?
+            ))
+        }
+        Expr::Binary(left, operator, right) | Expr::Logical(left, operator, right) => {
+            let (operator, precedence) = operator_to_lua(&operator.raw)?;
+            // 保持语法树的结合方式，左侧优先级更低、右侧不高于当前运算符时加括号；
+            // ^ 右结合，与之相反，并且左侧的一元运算也要加括号
+            let right_assoc = operator == "^";
+            let left = match left.as_ref() {
+                Expr::Binary(_, op, _) | Expr::Logical(_, op, _) => {
+                    let p = operator_to_lua(&op.raw)?.1;
+                    parens(
+                        expr_to_lua(left)?,
+                        p < precedence || right_assoc && p == precedence,
+                    )
+                }
+                Expr::Unary(..) => parens(expr_to_lua(left)?, right_assoc),
+                _ => expr_to_lua(left)?,
+            };
+            let right = match right.as_ref() {
+                Expr::Binary(_, op, _) | Expr::Logical(_, op, _) => {
+                    let p = operator_to_lua(&op.raw)?.1;
+                    parens(
+                        expr_to_lua(right)?,
+                        p < precedence || !right_assoc && p == precedence,
+                    )
+                }
+                _ => expr_to_lua(right)?,
+            };
+            Ok(format!("{} {} {}", left, operator, right))
+        }
+        Expr::Literal(value) => Ok(literal_to_lua(value)),
+        Expr::Table(_, fields) => {
+            let fields = fields
+                .iter()
+                .map(|(key, value)| {
+                    Ok(match key {
+                        Expr::None => expr_to_lua(value)?,
+                        Expr::Literal(Value::String(name)) if is_lua_name(name) => {
+                            format!("{} = {}", name, expr_to_lua(value)?)
+                        }
+                        _ => format!("[{}] = {}", expr_to_lua(key)?, expr_to_lua(value)?),
+                    })
+                })
+                .collect::<Result<Vec<_>, Error>>()?;
+            Ok(format!("{{{}}}", fields.join(", ")))
+        }
+        Expr::Index(table, _, key) => {
+            let table = match table.as_ref() {
+                Expr::Variable(_) | Expr::Call(..) | Expr::Index(..) => expr_to_lua(table)?,
+                _ => format!("({})", expr_to_lua(table)?),
+            };
+            match key.as_ref() {
+                Expr::Literal(Value::String(name)) if is_lua_name(name) => {
+                    Ok(format!("{}.{}", table, name))
+                }
+                _ => Ok(format!("{}[{}]", table, expr_to_lua(key)?)),
+            }
+        }
+        Expr::None => Ok("nil".to_string()),
+    }
+}
+
+// 浮点数字面量参与的除法在 plua 与 Lua 中都是浮点数除法
+fn is_float(expr: &Expr) -> bool {
+    matches!(expr, Expr::Literal(Value::Float(_)))
+}
+
+fn parens(expr: String, wrap: bool) -> String {
+    if wrap {
+        format!("({})", expr)
+    } else {
+        expr
+    }
+}
+
+fn literal_to_lua(value: &Value) -> String {
+    match value {
+        Value::Nil => "nil".to_string(),
+        Value::String(s) => {
+            let mut literal = String::from("\"");
+            for c in s.chars() {
+                match c {
+                    '"' => literal.push_str("\\\""),
+                    '\\' => literal.push_str("\\\\"),
+                    '\n' => literal.push_str("\\n"),
+                    _ => literal.push(c),
+                }
+            }
+            literal + "\""
+        }
+        Value::Float(f) if f.fract() == 0.0 => format!("{:.1}", f),
+        _ => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::transpile;
+    use crate::error::Error;
+    use crate::parser::Parser;
+    use crate::scanner::Scanner;
+
+    fn to_lua(source: &str) -> Result<String, Error> {
+        let statements = Parser::from_stream(Scanner::new(source.to_string())).parse()?;
+        transpile(&statements)
+    }
+
+    #[test]
+    fn test_transpile() {
+        let source = r#"
+        function fib(n)
+          if n < 2 then
+            return n;
+          end
+          local half = n / 2;
+          return fib(n - 1) + fib(n - 2);
+        end
+        local a;
+        a = - -fib(4) * 2 - 1;
+        print(a != 3 == !a);
+        print(a and a < 2 or !a);
+        print(-2 ^ 2 ^ -a % 3 * 2);
+        t.f(1)(2)[3]();
+        return a;
+        print(a);
+        "#;
+        let expected = r#"local function plua_div(a, b)
+  if math.type(a) == "integer" and math.type(b) == "integer" then
+    local q = a // b
+    if q < 0 and q * b ~= a then
+      q = q + 1
+    end
+    return q
+  end
+  return a / b
+end
+function fib(n)
+  if n < 2 then
+    return n
+  end
+  local half = plua_div(n, 2)
+  return fib(n - 1) + fib(n - 2)
+end
+a = nil
+a = -(-fib(4)) * 2 - 1
+print(a ~= 3 == not a)
+print(a and a < 2 or not a)
+print(-(2 ^ 2 ^ -a) % 3 * 2)
+t.f(1)(2)[3]()
+do
+  return a
+end
+print(a)
+"#;
+        assert_eq!(to_lua(source).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_transpile_div() {
+        // plua 中 -7 / 2 为 -3，7.0 / 2 为 3.5
+        let lua = to_lua("print(-7 / 2);\nprint(7.0 / 2);\nprint(1 + 6 / -4 * 2);").unwrap();
+        let body = lua.strip_prefix(super::DIV_HELPER).unwrap();
+        assert_eq!(
+            body,
+            "print(plua_div(-7, 2))\nprint(7.0 / 2)\nprint(1 + plua_div(6, -4) * 2)\n"
+        );
+        assert_eq!(to_lua("print(1.5 / 2);").unwrap(), "print(1.5 / 2)\n");
+    }
+
+    #[test]
+    fn test_transpile_errors() {
+        assert!(matches!(
+            to_lua("local a = b = 1;"),
+            Err(Error::TranspileError(_))
+        ));
+        assert!(matches!(
+            to_lua("local goto = 1;"),
+            Err(Error::TranspileError(_))
+        ));
+    }
+}
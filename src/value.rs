@@ -1,15 +1,19 @@
 use enum_as_inner::EnumAsInner;
+use serde::{Deserialize, Serialize};
 
+use std::collections::BTreeMap;
 use std::fmt::{Display, Formatter};
-use std::ops::{Add, AddAssign, Div, Mul, Sub, SubAssign};
+use std::ops::{Add, AddAssign, Div, Mul, RangeInclusive, Sub, SubAssign};
+use std::sync::Arc;
 
+use crate::native::NativeFunction;
 use crate::statement::Stmt;
 
 //
 // Operations on Value
 // @see https://doc.rust-lang.org/book/appendix-02-operators.html
 //
-#[derive(Debug, Clone, EnumAsInner)]
+#[derive(Debug, Clone, EnumAsInner, Serialize, Deserialize)]
 pub enum Value {
     /// Common Basic types
     Int(i32),
@@ -18,14 +22,56 @@ pub enum Value {
     String(String),
     Nil,
 
-    /// Function AST tree-walking interpreter
-    Function(String, Vec<String>, Vec<Stmt>),
+    /// Table, array part stores keys 1..=n
+    Table(Table),
+
+    /// Function AST tree-walking interpreter, the body is shared with `Stmt::FunctionStmt`
+    Function(String, Vec<String>, Arc<[Stmt]>),
 
     /// Closure bytecode interpreter
     Closure(usize, Vec<usize>),
+
+    /// Function registered by the host, can not be serialized
+    #[serde(skip)]
+    Native(NativeFunction),
+}
+
+// 常量表中共享的小整数范围
+pub const SMALL_INTS: RangeInclusive<i32> = -128..=1024;
+
+// 常用值：小整数、nil、布尔值与空字符串，常量表中只保存一份
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CommonValue {
+    Int(i32),
+    Bool(bool),
+    Nil,
+    EmptyString,
 }
 
 impl Value {
+    // 类型名，用于出错信息
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Int(_) => "integer",
+            Value::Float(_) => "number",
+            Value::Bool(_) => "boolean",
+            Value::String(_) => "string",
+            Value::Nil => "nil",
+            Value::Table(_) => "table",
+            Value::Function(..) | Value::Closure(..) | Value::Native(_) => "function",
+        }
+    }
+
+    pub fn as_common(&self) -> Option<CommonValue> {
+        match self {
+            Value::Int(i) if SMALL_INTS.contains(i) => Some(CommonValue::Int(*i)),
+            Value::Bool(b) => Some(CommonValue::Bool(*b)),
+            Value::Nil => Some(CommonValue::Nil),
+            Value::String(s) if s.is_empty() => Some(CommonValue::EmptyString),
+            _ => None,
+        }
+    }
+
     pub(crate) fn is_truthy(&self) -> bool {
         match self {
             Value::Nil => false,
@@ -33,6 +79,121 @@ impl Value {
             _ => true,
         }
     }
+
+    // 整数除以整数 0 时返回 None，整数溢出时回绕；浮点数除以 0 得到 inf
+    pub fn checked_div(self, rhs: Self) -> Option<Value> {
+        let value = match (self, rhs) {
+            (Value::Int(_), Value::Int(0)) => return None,
+            (Value::Int(i), Value::Int(j)) => Value::Int(i.wrapping_div(j)),
+            (Value::Int(i), Value::Float(j)) => Value::Float(i as f32 / j),
+            (Value::Float(i), Value::Int(j)) => Value::Float(i / j as f32),
+            (Value::Float(i), Value::Float(j)) => Value::Float(i / j),
+            (Value::Float(i), Value::Nil) => Value::Float(i),
+            (Value::Int(i), Value::Nil) => Value::Int(i),
+            _ => Value::Nil,
+        };
+        Some(value)
+    }
+
+    // 与 Lua 相同，向下取整取余，余数的符号与除数相同；整数除以 0 时返回 None
+    pub fn checked_rem(self, rhs: Self) -> Option<Value> {
+        let float_rem = |i: f32, j: f32| {
+            let r = i % j;
+            if r != 0.0 && (r < 0.0) != (j < 0.0) {
+                Value::Float(r + j)
+            } else {
+                Value::Float(r)
+            }
+        };
+        let value = match (self, rhs) {
+            (Value::Int(_), Value::Int(0)) => return None,
+            (Value::Int(i), Value::Int(j)) => {
+                let r = i.wrapping_rem(j);
+                if r != 0 && (r < 0) != (j < 0) {
+                    Value::Int(r + j)
+                } else {
+                    Value::Int(r)
+                }
+            }
+            (Value::Int(i), Value::Float(j)) => float_rem(i as f32, j),
+            (Value::Float(i), Value::Int(j)) => float_rem(i, j as f32),
+            (Value::Float(i), Value::Float(j)) => float_rem(i, j),
+            _ => Value::Nil,
+        };
+        Some(value)
+    }
+
+    // 与 Lua 相同，乘方的结果总是浮点数
+    pub fn pow(self, rhs: Self) -> Value {
+        let float = |v: Value| match v {
+            Value::Int(i) => Some(i as f32),
+            Value::Float(f) => Some(f),
+            _ => None,
+        };
+        match (float(self), float(rhs)) {
+            (Some(i), Some(j)) => Value::Float(i.powf(j)),
+            _ => Value::Nil,
+        }
+    }
+
+    // 字符串与数字可以连接，其它类型返回 None
+    pub fn concat(&self, rhs: &Value) -> Option<Value> {
+        let is_text = |v: &Value| matches!(v, Value::String(_) | Value::Int(_) | Value::Float(_));
+        (is_text(self) && is_text(rhs)).then(|| Value::String(format!("{}{}", self, rhs)))
+    }
+}
+
+// 表，目前是值语义，赋值时整体拷贝
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Table {
+    pub array: Vec<Value>,
+    pub hash: BTreeMap<String, Value>,
+}
+
+impl Table {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn from_array(array: Vec<Value>) -> Self {
+        Self {
+            array,
+            hash: BTreeMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.array.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.array.is_empty() && self.hash.is_empty()
+    }
+
+    // 只能修改已有的数组元素或字符串键，成功时返回 true
+    pub fn set(&mut self, key: &Value, value: Value) -> bool {
+        match key {
+            Value::Int(i) if *i >= 1 && (*i as usize) <= self.array.len() => {
+                self.array[*i as usize - 1] = value;
+                true
+            }
+            Value::String(s) => {
+                self.hash.insert(s.clone(), value);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn get(&self, key: &Value) -> Value {
+        match key {
+            Value::Int(i) if *i >= 1 && (*i as usize) <= self.array.len() => {
+                self.array[*i as usize - 1].clone()
+            }
+            Value::String(s) => self.hash.get(s).cloned().unwrap_or(Value::Nil),
+            _ => Value::Nil,
+        }
+    }
 }
 
 impl PartialEq for Value {
@@ -43,6 +204,7 @@ impl PartialEq for Value {
             (Self::Bool(l0), Self::Bool(r0)) => l0 == r0,
             (Self::String(l0), Self::String(r0)) => l0 == r0,
             (Self::Nil, Self::Nil) => true,
+            (Self::Table(l0), Self::Table(r0)) => l0 == r0,
             _ => false,
         }
     }
@@ -65,8 +227,8 @@ impl Add for Value {
 
     fn add(self, rhs: Self) -> Self::Output {
         match (self, rhs) {
-            (Value::Int(i), Value::Int(j)) => Value::Int(i + j),
-            (Value::Int(i), Value::Float(j)) => Value::Int(i + j as i32),
+            (Value::Int(i), Value::Int(j)) => Value::Int(i.wrapping_add(j)),
+            (Value::Int(i), Value::Float(j)) => Value::Float(i as f32 + j),
             (Value::Float(i), Value::Int(j)) => Value::Float(i + j as f32),
             (Value::Float(i), Value::Float(j)) => Value::Float(i + j),
             (Value::Float(i), Value::Nil) => Value::Float(i),
@@ -80,9 +242,9 @@ impl Add for Value {
 
 impl AddAssign for Value {
     fn add_assign(&mut self, rhs: Self) {
-        match (self, rhs) {
-            (Value::Int(i), Value::Int(j)) => *i += j,
-            (Value::Int(i), Value::Float(j)) => *i += j as i32,
+        match (&mut *self, rhs) {
+            (Value::Int(i), Value::Int(j)) => *i = i.wrapping_add(j),
+            (Value::Int(i), Value::Float(j)) => *self = Value::Float(*i as f32 + j),
             (Value::Float(i), Value::Int(j)) => *i += j as f32,
             (Value::Float(i), Value::Float(j)) => *i += j,
             _ => {}
@@ -95,8 +257,8 @@ impl Sub for Value {
 
     fn sub(self, rhs: Self) -> Self::Output {
         match (self, rhs) {
-            (Value::Int(i), Value::Int(j)) => Value::Int(i - j),
-            (Value::Int(i), Value::Float(j)) => Value::Int(i - j as i32),
+            (Value::Int(i), Value::Int(j)) => Value::Int(i.wrapping_sub(j)),
+            (Value::Int(i), Value::Float(j)) => Value::Float(i as f32 - j),
             (Value::Float(i), Value::Int(j)) => Value::Float(i - j as f32),
             (Value::Float(i), Value::Float(j)) => Value::Float(i - j),
             (Value::Float(i), Value::Nil) => Value::Float(i),
@@ -110,9 +272,9 @@ impl Sub for Value {
 
 impl SubAssign for Value {
     fn sub_assign(&mut self, rhs: Self) {
-        match (self, rhs) {
-            (Value::Int(i), Value::Int(j)) => *i -= j,
-            (Value::Int(i), Value::Float(j)) => *i -= j as i32,
+        match (&mut *self, rhs) {
+            (Value::Int(i), Value::Int(j)) => *i = i.wrapping_sub(j),
+            (Value::Int(i), Value::Float(j)) => *self = Value::Float(*i as f32 - j),
             (Value::Float(i), Value::Int(j)) => *i -= j as f32,
             (Value::Float(i), Value::Float(j)) => *i -= j,
             _ => {}
@@ -125,8 +287,8 @@ impl Mul for Value {
 
     fn mul(self, rhs: Self) -> Self::Output {
         match (self, rhs) {
-            (Value::Int(i), Value::Int(j)) => Value::Int(i * j),
-            (Value::Int(i), Value::Float(j)) => Value::Int(i * j as i32),
+            (Value::Int(i), Value::Int(j)) => Value::Int(i.wrapping_mul(j)),
+            (Value::Int(i), Value::Float(j)) => Value::Float(i as f32 * j),
             (Value::Float(i), Value::Int(j)) => Value::Float(i * j as f32),
             (Value::Float(i), Value::Float(j)) => Value::Float(i * j),
             (Value::Float(i), Value::Nil) => Value::Float(i),
@@ -139,16 +301,9 @@ impl Mul for Value {
 impl Div for Value {
     type Output = Value;
 
+    // 整数除以 0 得到 nil，需要报错时使用 checked_div
     fn div(self, rhs: Self) -> Self::Output {
-        match (self, rhs) {
-            (Value::Int(i), Value::Int(j)) => Value::Int(i / j),
-            (Value::Int(i), Value::Float(j)) => Value::Int(i / j as i32),
-            (Value::Float(i), Value::Int(j)) => Value::Float(i / j as f32),
-            (Value::Float(i), Value::Float(j)) => Value::Float(i / j),
-            (Value::Float(i), Value::Nil) => Value::Float(i),
-            (Value::Int(i), Value::Nil) => Value::Int(i),
-            _ => Value::Nil,
-        }
+        self.checked_div(rhs).unwrap_or(Value::Nil)
     }
 }
 
@@ -176,13 +331,27 @@ impl Display for Value {
             Value::Closure(s, params) => {
                 write!(f, "Closure@{}({:?})", s, params)
             }
+            Value::Native(native) => {
+                write!(f, "NativeFunction@{}", native.name)
+            }
+            Value::Table(t) => {
+                write!(f, "{}", t)
+            }
         }
     }
 }
 
+impl Display for Table {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut fields: Vec<String> = self.array.iter().map(|v| v.to_string()).collect();
+        fields.extend(self.hash.iter().map(|(k, v)| format!("{} = {}", k, v)));
+        write!(f, "{{{}}}", fields.join(", "))
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::Value;
+    use super::{Table, Value};
 
     #[test]
     fn test_value_operation() {
@@ -193,7 +362,7 @@ mod tests {
         assert_eq!(r, Value::Float(5.0));
 
         let r = Value::Int(1) + Value::Float(2.0);
-        assert_eq!(r, Value::Int(3));
+        assert_eq!(r, Value::Float(3.0));
 
         let r = Value::Float(1.0) + Value::Float(2.0);
         assert_eq!(r, Value::Float(3.0));
@@ -211,7 +380,7 @@ mod tests {
         assert_eq!(r, Value::Float(3.0));
 
         let r = Value::Int(3) * Value::Float(1.0);
-        assert_eq!(r, Value::Int(3));
+        assert_eq!(r, Value::Float(3.0));
 
         let r = Value::Int(3) * Value::Int(1);
         assert_eq!(r, Value::Int(3));
@@ -223,10 +392,27 @@ mod tests {
         assert_eq!(r, Value::Float(3.0));
 
         let r = Value::Int(3) / Value::Float(1.0);
-        assert_eq!(r, Value::Int(3));
+        assert_eq!(r, Value::Float(3.0));
 
         let r = Value::Int(3) / Value::Int(1);
         assert_eq!(r, Value::Int(3));
+
+        assert_eq!(Value::Int(3).checked_div(Value::Int(0)), None);
+        assert_eq!(
+            Value::Int(3).checked_div(Value::Float(0.5)),
+            Some(Value::Float(6.0))
+        );
+        assert_eq!(
+            Value::Int(1).checked_div(Value::Float(0.0)),
+            Some(Value::Float(f32::INFINITY))
+        );
+        assert_eq!(Value::Int(3) / Value::Int(0), Value::Nil);
+        assert_eq!(
+            Value::Int(i32::MIN).checked_div(Value::Int(-1)),
+            Some(Value::Int(i32::MIN))
+        );
+        assert_eq!(Value::Int(i32::MAX) + Value::Int(1), Value::Int(i32::MIN));
+        assert_eq!(Value::Int(i32::MIN) - Value::Int(1), Value::Int(i32::MAX));
     }
 
     #[test]
@@ -258,4 +444,16 @@ mod tests {
         let r = Value::Bool(true) == Value::Int(1);
         assert!(r == false);
     }
+
+    #[test]
+    fn test_table() {
+        let mut t = Table::from_array(vec![Value::Int(1), Value::String("a".to_string())]);
+        t.hash.insert("k".to_string(), Value::Bool(true));
+        assert_eq!(t.len(), 2);
+        assert_eq!(t.get(&Value::Int(1)), Value::Int(1));
+        assert_eq!(t.get(&Value::Int(3)), Value::Nil);
+        assert_eq!(t.get(&Value::String("k".to_string())), Value::Bool(true));
+        assert_eq!(Value::Table(t).to_string(), "{1, a, k = true}");
+        assert!(Table::new().is_empty());
+    }
 }
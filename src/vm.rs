@@ -1,15 +1,103 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 use crate::bytecode::ByteCode;
 use crate::emitter::{Chunk, Function};
-use crate::value::Value;
+use crate::error::Error;
+use crate::heap::objects;
+use crate::stdio::Stdio;
+use crate::trace::Tracer;
+use crate::value::{Table, Value};
 
 #[derive(Debug, Default)]
 pub struct VM {
-    globals: HashMap<String, Value>,
-    frames: Vec<Frame>,
-    current_frame: Option<usize>,
+    globals: BTreeMap<String, Value>,
     funcs: Vec<Function>,
+    stats: Stats,
+    limits: Limits,
+    tracer: Option<Tracer>,
+    stdio: Stdio,
+}
+
+// 运行统计
+#[derive(Debug, Default, Clone)]
+pub struct Stats {
+    // 执行的字节码条数
+    pub instructions: usize,
+    // 函数调用次数
+    pub calls: usize,
+    // 值栈的最大深度
+    pub max_stack: usize,
+}
+
+// 内存使用情况，嵌入方可以据此在触及 Limits 之前提前处理
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MemoryStats {
+    // 所有函数常量表占用的字节数(估算)
+    pub constants_bytes: usize,
+    // 全局变量的值占用的字节数(估算)
+    pub globals_bytes: usize,
+    // 值栈的最大深度
+    pub max_stack: usize,
+    // 全局变量个数
+    pub globals: usize,
+    // 常量表中不重复的字符串，vm 中的名字与字符串字面量都存放在常量表中
+    pub interned_strings: usize,
+    // 全局变量中的字符串、表与函数
+    pub heap_objects: usize,
+}
+
+impl MemoryStats {
+    // 常量与全局变量占用的字节数(估算)
+    pub fn total_bytes(&self) -> usize {
+        self.constants_bytes + self.globals_bytes
+    }
+}
+
+// 值占用的字节数(估算)，包括字符串与表中的内容
+fn value_bytes(value: &Value) -> usize {
+    let size = std::mem::size_of::<Value>();
+    match value {
+        Value::String(s) => size + s.len(),
+        Value::Table(table) => {
+            let array: usize = table.array.iter().map(value_bytes).sum();
+            let hash: usize = table
+                .hash
+                .iter()
+                .map(|(key, value)| key.len() + value_bytes(value))
+                .sum();
+            size + array + hash
+        }
+        _ => size,
+    }
+}
+
+// 运行限制，用于执行不可信的脚本
+#[derive(Debug, Default, Clone)]
+pub struct Limits {
+    // 最多执行的指令条数
+    pub max_steps: Option<usize>,
+    // 存活值占用的最大字节数(估算)
+    pub max_memory: Option<usize>,
+}
+
+impl Limits {
+    // 检查已执行的指令数与存活值个数是否超出限制
+    pub fn check(&self, steps: usize, values: usize) -> Result<(), Error> {
+        if let Some(max) = self.max_steps {
+            if steps > max {
+                return Err(Error::LimitError(format!("exceeded {} steps", max)));
+            }
+        }
+        if let Some(max) = self.max_memory {
+            if values * std::mem::size_of::<Value>() > max {
+                return Err(Error::LimitError(format!(
+                    "exceeded {} bytes of memory",
+                    max
+                )));
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
@@ -32,32 +120,55 @@ impl Frame {
 impl VM {
     pub fn new() -> Self {
         Self {
-            globals: HashMap::new(),
-            frames: Vec::new(),
-            current_frame: None,
+            globals: BTreeMap::new(),
             funcs: Vec::new(),
+            stats: Stats::default(),
+            limits: Limits::default(),
+            tracer: None,
+            stdio: Stdio::default(),
         }
     }
 
     pub fn new_with_funcs(funcs: Vec<Function>) -> Self {
         Self {
-            globals: HashMap::new(),
-            frames: Vec::new(),
-            current_frame: None,
+            globals: BTreeMap::new(),
             funcs,
+            stats: Stats::default(),
+            limits: Limits::default(),
+            tracer: None,
+            stdio: Stdio::default(),
         }
     }
 
-    pub fn eval_all(&mut self) -> Value {
+    // 定义全局变量
+    pub fn define_global(&mut self, name: &str, value: Value) {
+        self.globals.insert(name.to_string(), value);
+    }
+
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    // 替换 print 输出到的流
+    pub fn set_stdio(&mut self, stdio: Stdio) {
+        self.stdio = stdio;
+    }
+
+    // 记录每条执行的字节码与执行前的栈深度
+    pub fn set_tracer(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    pub fn eval_all(&mut self) -> Result<Value, Error> {
         let chunk = self.funcs.first().map(|func| func.chunk()).cloned();
         if let Some(chunk) = chunk.as_ref() {
             self.eval(chunk)
         } else {
-            Value::Nil
+            Ok(Value::Nil)
         }
     }
 
-    pub fn eval(&mut self, chunk: &Chunk) -> Value {
+    pub fn eval(&mut self, chunk: &Chunk) -> Result<Value, Error> {
         let mut stack: Vec<Value> = Vec::new();
         let mut ip = 0;
         let code = &chunk.codes;
@@ -65,52 +176,90 @@ impl VM {
         let mut ret = Value::Nil;
 
         while let Some(op) = code.get(ip) {
+            if let Some(tracer) = self.tracer.as_mut() {
+                tracer.log(format_args!("{:04} {:?} stack={}", ip, op, stack.len()));
+            }
             ip += 1;
+            self.stats.instructions += 1;
             match op {
                 ByteCode::Push(d) => stack.push(d.clone()),
                 ByteCode::Pop => {
                     stack.pop();
                 }
                 ByteCode::Add => {
-                    let (a, b) = (stack.pop(), stack.pop());
-                    stack.push(a.unwrap() + b.unwrap())
+                    let (a, b) = (pop(&mut stack)?, pop(&mut stack)?);
+                    stack.push(a + b)
                 }
                 ByteCode::Sub => {
-                    let (a, b) = (stack.pop(), stack.pop());
-                    stack.push(b.unwrap() - a.unwrap())
+                    let (a, b) = (pop(&mut stack)?, pop(&mut stack)?);
+                    stack.push(b - a)
                 }
                 ByteCode::Mul => {
-                    let (a, b) = (stack.pop(), stack.pop());
-                    stack.push(a.unwrap() * b.unwrap())
+                    let (a, b) = (pop(&mut stack)?, pop(&mut stack)?);
+                    stack.push(a * b)
                 }
                 ByteCode::Div => {
-                    let (a, b) = (stack.pop(), stack.pop());
-                    stack.push(b.unwrap() / a.unwrap())
+                    let (a, b) = (pop(&mut stack)?, pop(&mut stack)?);
+                    let value = b.checked_div(a).ok_or_else(|| {
+                        Error::RuntimeError("attempt to divide by zero".to_string())
+                    })?;
+                    stack.push(value)
+                }
+                ByteCode::Mod => {
+                    let (a, b) = (pop(&mut stack)?, pop(&mut stack)?);
+                    let value = b.checked_rem(a).ok_or_else(|| {
+                        Error::RuntimeError("attempt to perform modulo by zero".to_string())
+                    })?;
+                    stack.push(value)
+                }
+                ByteCode::Pow => {
+                    let (a, b) = (pop(&mut stack)?, pop(&mut stack)?);
+                    stack.push(b.pow(a))
+                }
+                ByteCode::Concat => {
+                    let (a, b) = (pop(&mut stack)?, pop(&mut stack)?);
+                    let value = b.concat(&a).ok_or_else(|| {
+                        Error::RuntimeError(format!(
+                            "attempt to concatenate {} and {}",
+                            b.type_name(),
+                            a.type_name()
+                        ))
+                    })?;
+                    stack.push(value)
                 }
                 ByteCode::Incr => {
-                    *stack.last_mut().unwrap() += Value::Int(1);
+                    *top(&mut stack)? += Value::Int(1);
                 }
                 ByteCode::Decr => {
-                    *stack.last_mut().unwrap() -= Value::Int(1);
+                    *top(&mut stack)? -= Value::Int(1);
                 }
                 ByteCode::Greater => {
-                    let (a, b) = (stack.pop(), stack.pop());
-                    let ok = b.unwrap() > a.unwrap();
-                    stack.push(Value::Bool(ok));
+                    let (a, b) = (pop(&mut stack)?, pop(&mut stack)?);
+                    stack.push(Value::Bool(b > a));
                 }
                 ByteCode::Less => {
-                    let (a, b) = (stack.pop(), stack.pop());
-                    let ok = b.unwrap() < a.unwrap();
-                    stack.push(Value::Bool(ok));
+                    let (a, b) = (pop(&mut stack)?, pop(&mut stack)?);
+                    stack.push(Value::Bool(b < a));
+                }
+                ByteCode::GreaterEqual => {
+                    let (a, b) = (pop(&mut stack)?, pop(&mut stack)?);
+                    stack.push(Value::Bool(b >= a));
+                }
+                ByteCode::LessEqual => {
+                    let (a, b) = (pop(&mut stack)?, pop(&mut stack)?);
+                    stack.push(Value::Bool(b <= a));
                 }
                 ByteCode::EqualEqual => {
-                    let (a, b) = (stack.pop(), stack.pop());
-                    let b = a.unwrap() == b.unwrap();
-                    stack.push(Value::Bool(b));
+                    let (a, b) = (pop(&mut stack)?, pop(&mut stack)?);
+                    stack.push(Value::Bool(a == b));
+                }
+                ByteCode::NotEqual => {
+                    let (a, b) = (pop(&mut stack)?, pop(&mut stack)?);
+                    stack.push(Value::Bool(a != b));
                 }
                 ByteCode::Jump(p) => ip = *p,
                 ByteCode::GetLocal(i) => {
-                    let name = constant.get(*i).unwrap();
+                    let name = constant_at(constant, *i)?;
                     // let val = self
                     //     .current_frame()
                     //     .locals
@@ -118,198 +267,209 @@ impl VM {
                     //     .unwrap();
                     // stack.push(val.clone());
                 }
-                ByteCode::SetLocal(_i) => todo!(),
+                ByteCode::SetLocal(_i) => return Err(unsupported(op)),
                 ByteCode::Print => {
-                    let val = stack.pop().unwrap();
-                    print!("{}", val);
+                    let val = pop(&mut stack)?;
+                    self.stdio
+                        .write(format_args!("{}\n", val))
+                        .map_err(|e| Error::RuntimeError(e.to_string()))?;
                 }
                 ByteCode::Call(arg_count) => {
-                    // Save current frame,
-                    let current_frame = Frame::new(stack.len(), ip - 1);
-                    self.frames.push(current_frame);
-
-                    // Create new frame
+                    self.stats.calls += 1;
                     for _ in 0..*arg_count {
-                        let arg = stack.pop().unwrap();
+                        pop(&mut stack)?;
                     }
-                    let func = stack.pop().unwrap();
-
-                    let closure = func.as_closure().unwrap();
-                    let func_name = constant.get(*closure.0).unwrap();
-                    // let param_names = constant.get(*closure.1).unwrap();
-                    let next_func = self
-                        .funcs
-                        .iter()
-                        .find(|f| f.name.as_str() == func_name.as_string().unwrap())
-                        .unwrap();
-                    println!("next_func: {:?}", next_func);
-                    // let ret = self.eval_func(next_func, *arg_count);
-                    // stack.push(ret);
-                    // let frame = Frame::new(stack.len() - *arg_count, 0);
+                    let func = pop(&mut stack)?;
+
+                    let closure = func
+                        .as_closure()
+                        .ok_or_else(|| Error::RuntimeError(format!("{} is not callable", func)))?;
+                    let func_name = name_at(constant, *closure.0)?;
+                    if !self.funcs.iter().any(|f| &f.name == func_name) {
+                        return Err(Error::RuntimeError(format!(
+                            "undefined function {}",
+                            func_name
+                        )));
+                    }
+                    // 还不能执行函数体，调用时报错，而不是不压入返回值导致之后栈下溢
+                    return Err(Error::RuntimeError(format!(
+                        "cannot call function {}: function calls are not supported by the vm",
+                        func_name
+                    )));
                 }
                 ByteCode::Ret => {
-                    let val = stack.pop().unwrap();
-                    ret = val;
+                    ret = pop(&mut stack)?;
+                    break;
+                }
+                ByteCode::JumpIfFalse(p) => {
+                    if !pop(&mut stack)?.is_truthy() {
+                        ip = *p;
+                    }
+                }
+                ByteCode::JumpIfFalseOrPop(p) => {
+                    if top(&mut stack)?.is_truthy() {
+                        stack.pop();
+                    } else {
+                        ip = *p;
+                    }
+                }
+                ByteCode::JumpIfTrueOrPop(p) => {
+                    if top(&mut stack)?.is_truthy() {
+                        ip = *p;
+                    } else {
+                        stack.pop();
+                    }
                 }
-                ByteCode::JumpIfFalse(_) => todo!(),
                 ByteCode::Closure(i) => {
-                    let value = constant.get(*i).unwrap();
+                    let value = constant_at(constant, *i)?;
                     stack.push(value.clone());
                 }
-                ByteCode::Equal => todo!(),
+                ByteCode::Equal => return Err(unsupported(op)),
                 ByteCode::DefineGlabal(i) => {
-                    let val = stack.pop().unwrap();
-                    let name = constant.get(*i).unwrap();
-                    self.globals.insert(name.as_string().unwrap().clone(), val);
+                    let val = pop(&mut stack)?;
+                    let name = name_at(constant, *i)?;
+                    self.globals.insert(name.clone(), val);
                 }
                 ByteCode::GetGlobal(i) => {
-                    let name = constant.get(*i).unwrap();
-                    let val = self.globals.get(name.as_string().unwrap()).unwrap();
+                    let name = name_at(constant, *i)?;
+                    let val = self.globals.get(name).ok_or_else(|| {
+                        Error::RuntimeError(format!("undefined variable {}", name))
+                    })?;
                     stack.push(val.clone());
                 }
-                ByteCode::SetGlobal(_) => todo!(),
+                // vm 中只有全局变量，赋值与定义相同
+                ByteCode::SetGlobal(i) => {
+                    let val = pop(&mut stack)?;
+                    let name = name_at(constant, *i)?;
+                    self.globals.insert(name.clone(), val);
+                }
                 ByteCode::Constant(i) => {
-                    let val = constant.get(*i).unwrap();
+                    let val = constant_at(constant, *i)?;
                     stack.push(val.clone());
                 }
                 ByteCode::Nil => {
                     stack.push(Value::Nil);
                 }
+                ByteCode::GetIndex(i) => {
+                    let key = pop(&mut stack)?;
+                    let table = self.global_table(constant, *i)?;
+                    stack.push(table.get(&key));
+                }
+                ByteCode::SetIndex(i) => {
+                    let (value, key) = (pop(&mut stack)?, pop(&mut stack)?);
+                    let table = self.global_table(constant, *i)?;
+                    if !table.set(&key, value) {
+                        return Err(Error::RuntimeError(format!("index {} out of range", key)));
+                    }
+                }
+                ByteCode::GetChar => {
+                    let default = pop(&mut stack)?;
+                    let value = match self.stdio.read_byte() {
+                        Ok(Some(byte)) => Value::Int(byte as i32),
+                        Ok(None) => default,
+                        Err(e) => return Err(Error::RuntimeError(e.to_string())),
+                    };
+                    stack.push(value);
+                }
+                ByteCode::PutChar => {
+                    let value = pop(&mut stack)?;
+                    let byte = value.as_int().copied().unwrap_or_default() as u8;
+                    self.stdio
+                        .write_bytes(&[byte])
+                        .map_err(|e| Error::RuntimeError(e.to_string()))?;
+                }
             }
+            self.stats.max_stack = self.stats.max_stack.max(stack.len());
+            self.limits
+                .check(self.stats.instructions, stack.len() + self.globals.len())?;
         }
 
-        ret
+        Ok(ret)
     }
 
-    fn eval_func(&mut self, func: &Function, arg_count: usize) -> Value {
-        // if func.arity != arg_count {
-        // }
-        let mut stack: Vec<Value> = Vec::new();
-        let chunk = func.chunk();
-        let mut ip = 0;
-        let code = &chunk.codes;
-        let constant = &chunk.constants;
-        let mut ret = Value::Nil;
+    // 替换函数的字节码，返回旧的字节码；之后的执行使用新的字节码，
+    // 已经在执行中的 chunk 是拷贝，不受影响
+    pub fn redefine_function(&mut self, name: &str, chunk: Chunk) -> Result<Chunk, Error> {
+        let func = self
+            .funcs
+            .iter_mut()
+            .find(|func| func.name == name)
+            .ok_or_else(|| Error::RuntimeError(format!("undefined function {}", name)))?;
+        Ok(std::mem::replace(func.chunk_mut(), chunk))
+    }
 
-        while let Some(op) = code.get(ip) {
-            ip += 1;
-            match op {
-                ByteCode::Push(d) => stack.push(d.clone()),
-                ByteCode::Pop => {
-                    stack.pop();
-                }
-                ByteCode::Add => {
-                    let (a, b) = (stack.pop(), stack.pop());
-                    stack.push(a.unwrap() + b.unwrap())
-                }
-                ByteCode::Sub => {
-                    let (a, b) = (stack.pop(), stack.pop());
-                    stack.push(b.unwrap() - a.unwrap())
-                }
-                ByteCode::Mul => {
-                    let (a, b) = (stack.pop(), stack.pop());
-                    stack.push(a.unwrap() * b.unwrap())
-                }
-                ByteCode::Div => {
-                    let (a, b) = (stack.pop(), stack.pop());
-                    stack.push(b.unwrap() / a.unwrap())
-                }
-                ByteCode::Incr => {
-                    *stack.last_mut().unwrap() += Value::Int(1);
-                }
-                ByteCode::Decr => {
-                    *stack.last_mut().unwrap() -= Value::Int(1);
-                }
-                ByteCode::Greater => {
-                    let (a, b) = (stack.pop(), stack.pop());
-                    let ok = b.unwrap() > a.unwrap();
-                    stack.push(Value::Bool(ok));
-                }
-                ByteCode::Less => {
-                    let (a, b) = (stack.pop(), stack.pop());
-                    let ok = b.unwrap() < a.unwrap();
-                    stack.push(Value::Bool(ok));
-                }
-                ByteCode::EqualEqual => {
-                    let (a, b) = (stack.pop(), stack.pop());
-                    let b = a.unwrap() == b.unwrap();
-                    stack.push(Value::Bool(b));
-                }
-                ByteCode::Jump(p) => ip = *p,
-                ByteCode::GetLocal(i) => {
-                    let name = constant.get(*i).unwrap();
-                    // let val = self
-                    //     .current_frame()
-                    //     .locals
-                    //     .get(name.as_string().unwrap())
-                    //     .unwrap();
-                    // stack.push(val.clone());
-                }
-                ByteCode::SetLocal(_i) => todo!(),
-                ByteCode::Print => {
-                    let val = stack.pop().unwrap();
-                    print!("{}", val);
-                }
-                ByteCode::Call(arg_count) => {
-                    // Save current frame,
-                    let current_frame = Frame::new(stack.len(), ip - 1);
-                    self.frames.push(current_frame);
-
-                    // Create new frame
-                    let func = stack.pop().unwrap();
-                    let closure = func.as_closure().unwrap();
-
-                    let func_name = constant.get(*closure.0).unwrap();
-                    // let param_names = constant.get(*closure.1).unwrap();
-                    let next_func = self
-                        .funcs
-                        .iter()
-                        .find(|f| f.name.as_str() == func_name.as_string().unwrap())
-                        .unwrap();
-                    // let ret = self.eval_func(next_func, *arg_count);
-                    // stack.push(ret);
-                    // let frame = Frame::new(stack.len() - *arg_count, 0);
-                }
-                ByteCode::Ret => {
-                    let val = stack.pop().unwrap();
-                    ret = val;
-                }
-                ByteCode::JumpIfFalse(_) => todo!(),
-                ByteCode::Closure(i) => {
-                    let value = constant.get(*i).unwrap();
-                    stack.push(value.clone());
-                }
-                ByteCode::Equal => todo!(),
-                ByteCode::DefineGlabal(i) => {
-                    let val = stack.pop().unwrap();
-                    let name = constant.get(*i).unwrap();
-                    self.globals.insert(name.as_string().unwrap().clone(), val);
-                }
-                ByteCode::GetGlobal(i) => {
-                    let name = constant.get(*i).unwrap();
-                    let val = self.globals.get(name.as_string().unwrap()).unwrap();
-                    stack.push(val.clone());
-                }
-                ByteCode::SetGlobal(_) => todo!(),
-                ByteCode::Constant(i) => {
-                    let val = constant.get(*i).unwrap();
-                    stack.push(val.clone());
-                }
-                ByteCode::Nil => {
-                    stack.push(Value::Nil);
-                }
-            }
+    pub fn global(&self, name: &str) -> Option<&Value> {
+        self.globals.get(name)
+    }
+
+    // 常量 i 为名字的全局表，原地访问，不拷贝
+    fn global_table(&mut self, constant: &[Value], i: usize) -> Result<&mut Table, Error> {
+        let name = name_at(constant, i)?;
+        match self.globals.get_mut(name) {
+            Some(Value::Table(table)) => Ok(table),
+            _ => Err(Error::RuntimeError(format!("{} is not a table", name))),
         }
+    }
 
-        ret
+    pub fn stats(&self) -> &Stats {
+        &self.stats
     }
 
-    fn current_frame(&mut self) -> &mut Frame {
-        let idx = self.current_frame.unwrap();
-        self.frames.get_mut(idx).unwrap()
+    // 当前的内存使用情况
+    pub fn memory_stats(&self) -> MemoryStats {
+        let constants = self.funcs.iter().flat_map(|func| &func.chunk().constants);
+        let strings: BTreeSet<&str> = constants
+            .clone()
+            .filter_map(|value| value.as_string().map(String::as_str))
+            .collect();
+        let mut heap_objects = 0;
+        for value in self.globals.values() {
+            objects(value, &mut |_| heap_objects += 1);
+        }
+        MemoryStats {
+            constants_bytes: constants.map(value_bytes).sum(),
+            globals_bytes: self
+                .globals
+                .iter()
+                .map(|(name, value)| name.len() + value_bytes(value))
+                .sum(),
+            max_stack: self.stats.max_stack,
+            globals: self.globals.len(),
+            interned_strings: strings.len(),
+            heap_objects,
+        }
     }
 }
 
+// 字节码可能来自不可信的文件，栈与常量的访问出错时返回错误而不是 panic
+fn pop(stack: &mut Vec<Value>) -> Result<Value, Error> {
+    stack
+        .pop()
+        .ok_or_else(|| Error::RuntimeError("stack underflow".to_string()))
+}
+
+fn top(stack: &mut [Value]) -> Result<&mut Value, Error> {
+    stack
+        .last_mut()
+        .ok_or_else(|| Error::RuntimeError("stack underflow".to_string()))
+}
+
+fn constant_at(constant: &[Value], i: usize) -> Result<&Value, Error> {
+    constant
+        .get(i)
+        .ok_or_else(|| Error::RuntimeError(format!("constant {} out of range", i)))
+}
+
+fn name_at(constant: &[Value], i: usize) -> Result<&String, Error> {
+    constant_at(constant, i)?
+        .as_string()
+        .ok_or_else(|| Error::RuntimeError(format!("constant {} is not a name", i)))
+}
+
+fn unsupported(op: &ByteCode) -> Error {
+    Error::RuntimeError(format!("unsupported bytecode {:?}", op))
+}
+
 #[cfg(test)]
 mod tests {
     use crate::bytecode::ByteCode;
@@ -317,7 +477,8 @@ mod tests {
     use crate::emitter::{Chunk, Emitter};
     use crate::parser::Parser;
     use crate::scanner::Scanner;
-    use crate::value::Value;
+    use crate::stdio::Stdio;
+    use crate::value::{Table, Value};
 
     use super::VM;
 
@@ -343,8 +504,237 @@ mod tests {
         chunk.add_bytecode(ByteCode::Ret);
 
         let mut vm = VM::default();
-        let ret = vm.eval(&chunk);
+        let ret = vm.eval(&chunk).unwrap();
         assert_eq!(ret, Value::Nil);
+        assert_eq!(vm.stats().instructions, 10);
+        assert_eq!(vm.stats().calls, 0);
+        assert_eq!(vm.stats().max_stack, 2);
+    }
+
+    #[test]
+    fn test_redefine_function() {
+        let compile = |source: &str| {
+            let mut scanner = Scanner::new(source.to_string());
+            let statements = Parser::new(scanner.scan_tokens().unwrap().clone())
+                .parse()
+                .unwrap();
+            Emitter::new().emit_all(&statements).unwrap().clone()
+        };
+        let funcs = compile("return 1 + 2;");
+        let name = funcs[0].name.clone();
+        let mut vm = VM::new_with_funcs(funcs);
+        assert_eq!(vm.eval_all().unwrap(), Value::Int(3));
+
+        let chunk = compile("return 2 * 5;")[0].chunk().clone();
+        let old = vm.redefine_function(&name, chunk).unwrap();
+        assert_eq!(vm.eval_all().unwrap(), Value::Int(10));
+        vm.redefine_function(&name, old).unwrap();
+        assert_eq!(vm.eval_all().unwrap(), Value::Int(3));
+
+        let err = vm.redefine_function("missing", Chunk::new()).unwrap_err();
+        assert_eq!(err.to_string(), "Runtime error: undefined function missing");
+    }
+
+    #[test]
+    fn test_define_global() {
+        let mut chunk = Chunk::new();
+        let index = chunk.add_constant(Value::String("n".to_string()));
+        chunk.add_bytecode(ByteCode::GetGlobal(index));
+        chunk.add_bytecode(ByteCode::Ret);
+
+        let mut vm = VM::default();
+        vm.define_global("n", Value::Int(3));
+        assert_eq!(vm.eval(&chunk).unwrap(), Value::Int(3));
+    }
+
+    #[test]
+    fn test_while_loop() {
+        let source = "local i = 0;
+local s = 0;
+while i < 4 do
+  s = s + i;
+  i = i + 1;
+end
+return s;";
+        let mut scanner = Scanner::new(source.to_string());
+        let statements = Parser::new(scanner.scan_tokens().unwrap().clone())
+            .parse()
+            .unwrap();
+        let funcs = Emitter::new().emit_all(&statements).unwrap().clone();
+        let mut vm = VM::new_with_funcs(funcs);
+        assert_eq!(vm.eval_all().unwrap(), Value::Int(6));
+        assert_eq!(vm.global("i"), Some(&Value::Int(4)));
+    }
+
+    #[test]
+    fn test_break_loop() {
+        let source = "local i = 0;
+while 1 do
+  local j = 0;
+  while 1 do
+    if j > i - 1 then break; end
+    j = j + 1;
+  end
+  if i > 2 then break; end
+  i = i + 1;
+end
+return i;";
+        let mut scanner = Scanner::new(source.to_string());
+        let statements = Parser::new(scanner.scan_tokens().unwrap().clone())
+            .parse()
+            .unwrap();
+        let funcs = Emitter::new().emit_all(&statements).unwrap().clone();
+        let mut vm = VM::new_with_funcs(funcs);
+        assert_eq!(vm.eval_all().unwrap(), Value::Int(3));
+    }
+
+    #[test]
+    fn test_repeat_loop() {
+        let source = "local s = 0;
+repeat
+  s = s + 1;
+  local done = s > 2;
+until done
+repeat
+  s = s * 2;
+  break;
+until nil
+return s;";
+        let mut scanner = Scanner::new(source.to_string());
+        let statements = Parser::new(scanner.scan_tokens().unwrap().clone())
+            .parse()
+            .unwrap();
+        let funcs = Emitter::new().emit_all(&statements).unwrap().clone();
+        let mut vm = VM::new_with_funcs(funcs);
+        assert_eq!(vm.eval_all().unwrap(), Value::Int(6));
+    }
+
+    #[test]
+    fn test_mixed_arithmetic() {
+        let source = "local a = 2 * 1.5;\nlocal b = 1 + 0.5;\nreturn 2 - 0.5;";
+        let mut scanner = Scanner::new(source.to_string());
+        let statements = Parser::new(scanner.scan_tokens().unwrap().clone())
+            .parse()
+            .unwrap();
+        let funcs = Emitter::new().emit_all(&statements).unwrap().clone();
+        let mut vm = VM::new_with_funcs(funcs);
+        assert_eq!(vm.eval_all().unwrap(), Value::Float(1.5));
+        assert_eq!(vm.global("a"), Some(&Value::Float(3.0)));
+        assert_eq!(vm.global("b"), Some(&Value::Float(1.5)));
+    }
+
+    #[test]
+    fn test_floored_mod() {
+        let source = "local n = 0 - 7;\nlocal m = 0 - 3;\nlocal x = 0 - 5.5;\nlocal a = n % 3;\nlocal b = 7 % m;\nreturn x % 2;";
+        let mut scanner = Scanner::new(source.to_string());
+        let statements = Parser::new(scanner.scan_tokens().unwrap().clone())
+            .parse()
+            .unwrap();
+        let funcs = Emitter::new().emit_all(&statements).unwrap().clone();
+        let mut vm = VM::new_with_funcs(funcs);
+        assert_eq!(vm.eval_all().unwrap(), Value::Float(0.5));
+        assert_eq!(vm.global("a"), Some(&Value::Int(2)));
+        assert_eq!(vm.global("b"), Some(&Value::Int(-2)));
+    }
+
+    #[test]
+    fn test_call_unsupported() {
+        let source = "function f(a)\n  return a;\nend\nprint(f(1));";
+        let mut scanner = Scanner::new(source.to_string());
+        let statements = Parser::new(scanner.scan_tokens().unwrap().clone())
+            .parse()
+            .unwrap();
+        let funcs = Emitter::new().emit_all(&statements).unwrap().clone();
+        let err = VM::new_with_funcs(funcs).eval_all().unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Runtime error: cannot call function f: function calls are not supported by the vm"
+        );
+    }
+
+    #[test]
+    fn test_memory_stats() {
+        let source = "local a = 1 + 2;\nlocal b = a * 3;\nprint(b);\nreturn a;";
+        let mut scanner = Scanner::new(source.to_string());
+        let statements = Parser::new(scanner.scan_tokens().unwrap().clone())
+            .parse()
+            .unwrap();
+        let funcs = Emitter::new().emit_all(&statements).unwrap().clone();
+        let mut vm = VM::new_with_funcs(funcs);
+        let mut stdio = Stdio::default();
+        stdio.set_stdout(std::io::sink());
+        vm.set_stdio(stdio);
+        let mut arg = Table::new();
+        arg.array.push(Value::String("x".to_string()));
+        vm.define_global("arg", Value::Table(arg));
+        vm.eval_all().unwrap();
+
+        let stats = vm.memory_stats();
+        let size = std::mem::size_of::<Value>();
+        assert_eq!(stats.globals, 3);
+        assert_eq!(stats.heap_objects, 2);
+        assert_eq!(stats.interned_strings, 2);
+        assert_eq!(stats.max_stack, vm.stats().max_stack);
+        assert_eq!(
+            stats.globals_bytes,
+            "arg".len() + 2 * size + 1 + 2 * (1 + size)
+        );
+        assert_eq!(
+            stats.total_bytes(),
+            stats.constants_bytes + stats.globals_bytes
+        );
+    }
+
+    #[test]
+    fn test_globals_debug_order() {
+        let mut vm = VM::default();
+        for name in ["c", "a", "b"] {
+            vm.define_global(name, Value::Nil);
+        }
+        let dump = format!("{:?}", vm);
+        assert!(dump.contains(r#"globals: {"a": Nil, "b": Nil, "c": Nil}"#));
+    }
+
+    #[test]
+    fn test_malformed_chunk() {
+        let eval = |codes: Vec<ByteCode>| {
+            let mut chunk = Chunk::new();
+            chunk.add_constant(Value::Int(1));
+            for code in codes {
+                chunk.add_bytecode(code);
+            }
+            VM::default().eval(&chunk).unwrap_err().to_string()
+        };
+        assert_eq!(eval(vec![ByteCode::Add]), "Runtime error: stack underflow");
+        assert_eq!(eval(vec![ByteCode::Incr]), "Runtime error: stack underflow");
+        assert_eq!(
+            eval(vec![ByteCode::Constant(7)]),
+            "Runtime error: constant 7 out of range"
+        );
+        assert_eq!(
+            eval(vec![ByteCode::GetGlobal(0)]),
+            "Runtime error: constant 0 is not a name"
+        );
+        assert_eq!(
+            eval(vec![
+                ByteCode::Constant(0),
+                ByteCode::Push(Value::Int(0)),
+                ByteCode::Div
+            ]),
+            "Runtime error: attempt to divide by zero"
+        );
+        assert_eq!(
+            eval(vec![
+                ByteCode::Constant(0),
+                ByteCode::Push(Value::Int(0)),
+                ByteCode::Mod
+            ]),
+            "Runtime error: attempt to perform modulo by zero"
+        );
+        assert_eq!(
+            eval(vec![ByteCode::Equal]),
+            "Runtime error: unsupported bytecode Equal"
+        );
     }
 
     #[test]
@@ -367,7 +757,7 @@ mod tests {
         debug(chunk);
 
         let mut vm = VM::default();
-        let ret = vm.eval(&chunk);
+        let ret = vm.eval(&chunk).unwrap();
         assert_eq!(ret, Value::Nil);
     }
 
@@ -394,7 +784,7 @@ mod tests {
         debug_all(funcs);
 
         let mut vm = VM::new_with_funcs(funcs.clone());
-        let ret = vm.eval_all();
+        let ret = vm.eval_all().unwrap();
         assert_eq!(ret, Value::Nil);
     }
 
@@ -431,7 +821,7 @@ mod tests {
         debug(r);
 
         let mut vm = VM::default();
-        vm.eval(r);
+        vm.eval(r).unwrap();
     }
 
     #[test]
@@ -462,6 +852,6 @@ mod tests {
         debug(chunk);
 
         let mut vm = VM::default();
-        vm.eval(chunk);
+        vm.eval(chunk).unwrap();
     }
 }
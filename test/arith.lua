@@ -0,0 +1,5 @@
+local a = 1 + 2 * 3;
+local b = a - 4 / 2;
+print(a < b);
+print(a > b);
+return b;
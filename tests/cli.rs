@@ -0,0 +1,23 @@
+use std::process::Command;
+
+// 输入文件不存在时输出错误并以 1 退出，而不是 panic
+#[test]
+fn test_missing_input() {
+    let tinylua = env!("CARGO_BIN_EXE_tinylua");
+    for args in [
+        vec!["missing.lua"],
+        vec!["run", "missing.luac"],
+        vec!["fmt", "missing.lua"],
+    ] {
+        let output = Command::new(tinylua).args(&args).output().unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert_eq!(output.status.code(), Some(1), "{:?}: {}", args, stderr);
+        assert!(
+            stderr.contains("cannot open missing.lu"),
+            "{:?}: {}",
+            args,
+            stderr
+        );
+        assert!(!stderr.contains("panicked"), "{:?}: {}", args, stderr);
+    }
+}